@@ -0,0 +1,64 @@
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use transfer::{self, Limits};
+
+/// The bytes that make the kernel's shell reset the Pi into the bootloader.
+/// Must match `RESET_MAGIC` in the kernel's `shell.rs`.
+pub const RESET_MAGIC: &[u8] = b"\x1b!reset-to-bootloader!\x1b";
+
+/// How long to wait for the bootloader after a reset, unless the handshake
+/// timeout is set.
+const DEFAULT_BOOT_TIMEOUT: u64 = 30;
+
+const NAK: u8 = 0x15;
+const CRC: u8 = b'C';
+
+/// Asks the kernel on the other end of `port` to reset the Pi into the
+/// bootloader.
+pub fn reset<P: io::Write>(port: &mut P) -> io::Result<()> {
+    port.write_all(RESET_MAGIC)?;
+    port.flush()
+}
+
+/// Discards everything read from `port`, like the kernel's last words, until
+/// the bootloader asks for a transfer with a `NAK` or a `C`.
+///
+/// # Errors
+///
+/// Returns an error of `NotConnected` if the bootloader doesn't start within
+/// `timeout`.
+pub fn wait_for_handshake<P: io::Read>(port: &mut P, timeout: Duration) -> io::Result<()> {
+    let start = Instant::now();
+    let mut byte = [0u8];
+    while start.elapsed() < timeout {
+        match port.read(&mut byte) {
+            Ok(1) if byte[0] == NAK || byte[0] == CRC => return Ok(()),
+            Ok(_) => continue,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::NotConnected, "bootloader didn't start"))
+}
+
+/// Uploads the kernel at `path` to the bootloader, first resetting the Pi if
+/// `reset` is set. The bootloader's handshake is awaited for the handshake
+/// timeout of `limits`, or 30 seconds.
+pub fn load<P>(port: &mut P, path: &Path, reset: bool, one_k: bool, limits: Limits)
+    -> io::Result<usize>
+    where P: io::Read + io::Write
+{
+    if reset {
+        self::reset(port)?;
+    }
+
+    eprintln!("Waiting for the bootloader...");
+    let timeout = limits.handshake_timeout.unwrap_or(DEFAULT_BOOT_TIMEOUT);
+    wait_for_handshake(port, Duration::from_secs(timeout))?;
+
+    // The bootloader asks again once its read of the first packet times out.
+    transfer::send_file(port, path, one_k, limits)
+}
@@ -0,0 +1,453 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serial::core::{BaudRate, CharSize, FlowControl, StopBits};
+use toml::Value;
+
+use parsers::{parse_baud_rate, parse_width, parse_stop_bits, parse_flow_control, parse_protocol};
+
+/// The name of configuration files.
+const FILE_NAME: &str = "ttywrite.toml";
+
+/// How files are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Xmodem,
+    Xmodem1k,
+    Raw,
+}
+
+/// How a profile selects its TTY.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tty {
+    /// A path, whose file name may contain `*` and `?` wildcards, e.g.,
+    /// `/dev/serial/by-id/usb-Silicon_Labs_CP2102*`.
+    Path(PathBuf),
+    /// The serial number of the USB device the TTY belongs to.
+    UsbSerial(String),
+}
+
+/// A named set of settings for a board. Settings that aren't set fall back to
+/// the command line's defaults.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub tty: Option<Tty>,
+    pub baud_rate: Option<BaudRate>,
+    pub char_width: Option<CharSize>,
+    pub stop_bits: Option<StopBits>,
+    pub flow_control: Option<FlowControl>,
+    pub timeout: Option<u64>,
+    pub protocol: Option<Protocol>,
+    /// The file sent by default, relative to the configuration file.
+    pub file: Option<PathBuf>,
+}
+
+/// The profiles of the configuration files.
+///
+/// A configuration file holds the name of the profile used when none is
+/// selected, and a table per profile:
+///
+/// ```toml
+/// default = "pi3"
+///
+/// [profiles.pi3]
+/// tty = "/dev/serial/by-id/usb-Silicon_Labs_CP2102*"   # or usb-serial = "0001"
+/// baud = 115200
+/// width = 8
+/// stop-bits = 1
+/// flow-control = "none"
+/// timeout = 10
+/// protocol = "xmodem-1k"                               # "xmodem" or "raw"
+/// file = "build/kernel.bin"
+/// ```
+#[derive(Debug, Default)]
+pub struct Config {
+    default: Option<String>,
+    profiles: BTreeMap<String, Profile>,
+}
+
+/// Returns the path of the user's configuration file:
+/// `$XDG_CONFIG_HOME/ttywrite.toml`, or `~/.config/ttywrite.toml`.
+fn user_file() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join(FILE_NAME))
+}
+
+/// Returns the path of the project's configuration file: the first
+/// `ttywrite.toml` in the current directory or its ancestors.
+fn project_file() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    let mut dir = Some(cwd.as_path());
+    while let Some(current) = dir {
+        let path = current.join(FILE_NAME);
+        if path.is_file() {
+            return Some(path);
+        }
+
+        dir = current.parent();
+    }
+
+    None
+}
+
+/// Returns the text of the setting `value`, which may be a string or an
+/// integer, so that it can be validated like a command line argument.
+fn setting(value: &Value) -> Result<String, String> {
+    match *value {
+        Value::String(ref s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        _ => Err("expected a string or an integer".to_string()),
+    }
+}
+
+impl Config {
+    /// Loads the user's configuration file, then the project's. The project's
+    /// profiles replace the user's profiles of the same name, and its default
+    /// profile replaces the user's. Missing files are skipped.
+    pub fn load() -> Result<Config, String> {
+        let mut config = Config::default();
+        for path in user_file().into_iter().chain(project_file()) {
+            if path.is_file() {
+                config.merge(Config::read(&path)?);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Reads the configuration file at `path`.
+    pub fn read(path: &Path) -> Result<Config, String> {
+        let error = |e: String| format!("{}: {}", path.display(), e);
+        let text = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        let dir = match path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+
+        Config::parse(&text, dir).map_err(error)
+    }
+
+    /// Parses `text`, the contents of a configuration file in `dir`.
+    pub fn parse(text: &str, dir: &Path) -> Result<Config, String> {
+        let value: Value = text.parse().map_err(|e: ::toml::de::Error| e.to_string())?;
+        let table = match value {
+            Value::Table(table) => table,
+            _ => return Err("expected a table".to_string()),
+        };
+
+        let mut config = Config::default();
+        for (key, value) in &table {
+            match &key[..] {
+                "default" => config.default = Some(setting(value)?),
+                "profiles" => {
+                    let profiles = value.as_table()
+                        .ok_or_else(|| "'profiles' must be a table".to_string())?;
+                    for (name, profile) in profiles {
+                        config.profiles.insert(name.clone(), Profile::parse(name, profile, dir)?);
+                    }
+                }
+                _ => return Err(format!("unknown key '{}'", key)),
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Adds the settings of `other`, replacing those already set.
+    fn merge(&mut self, other: Config) {
+        if other.default.is_some() {
+            self.default = other.default;
+        }
+
+        self.profiles.extend(other.profiles);
+    }
+
+    /// Returns the profile named `name`, or the default profile if `name` is
+    /// `None`. Without a default profile, returns an empty profile.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, String> {
+        let name = match name.or(self.default.as_ref().map(|name| &name[..])) {
+            Some(name) => name,
+            None => return Ok(Profile::default()),
+        };
+
+        self.profiles.get(name).cloned().ok_or_else(|| {
+            let known: Vec<&str> = self.profiles.keys().map(|name| &name[..]).collect();
+            match known.len() {
+                0 => format!("no profile named '{}': no ttywrite.toml defines profiles", name),
+                _ => format!("no profile named '{}' (known profiles: {})", name, known.join(", ")),
+            }
+        })
+    }
+}
+
+impl Profile {
+    /// Parses the table `value` of the profile `name` from a configuration
+    /// file in `dir`.
+    fn parse(name: &str, value: &Value, dir: &Path) -> Result<Profile, String> {
+        let table = value.as_table()
+            .ok_or_else(|| format!("profile '{}' must be a table", name))?;
+
+        let mut profile = Profile::default();
+        for (key, value) in table {
+            let error = |e: &str| format!("profile '{}': invalid {}: {}", name, key, e);
+            let text = setting(value).map_err(|e| error(&e))?;
+            match &key[..] {
+                "tty" | "usb-serial" if profile.tty.is_some() => {
+                    return Err(error("only one of 'tty' and 'usb-serial' can be set"));
+                }
+                "tty" => profile.tty = Some(Tty::Path(PathBuf::from(text))),
+                "usb-serial" => profile.tty = Some(Tty::UsbSerial(text)),
+                "baud" => {
+                    let baud_rate = parse_baud_rate(&text).map_err(|e| error(&e.to_string()))?;
+                    profile.baud_rate = Some(baud_rate);
+                }
+                "width" => profile.char_width = Some(parse_width(&text).map_err(error)?),
+                "stop-bits" => profile.stop_bits = Some(parse_stop_bits(&text).map_err(error)?),
+                "flow-control" => {
+                    profile.flow_control = Some(parse_flow_control(&text).map_err(error)?);
+                }
+                "timeout" => {
+                    let timeout = text.parse().map_err(|_| error("expected seconds"))?;
+                    profile.timeout = Some(timeout);
+                }
+                "protocol" => profile.protocol = Some(parse_protocol(&text).map_err(error)?),
+                "file" => profile.file = Some(dir.join(text)),
+                _ => return Err(format!("profile '{}': unknown key '{}'", name, key)),
+            }
+        }
+
+        Ok(profile)
+    }
+}
+
+/// Returns `true` if `name` matches `pattern`, where `*` matches any number
+/// of bytes and `?` matches one byte.
+///
+/// On a mismatch, only the last `*` is retried with one more byte, which
+/// keeps matching linear in the length of `name` for each `*`.
+fn wildcard(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // The pattern index after the last `*` and the name index it resumes at.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(&b'*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((next, resume)) => {
+                    star = Some((next, resume + 1));
+                    p = next;
+                    n = resume + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Returns the only path in `found`, described by `what` in errors.
+fn only(mut found: Vec<PathBuf>, what: &str) -> Result<PathBuf, String> {
+    match found.len() {
+        0 => Err(format!("no TTY matches {}", what)),
+        1 => Ok(found.remove(0)),
+        _ => {
+            found.sort();
+            let paths: Vec<String> = found.iter().map(|path| path.display().to_string()).collect();
+            Err(format!("several TTYs match {}: {}", what, paths.join(", ")))
+        }
+    }
+}
+
+/// Returns the path matching `path`, whose file name may contain wildcards.
+fn find_by_glob(path: &Path) -> Result<PathBuf, String> {
+    let pattern = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) if name.contains('*') || name.contains('?') => name,
+        _ => return Ok(path.to_path_buf()),
+    };
+
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+
+    let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let found = entries.filter_map(|entry| entry.ok())
+        .filter(|entry| match entry.file_name().to_str() {
+            Some(name) => wildcard(pattern.as_bytes(), name.as_bytes()),
+            None => false,
+        })
+        .map(|entry| entry.path())
+        .collect();
+
+    only(found, &format!("'{}'", path.display()))
+}
+
+/// Returns the TTY of the USB device with the serial number `serial`, found
+/// through sysfs: the serial number belongs to the USB device, one of the
+/// ancestors of the TTY's device.
+fn find_by_usb_serial(serial: &str) -> Result<PathBuf, String> {
+    let entries = fs::read_dir("/sys/class/tty")
+        .map_err(|e| format!("can't list TTYs: {}", e))?;
+
+    let mut found = Vec::new();
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let device = match fs::canonicalize(entry.path().join("device")) {
+            Ok(device) => device,
+            Err(_) => continue,
+        };
+
+        let mut dir = Some(device.as_path());
+        while let Some(current) = dir {
+            if let Ok(number) = fs::read_to_string(current.join("serial")) {
+                if number.trim() == serial {
+                    found.push(Path::new("/dev").join(entry.file_name()));
+                }
+
+                break;
+            }
+
+            dir = current.parent();
+        }
+    }
+
+    only(found, &format!("USB serial number '{}'", serial))
+}
+
+impl Tty {
+    /// Returns the path of the TTY this selects.
+    pub fn resolve(&self) -> Result<PathBuf, String> {
+        match *self {
+            Tty::Path(ref path) => find_by_glob(path),
+            Tty::UsbSerial(ref serial) => find_by_usb_serial(serial),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = r#"
+        default = "pi3"
+
+        [profiles.pi3]
+        tty = "/dev/ttyUSB*"
+        baud = 57600
+        width = "7"
+        stop-bits = 2
+        flow-control = "hardware"
+        timeout = 3
+        protocol = "xmodem-1k"
+        file = "build/kernel.bin"
+
+        [profiles.bench]
+        usb-serial = "A600B1"
+        protocol = "raw"
+    "#;
+
+    #[test]
+    fn profiles() {
+        let config = Config::parse(EXAMPLE, Path::new("/project")).expect("valid config");
+
+        let pi3 = config.profile(None).expect("default profile");
+        assert_eq!(pi3.tty, Some(Tty::Path(PathBuf::from("/dev/ttyUSB*"))));
+        assert_eq!(pi3.baud_rate, Some(BaudRate::Baud57600));
+        assert_eq!(pi3.char_width, Some(CharSize::Bits7));
+        assert_eq!(pi3.stop_bits, Some(StopBits::Stop2));
+        assert_eq!(pi3.flow_control, Some(FlowControl::FlowHardware));
+        assert_eq!(pi3.timeout, Some(3));
+        assert_eq!(pi3.protocol, Some(Protocol::Xmodem1k));
+        assert_eq!(pi3.file, Some(PathBuf::from("/project/build/kernel.bin")));
+
+        let bench = config.profile(Some("bench")).expect("bench profile");
+        assert_eq!(bench.tty, Some(Tty::UsbSerial("A600B1".to_string())));
+        assert_eq!(bench.protocol, Some(Protocol::Raw));
+        assert_eq!(bench.baud_rate, None);
+
+        assert!(config.profile(Some("missing")).unwrap_err().contains("bench, pi3"));
+    }
+
+    #[test]
+    fn merge() {
+        let mut config = Config::parse(EXAMPLE, Path::new("/home")).expect("user config");
+        let project = r#"
+            [profiles.pi3]
+            baud = 9600
+        "#;
+        config.merge(Config::parse(project, Path::new("/project")).expect("project config"));
+
+        let pi3 = config.profile(None).expect("default profile");
+        assert_eq!(pi3.baud_rate, Some(BaudRate::Baud9600));
+        assert_eq!(pi3.protocol, None);
+        assert!(config.profile(Some("bench")).is_ok());
+    }
+
+    #[test]
+    fn no_profile() {
+        let config = Config::parse("", Path::new(".")).expect("empty config");
+        assert!(config.profile(None).expect("no profile").tty.is_none());
+    }
+
+    #[test]
+    fn invalid() {
+        let invalid = |text: &str| Config::parse(text, Path::new(".")).unwrap_err();
+
+        assert!(invalid("[profiles.a]\nbaud = \"fast\"").contains("invalid baud"));
+        assert!(invalid("[profiles.a]\nwidth = 9").contains("value must be >= 5"));
+        assert!(invalid("[profiles.a]\nstop-bits = 3").contains("invalid stop-bits"));
+        assert!(invalid("[profiles.a]\nflow-control = \"rts\"").contains("flow-control"));
+        assert!(invalid("[profiles.a]\nprotocol = \"zmodem\"").contains("protocol"));
+        assert!(invalid("[profiles.a]\ntimeout = -1").contains("expected seconds"));
+        assert!(invalid("[profiles.a]\nparity = \"odd\"").contains("unknown key 'parity'"));
+        assert!(invalid("[profiles.a]\ntty = \"a\"\nusb-serial = \"b\"").contains("only one"));
+        assert!(invalid("[profiles.a]\nbaud = 1.5").contains("expected a string"));
+        assert!(invalid("profile = \"a\"").contains("unknown key 'profile'"));
+        assert!(!invalid("[profiles").is_empty());
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard(b"ttyUSB*", b"ttyUSB0"));
+        assert!(wildcard(b"ttyUSB*", b"ttyUSB"));
+        assert!(wildcard(b"usb-*-if00*", b"usb-FTDI_FT232R_A600B1-if00-port0"));
+        assert!(wildcard(b"tty?", b"tty1"));
+        assert!(!wildcard(b"tty?", b"tty"));
+        assert!(!wildcard(b"ttyUSB*", b"ttyACM0"));
+        assert!(wildcard(b"*", b""));
+        assert!(wildcard(b"a*b*c", b"aXbYbZc"));
+        assert!(!wildcard(b"a*b*c", b"aXbYbZ"));
+
+        // Retrying every `*` would take exponential time.
+        let name = [b'a'; 200];
+        assert!(!wildcard(b"*a*a*a*a*a*a*a*a*a*a*b", &name));
+        assert!(wildcard(b"*a*a*a*a*a*a*a*a*a*a*", &name));
+    }
+
+    #[test]
+    fn globs() {
+        let dir = env::temp_dir().join(format!("ttywrite-config-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("scratch dir");
+        for name in &["usb-A-port0", "usb-B-port0", "pci-C"] {
+            fs::write(dir.join(name), b"").expect("scratch file");
+        }
+
+        let tty = |pattern: &str| Tty::Path(dir.join(pattern)).resolve();
+        assert_eq!(tty("pci-*"), Ok(dir.join("pci-C")));
+        assert_eq!(tty("usb-B*"), Ok(dir.join("usb-B-port0")));
+        assert_eq!(tty("ttyS0"), Ok(dir.join("ttyS0")));
+        assert!(tty("usb-*").unwrap_err().contains("several TTYs"));
+        assert!(tty("none-*").unwrap_err().contains("no TTY"));
+    }
+}
@@ -1,91 +1,321 @@
+extern crate bootimage;
+extern crate libc;
 extern crate serial;
 extern crate structopt;
+extern crate termios;
+extern crate toml;
 extern crate xmodem;
 #[macro_use] extern crate structopt_derive;
 
-use std::io::Write;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::time::Duration;
 
 use structopt::StructOpt;
 use serial::core::{CharSize, BaudRate, StopBits, FlowControl, SerialDevice, SerialPortSettings};
-use xmodem::{Xmodem, Progress};
 
+mod bootloader;
+mod config;
 mod parsers;
+mod progress_bar;
+mod term;
+mod transfer;
+mod watch;
 
-use parsers::{parse_width, parse_stop_bits, parse_flow_control, parse_baud_rate};
+use config::{Config, Protocol};
+use parsers::{parse_width, parse_stop_bits, parse_flow_control, parse_baud_rate, parse_enter,
+              parse_address};
+use term::Enter;
+use transfer::Limits;
 
 #[derive(StructOpt, Debug)]
-#[structopt(about = "Write to TTY using the XMODEM protocol by default.")]
+#[structopt(about = "Talk to a TTY: send or receive files with XMODEM, or open a terminal.")]
 struct Opt {
-    #[structopt(short = "i", help = "Input file (defaults to stdin if not set)", parse(from_os_str))]
-    input: Option<PathBuf>,
+    #[structopt(short = "p", long = "profile",
+                help = "Use a profile of ttywrite.toml (defaults to its default profile)")]
+    profile: Option<String>,
 
     #[structopt(short = "b", long = "baud", parse(try_from_str = "parse_baud_rate"),
-                help = "Set baud rate", default_value = "115200")]
-    baud_rate: BaudRate,
+                help = "Set baud rate [default: 115200]")]
+    baud_rate: Option<BaudRate>,
 
     #[structopt(short = "t", long = "timeout", parse(try_from_str),
-                help = "Set timeout in seconds", default_value = "10")]
-    timeout: u64,
+                help = "Set timeout in seconds [default: 10]")]
+    timeout: Option<u64>,
 
     #[structopt(short = "w", long = "width", parse(try_from_str = "parse_width"),
-                help = "Set data character width in bits", default_value = "8")]
-    char_width: CharSize,
+                help = "Set data character width in bits [default: 8]")]
+    char_width: Option<CharSize>,
 
-    #[structopt(help = "Path to TTY device", parse(from_os_str))]
-    tty_path: PathBuf,
+    #[structopt(help = "Path to TTY device (defaults to the profile's)", parse(from_os_str))]
+    tty_path: Option<PathBuf>,
 
     #[structopt(short = "f", long = "flow-control", parse(try_from_str = "parse_flow_control"),
-                help = "Enable flow control ('hardware' or 'software')", default_value = "none")]
-    flow_control: FlowControl,
+                help = "Enable flow control ('hardware' or 'software') [default: none]")]
+    flow_control: Option<FlowControl>,
 
     #[structopt(short = "s", long = "stop-bits", parse(try_from_str = "parse_stop_bits"),
-                help = "Set number of stop bits", default_value = "1")]
-    stop_bits: StopBits,
+                help = "Set number of stop bits [default: 1]")]
+    stop_bits: Option<StopBits>,
 
-    #[structopt(short = "r", long = "raw", help = "Disable XMODEM")]
-    raw: bool,
+    #[structopt(long = "retries", parse(try_from_str),
+                help = "Give up after sending or requesting a packet this many times",
+                default_value = "10")]
+    retries: usize,
+
+    #[structopt(long = "handshake-timeout", parse(try_from_str),
+                help = "Wait this many seconds for the peer to start (defaults to the timeout)")]
+    handshake_timeout: Option<u64>,
+
+    #[structopt(long = "deadline", parse(try_from_str),
+                help = "Give up if a transfer takes longer than this many seconds")]
+    deadline: Option<u64>,
+
+    #[structopt(subcommand)]
+    command: Command,
 }
 
-fn main() {
-    use std::fs::File;
-    use std::io::{self, BufReader};
+#[derive(StructOpt, Debug)]
+enum Command {
+    #[structopt(name = "send", about = "Send a file using the XMODEM protocol by default.")]
+    Send {
+        #[structopt(short = "i",
+                    help = "Input file (defaults to the profile's file, or stdin if not set)",
+                    parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        #[structopt(short = "r", long = "raw", help = "Disable XMODEM")]
+        raw: bool,
+
+        #[structopt(short = "k", long = "1k",
+                    help = "Send 1024-byte XMODEM blocks if the receiver uses CRC-16")]
+        one_k: bool,
+
+        #[structopt(long = "watch",
+                    help = "Send the input file to the bootloader again whenever it changes")]
+        watch: bool,
+
+        #[structopt(long = "no-reset",
+                    help = "With --watch, don't ask the kernel to reset the Pi before sending")]
+        no_reset: bool,
+    },
+
+    #[structopt(name = "recv", about = "Receive a file with XMODEM, or a batch with YMODEM.")]
+    Recv {
+        #[structopt(short = "o",
+                    help = "Output file, or directory with --ymodem \
+                            (defaults to stdout, or the current directory)",
+                    parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        #[structopt(short = "y", long = "ymodem", help = "Receive a YMODEM batch")]
+        ymodem: bool,
+
+        #[structopt(short = "c", long = "checksum",
+                    help = "Ask for XMODEM checksums instead of CRC-16")]
+        checksum: bool,
+    },
+
+    #[structopt(name = "pack", about = "Wrap a kernel in a boot image header for the bootloader.")]
+    Pack {
+        #[structopt(short = "i", help = "Input file, the raw kernel binary", parse(from_os_str))]
+        input: PathBuf,
+
+        #[structopt(short = "o", help = "Output file, the boot image", parse(from_os_str))]
+        output: PathBuf,
+
+        #[structopt(long = "load", parse(try_from_str = "parse_address"),
+                    help = "Address the kernel is loaded to", default_value = "0x80000")]
+        load_addr: u64,
+
+        #[structopt(long = "entry", parse(try_from_str = "parse_address"),
+                    help = "Address the bootloader jumps to (defaults to the load address)")]
+        entry: Option<u64>,
+
+        #[structopt(short = "z", long = "compress",
+                    help = "Compress the kernel, which the bootloader decompresses")]
+        compress: bool,
+    },
+
+    #[structopt(name = "term", about = "Open an interactive terminal. Ctrl-A h shows the commands.")]
+    Term {
+        #[structopt(short = "e", long = "echo", help = "Echo typed characters locally")]
+        echo: bool,
+
+        #[structopt(long = "enter", parse(try_from_str = "parse_enter"),
+                    help = "What the Enter key sends ('cr', 'lf', or 'crlf')",
+                    default_value = "cr")]
+        enter: Enter,
+
+        #[structopt(long = "crlf", help = "Display received LF as CR LF")]
+        crlf: bool,
+
+        #[structopt(short = "l", long = "log", help = "Append everything received to a file",
+                    parse(from_os_str))]
+        log: Option<PathBuf>,
+
+        #[structopt(short = "u", long = "upload",
+                    help = "File uploaded by Ctrl-A u and Ctrl-A r (defaults to the profile's)",
+                    parse(from_os_str))]
+        upload: Option<PathBuf>,
+
+        #[structopt(long = "watch",
+                    help = "Reset the Pi and upload the --upload file whenever it changes")]
+        watch: bool,
+
+        #[structopt(short = "k", long = "1k",
+                    help = "Upload 1024-byte XMODEM blocks if the receiver uses CRC-16")]
+        one_k: bool,
+    },
+}
+
+/// Exits with an explanation of the failed transfer `e`.
+fn fail(e: &io::Error) -> ! {
+    eprintln!("{}", transfer::explain(e));
+    process::exit(1);
+}
+
+/// Exits with the error `e`.
+fn fatal(e: &str) -> ! {
+    eprintln!("ttywrite: {}", e);
+    process::exit(1);
+}
+
+/// Writes the boot image of the kernel at `input` to `output`.
+fn pack(input: &Path, output: &Path, load_addr: u64, entry: Option<u64>, compress: bool) {
+    let payload = fs::read(input).expect("Failed to read input file");
+    let image = bootimage::pack(&payload, load_addr, entry.unwrap_or(load_addr), compress)
+        .unwrap_or_else(|e| fatal(&e));
+    fs::write(output, &image).expect("Failed to write output file");
+    println!("Wrote {} bytes.", image.len());
+}
+
+/// Sends `data`, `total` bytes long if known, over `port`: as it is if `raw`
+/// is set, with XMODEM otherwise.
+fn send<T, R>(mut port: T, mut data: R, total: Option<u64>, raw: bool, one_k: bool,
+              limits: Limits)
+    where T: io::Read + io::Write, R: io::Read
+{
+    if raw {
+        let bytes = io::copy(&mut data, &mut port).expect("Write failed");
+        println!("Wrote {} bytes.", bytes);
+    } else {
+        match transfer::send(port, data, total, one_k, limits) {
+            Ok(bytes) => println!("Wrote {} bytes.", bytes),
+            Err(e) => fail(&e),
+        }
+    }
+}
+
+/// Receives a file from `port` with XMODEM into `into`.
+fn receive<T, W>(port: T, into: W, checksum: bool, limits: Limits)
+    where T: io::Read + io::Write, W: io::Write
+{
+    match transfer::receive(port, into, checksum, limits) {
+        Ok(bytes) => eprintln!("Read {} bytes.", bytes),
+        Err(e) => fail(&e),
+    }
+}
 
+fn main() {
     let opt = Opt::from_args();
-    let mut serial = serial::open(&opt.tty_path).expect("Path points to invalid TTY");
+    if let Command::Pack { ref input, ref output, load_addr, entry, compress } = opt.command {
+        return pack(input, output, load_addr, entry, compress);
+    }
+
+    let profile = Config::load()
+        .and_then(|config| config.profile(opt.profile.as_ref().map(|name| &name[..])))
+        .unwrap_or_else(|e| fatal(&e));
+
+    let tty_path = match (opt.tty_path, profile.tty.as_ref()) {
+        (Some(path), _) => path,
+        (None, Some(tty)) => tty.resolve().unwrap_or_else(|e| fatal(&e)),
+        (None, None) => fatal("no TTY: give its path or select a profile with one"),
+    };
+
+    let mut serial = serial::open(&tty_path).expect("Path points to invalid TTY");
 
     let mut settings = serial.read_settings().expect("Failed to load settings");
-    settings.set_baud_rate(opt.baud_rate).expect("Invalid baud rate");
-    settings.set_char_size(opt.char_width);
-    settings.set_flow_control(opt.flow_control);
-    settings.set_stop_bits(opt.stop_bits);
+    settings.set_baud_rate(opt.baud_rate.or(profile.baud_rate).unwrap_or(BaudRate::Baud115200))
+        .expect("Invalid baud rate");
+    settings.set_char_size(opt.char_width.or(profile.char_width).unwrap_or(CharSize::Bits8));
+    settings.set_flow_control(opt.flow_control.or(profile.flow_control)
+        .unwrap_or(FlowControl::FlowNone));
+    settings.set_stop_bits(opt.stop_bits.or(profile.stop_bits).unwrap_or(StopBits::Stop1));
     serial.write_settings(&settings).expect("Failed to apply serial settings");
 
-    serial.set_timeout(Duration::from_secs(opt.timeout)).expect("Invalid timeout");
+    let timeout = opt.timeout.or(profile.timeout).unwrap_or(10);
+    serial.set_timeout(Duration::from_secs(timeout)).expect("Invalid timeout");
 
-    let mut reader: Box<io::Read> = if let Some(path) = opt.input {
-        let file = File::open(path).expect("Failed to open file");
-        Box::new(BufReader::new(file))
-    } else {
-        Box::new(BufReader::new(io::stdin()))
+    let limits = Limits {
+        retries: opt.retries,
+        handshake_timeout: opt.handshake_timeout,
+        deadline: opt.deadline,
     };
 
-    if opt.raw {
-        let bytes = io::copy(&mut reader, &mut serial).expect("Write failed");
-        println!("Wrote {} bytes.", bytes);
-    } else {
-        let bytes = Xmodem::transmit_with_progress(reader, serial, |progress| {
-            if let Progress::Packet(_) = progress {
-                print!(".");
-                io::stdout().flush().unwrap();
-            } else if let Progress::Waiting = progress {
-                println!("Ready");
-            } else {
-                assert!(false);
+    // The profile's protocol and file are defaults: flags and paths given on
+    // the command line win.
+    let profile_raw = profile.protocol == Some(Protocol::Raw);
+    let profile_one_k = profile.protocol == Some(Protocol::Xmodem1k);
+    let command = match opt.command {
+        Command::Send { input, raw, one_k, watch, no_reset } => Command::Send {
+            input: input.or(profile.file),
+            raw: raw || (profile_raw && !one_k),
+            one_k: one_k || profile_one_k,
+            watch,
+            no_reset,
+        },
+        Command::Term { echo, enter, crlf, log, upload, watch, one_k } => Command::Term {
+            upload: upload.or(profile.file),
+            one_k: one_k || profile_one_k,
+            echo, enter, crlf, log, watch,
+        },
+        command => command,
+    };
+
+    match command {
+        Command::Send { input: Some(path), watch: true, raw: false, one_k, no_reset } => {
+            watch::run(&mut serial, &path, !no_reset, one_k, limits)
+        }
+        Command::Send { watch: true, .. } => {
+            eprintln!("--watch needs an input file (-i) and XMODEM");
+            process::exit(1);
+        }
+        Command::Send { input: Some(path), raw, one_k, .. } => {
+            let file = File::open(path).expect("Failed to open file");
+            let total = file.metadata().ok().map(|metadata| metadata.len());
+            send(serial, BufReader::new(file), total, raw, one_k, limits)
+        }
+        Command::Send { input: None, raw, one_k, .. } => {
+            send(serial, BufReader::new(io::stdin()), None, raw, one_k, limits)
+        }
+        Command::Recv { output, ymodem: true, .. } => {
+            let dir = output.unwrap_or_else(|| PathBuf::from("."));
+            match transfer::receive_batch(serial, &dir, limits) {
+                Ok(files) => println!("Received {} files.", files.len()),
+                Err(e) => fail(&e),
             }
-        }).expect("Write failed");
-        println!("");
-        println!("Wrote {} bytes.", bytes);
+        }
+        Command::Recv { output: Some(path), checksum, .. } => {
+            let file = File::create(path).expect("Failed to create file");
+            receive(serial, BufWriter::new(file), checksum, limits)
+        }
+        Command::Recv { output: None, checksum, .. } => {
+            receive(serial, io::stdout(), checksum, limits)
+        }
+        Command::Term { watch: true, upload: None, .. } => {
+            eprintln!("--watch needs a file to upload (-u)");
+            process::exit(1);
+        }
+        Command::Pack { .. } => unreachable!("packing doesn't use the TTY"),
+        Command::Term { echo, enter, crlf, log, upload, watch, one_k } => {
+            let options = term::Options { echo, enter, crlf, log, upload, watch, one_k, limits };
+            if let Err(e) = term::run(&mut serial, options) {
+                eprintln!("Terminal failed: {}", e);
+                process::exit(1);
+            }
+        }
     }
 }
@@ -1,5 +1,8 @@
 use serial::core::{CharSize, BaudRate, StopBits, FlowControl};
 
+use config::Protocol;
+use term::Enter;
+
 pub fn parse_width(s: &str) -> Result<CharSize, &str> {
     match s {
         "5" => Ok(CharSize::Bits5),
@@ -30,3 +33,93 @@ pub fn parse_flow_control(s: &str) -> Result<FlowControl, &str> {
 pub fn parse_baud_rate(s: &str) -> Result<BaudRate, ::std::num::ParseIntError> {
     Ok(BaudRate::from_speed(s.parse()?))
 }
+
+pub fn parse_enter(s: &str) -> Result<Enter, &str> {
+    match s {
+        "cr" => Ok(Enter::Cr),
+        "lf" => Ok(Enter::Lf),
+        "crlf" => Ok(Enter::CrLf),
+        _ => Err("value must be 'cr', 'lf', or 'crlf'")
+    }
+}
+
+pub fn parse_address(s: &str) -> Result<u64, ::std::num::ParseIntError> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u64::from_str_radix(&s[2..], 16)
+    } else {
+        s.parse()
+    }
+}
+
+pub fn parse_protocol(s: &str) -> Result<Protocol, &str> {
+    match s {
+        "xmodem" => Ok(Protocol::Xmodem),
+        "xmodem-1k" => Ok(Protocol::Xmodem1k),
+        "raw" => Ok(Protocol::Raw),
+        _ => Err("value must be 'xmodem', 'xmodem-1k', or 'raw'")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widths() {
+        assert_eq!(parse_width("5"), Ok(CharSize::Bits5));
+        assert_eq!(parse_width("6"), Ok(CharSize::Bits6));
+        assert_eq!(parse_width("7"), Ok(CharSize::Bits7));
+        assert_eq!(parse_width("8"), Ok(CharSize::Bits8));
+        assert!(parse_width("4").is_err());
+        assert!(parse_width("9").is_err());
+    }
+
+    #[test]
+    fn stop_bits() {
+        assert_eq!(parse_stop_bits("1"), Ok(StopBits::Stop1));
+        assert_eq!(parse_stop_bits("2"), Ok(StopBits::Stop2));
+        assert!(parse_stop_bits("1.5").is_err());
+    }
+
+    #[test]
+    fn flow_control() {
+        assert_eq!(parse_flow_control("none"), Ok(FlowControl::FlowNone));
+        assert_eq!(parse_flow_control("software"), Ok(FlowControl::FlowSoftware));
+        assert_eq!(parse_flow_control("hardware"), Ok(FlowControl::FlowHardware));
+        assert!(parse_flow_control("xon").is_err());
+    }
+
+    #[test]
+    fn baud_rates() {
+        assert_eq!(parse_baud_rate("115200"), Ok(BaudRate::Baud115200));
+        assert_eq!(parse_baud_rate("9600"), Ok(BaudRate::Baud9600));
+        assert_eq!(parse_baud_rate("250000"), Ok(BaudRate::BaudOther(250000)));
+        assert!(parse_baud_rate("fast").is_err());
+        assert!(parse_baud_rate("-1").is_err());
+    }
+
+    #[test]
+    fn enter() {
+        assert_eq!(parse_enter("cr"), Ok(Enter::Cr));
+        assert_eq!(parse_enter("lf"), Ok(Enter::Lf));
+        assert_eq!(parse_enter("crlf"), Ok(Enter::CrLf));
+        assert!(parse_enter("CRLF").is_err());
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(parse_address("0x80000"), Ok(0x80000));
+        assert_eq!(parse_address("0X4000000"), Ok(0x4000000));
+        assert_eq!(parse_address("524288"), Ok(0x80000));
+        assert!(parse_address("0x").is_err());
+        assert!(parse_address("80000h").is_err());
+    }
+
+    #[test]
+    fn protocols() {
+        assert_eq!(parse_protocol("xmodem"), Ok(Protocol::Xmodem));
+        assert_eq!(parse_protocol("xmodem-1k"), Ok(Protocol::Xmodem1k));
+        assert_eq!(parse_protocol("raw"), Ok(Protocol::Raw));
+        assert!(parse_protocol("ymodem").is_err());
+    }
+}
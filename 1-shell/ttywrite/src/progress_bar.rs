@@ -0,0 +1,83 @@
+use std::io::{self, Write};
+use std::time::Instant;
+
+use xmodem::{Progress, Checksum, RetryReason};
+
+/// The number of characters in the bar itself.
+const BAR_WIDTH: usize = 30;
+
+/// Draws the progress of a transfer on a single terminal line: a bar with the
+/// percentage and ETA when the total size is known, the throughput, and the
+/// number of retries with the reason for the last one.
+pub struct ProgressBar {
+    started: Option<Instant>,
+    retries: u64,
+    last_retry: Option<RetryReason>,
+}
+
+/// Formats `secs` seconds as `m:ss`.
+fn format_time(secs: u64) -> String {
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+impl ProgressBar {
+    pub fn new() -> ProgressBar {
+        ProgressBar { started: None, retries: 0, last_retry: None }
+    }
+
+    /// Updates the display with the event `progress`.
+    pub fn update(&mut self, progress: Progress) {
+        match progress {
+            Progress::Waiting => eprintln!("Ready"),
+            Progress::Started => (),
+            Progress::Negotiated(checksum) => {
+                self.started = Some(Instant::now());
+                match checksum {
+                    Checksum::Sum => eprintln!("Using checksums"),
+                    Checksum::Crc16 => eprintln!("Using CRC-16"),
+                }
+            }
+            Progress::Retry { reason, retries, .. } => {
+                self.retries = retries;
+                self.last_retry = Some(reason);
+            }
+            Progress::Packet { bytes, total, .. } => self.draw(bytes, total),
+        }
+    }
+
+    fn draw(&mut self, bytes: u64, total: Option<u64>) {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let elapsed = started.elapsed();
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        let rate = if secs > 0.0 { bytes as f64 / secs } else { 0.0 };
+
+        let mut line = String::new();
+        match total {
+            Some(total) if total > 0 => {
+                let done = ::std::cmp::min(bytes, total);
+                let filled = (done as usize * BAR_WIDTH) / total as usize;
+                line.push('[');
+                line.extend((0..BAR_WIDTH).map(|i| if i < filled { '#' } else { '-' }));
+                line.push_str(&format!("] {:3}%", done * 100 / total));
+                if rate > 0.0 {
+                    let eta = ((total - done) as f64 / rate) as u64;
+                    line.push_str(&format!("  ETA {}", format_time(eta)));
+                }
+            }
+            _ => line.push_str(&format!("{} bytes", bytes)),
+        }
+
+        line.push_str(&format!("  {:.1} KiB/s", rate / 1024.0));
+        if let Some(reason) = self.last_retry {
+            let reason = match reason {
+                RetryReason::Rejected => "rejected",
+                RetryReason::Corrupted => "corrupted",
+                RetryReason::Timeout => "timed out",
+            };
+            line.push_str(&format!("  {} retries (last: {})", self.retries, reason));
+        }
+
+        eprint!("\r{}", line);
+        io::stderr().flush().unwrap();
+    }
+}
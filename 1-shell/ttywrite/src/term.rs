@@ -0,0 +1,326 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use libc;
+use termios::{self, Termios};
+
+use bootloader;
+use transfer::{self, Limits};
+use watch::Watcher;
+
+/// The key that starts a command: `Ctrl-A`, followed by the command's key.
+const ESCAPE: u8 = 0x01;
+
+const STDIN: RawFd = 0;
+
+/// How often the uploaded file is checked for changes in watch mode.
+const WATCH_INTERVAL_MS: libc::c_int = 250;
+
+const HELP: &str = "Ctrl-A commands:\r\n\
+    \x20 u      upload a file with XMODEM\r\n\
+    \x20 r      reset the Pi into the bootloader and upload a kernel\r\n\
+    \x20 e      toggle local echo\r\n\
+    \x20 x      exit the terminal\r\n\
+    \x20 h      show this help\r\n\
+    \x20 Ctrl-A send Ctrl-A\r\n";
+
+/// What the Enter key sends to the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enter {
+    Cr,
+    Lf,
+    CrLf,
+}
+
+impl Enter {
+    fn bytes(self) -> &'static [u8] {
+        match self {
+            Enter::Cr => b"\r",
+            Enter::Lf => b"\n",
+            Enter::CrLf => b"\r\n",
+        }
+    }
+}
+
+/// The settings of an interactive terminal session.
+#[derive(Debug)]
+pub struct Options {
+    /// Whether typed characters are echoed locally.
+    pub echo: bool,
+    /// What the Enter key sends.
+    pub enter: Enter,
+    /// Whether `LF` received from the device is displayed as `CR LF`.
+    pub crlf: bool,
+    /// The file everything received from the device is appended to.
+    pub log: Option<PathBuf>,
+    /// The file uploaded by default with `Ctrl-A u` and `Ctrl-A r`.
+    pub upload: Option<PathBuf>,
+    /// Whether `upload` is uploaded again, after resetting the Pi into the
+    /// bootloader, whenever it changes.
+    pub watch: bool,
+    /// Whether uploads use 1024-byte XMODEM blocks when possible.
+    pub one_k: bool,
+    /// The limits of uploads.
+    pub limits: Limits,
+}
+
+/// Puts a terminal in raw mode while it is alive, restoring its original
+/// settings when dropped.
+struct RawMode {
+    fd: RawFd,
+    original: Termios,
+    raw: Termios,
+}
+
+impl RawMode {
+    fn enable(fd: RawFd) -> io::Result<RawMode> {
+        let original = Termios::from_fd(fd)?;
+        let mut raw = original;
+        termios::cfmakeraw(&mut raw);
+
+        let mode = RawMode { fd, original, raw };
+        mode.apply()?;
+        Ok(mode)
+    }
+
+    /// Switches the terminal to raw mode.
+    fn apply(&self) -> io::Result<()> {
+        termios::tcsetattr(self.fd, termios::TCSANOW, &self.raw)
+    }
+
+    /// Switches the terminal back to its original settings.
+    fn restore(&self) -> io::Result<()> {
+        termios::tcsetattr(self.fd, termios::TCSANOW, &self.original)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = self.restore();
+    }
+}
+
+/// Waits until `a` or `b` can be read from, or for `timeout` milliseconds
+/// if it isn't negative. Returns whether each can.
+fn wait_readable(a: RawFd, b: RawFd, timeout: libc::c_int) -> io::Result<(bool, bool)> {
+    let mut fds = [
+        libc::pollfd { fd: a, events: libc::POLLIN, revents: 0 },
+        libc::pollfd { fd: b, events: libc::POLLIN, revents: 0 },
+    ];
+
+    loop {
+        if unsafe { libc::poll(fds.as_mut_ptr(), 2, timeout) } >= 0 {
+            let ready = |fd: &libc::pollfd| fd.revents != 0;
+            return Ok((ready(&fds[0]), ready(&fds[1])));
+        }
+
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Reads from the unbuffered standard input.
+fn read_stdin(buf: &mut [u8]) -> io::Result<usize> {
+    let n = unsafe { libc::read(STDIN, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(n as usize)
+}
+
+/// Writes `text` to the local terminal.
+fn show(text: &[u8]) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    stdout.write_all(text)?;
+    stdout.flush()
+}
+
+/// Writes `data` to the local terminal, translating `LF` to `CR LF` if
+/// `crlf` is set.
+fn display(data: &[u8], crlf: bool) -> io::Result<()> {
+    if !crlf {
+        return show(data);
+    }
+
+    let mut translated = Vec::with_capacity(data.len() * 2);
+    for &byte in data {
+        if byte == b'\n' {
+            translated.push(b'\r');
+        }
+        translated.push(byte);
+    }
+
+    show(&translated)
+}
+
+/// Asks for the file to upload, suggesting `default`. Returns `None` if no
+/// file was given.
+fn prompt_upload(default: &Option<PathBuf>) -> io::Result<Option<PathBuf>> {
+    match *default {
+        Some(ref path) => print!("File to upload [{}]: ", path.display()),
+        None => print!("File to upload: "),
+    }
+    io::stdout().flush()?;
+
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    let line = line.trim();
+    Ok(if line.is_empty() { default.clone() } else { Some(PathBuf::from(line)) })
+}
+
+/// The state of a terminal session.
+struct Session<'a, P: 'a> {
+    port: &'a mut P,
+    options: Options,
+    log: Option<File>,
+    raw: RawMode,
+    escaped: bool,
+}
+
+impl<'a, P: io::Read + io::Write + AsRawFd> Session<'a, P> {
+    /// Handles the bytes typed by the user. Returns `false` once the user
+    /// asked to exit.
+    fn typed(&mut self, input: &[u8]) -> io::Result<bool> {
+        let mut out = Vec::with_capacity(input.len());
+        for &byte in input {
+            if self.escaped {
+                self.escaped = false;
+                match byte {
+                    b'x' | b'X' | b'q' | b'Q' => {
+                        self.port.write_all(&out)?;
+                        return Ok(false);
+                    }
+                    b'u' | b'U' | b'r' | b'R' => {
+                        self.port.write_all(&out)?;
+                        out.clear();
+                        self.upload(byte == b'r' || byte == b'R')?;
+                    }
+                    b'e' | b'E' => {
+                        self.options.echo = !self.options.echo;
+                        let state = if self.options.echo { "on" } else { "off" };
+                        show(format!("\r\n[local echo {}]\r\n", state).as_bytes())?;
+                    }
+                    ESCAPE => out.push(ESCAPE),
+                    _ => show(HELP.as_bytes())?,
+                }
+            } else if byte == ESCAPE {
+                self.escaped = true;
+            } else if byte == b'\r' {
+                out.extend_from_slice(self.options.enter.bytes());
+                if self.options.echo {
+                    show(b"\r\n")?;
+                }
+            } else {
+                out.push(byte);
+                if self.options.echo {
+                    show(&[byte])?;
+                }
+            }
+        }
+
+        self.port.write_all(&out)?;
+        self.port.flush()?;
+        Ok(true)
+    }
+
+    /// Handles the bytes received from the device.
+    fn received(&mut self, data: &[u8]) -> io::Result<()> {
+        if let Some(ref mut log) = self.log {
+            log.write_all(data)?;
+        }
+
+        display(data, self.options.crlf)
+    }
+
+    /// Uploads a file chosen by the user with XMODEM, to the bootloader
+    /// after resetting the Pi if `reset` is set.
+    fn upload(&mut self, reset: bool) -> io::Result<()> {
+        self.raw.restore()?;
+        println!();
+        if let Some(path) = prompt_upload(&self.options.upload)? {
+            self.load(&path, reset);
+        }
+
+        self.raw.apply()
+    }
+
+    /// Resets the Pi into the bootloader and uploads the watched file, which
+    /// changed.
+    fn reload(&mut self, path: &Path) -> io::Result<()> {
+        self.raw.restore()?;
+        println!("\n{} changed.", path.display());
+        self.load(path, true);
+        self.raw.apply()
+    }
+
+    /// Uploads the file at `path`. A failed upload is reported but doesn't
+    /// end the session.
+    fn load(&mut self, path: &Path, reset: bool) {
+        let (one_k, limits) = (self.options.one_k, self.options.limits);
+        let result = if reset {
+            bootloader::load(&mut *self.port, path, true, one_k, limits)
+        } else {
+            transfer::send_file(&mut *self.port, path, one_k, limits)
+        };
+
+        match result {
+            Ok(bytes) => println!("Wrote {} bytes.", bytes),
+            Err(e) => println!("{}", transfer::explain(&e)),
+        }
+    }
+}
+
+/// Runs an interactive terminal on `port` until the user exits with
+/// `Ctrl-A x` or closes the standard input.
+pub fn run<P>(port: &mut P, options: Options) -> io::Result<()>
+    where P: io::Read + io::Write + AsRawFd
+{
+    let log = match options.log {
+        Some(ref path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+        None => None,
+    };
+
+    let mut watcher = match options.upload {
+        Some(ref path) if options.watch => Some(Watcher::new(path.clone())),
+        _ => None,
+    };
+
+    println!("Connected. Press Ctrl-A h for help, Ctrl-A x to exit.");
+    let raw = RawMode::enable(STDIN)?;
+    let port_fd = port.as_raw_fd();
+    let mut session = Session { port, options, log, raw, escaped: false };
+
+    let mut buf = [0u8; 1024];
+    loop {
+        let timeout = if watcher.is_some() { WATCH_INTERVAL_MS } else { -1 };
+        let (input, output) = wait_readable(STDIN, port_fd, timeout)?;
+        if let Some(ref mut watcher) = watcher {
+            if watcher.changed() {
+                session.reload(watcher.path())?;
+                continue;
+            }
+        }
+
+        if output {
+            match session.port.read(&mut buf) {
+                Ok(n) => session.received(&buf[..n])?,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if input {
+            let n = read_stdin(&mut buf)?;
+            if n == 0 || !session.typed(&buf[..n])? {
+                break;
+            }
+        }
+    }
+
+    show(b"\r\n")
+}
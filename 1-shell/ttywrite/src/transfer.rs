@@ -0,0 +1,126 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use xmodem::{Xmodem, Ymodem, XmodemBuilder, BlockSize, Checksum, FileInfo, Progress};
+
+use progress_bar::ProgressBar;
+
+/// The limits of an XMODEM transfer.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub retries: usize,
+    pub handshake_timeout: Option<u64>,
+    pub deadline: Option<u64>,
+}
+
+/// Clock for the XMODEM handshake timeout and deadline.
+fn clock() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0))
+}
+
+/// Returns a builder with `limits` that draws a progress bar.
+fn builder(limits: Limits) -> XmodemBuilder<impl FnMut(Progress)> {
+    let mut builder = Xmodem::builder()
+        .max_retries(limits.retries)
+        .clock(clock);
+    if let Some(secs) = limits.handshake_timeout {
+        builder = builder.handshake_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = limits.deadline {
+        builder = builder.deadline(Duration::from_secs(secs));
+    }
+
+    let mut bar = ProgressBar::new();
+    builder.progress(move |progress| bar.update(progress))
+}
+
+/// Explains a failed XMODEM transfer and what might fix it.
+pub fn explain(e: &io::Error) -> String {
+    let hint = match e.kind() {
+        io::ErrorKind::NotConnected => "the peer never started the transfer; \
+            check that it is waiting for XMODEM and that the baud rate matches",
+        io::ErrorKind::TimedOut => "the transfer took longer than the deadline",
+        io::ErrorKind::BrokenPipe => "too many packets were rejected; \
+            check the line's settings or try a lower baud rate",
+        io::ErrorKind::ConnectionAborted => "the peer cancelled the transfer",
+        _ => "the serial device or the file failed",
+    };
+
+    format!("Transfer failed: {} ({})", hint, e)
+}
+
+/// Sends `data`, `total` bytes long if known, over `port` with XMODEM.
+/// Sends 1024-byte blocks if `one_k` is set and the receiver uses CRC-16.
+pub fn send<T, R>(port: T, data: R, total: Option<u64>, one_k: bool, limits: Limits)
+    -> io::Result<usize>
+    where T: io::Read + io::Write, R: io::Read
+{
+    let block_size = if one_k { BlockSize::OneK } else { BlockSize::Standard };
+    let mut builder = builder(limits).block_size(block_size);
+    if let Some(bytes) = total {
+        builder = builder.total(bytes);
+    }
+
+    let result = builder.build(port).and_then(|mut xmodem| xmodem.write_data(data));
+    eprintln!();
+    result
+}
+
+/// Sends the file at `path` over `port` with XMODEM. See [`send()`].
+pub fn send_file<T>(port: T, path: &Path, one_k: bool, limits: Limits) -> io::Result<usize>
+    where T: io::Read + io::Write
+{
+    let file = File::open(path)?;
+    let total = file.metadata().ok().map(|metadata| metadata.len());
+    send(port, BufReader::new(file), total, one_k, limits)
+}
+
+/// Receives a file from `port` with XMODEM into `into`, asking for CRC-16
+/// unless `checksum` is set.
+pub fn receive<T, W>(port: T, into: W, checksum: bool, limits: Limits) -> io::Result<usize>
+    where T: io::Read + io::Write, W: io::Write
+{
+    let checksum = if checksum { Checksum::Sum } else { Checksum::Crc16 };
+    let result = builder(limits).checksum(checksum).build(port)
+        .and_then(|mut xmodem| xmodem.read_data(into, None));
+    eprintln!();
+    result
+}
+
+/// Receives a YMODEM batch from `port`, writing each file into the directory
+/// `dir` under the base name the sender gave it. Returns the files received.
+pub fn receive_batch<T>(port: T, dir: &Path, limits: Limits) -> io::Result<Vec<FileInfo>>
+    where T: io::Read + io::Write
+{
+    let builder = builder(limits).checksum(Checksum::Crc16).block_size(BlockSize::OneK);
+    let mut receiver = Ymodem::from_xmodem(builder.build(port)?);
+    let mut files = Vec::new();
+    while let Some(info) = receiver.next_file()? {
+        let path = batch_path(dir, &info.name)?;
+        match info.size {
+            Some(size) => println!("Receiving {} ({} bytes)", path.display(), size),
+            None => println!("Receiving {}", path.display()),
+        }
+        let file = BufWriter::new(File::create(&path)?);
+        receiver.receive_file(file)?;
+        eprintln!();
+        files.push(info);
+    }
+
+    Ok(files)
+}
+
+/// Returns the path in `dir` for the batch file the sender named `name`,
+/// keeping only its base name so that it can't be written outside of `dir`.
+fn batch_path(dir: &Path, name: &str) -> io::Result<PathBuf> {
+    match Path::new(name).file_name() {
+        Some(base) => {
+            fs::create_dir_all(dir)?;
+            Ok(dir.join(base))
+        }
+        None => Err(io::Error::new(io::ErrorKind::InvalidData,
+                                   format!("invalid file name '{}'", name))),
+    }
+}
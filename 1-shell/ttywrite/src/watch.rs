@@ -0,0 +1,92 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use bootloader;
+use transfer::{self, Limits};
+
+/// How often a watched file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long a changed file must stay the same before it is considered
+/// completely written.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// What identifies a version of a file: its modification time and length.
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Watches a file for changes.
+pub struct Watcher {
+    path: PathBuf,
+    stamp: Stamp,
+}
+
+impl Watcher {
+    /// Returns a watcher for the file at `path`, considering its current
+    /// version as seen.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Watcher {
+        let path = path.into();
+        let stamp = stamp(&path);
+        Watcher { path, stamp }
+    }
+
+    /// Returns the path of the watched file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns `true` if the file changed since the last version seen, once
+    /// it has stopped changing. A missing file is not a change: it is
+    /// probably being rebuilt.
+    pub fn changed(&mut self) -> bool {
+        let mut current = stamp(&self.path);
+        if current.is_none() || current == self.stamp {
+            return false;
+        }
+
+        loop {
+            thread::sleep(SETTLE_TIME);
+            let settled = stamp(&self.path);
+            if settled == current {
+                break;
+            }
+
+            current = settled;
+        }
+
+        self.stamp = current;
+        current.is_some()
+    }
+
+    /// Blocks until the file changes.
+    pub fn wait(&mut self) {
+        while !self.changed() {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Sends the kernel at `path` to the bootloader now and again whenever it
+/// changes, resetting the Pi into the bootloader first if `reset` is set.
+/// Failed uploads are reported and tried again at the next change.
+pub fn run<P>(port: &mut P, path: &Path, reset: bool, one_k: bool, limits: Limits) -> !
+    where P: io::Read + io::Write
+{
+    let mut watcher = Watcher::new(path);
+    loop {
+        match bootloader::load(port, path, reset, one_k, limits) {
+            Ok(bytes) => println!("Wrote {} bytes.", bytes),
+            Err(e) => eprintln!("{}", transfer::explain(&e)),
+        }
+
+        println!("Watching {} for changes...", watcher.path().display());
+        watcher.wait();
+    }
+}
@@ -0,0 +1,579 @@
+//! End-to-end tests running `ttywrite` against one end of a pseudo-terminal
+//! with an XMODEM peer on the other end.
+
+extern crate libc;
+extern crate termios;
+extern crate xmodem;
+
+use std::cell::Cell;
+use std::env;
+use std::ffi::CStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use termios::Termios;
+use termios::os::target::{B115200, B230400, CRTSCTS};
+use xmodem::{Xmodem, XmodemBuilder, Ymodem, BlockSize, Checksum, FileInfo, Progress};
+
+/// How long the peer waits for a byte before a read times out.
+const READ_TIMEOUT_MS: i32 = 1000;
+
+/// The bytes `ttywrite` sends to make the kernel reset the Pi.
+const RESET_MAGIC: &[u8] = b"\x1b!reset-to-bootloader!\x1b";
+
+/// How long the peer keeps starting a transfer. `ttywrite` discards input
+/// when it applies its serial settings, which may swallow the first `NAK`.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A pseudo-terminal pair. `ttywrite` opens the slave by its path; the test
+/// talks to it through the master. The slave is also kept open so that its
+/// settings can be inspected after `ttywrite` exits.
+struct Pty {
+    master: RawFd,
+    slave: File,
+    path: PathBuf,
+}
+
+impl Pty {
+    fn open() -> Pty {
+        unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(master >= 0, "posix_openpt: {}", io::Error::last_os_error());
+            assert_eq!(libc::grantpt(master), 0);
+            assert_eq!(libc::unlockpt(master), 0);
+
+            let mut name = [0 as libc::c_char; 128];
+            assert_eq!(libc::ptsname_r(master, name.as_mut_ptr(), name.len()), 0);
+            let path = PathBuf::from(CStr::from_ptr(name.as_ptr()).to_str().unwrap());
+            let slave = OpenOptions::new().read(true).write(true)
+                .custom_flags(libc::O_NOCTTY)
+                .open(&path)
+                .expect("open slave");
+
+            let pty = Pty { master, slave, path };
+            make_raw(pty.master);
+            make_raw(pty.slave.as_raw_fd());
+            pty
+        }
+    }
+
+    /// Returns the end of the line the peer uses.
+    fn line(&self) -> Line {
+        Line(self.master)
+    }
+
+    /// Returns the current settings of the slave.
+    fn settings(&self) -> Termios {
+        Termios::from_fd(self.slave.as_raw_fd()).expect("slave settings")
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.master); }
+    }
+}
+
+fn make_raw(fd: RawFd) {
+    let mut settings = Termios::from_fd(fd).expect("settings");
+    termios::cfmakeraw(&mut settings);
+    termios::tcsetattr(fd, termios::TCSANOW, &settings).expect("raw mode");
+}
+
+/// The master of a `Pty`. Reads fail with `TimedOut` after
+/// `READ_TIMEOUT_MS` without data.
+struct Line(RawFd);
+
+impl Read for Line {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut fd = libc::pollfd { fd: self.0, events: libc::POLLIN, revents: 0 };
+        match unsafe { libc::poll(&mut fd, 1, READ_TIMEOUT_MS) } {
+            0 => return Err(io::Error::new(io::ErrorKind::TimedOut, "line timed out")),
+            n if n < 0 => return Err(io::Error::last_os_error()),
+            _ => (),
+        }
+
+        let n = unsafe { libc::read(self.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(n as usize)
+    }
+}
+
+impl Write for Line {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = unsafe { libc::write(self.0, buf.as_ptr() as *const libc::c_void, buf.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(n as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Faults injected into what the peer reads: the byte at each offset in
+/// `corrupt` is flipped, and the read that reaches offset `delay_at` is held
+/// back for `delay`.
+#[derive(Default, Clone)]
+struct Faults {
+    corrupt: Vec<u64>,
+    delay_at: Option<u64>,
+    delay: Duration,
+}
+
+struct Faulty<T> {
+    inner: T,
+    faults: Faults,
+    position: u64,
+}
+
+impl<T: Read> Read for Faulty<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        let (start, end) = (self.position, self.position + n as u64);
+        for &at in &self.faults.corrupt {
+            if at >= start && at < end {
+                buf[(at - start) as usize] ^= 0xFF;
+            }
+        }
+
+        if let Some(at) = self.faults.delay_at {
+            if at >= start && at < end {
+                thread::sleep(self.faults.delay);
+            }
+        }
+
+        self.position = end;
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Faulty<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn clock() -> Duration {
+    thread_local!(static START: Instant = Instant::now());
+    START.with(|start| start.elapsed())
+}
+
+/// Returns a builder for the peer that counts the packets it transfers
+/// into `packets`.
+fn peer(packets: &Rc<Cell<usize>>) -> XmodemBuilder<impl FnMut(Progress)> {
+    let packets = packets.clone();
+    Xmodem::builder()
+        .clock(clock)
+        .handshake_timeout(HANDSHAKE_TIMEOUT)
+        .progress(move |progress| if let Progress::Packet { .. } = progress {
+            packets.set(packets.get() + 1);
+        })
+}
+
+/// Returns a command running the `ttywrite` binary built with these tests.
+fn ttywrite() -> Command {
+    let mut path = env::current_exe().expect("test path");
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+
+    // Keeps the user's and the project's ttywrite.toml from changing the
+    // settings under test.
+    let mut command = Command::new(path.join("ttywrite"));
+    command.env("XDG_CONFIG_HOME", env::temp_dir().join("ttywrite-no-config"))
+        .current_dir(env::temp_dir());
+    command
+}
+
+/// Returns a new empty directory for the files of test `name`.
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("ttywrite-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("scratch dir");
+    dir
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+}
+
+/// Starts `ttywrite` on `pty` with the serial settings `settings`, then
+/// the subcommand and its arguments `args`.
+fn spawn(pty: &Pty, settings: &[&str], args: &[&str]) -> Child {
+    ttywrite()
+        .args(settings)
+        .arg(&pty.path)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn ttywrite")
+}
+
+fn finish(child: Child) -> Output {
+    let output = child.wait_with_output().expect("wait for ttywrite");
+    assert!(output.status.success(), "ttywrite failed: {}",
+            String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+/// Sends `data` with `ttywrite send` using `settings` and `args` to an XMODEM
+/// receiver asking for `checksum`, with `faults` injected. Returns what the
+/// receiver got, including padding, and the number of packets received.
+fn send(data: &[u8], settings: &[&str], args: &[&str], checksum: Checksum,
+        faults: Faults) -> (Vec<u8>, usize) {
+    let dir = scratch("send");
+    let input = dir.join("input.bin");
+    fs::write(&input, data).expect("write input");
+
+    let pty = Pty::open();
+    let mut send_args = vec!["send", "-i", input.to_str().unwrap()];
+    send_args.extend_from_slice(args);
+    let child = spawn(&pty, settings, &send_args);
+
+    let line = Faulty { inner: pty.line(), faults, position: 0 };
+    let packets = Rc::new(Cell::new(0));
+    let mut received = Vec::new();
+    peer(&packets)
+        .checksum(checksum)
+        .build(line)
+        .expect("build")
+        .read_data(&mut received, None)
+        .expect("receive");
+
+    finish(child);
+    (received, packets.get())
+}
+
+/// Returns the number of packets needed to send `len` bytes in `block`-byte
+/// packets, the last ones possibly shortened to 128 bytes.
+fn packets(len: usize, block: usize) -> usize {
+    let tail = len % block;
+    len / block + tail / 128 + (tail & 127 != 0) as usize
+}
+
+/// Asserts that `received` is `data` padded with zeroes to 128 bytes.
+fn assert_padded(received: &[u8], data: &[u8]) {
+    assert_eq!(&received[..data.len()], data);
+    assert_eq!(received.len() % 128, 0, "{} bytes received", received.len());
+    assert!(received.len() - data.len() < 128);
+    assert!(received[data.len()..].iter().all(|&b| b == 0));
+}
+
+const SETTINGS: &[&[&str]] = &[
+    &[],
+    &["-b", "9600", "-s", "2"],
+    &["-b", "230400", "-f", "hardware"],
+];
+
+#[test]
+fn send_variants() {
+    let data = pattern(3000);
+    for settings in SETTINGS {
+        for &(args, checksum, block) in &[
+            (&[][..], Checksum::Sum, 128),
+            (&[][..], Checksum::Crc16, 128),
+            (&["-k"][..], Checksum::Crc16, 1024),
+            (&["-k"][..], Checksum::Sum, 128),
+        ] {
+            let (received, count) = send(&data, settings, args, checksum,
+                                         Faults::default());
+            assert_padded(&received, &data);
+            assert_eq!(count, packets(data.len(), block), "{:?} {:?}", settings, args);
+        }
+    }
+}
+
+#[test]
+fn send_exact_blocks() {
+    for &len in &[0, 128, 1024] {
+        let data = pattern(len);
+        let (received, _) = send(&data, &[], &["-k"], Checksum::Crc16, Faults::default());
+        assert_eq!(received, data);
+    }
+}
+
+#[test]
+fn send_raw() {
+    let data = pattern(5000);
+    let dir = scratch("raw");
+    let input = dir.join("input.bin");
+    fs::write(&input, &data).expect("write input");
+
+    let pty = Pty::open();
+    let child = spawn(&pty, &[], &["send", "-r", "-i", input.to_str().unwrap()]);
+    let mut received = vec![0; data.len()];
+    pty.line().read_exact(&mut received).expect("raw data");
+    let output = finish(child);
+
+    assert_eq!(received, data);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Wrote 5000 bytes."));
+}
+
+#[test]
+fn send_recovers_from_corruption() {
+    let data = pattern(2000);
+    for &(args, checksum, block, check_len) in &[
+        (&[][..], Checksum::Sum, 128, 1),
+        (&["-k"][..], Checksum::Crc16, 1024, 2),
+    ] {
+        // Corrupts the data of the first packet, the checksum of its first
+        // resend, then the data of the second packet.
+        let packet = (3 + block + check_len) as u64;
+        let faults = Faults {
+            corrupt: vec![40, 2 * packet - 1, 3 * packet + 10],
+            ..Faults::default()
+        };
+
+        let (received, count) = send(&data, &[], args, checksum, faults);
+        assert_padded(&received, &data);
+        assert_eq!(count, packets(data.len(), block));
+    }
+}
+
+#[test]
+fn send_to_slow_receiver() {
+    let data = pattern(1000);
+    let faults = Faults { delay_at: Some(300), delay: Duration::from_millis(500),
+                          ..Faults::default() };
+    let (received, _) = send(&data, &["-t", "2"], &[], Checksum::Crc16, faults);
+    assert_padded(&received, &data);
+}
+
+#[test]
+fn send_waits_for_late_receiver() {
+    let data = pattern(300);
+    let dir = scratch("late");
+    let input = dir.join("input.bin");
+    fs::write(&input, &data).expect("write input");
+
+    let pty = Pty::open();
+    let child = spawn(&pty, &["-t", "1", "--handshake-timeout", "10"],
+                      &["send", "-i", input.to_str().unwrap()]);
+    thread::sleep(Duration::from_millis(2500));
+
+    let mut received = Vec::new();
+    peer(&Rc::new(Cell::new(0))).checksum(Checksum::Crc16).build(pty.line())
+        .expect("build")
+        .read_data(&mut received, None)
+        .expect("receive");
+    finish(child);
+    assert_padded(&received, &data);
+}
+
+#[test]
+fn send_fails_without_receiver() {
+    let pty = Pty::open();
+    let child = spawn(&pty, &["-t", "1"], &["send", "-i", "/dev/null"]);
+    let output = child.wait_with_output().expect("wait for ttywrite");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("never started"));
+}
+
+#[test]
+fn send_watch_resets_and_resends() {
+    let dir = scratch("watch");
+    let input = dir.join("kernel.bin");
+    fs::write(&input, pattern(500)).expect("write input");
+
+    let pty = Pty::open();
+    let mut child = spawn(&pty, &[], &["send", "--watch", "-i", input.to_str().unwrap()]);
+    for &len in &[500, 700] {
+        if len != 500 {
+            fs::write(&input, pattern(len)).expect("rewrite input");
+        }
+
+        let mut magic = vec![0; RESET_MAGIC.len()];
+        pty.line().read_exact(&mut magic).expect("reset request");
+        assert_eq!(&magic[..], RESET_MAGIC);
+
+        let mut received = Vec::new();
+        peer(&Rc::new(Cell::new(0))).checksum(Checksum::Crc16).build(pty.line())
+            .expect("build")
+            .read_data(&mut received, None)
+            .expect("receive");
+        assert_padded(&received, &pattern(len));
+    }
+
+    child.kill().expect("stop watching");
+    child.wait().expect("wait for ttywrite");
+}
+
+#[test]
+fn recv_variants() {
+    let data = pattern(2500);
+    for &(args, block_size, block) in &[
+        (&[][..], BlockSize::Standard, 128),
+        (&[][..], BlockSize::OneK, 1024),
+        (&["-c"][..], BlockSize::OneK, 128),
+    ] {
+        let dir = scratch("recv");
+        let output = dir.join("output.bin");
+        let pty = Pty::open();
+        let mut recv_args = vec!["recv", "-o", output.to_str().unwrap()];
+        recv_args.extend_from_slice(args);
+        let child = spawn(&pty, &[], &recv_args);
+
+        let count = Rc::new(Cell::new(0));
+        peer(&count)
+            .block_size(block_size)
+            .build(pty.line())
+            .expect("build")
+            .write_data(&data[..])
+            .expect("send");
+        finish(child);
+
+        let received = fs::read(&output).expect("read output");
+        assert_padded(&received, &data);
+        assert_eq!(count.get(), packets(data.len(), block), "{:?}", args);
+    }
+}
+
+#[test]
+fn recv_ymodem_batch() {
+    let files = vec![
+        (FileInfo::new("kernel.log", 3000), pattern(3000)),
+        (FileInfo::new("../../escape.txt", 5), b"hello".to_vec()),
+        (FileInfo::new("empty", 0), vec![]),
+    ];
+
+    let dir = scratch("ymodem");
+    let pty = Pty::open();
+    let child = spawn(&pty, &[], &["recv", "-y", "-o", dir.to_str().unwrap()]);
+
+    let count = Rc::new(Cell::new(0));
+    let mut sender = Ymodem::from_xmodem(peer(&count).block_size(BlockSize::OneK)
+                                         .build(pty.line()).expect("build"));
+    for file in &files {
+        sender.send_file(&file.0, &file.1[..]).expect("send file");
+    }
+    sender.finish().expect("finish batch");
+    let output = finish(child);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Received 3 files."));
+
+    for file in &files {
+        let name = Path::new(&file.0.name).file_name().unwrap();
+        assert_eq!(fs::read(dir.join(name)).expect("received file"), file.1);
+    }
+}
+
+/// Ptys only support 8-bit characters, so the width isn't checked here.
+#[test]
+fn serial_settings() {
+    for &(args, speed, two_stop, hardware, software) in &[
+        (&[][..], B115200, false, false, false),
+        (&["-b", "9600", "-w", "8"][..], termios::B9600, false, false, false),
+        (&["-b", "230400", "-s", "2"][..], B230400, true, false, false),
+        (&["-f", "hardware"][..], B115200, false, true, false),
+        (&["-b", "19200", "-s", "2", "-f", "software"][..], termios::B19200, true, false, true),
+    ] {
+        let pty = Pty::open();
+        finish(spawn(&pty, args, &["send", "-r", "-i", "/dev/null"]));
+
+        let settings = pty.settings();
+        assert_eq!(termios::cfgetospeed(&settings), speed, "{:?}", args);
+        assert_eq!(termios::cfgetispeed(&settings), speed, "{:?}", args);
+        assert_eq!(settings.c_cflag & termios::CSIZE, termios::CS8, "{:?}", args);
+        assert_eq!(settings.c_cflag & termios::CSTOPB != 0, two_stop, "{:?}", args);
+        assert_eq!(settings.c_cflag & CRTSCTS != 0, hardware, "{:?}", args);
+        assert_eq!(settings.c_iflag & termios::IXON != 0, software, "{:?}", args);
+        assert_eq!(settings.c_iflag & termios::IXOFF != 0, software, "{:?}", args);
+    }
+}
+
+#[test]
+fn invalid_settings_are_rejected() {
+    for args in &[["-w", "9"], ["-s", "3"], ["-f", "maybe"], ["-b", "fast"]] {
+        let pty = Pty::open();
+        let output = spawn(&pty, args, &["send", "-r", "-i", "/dev/null"])
+            .wait_with_output()
+            .expect("wait for ttywrite");
+        assert!(!output.status.success(), "{:?} accepted", args);
+    }
+}
+
+#[test]
+fn profiles() {
+    let dir = scratch("profiles");
+    let data = pattern(300);
+    fs::write(dir.join("kernel.bin"), &data).expect("write kernel");
+
+    let pty = Pty::open();
+    let config = format!(r#"
+        default = "pi"
+
+        [profiles.pi]
+        tty = "{}"
+        baud = 19200
+        stop-bits = 2
+        protocol = "raw"
+        file = "kernel.bin"
+    "#, pty.path.display());
+    fs::write(dir.join("ttywrite.toml"), config).expect("write config");
+
+    // The project's configuration is found from its subdirectories.
+    let cwd = dir.join("build");
+    fs::create_dir_all(&cwd).expect("build dir");
+
+    for &(args, speed) in &[(&[][..], termios::B19200), (&["-b", "9600"][..], termios::B9600)] {
+        let child = ttywrite()
+            .current_dir(&cwd)
+            .args(args)
+            .arg("send")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("spawn ttywrite");
+
+        let mut received = vec![0; data.len()];
+        pty.line().read_exact(&mut received).expect("raw data");
+        finish(child);
+
+        assert_eq!(received, data, "{:?}", args);
+        let settings = pty.settings();
+        assert_eq!(termios::cfgetospeed(&settings), speed, "{:?}", args);
+        assert!(settings.c_cflag & termios::CSTOPB != 0, "{:?}", args);
+    }
+
+    let output = ttywrite()
+        .current_dir(&cwd)
+        .arg("-p").arg("missing").arg("send")
+        .output()
+        .expect("run ttywrite");
+    assert!(!output.status.success(), "missing profile accepted");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no profile named 'missing'"));
+
+    // Invalid settings are rejected even when another profile is used.
+    let broken = scratch("broken-profile");
+    fs::write(broken.join("ttywrite.toml"), "[profiles.broken]\nflow-control = \"maybe\"\n")
+        .expect("write config");
+    let output = ttywrite()
+        .env("XDG_CONFIG_HOME", &broken)
+        .current_dir(&cwd)
+        .arg("send")
+        .output()
+        .expect("run ttywrite");
+    assert!(!output.status.success(), "broken profile accepted");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid flow-control"));
+}
@@ -0,0 +1,197 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use core::time::Duration;
+
+use progress::{self, Progress, ProgressFn};
+use {Xmodem, Checksum, BlockSize};
+
+/// The number of times a packet is sent or received before giving up, unless
+/// configured otherwise with [`XmodemBuilder::max_retries()`].
+pub(crate) const DEFAULT_MAX_RETRIES: usize = 10;
+
+/// Type for clocks: returns the time elapsed since an arbitrary, fixed point.
+pub type Clock = fn() -> Duration;
+
+/// A flag that cancels the transfers it is given to.
+///
+/// Clones share the flag, so a transfer can be given one clone while another
+/// is kept elsewhere, e.g., by a signal handler. Transfers check the flag
+/// between packets.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Returns a new token that has not been cancelled.
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Cancels every transfer using this token or one of its clones.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if `cancel()` has been called on this token or one of
+    /// its clones.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// The limits of a transfer.
+#[derive(Clone)]
+pub(crate) struct Config {
+    pub max_retries: usize,
+    pub handshake_timeout: Option<Duration>,
+    pub deadline: Option<Duration>,
+    pub abort_with_can: bool,
+    pub cancel: Option<CancelToken>,
+    pub clock: Option<Clock>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            max_retries: DEFAULT_MAX_RETRIES,
+            handshake_timeout: None,
+            deadline: None,
+            abort_with_can: true,
+            cancel: None,
+            clock: None,
+        }
+    }
+}
+
+/// Builds an [`Xmodem`] instance with custom limits. Returned by
+/// [`Xmodem::builder()`].
+///
+/// A transfer that fails because of these limits returns an error whose kind
+/// identifies the failure:
+///
+///   * `NotConnected`: the peer didn't answer the handshake in time.
+///   * `TimedOut`: the transfer's deadline passed.
+///   * `BrokenPipe`: a packet was retried too many times.
+///   * `Other`: the transfer was cancelled with its `CancelToken`.
+///
+/// A transfer cancelled by the peer fails with `ConnectionAborted`.
+pub struct XmodemBuilder<F = ProgressFn> {
+    config: Config,
+    checksum: Checksum,
+    block_size: BlockSize,
+    progress: F,
+    total: Option<u64>,
+}
+
+impl XmodemBuilder {
+    pub(crate) fn new() -> XmodemBuilder {
+        XmodemBuilder {
+            config: Config::default(),
+            checksum: Checksum::Crc16,
+            block_size: BlockSize::Standard,
+            progress: progress::noop,
+            total: None,
+        }
+    }
+}
+
+impl<F: FnMut(Progress)> XmodemBuilder<F> {
+    /// Sets the number of times each packet is sent, or its reception is
+    /// attempted, before the transfer fails. Defaults to 10.
+    pub fn max_retries(mut self, retries: usize) -> Self {
+        self.config.max_retries = retries;
+        self
+    }
+
+    /// Keeps waiting for the peer to answer the initial handshake for up to
+    /// `timeout`, retrying whenever a read of the inner stream times out.
+    /// Without a handshake timeout, the first read timeout fails the
+    /// handshake. Requires a `clock()`: see [`build()`](#method.build).
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.config.handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Fails the transfer once `deadline` has passed since it started. The
+    /// deadline is checked between packets. Requires a `clock()`: see
+    /// [`build()`](#method.build).
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.config.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets the clock used to enforce the handshake timeout and the deadline.
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.config.clock = Some(clock);
+        self
+    }
+
+    /// Sets whether two `CAN` bytes are sent to the peer when the transfer
+    /// fails because of a limit or is cancelled, so that the peer stops too.
+    /// Defaults to `true`.
+    pub fn abort_with_can(mut self, abort: bool) -> Self {
+        self.config.abort_with_can = abort;
+        self
+    }
+
+    /// Cancels the transfer, at the next packet, once `token` is cancelled.
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.config.cancel = Some(token);
+        self
+    }
+
+    /// Sets the checksum a receiver asks for. See
+    /// [`Xmodem::with_checksum()`].
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Sets the largest packets a sender may use. See
+    /// [`Xmodem::with_block_size()`].
+    pub fn block_size(mut self, block_size: BlockSize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Sets the progress callback. See [`Xmodem::new_with_progress()`].
+    pub fn progress<G: FnMut(Progress)>(self, f: G) -> XmodemBuilder<G> {
+        XmodemBuilder {
+            config: self.config,
+            checksum: self.checksum,
+            block_size: self.block_size,
+            progress: f,
+            total: self.total,
+        }
+    }
+
+    /// Sets the number of data bytes expected to be transferred, which is
+    /// reported with each packet's progress.
+    pub fn total(mut self, bytes: u64) -> Self {
+        self.total = Some(bytes);
+        self
+    }
+
+    /// Returns a new `Xmodem` instance with the internal reader/writer set to
+    /// `inner` and this builder's settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if a handshake timeout or a deadline
+    /// is set without a clock to enforce it.
+    pub fn build<T: io::Read + io::Write>(self, inner: T) -> io::Result<Xmodem<T, F>> {
+        let timed = self.config.handshake_timeout.is_some() || self.config.deadline.is_some();
+        if timed && self.config.clock.is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "handshake timeout or deadline set without a clock"));
+        }
+
+        let mut xmodem = Xmodem::new_with_progress(inner, self.progress)
+            .with_checksum(self.checksum)
+            .with_block_size(self.block_size);
+        xmodem.total = self.total;
+        xmodem.config = self.config;
+        Ok(xmodem)
+    }
+}
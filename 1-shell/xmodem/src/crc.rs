@@ -0,0 +1,44 @@
+/// Computes the CRC-16/XMODEM of `data`: polynomial `0x1021`, initial value
+/// `0`, no reflection and no final XOR.
+pub fn crc16(data: &[u8]) -> u16 {
+    crc16_update(0, data)
+}
+
+/// Continues a CRC-16/XMODEM computation whose value so far is `crc` with the
+/// bytes in `data`.
+pub fn crc16_update(crc: u16, data: &[u8]) -> u16 {
+    data.iter().fold(crc, |crc, &byte| {
+        let mut crc = crc ^ ((byte as u16) << 8);
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+
+        crc
+    })
+}
+
+/// Computes the CRC-32 (IEEE 802.3) of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+/// Continues a CRC-32 computation whose register so far is `crc` with the
+/// bytes in `data`. The register starts at `!0` and the CRC is its complement.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &byte| {
+        let mut crc = crc ^ byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+
+        crc
+    })
+}
@@ -1,28 +1,98 @@
+extern crate core;
+
 use std::io;
+use std::cmp::min;
+
+use core::time::Duration;
 
 #[cfg(test)] mod tests;
 mod read_ext;
 mod progress;
+mod crc;
+mod config;
+mod ymodem;
+mod zmodem;
 
-pub use progress::{Progress, ProgressFn};
+pub use progress::{Progress, ProgressFn, RetryReason};
+pub use config::{XmodemBuilder, CancelToken, Clock};
+pub use ymodem::{Ymodem, FileInfo};
+pub use zmodem::Zmodem;
+pub use crc::{crc16, crc32};
 
 use read_ext::ReadExt;
+use config::Config;
+use progress::Retries;
 
 const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
 const EOT: u8 = 0x04;
 const ACK: u8 = 0x06;
 const NAK: u8 = 0x15;
 const CAN: u8 = 0x18;
+const CRC: u8 = b'C';
+
+/// The number of times a receiver requests CRC mode with `C` before falling
+/// back to checksum mode.
+const CRC_HANDSHAKE_ATTEMPTS: usize = 3;
+
+/// The integrity check appended to each packet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Checksum {
+    /// The original 8-bit sum of the packet's bytes. The receiver starts the
+    /// transfer with `NAK`.
+    Sum,
+    /// A CRC-16/XMODEM, sent most significant byte first. The receiver starts
+    /// the transfer with `C`.
+    Crc16,
+}
+
+/// The size of the data packets a sender uses.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockSize {
+    /// 128-byte packets started by `SOH`.
+    Standard,
+    /// 1024-byte packets started by `STX`, as in XMODEM-1K. These are only
+    /// sent if the receiver asks for `Checksum::Crc16`; the tail of the data
+    /// is sent in 128-byte packets to limit padding.
+    OneK,
+}
+
+impl BlockSize {
+    /// The number of data bytes in a packet of this size.
+    pub fn bytes(self) -> usize {
+        match self {
+            BlockSize::Standard => 128,
+            BlockSize::OneK => 1024,
+        }
+    }
+}
 
 /// Implementation of the XMODEM protocol.
-pub struct Xmodem<R> {
+pub struct Xmodem<R, F = ProgressFn> {
     packet: u8,
     inner: R,
     started: bool,
-    progress: ProgressFn
+    checksum: Checksum,
+    /// Whether a receiver requesting CRC mode falls back to checksum mode.
+    checksum_fallback: bool,
+    block_size: BlockSize,
+    progress: F,
+    /// The data bytes transferred so far and the number expected, if known.
+    bytes: u64,
+    total: Option<u64>,
+    retries: Retries,
+    config: Config,
+    /// The clock's time when the transfer started, if there is a clock.
+    started_at: Option<Duration>,
 }
 
 impl Xmodem<()> {
+    /// Returns a builder for an `Xmodem` instance with custom retry limits,
+    /// timeouts or cancellation. See [`XmodemBuilder`].
+    pub fn builder() -> XmodemBuilder {
+        XmodemBuilder::new()
+    }
+
     /// Transmits `data` to the receiver `to` using the XMODEM protocol. If the
     /// length of the total data yielded by `data` is not a multiple of 128
     /// bytes, the data is padded with zeroes and sent to the receiver.
@@ -43,34 +113,29 @@ impl Xmodem<()> {
     /// the transmission. See the [`Progress`] enum for more information.
     ///
     /// Returns the number of bytes written to `to`, excluding padding zeroes.
-    pub fn transmit_with_progress<R, W>(mut data: R, to: W, f: ProgressFn) -> io::Result<usize>
-        where W: io::Read + io::Write, R: io::Read
+    pub fn transmit_with_progress<R, W, F>(data: R, to: W, f: F) -> io::Result<usize>
+        where W: io::Read + io::Write, R: io::Read, F: FnMut(Progress)
     {
-        let mut transmitter = Xmodem::new_with_progress(to, f);
-        let mut packet = [0u8; 128];
-        let mut written = 0;
-        'next_packet: loop {
-            let n = data.read_max(&mut packet)?;
-            packet[n..].iter_mut().for_each(|b| *b = 0);
-
-            if n == 0 {
-                transmitter.write_packet(&[])?;
-                return Ok(written);
-            }
-
-            for _ in 0..10 {
-                match transmitter.write_packet(&packet) {
-                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(e),
-                    Ok(_) => {
-                        written += n;
-                        continue 'next_packet;
-                    }
-                }
-            }
+        Xmodem::transmit_with_block_size(data, to, BlockSize::Standard, f)
+    }
 
-            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "bad transmit"));
-        }
+    /// Transmits `data` to the receiver `to` using the XMODEM protocol, in
+    /// packets of at most `block_size`. If the length of the total data
+    /// yielded by `data` is not a multiple of 128 bytes, the data is padded
+    /// with zeroes and sent to the receiver.
+    ///
+    /// See [`BlockSize`] for when 1024-byte packets are used. The function `f`
+    /// is used as a callback to indicate progress throughout the transmission.
+    /// See the [`Progress`] enum for more information.
+    ///
+    /// Returns the number of bytes written to `to`, excluding padding zeroes.
+    pub fn transmit_with_block_size<R, W, F>(data: R, to: W, block_size: BlockSize,
+                                             f: F) -> io::Result<usize>
+        where W: io::Read + io::Write, R: io::Read, F: FnMut(Progress)
+    {
+        Xmodem::new_with_progress(to, f)
+            .with_block_size(block_size)
+            .write_data(data)
     }
 
     /// Receives `data` from `from` using the XMODEM protocol and writes it into
@@ -87,48 +152,97 @@ impl Xmodem<()> {
     ///
     /// The function `f` is used as a callback to indicate progress throughout
     /// the reception. See the [`Progress`] enum for more information.
-    pub fn receive_with_progress<R, W>(from: R, mut into: W, f: ProgressFn) -> io::Result<usize>
-       where R: io::Read + io::Write, W: io::Write
+    pub fn receive_with_progress<R, W, F>(from: R, into: W, f: F) -> io::Result<usize>
+       where R: io::Read + io::Write, W: io::Write, F: FnMut(Progress)
     {
-        let mut receiver = Xmodem::new_with_progress(from, f);
-        let mut packet = [0u8; 128];
-        let mut received = 0;
-        'next_packet: loop {
-            for _ in 0..10 {
-                match receiver.read_packet(&mut packet) {
-                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(e),
-                    Ok(0) => break 'next_packet,
-                    Ok(n) => {
-                        received += n;
-                        into.write_all(&packet)?;
-                        continue 'next_packet;
-                    }
-                }
-            }
-
-            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "bad receive"));
-        }
+        Xmodem::receive_with_checksum(from, into, Checksum::Crc16, f)
+    }
 
-        Ok(received)
+    /// Receives `data` from `from` using the XMODEM protocol and writes it into
+    /// `into`. Returns the number of bytes read from `from`, a multiple of 128.
+    ///
+    /// The receiver asks the sender to use `checksum`. See
+    /// [`Xmodem::with_checksum()`] for how CRC mode is negotiated. The function
+    /// `f` is used as a callback to indicate progress throughout the
+    /// reception. See the [`Progress`] enum for more information.
+    pub fn receive_with_checksum<R, W, F>(from: R, into: W, checksum: Checksum,
+                                          f: F) -> io::Result<usize>
+       where R: io::Read + io::Write, W: io::Write, F: FnMut(Progress)
+    {
+        Xmodem::new_with_progress(from, f)
+            .with_checksum(checksum)
+            .read_data(into, None)
     }
 }
 
+/// Returns the 8-bit sum of the bytes in `buf`.
+fn sum(buf: &[u8]) -> u8 {
+    buf.iter().fold(0, |acc, &x| acc.wrapping_add(x))
+}
+
+/// Returns `true` if `e` indicates that a read timed out.
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock
+}
+
 impl<T: io::Read + io::Write> Xmodem<T> {
     /// Returns a new `Xmodem` instance with the internal reader/writer set to
     /// `inner`. The returned instance can be used for both receiving
     /// (downloading) and sending (uploading).
     pub fn new(inner: T) -> Self {
-        Xmodem { packet: 1, started: false, inner, progress: progress::noop}
+        Xmodem::new_with_progress(inner, progress::noop as ProgressFn)
     }
+}
 
+impl<T: io::Read + io::Write, F: FnMut(Progress)> Xmodem<T, F> {
     /// Returns a new `Xmodem` instance with the internal reader/writer set to
     /// `inner`. The returned instance can be used for both receiving
     /// (downloading) and sending (uploading). The function `f` is used as a
     /// callback to indicate progress throughout the transfer. See the
     /// [`Progress`] enum for more information.
-    pub fn new_with_progress(inner: T, f: ProgressFn) -> Self {
-        Xmodem { packet: 1, started: false, inner, progress: f }
+    pub fn new_with_progress(inner: T, f: F) -> Self {
+        Xmodem { packet: 1, started: false, inner, checksum: Checksum::Crc16,
+                 checksum_fallback: true, block_size: BlockSize::Standard, progress: f,
+                 bytes: 0, total: None, retries: Retries::default(),
+                 config: Config::default(), started_at: None }
+    }
+
+    /// Sets the largest packets a sender may use. Defaults to
+    /// `BlockSize::Standard`. See [`BlockSize`] for details. A receiver always
+    /// accepts 1024-byte packets in CRC mode, so this setting has no effect on
+    /// it.
+    pub fn with_block_size(mut self, block_size: BlockSize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Returns the largest number of data bytes a packet sent now may carry:
+    /// 1024 if 1K blocks are enabled and the receiver asked for CRC mode, 128
+    /// otherwise.
+    fn max_packet_len(&self) -> usize {
+        match self.checksum {
+            Checksum::Crc16 => self.block_size.bytes(),
+            Checksum::Sum => BlockSize::Standard.bytes(),
+        }
+    }
+
+    /// Sets the checksum a receiver asks the sender to use. Defaults to
+    /// `Checksum::Crc16`; `Checksum::Sum` makes the receiver start with `NAK`
+    /// right away.
+    ///
+    /// A receiver preferring `Checksum::Crc16` starts the transfer by sending
+    /// `C`, and falls back to `Checksum::Sum` if the sender doesn't start
+    /// sending within three read timeouts. A sender always uses the checksum
+    /// requested by the receiver, so this setting has no effect on it.
+    pub fn with_checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Returns the checksum in use. Before the transfer has started, this is
+    /// the checksum that will be requested.
+    pub fn checksum(&self) -> Checksum {
+        self.checksum
     }
 
     /// Reads a single byte from the inner I/O stream. If `abort_on_can` is
@@ -202,12 +316,182 @@ impl<T: io::Read + io::Write> Xmodem<T> {
         }
     }
 
+    /// Fails the transfer with an error of `kind` and message `msg`, first
+    /// sending two `CAN` bytes to the peer if so configured.
+    fn abort(&mut self, kind: io::ErrorKind, msg: &'static str) -> io::Error {
+        if self.config.abort_with_can {
+            // The peer may already be gone; the failure is reported regardless.
+            let _ = self.inner.write_all(&[CAN, CAN]);
+            let _ = self.inner.flush();
+        }
+
+        io::Error::new(kind, msg)
+    }
+
+    /// Returns the time elapsed since the transfer started, if there is a
+    /// clock.
+    fn elapsed(&self) -> Option<Duration> {
+        match (self.config.clock, self.started_at) {
+            (Some(clock), Some(start)) => Some(clock().checked_sub(start).unwrap_or_default()),
+            _ => None,
+        }
+    }
+
+    /// Records the start of the transfer.
+    fn mark_start(&mut self) {
+        self.started_at = self.config.clock.map(|clock| clock());
+    }
+
+    /// Fails the transfer if it was cancelled or its deadline has passed.
+    fn check_limits(&mut self) -> io::Result<()> {
+        let cancelled = match self.config.cancel {
+            Some(ref token) => token.is_cancelled(),
+            None => false,
+        };
+
+        if cancelled {
+            return Err(self.abort(io::ErrorKind::Other, "Transfer cancelled"));
+        }
+
+        match (self.config.deadline, self.elapsed()) {
+            (Some(deadline), Some(elapsed)) if elapsed >= deadline => {
+                Err(self.abort(io::ErrorKind::TimedOut, "Transfer deadline exceeded"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads the peer's first byte. Each time a read times out, `on_timeout`
+    /// is called and the read retried, as long as the handshake timeout
+    /// hasn't passed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `NotConnected` if the handshake times out.
+    fn read_handshake<H>(&mut self, mut on_timeout: H) -> io::Result<u8>
+        where H: FnMut(&mut Self) -> io::Result<()>
+    {
+        loop {
+            self.check_limits()?;
+            match self.read_byte(true) {
+                Err(ref e) if is_timeout(e) => {
+                    let waiting = match (self.config.handshake_timeout, self.elapsed()) {
+                        (Some(timeout), Some(elapsed)) => elapsed < timeout,
+                        _ => false,
+                    };
+
+                    if !waiting {
+                        return Err(self.abort(io::ErrorKind::NotConnected,
+                                              "No response to handshake"));
+                    }
+
+                    on_timeout(self)?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Discards input until a read fails, normally by timing out, and asks the
+    /// sender to resend the current packet. Returns the error of kind
+    /// `Interrupted` to report.
+    fn request_resend(&mut self) -> io::Result<io::Error> {
+        while self.read_byte(false).is_ok() {  }
+        self.write_byte(NAK)?;
+        self.report_retry(RetryReason::Timeout, true);
+        Ok(io::Error::new(io::ErrorKind::Interrupted, "Timed out waiting for packet"))
+    }
+
+    /// Reports a retry because of `reason` to the progress callback.
+    fn report_retry(&mut self, reason: RetryReason, nak: bool) {
+        let event = self.retries.record(reason, nak);
+        (self.progress)(event);
+    }
+
+    /// Reports the transfer of packet `number` holding `len` data bytes to the
+    /// progress callback.
+    fn report_packet(&mut self, number: u8, len: usize) {
+        self.bytes += len as u64;
+        (self.progress)(Progress::Packet { number, bytes: self.bytes, total: self.total });
+    }
+
+    /// Starts a reception by requesting the configured checksum from the
+    /// sender and returns the first byte the sender replies with.
+    ///
+    /// In CRC mode, `C` is sent up to `CRC_HANDSHAKE_ATTEMPTS` times, each
+    /// time waiting for the inner stream's read to time out. If the sender
+    /// never replies, the receiver falls back to checksum mode and sends `NAK`,
+    /// repeating it while the handshake timeout allows. Without the fallback,
+    /// `C` is repeated instead.
+    fn start_receive(&mut self) -> io::Result<u8> {
+        self.started = true;
+        self.mark_start();
+        (self.progress)(Progress::Started);
+
+        if self.checksum == Checksum::Crc16 {
+            for _ in 0..CRC_HANDSHAKE_ATTEMPTS {
+                self.check_limits()?;
+                self.write_byte(CRC)?;
+                match self.read_byte(true) {
+                    Ok(byte) => {
+                        (self.progress)(Progress::Negotiated(Checksum::Crc16));
+                        return Ok(byte);
+                    }
+                    Err(ref e) if is_timeout(e) => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if !self.checksum_fallback {
+                self.write_byte(CRC)?;
+                let byte = self.read_handshake(|xmodem| xmodem.write_byte(CRC))?;
+                (self.progress)(Progress::Negotiated(Checksum::Crc16));
+                return Ok(byte);
+            }
+
+            self.checksum = Checksum::Sum;
+        }
+
+        self.write_byte(NAK)?;
+        (self.progress)(Progress::Negotiated(Checksum::Sum));
+        self.read_handshake(|xmodem| xmodem.write_byte(NAK))
+    }
+
+    /// Reads the checksum following the packet `buf` from the inner stream
+    /// and returns whether it matches `buf`.
+    fn read_and_verify_checksum(&mut self, buf: &[u8]) -> io::Result<bool> {
+        match self.checksum {
+            Checksum::Sum => Ok(self.read_byte(false)? == sum(buf)),
+            Checksum::Crc16 => {
+                let high = self.read_byte(false)? as u16;
+                let low = self.read_byte(false)? as u16;
+                Ok((high << 8 | low) == crc::crc16(buf))
+            }
+        }
+    }
+
+    /// Writes the checksum of the packet `buf` to the inner stream.
+    fn write_checksum(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self.checksum {
+            Checksum::Sum => self.write_byte(sum(buf)),
+            Checksum::Crc16 => {
+                let crc = crc::crc16(buf);
+                self.write_byte((crc >> 8) as u8)?;
+                self.write_byte(crc as u8)
+            }
+        }
+    }
+
     /// Reads (downloads) a single packet from the inner stream using the XMODEM
-    /// protocol. On success, returns the number of bytes read (always 128).
+    /// protocol into the start of `buf`. On success, returns the number of
+    /// bytes read: 128, or 1024 for a 1K packet. 1K packets are only accepted
+    /// in CRC mode and if `buf` can hold them.
     ///
-    /// The progress callback is called with `Progress::Start` when reception
-    /// for the first packet has started and subsequently with
-    /// `Progress::Packet` when a packet is received successfully.
+    /// The progress callback is called with `Progress::Started` when reception
+    /// for the first packet has started, with `Progress::Negotiated` once the
+    /// checksum in use is known and subsequently with `Progress::Packet` when
+    /// a packet is received successfully or `Progress::Retry` when the sender
+    /// is asked to resend it.
     ///
     /// # Errors
     ///
@@ -215,46 +499,71 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// point. Also returns an error if the XMODEM protocol indicates an error.
     /// In particular, an `InvalidData` error is returned when:
     ///
-    ///   * The sender's first byte for a packet isn't `EOT` or `SOH`.
+    ///   * The sender's first byte for a packet isn't `EOT`, `SOH` or `STX`.
+    ///   * The sender sends a 1K packet that can't be accepted. A `CAN` byte
+    ///     is sent to the sender in this case.
     ///   * The sender doesn't send a second `EOT` after the first.
     ///   * The received packet numbers don't match the expected values.
     ///
-    /// An error of kind `Interrupted` is returned if a packet checksum fails.
+    /// An error of kind `Interrupted` is returned if a packet checksum fails
+    /// or a read times out once the transfer has started. The sender has been
+    /// asked to resend the packet.
     ///
     /// An error of kind `ConnectionAborted` is returned if a `CAN` byte is
     /// received when not expected.
     ///
     /// An error of kind `UnexpectedEof` is returned if `buf.len() < 128`.
+    ///
+    /// The errors described by [`XmodemBuilder`] are returned if the handshake
+    /// times out, the deadline passes or the transfer is cancelled.
     pub fn read_packet(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if buf.len() != 128 {
-            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Packet not length 128"));
+        if buf.len() < 128 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Packet shorter than 128"));
         }
 
-        if !self.started {
-            self.write_byte(NAK)?;
-            self.started = true;
-            (self.progress)(Progress::Started);
+        let header = if !self.started {
+            self.start_receive()?
+        } else {
+            self.check_limits()?;
+            match self.read_byte(true) {
+                Err(ref e) if is_timeout(e) => return Err(self.request_resend()?),
+                result => result?,
+            }
+        };
+
+        match self.read_packet_body(buf, header) {
+            Err(ref e) if is_timeout(e) => Err(self.request_resend()?),
+            result => result,
         }
+    }
 
-        let header: u8 = self.read_byte(true)?;
+    /// Reads the rest of the packet started by `header` into `buf`. See
+    /// `read_packet()`.
+    fn read_packet_body(&mut self, buf: &mut [u8], header: u8) -> io::Result<usize> {
         match header {
-            SOH => {
+            SOH | STX => {
+                let len = if header == STX { 1024 } else { 128 };
+                if len > buf.len() || (len == 1024 && self.checksum != Checksum::Crc16) {
+                    self.write_byte(CAN)?;
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                              "Unexpected 1K packet"));
+                }
+
+                let buf = &mut buf[..len];
                 let expected_packet_number: u8 = self.packet;
                 self.expect_byte_or_cancel(expected_packet_number, "Invalid packet number")?;
                 self.expect_byte_or_cancel(255 - expected_packet_number, "Invalid packet number")?;
                 self.inner.read_exact(buf)?;
 
-                let checksum: u8 = buf.iter().fold(0, |acc, &x| {
-                    acc.wrapping_add(x)
-                });
-                if checksum != self.read_byte(false)? {
+                if !self.read_and_verify_checksum(buf)? {
                     self.write_byte(NAK)?;
+                    self.report_retry(RetryReason::Corrupted, true);
                     Err(io::Error::new(io::ErrorKind::Interrupted, "Checksum failed"))
                 } else {
                     self.packet = self.packet.wrapping_add(1);
                     self.write_byte(ACK)?;
-                    (self.progress)(Progress::Packet(expected_packet_number));
-                    Ok(128)
+                    self.report_packet(expected_packet_number, len);
+                    Ok(len)
                 }
             }
             EOT => {
@@ -275,10 +584,14 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// transmission is complete. On success, returns the number of bytes
     /// written.
     ///
+    /// A `buf` of 1024 bytes is sent as a 1K packet, which requires the
+    /// receiver to have asked for CRC mode.
+    ///
     /// The progress callback is called with `Progress::Waiting` before waiting
-    /// for the receiver's `NAK`, `Progress::Start` when transmission of the
-    /// first packet has started and subsequently with `Progress::Packet` when a
-    /// packet is sent successfully.
+    /// for the receiver's `NAK` or `C`, `Progress::Negotiated` when the
+    /// receiver has chosen the checksum and subsequently with
+    /// `Progress::Packet` when a packet is sent successfully or
+    /// `Progress::Retry` when it must be sent again.
     ///
     /// # Errors
     ///
@@ -286,31 +599,40 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// point. Also returns an error if the XMODEM protocol indicates an error.
     /// In particular, an `InvalidData` error is returned when:
     ///
-    ///   * The receiver's first byte isn't a `NAK`.
+    ///   * The receiver's first byte isn't a `NAK` or `C`.
     ///   * The receiver doesn't respond with a `NAK` to the first `EOT`.
     ///   * The receiver doesn't respond with an `ACK` to the second `EOT`.
     ///   * The receiver responds to a complete packet with something besides
     ///     `ACK` or `NAK`.
     ///
-    /// An error of kind `UnexpectedEof` is returned if `buf.len() < 128 &&
-    /// buf.len() != 0`.
+    /// An error of kind `UnexpectedEof` is returned if `buf.len()` is not 0,
+    /// 128 or 1024.
+    ///
+    /// An error of kind `InvalidInput` is returned if `buf.len() == 1024` and
+    /// the receiver asked for checksum mode.
     ///
     /// An error of kind `ConnectionAborted` is returned if a `CAN` byte is
     /// received when not expected.
     ///
-    /// An error of kind `Interrupted` is returned if a packet checksum fails.
+    /// An error of kind `Interrupted` is returned if the receiver rejects the
+    /// packet with a `NAK` or doesn't reply before a read times out. The
+    /// packet should be sent again.
+    ///
+    /// The errors described by [`XmodemBuilder`] are returned if the handshake
+    /// times out, the deadline passes or the transfer is cancelled.
     pub fn write_packet(&mut self, buf: &[u8]) -> io::Result<usize> {
-        if buf.len() != 128 && buf.len() != 0 {
+        if buf.len() != 128 && buf.len() != 1024 && !buf.is_empty() {
             return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Invalid packet length"));
         }
 
-        if !self.started {
-            (self.progress)(Progress::Waiting);
-            self.expect_byte(NAK, "Expected NAK from receiver to indicate start")?;
-            self.started = true;
+        self.start_transmit()?;
+        self.check_limits()?;
+        if buf.len() == 1024 && self.checksum != Checksum::Crc16 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "1K packets require CRC mode"));
         }
 
-        if buf.len() == 0 {
+        if buf.is_empty() {
             self.write_byte(EOT)?;
             self.expect_byte(NAK, "Expected NAK after EOT")?;
             self.write_byte(EOT)?;
@@ -319,24 +641,147 @@ impl<T: io::Read + io::Write> Xmodem<T> {
         } else {
             let packet_number: u8 = self.packet;
 
-            self.write_byte(SOH)?;
+            self.write_byte(if buf.len() == 1024 { STX } else { SOH })?;
             self.write_byte(packet_number)?;
             self.write_byte(255 - packet_number)?;
-            self.inner.write(buf)?;
-
-            let checksum: u8 = buf.iter().fold(0, |acc, &x| {
-                acc.wrapping_add(x)
-            });
-            self.write_byte(checksum)?;
+            self.inner.write_all(buf)?;
+            self.write_checksum(buf)?;
+
+            match self.read_byte(true) {
+                Ok(ACK) => (),
+                Ok(NAK) => {
+                    self.report_retry(RetryReason::Rejected, true);
+                    return Err(io::Error::new(io::ErrorKind::Interrupted, "Packet rejected"));
+                }
+                Ok(_) => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                   "Expected ACK after sending packet")),
+                Err(ref e) if is_timeout(e) => {
+                    self.report_retry(RetryReason::Timeout, false);
+                    return Err(io::Error::new(io::ErrorKind::Interrupted, "No reply to packet"))
+                }
+                Err(e) => return Err(e),
+            }
 
-            self.expect_byte(ACK, "Expected ACK after sending packet")?;
-            (self.progress)(Progress::Packet(packet_number));
+            self.report_packet(packet_number, buf.len());
 
             self.packet = self.packet.wrapping_add(1);
-            Ok(128)
+            Ok(buf.len())
+        }
+    }
+
+    /// Sends all of `data` followed by the end of transmission, padding the
+    /// last packet with zeroes. Packets the receiver rejects are resent up to
+    /// the configured number of retries. Returns the number of bytes read from
+    /// `data`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `BrokenPipe` if a packet is rejected too many
+    /// times, any error of `write_packet()` other than `Interrupted`, or any
+    /// error from reading `data`.
+    pub fn write_data<R: io::Read>(&mut self, mut data: R) -> io::Result<usize> {
+        self.start_transmit()?;
+
+        let max_len = self.max_packet_len();
+        let mut buf = [0u8; 1024];
+        let mut written = 0;
+        loop {
+            let n = data.read_max(&mut buf[..max_len])?;
+            if n == 0 {
+                self.write_packet(&[])?;
+                return Ok(written);
+            }
+
+            // Only the last read is short: send it in 128-byte packets.
+            let len = if n == max_len { max_len } else { 128 };
+            let padded = if n % len == 0 { n } else { n + len - n % len };
+            buf[n..padded].iter_mut().for_each(|b| *b = 0);
+
+            for packet in buf[..padded].chunks(len) {
+                self.write_packet_retrying(packet)?;
+            }
+
+            written += n;
         }
     }
 
+    /// Receives packets until the end of transmission and writes their data
+    /// into `into`, stopping after `limit` bytes if it is set. Packets that
+    /// fail to arrive intact are requested again up to the configured number
+    /// of retries. Returns the number of bytes received, a multiple of 128.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `BrokenPipe` if a packet fails too many times, any
+    /// error of `read_packet()` other than `Interrupted`, or any error from
+    /// writing to `into`.
+    pub fn read_data<W: io::Write>(&mut self, mut into: W, limit: Option<u64>)
+        -> io::Result<usize>
+    {
+        let mut packet = [0u8; 1024];
+        let mut received = 0;
+        'next_packet: loop {
+            for _ in 0..self.config.max_retries {
+                match self.read_packet(&mut packet) {
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                    Ok(0) => break 'next_packet,
+                    Ok(n) => {
+                        let keep = match limit {
+                            Some(limit) => min(n as u64, limit.saturating_sub(received as u64)) as usize,
+                            None => n,
+                        };
+
+                        received += n;
+                        into.write_all(&packet[..keep])?;
+                        continue 'next_packet;
+                    }
+                }
+            }
+
+            return Err(self.abort(io::ErrorKind::BrokenPipe, "Too many retries"));
+        }
+
+        Ok(received)
+    }
+
+    /// Sends a single packet with `write_packet`, resending it up to the
+    /// configured number of retries if the receiver rejects it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `BrokenPipe` if the receiver rejects every attempt,
+    /// or any other error returned by `write_packet`.
+    fn write_packet_retrying(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for _ in 0..self.config.max_retries {
+            match self.write_packet(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => return result,
+            }
+        }
+
+        Err(self.abort(io::ErrorKind::BrokenPipe, "Too many retries"))
+    }
+
+    /// Starts a transmission, if it hasn't started yet, by waiting for the
+    /// receiver to request a checksum with `NAK` or `C`.
+    fn start_transmit(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.mark_start();
+            (self.progress)(Progress::Waiting);
+            self.checksum = match self.read_handshake(|_| Ok(()))? {
+                NAK => Checksum::Sum,
+                CRC => Checksum::Crc16,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    "Expected NAK or C from receiver to indicate start")),
+            };
+            self.started = true;
+            (self.progress)(Progress::Negotiated(self.checksum));
+        }
+
+        Ok(())
+    }
+
     /// Flush this output stream, ensuring that all intermediately buffered
     /// contents reach their destination.
     ///
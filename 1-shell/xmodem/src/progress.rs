@@ -1,21 +1,77 @@
+use Checksum;
+
 /// Enum representing how much progress has been made transmitting/receiving.
 ///
 /// A value of this type is passed in to the progress callback supplied to
 /// methods like [`Xmodem::transmit_with_progress()`],
 /// [`Xmodem::receive_with_progress()`], and [`Xmodem::new_with_progress()`]. It
 /// is intended to be used by progress indicators or for debugging purposes.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Progress {
     /// Waiting for receiver to send NAK.
     Waiting,
     /// Download/upload has started.
     Started,
-    /// Packet `.0` was transmitted/received.
-    Packet(u8),
+    /// Sender and receiver agreed to protect packets with checksum `.0`.
+    Negotiated(Checksum),
+    /// Packet `number` was transmitted/received.
+    Packet {
+        /// The packet's number. XMODEM numbers wrap around after 255.
+        number: u8,
+        /// The number of data bytes transferred so far, including the
+        /// padding of XMODEM packets. For a ZMODEM file, this is the position
+        /// in the file, including any part that was resumed.
+        bytes: u64,
+        /// The number of bytes expected in total, if known.
+        total: Option<u64>,
+    },
+    /// A packet is being sent or requested again.
+    Retry {
+        /// Why the packet is being sent again.
+        reason: RetryReason,
+        /// The number of retries so far in the transfer, including this one.
+        retries: u64,
+        /// The number of those retries caused by a `NAK`, or the ZMODEM
+        /// equivalent, being sent or received.
+        naks: u64,
+    },
+}
+
+/// Why a packet is being sent or requested again.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RetryReason {
+    /// The receiver rejected the packet.
+    Rejected,
+    /// The packet arrived corrupted: its checksum or CRC didn't match.
+    Corrupted,
+    /// The peer didn't reply, or the packet didn't arrive, before a read
+    /// timed out.
+    Timeout,
 }
 
-/// Type for progress callbacks.
+/// Type for progress callbacks that capture nothing, like [`noop`]. Transfers
+/// created without a callback use this type.
 pub type ProgressFn = fn(Progress);
 
 /// Noop progress callback.
 pub fn noop(_: Progress) {  }
+
+/// Counts the retries of a transfer and reports them to a progress callback.
+#[derive(Debug, Default)]
+pub(crate) struct Retries {
+    retries: u64,
+    naks: u64,
+}
+
+impl Retries {
+    /// Records a retry because of `reason`, which involved a `NAK` if `nak`
+    /// is `true`, and returns the event to report.
+    pub fn record(&mut self, reason: RetryReason, nak: bool) -> Progress {
+        self.retries += 1;
+        if nak {
+            self.naks += 1;
+        }
+
+        Progress::Retry { reason, retries: self.retries, naks: self.naks }
+    }
+}
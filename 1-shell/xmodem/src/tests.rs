@@ -1,6 +1,7 @@
 use super::*;
 use std::sync::mpsc::{Receiver, Sender, channel};
 use std::io::Cursor;
+use core::time::Duration;
 
 struct Pipe(Sender<u8>, Receiver<u8>, Vec<u8>);
 
@@ -166,7 +167,8 @@ fn test_raw_transmission() {
     });
 
     let rx_thread = std::thread::spawn(move || {
-        Xmodem::receive(&mut tx, &mut output[..]).expect("receive okay");
+        Xmodem::receive_with_checksum(&mut tx, &mut output[..], Checksum::Sum,
+                                      progress::noop).expect("receive okay");
         tx.2
     });
 
@@ -237,14 +239,16 @@ fn test_read_packet() {
 
     let rx_thread = std::thread::spawn(move || {
         let mut xmodem = Xmodem::new_with_progress(rx, |progress| {
-            if let Progress::Packet(number) = progress {
+            if let Progress::Packet { number, .. } = progress {
                 assert_eq!(number, 1);
             } else if let Progress::Started = progress {
                 // Valid.
+            } else if let Progress::Negotiated(Checksum::Sum) = progress {
+                // Valid.
             } else {
                 assert!(false);
             }
-        });
+        }).with_checksum(Checksum::Sum);
 
         let mut dest_packet = [0u8; 128];
         xmodem.read_packet(&mut dest_packet).expect("read packet");
@@ -292,7 +296,7 @@ fn test_read_packet_control_characters() {
     use std::io::{Read, Write};
 
     let (mut tx, rx) = pipe();
-    let mut xmodem = Xmodem::new(rx);
+    let mut xmodem = Xmodem::new(rx).with_checksum(Checksum::Sum);
 
     let mut source_packet = [0u8; 128];
     source_packet[0..5].copy_from_slice(&[SOH, EOT, ACK, NAK, CAN]);
@@ -321,7 +325,7 @@ fn test_read_packet_checksum_can() {
     use std::io::{Read, Write};
 
     let (mut tx, rx) = pipe();
-    let mut xmodem = Xmodem::new(rx);
+    let mut xmodem = Xmodem::new(rx).with_checksum(Checksum::Sum);
 
     let mut source_packet = [0u8; 128];
     source_packet[0] = CAN;
@@ -347,7 +351,7 @@ fn test_read_packet_numbers() {
     use std::io::{Read, Write};
 
     let (mut tx, rx) = pipe();
-    let mut xmodem = Xmodem::new(rx);
+    let mut xmodem = Xmodem::new(rx).with_checksum(Checksum::Sum);
 
     let mut started = false;
 
@@ -389,10 +393,12 @@ fn test_write_packet() {
 
     let (tx, mut rx) = pipe();
     let mut xmodem = Xmodem::new_with_progress(tx, |progress| {
-        if let Progress::Packet(number) = progress {
+        if let Progress::Packet { number, .. } = progress {
             assert_eq!(number, 1);
         } else if let Progress::Waiting = progress {
             // Valid.
+        } else if let Progress::Negotiated(Checksum::Sum) = progress {
+            // Valid.
         } else {
             assert!(false);
         }
@@ -486,3 +492,1099 @@ fn test_write_packet_numbers() {
 
     // Don't validate the rest, that's handled by test_write_packet.
 }
+
+#[test]
+fn test_crc16() {
+    assert_eq!(crc::crc16(b""), 0);
+    assert_eq!(crc::crc16(b"123456789"), 0x31C3);
+}
+
+/// A pipe whose reads time out, rather than block, when no data is
+/// available.
+struct TimeoutPipe(Sender<u8>, Receiver<u8>);
+
+fn timeout_pipe() -> (TimeoutPipe, TimeoutPipe) {
+    let ((tx1, rx1), (tx2, rx2)) = (channel(), channel());
+    (TimeoutPipe(tx1, rx2), TimeoutPipe(tx2, rx1))
+}
+
+impl io::Read for TimeoutPipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::time::Duration;
+
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        match self.1.recv_timeout(Duration::from_millis(50)) {
+            Ok(byte) => { buf[0] = byte; Ok(1) },
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "timeout")),
+        }
+    }
+}
+
+impl io::Write for TimeoutPipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            let _ = self.0.send(byte);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_crc_loop() {
+    let mut input = [0u8; 300];
+    (0..300usize).for_each(|i| input[i] = (i * 7) as u8);
+
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut raw = rx;
+        Xmodem::transmit(&input[..], &mut raw).expect("transmit okay");
+        raw.2
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = [0u8; 384];
+        let mut tx = tx;
+        Xmodem::receive_with_checksum(&mut tx, &mut output[..], Checksum::Crc16,
+                                      progress::noop).expect("receive okay");
+        (output, tx.2)
+    });
+
+    let sent = tx_thread.join().expect("tx join okay");
+    let (output, responses) = rx_thread.join().expect("rx join okay");
+    assert_eq!(&input[..], &output[..300]);
+    assert_eq!(&output[300..], &[0u8; 84][..]);
+
+    // Packets carry a big-endian CRC-16 instead of a checksum byte.
+    let crc = crc::crc16(&input[..128]);
+    assert_eq!(&sent[0..3], &[SOH, 1, 254]);
+    assert_eq!(&sent[131..133], &[(crc >> 8) as u8, crc as u8]);
+    assert_eq!(&sent[133..136], &[SOH, 2, 253]);
+    assert_eq!(&responses[..], &[CRC, ACK, ACK, ACK, NAK, ACK]);
+}
+
+#[test]
+fn test_receive_defaults_to_crc() {
+    let input = [5u8; 128];
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut raw = rx;
+        Xmodem::transmit(&input[..], &mut raw).expect("transmit okay");
+        raw.2
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut tx = tx;
+        Xmodem::receive(&mut tx, io::sink()).expect("receive okay");
+        tx.2
+    });
+
+    let sent = tx_thread.join().expect("tx join okay");
+    let responses = rx_thread.join().expect("rx join okay");
+    assert_eq!(responses[0], CRC);
+    assert_eq!(sent.len(), 133 + 2);
+    assert_eq!(Xmodem::new(Cursor::new(Vec::new())).checksum(), Checksum::Crc16);
+}
+
+#[test]
+fn test_crc_read_packet_bad_crc() {
+    use std::io::{Read, Write};
+
+    let (mut tx, rx) = pipe();
+    let mut xmodem = Xmodem::new(rx).with_checksum(Checksum::Crc16);
+
+    let packet = [0xA5u8; 128];
+    let crc = crc::crc16(&packet);
+    tx.write_all(&[SOH, 1, 254]).expect("header");
+    tx.write_all(&packet).expect("packet");
+    tx.write_all(&[(crc >> 8) as u8, (crc as u8) ^ 1]).expect("bad crc");
+
+    let mut dest = [0u8; 128];
+    let e = xmodem.read_packet(&mut dest).expect_err("bad crc");
+    assert_eq!(e.kind(), io::ErrorKind::Interrupted);
+
+    tx.write_all(&[SOH, 1, 254]).expect("header");
+    tx.write_all(&packet).expect("packet");
+    tx.write_all(&[(crc >> 8) as u8, crc as u8]).expect("crc");
+    assert_eq!(xmodem.read_packet(&mut dest).expect("read packet"), 128);
+    assert_eq!(&dest[..], &packet[..]);
+
+    let mut responses = [0u8; 3];
+    tx.read_exact(&mut responses).expect("responses");
+    assert_eq!(&responses[..], &[CRC, NAK, ACK]);
+}
+
+#[test]
+fn test_crc_fallback_to_checksum() {
+    use std::io::{Read, Write};
+
+    let (mut sender, receiver) = timeout_pipe();
+    let rx_thread = std::thread::spawn(move || {
+        let mut xmodem = Xmodem::new(receiver).with_checksum(Checksum::Crc16);
+        let mut packet = [0u8; 128];
+        xmodem.read_packet(&mut packet).expect("read packet");
+        (xmodem.checksum(), packet)
+    });
+
+    // A checksum-only sender ignores `C` and waits for the `NAK`.
+    let mut byte = [0u8; 1];
+    loop {
+        match sender.read(&mut byte) {
+            Ok(1) if byte[0] == NAK => break,
+            Ok(1) => assert_eq!(byte[0], CRC),
+            _ => continue,
+        }
+    }
+
+    let packet = [3u8; 128];
+    sender.write_all(&[SOH, 1, 254]).expect("header");
+    sender.write_all(&packet).expect("packet");
+    sender.write_all(&[sum(&packet)]).expect("checksum");
+
+    let (checksum, received) = rx_thread.join().expect("rx join okay");
+    assert_eq!(checksum, Checksum::Sum);
+    assert_eq!(&received[..], &packet[..]);
+}
+
+#[test]
+fn test_1k_loop() {
+    let mut input = vec![0u8; 2500];
+    (0..2500usize).for_each(|i| input[i] = (i * 13) as u8);
+
+    let data = input.clone();
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut raw = rx;
+        let n = Xmodem::transmit_with_block_size(&data[..], &mut raw, BlockSize::OneK,
+                                                 progress::noop).expect("transmit okay");
+        (n, raw.2)
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = vec![];
+        let n = Xmodem::receive_with_checksum(tx, &mut output, Checksum::Crc16,
+                                              progress::noop).expect("receive okay");
+        (n, output)
+    });
+
+    let (written, sent) = tx_thread.join().expect("tx join okay");
+    let (received, output) = rx_thread.join().expect("rx join okay");
+    assert_eq!(written, 2500);
+    assert_eq!(received, 2560);
+    assert_eq!(&output[..2500], &input[..]);
+    assert!(output[2500..].iter().all(|&b| b == 0));
+
+    // Two 1K packets, then the 452-byte tail in four 128-byte packets.
+    assert_eq!(&sent[0..3], &[STX, 1, 254]);
+    assert_eq!(&sent[1029..1032], &[STX, 2, 253]);
+    for i in 0..4 {
+        let start = 2058 + i * 133;
+        assert_eq!(&sent[start..start + 3], &[SOH, 3 + i as u8, 252 - i as u8]);
+    }
+    assert_eq!(&sent[2058 + 4 * 133..], &[EOT, EOT]);
+}
+
+#[test]
+fn test_1k_requires_crc() {
+    let input = [7u8; 2048];
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut raw = rx;
+        Xmodem::transmit_with_block_size(&input[..], &mut raw, BlockSize::OneK,
+                                         progress::noop).expect("transmit okay");
+        raw.2
+    });
+    let rx_thread = std::thread::spawn(move || {
+        Xmodem::receive_with_checksum(tx, io::sink(), Checksum::Sum, progress::noop)
+            .expect("receive okay")
+    });
+
+    let sent = tx_thread.join().expect("tx join okay");
+    assert_eq!(rx_thread.join().expect("rx join okay"), 2048);
+
+    // A checksum-mode receiver gets 128-byte packets only.
+    assert_eq!(sent.len(), 16 * 132 + 2);
+    assert!(sent.chunks(132).take(16).all(|packet| packet[0] == SOH));
+}
+
+#[test]
+fn test_1k_rejected_in_checksum_mode() {
+    let mut buffer = vec![STX, 0];
+    let mut packet = [0u8; 1024];
+    let e = {
+        let mut xmodem = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
+            .with_checksum(Checksum::Sum);
+        xmodem.started = true;
+        xmodem.read_packet(&mut packet).expect_err("1K packet in checksum mode")
+    };
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(buffer[1], CAN);
+
+    let mut xmodem = Xmodem::new(Cursor::new(vec![NAK]));
+    let e = xmodem.write_packet(&packet).expect_err("1K packet in checksum mode");
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+}
+
+/// Wraps a stream, counting the `ACK`s written to it.
+struct Acks<T>(T, usize);
+
+impl<T: io::Read> io::Read for Acks<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: io::Write> io::Write for Acks<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.0.write(buf)?;
+        self.1 += buf[..n].iter().filter(|&&byte| byte == ACK).count();
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Transfers 32KiB and returns the number of `ACK`s the receiver sent: one
+/// per packet and one for the `EOT`.
+fn acked_transfer(block_size: BlockSize) -> usize {
+    let input = vec![0x5Au8; 32 * 1024];
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        Xmodem::transmit_with_block_size(&input[..], rx, block_size, progress::noop)
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut acks = Acks(tx, 0);
+        let received = Xmodem::receive_with_checksum(&mut acks, io::sink(), Checksum::Crc16,
+                                                     progress::noop);
+        (received, acks.1)
+    });
+
+    assert_eq!(tx_thread.join().expect("tx join okay").expect("tx okay"), 32 * 1024);
+    let (received, acks) = rx_thread.join().expect("rx join okay");
+    assert_eq!(received.expect("rx okay"), 32 * 1024);
+    acks
+}
+
+#[test]
+fn test_1k_round_trips() {
+    // Each round trip costs a turnaround on the link: 1K blocks need 8 times
+    // fewer.
+    assert_eq!(acked_transfer(BlockSize::Standard), 256 + 1);
+    assert_eq!(acked_transfer(BlockSize::OneK), 32 + 1);
+}
+
+#[test]
+fn test_ymodem_header() {
+    let info = FileInfo { name: "kernel.bin".to_string(), size: Some(1234),
+                          modified: Some(0o13), mode: Some(0o644) };
+    let header = info.encode().expect("encode");
+    assert_eq!(&header[..], &b"kernel.bin\x001234 13 644"[..]);
+
+    let mut packet = [0u8; 128];
+    packet[..header.len()].copy_from_slice(&header);
+    assert_eq!(FileInfo::decode(&packet).expect("decode"), Some(info));
+
+    let header = FileInfo::new("a", 5).encode().expect("encode");
+    assert_eq!(&header[..], &b"a\x005"[..]);
+
+    let info = FileInfo { name: "b".to_string(), mode: Some(0o755),
+                          ..FileInfo::default() };
+    assert_eq!(&info.encode().expect("encode")[..], &b"b\x000 0 755"[..]);
+
+    let unknown = FileInfo::decode(b"c\0junk\0").expect("decode").expect("file");
+    assert_eq!(unknown.size, None);
+
+    assert_eq!(FileInfo::decode(&[0u8; 128]).expect("decode"), None);
+    assert!(FileInfo::default().encode().is_err());
+}
+
+#[test]
+fn test_ymodem_batch() {
+    let first: Vec<u8> = (0..3000usize).map(|i| (i * 3) as u8).collect();
+    let second = b"short file".to_vec();
+    let files = vec![
+        (FileInfo::new("first.bin", first.len() as u64), first.clone()),
+        (FileInfo::new("empty", 0), vec![]),
+        (FileInfo::new("second.txt", second.len() as u64), second.clone()),
+    ];
+
+    let expected: Vec<FileInfo> = files.iter().map(|f| f.0.clone()).collect();
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut sender = Ymodem::new(rx);
+        for (info, data) in files {
+            let n = sender.send_file(&info, &data[..]).expect("send file");
+            assert_eq!(n, data.len() as u64);
+        }
+        sender.finish().expect("finish batch");
+    });
+
+    let outputs: Outputs = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let received = {
+        let outputs = outputs.clone();
+        Ymodem::receive_batch(tx, move |info| {
+            outputs.lock().unwrap().push((info.name.clone(), Vec::new()));
+            Ok(SharedWriter(outputs.clone()))
+        }).expect("receive batch")
+    };
+
+    tx_thread.join().expect("tx join okay");
+    assert_eq!(received, expected);
+
+    let outputs = outputs.lock().unwrap();
+    assert_eq!(outputs.len(), 3);
+    assert_eq!(outputs[0], ("first.bin".to_string(), first));
+    assert_eq!(outputs[1], ("empty".to_string(), vec![]));
+    assert_eq!(outputs[2], ("second.txt".to_string(), second));
+}
+
+/// Named outputs shared between a test and the writers it hands out.
+type Outputs = std::sync::Arc<std::sync::Mutex<Vec<(String, Vec<u8>)>>>;
+
+/// Appends everything written to the last output in a shared list.
+struct SharedWriter(Outputs);
+
+impl io::Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut outputs = self.0.lock().unwrap();
+        let last = outputs.len() - 1;
+        outputs[last].1.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_ymodem_requires_crc() {
+    let mut buffer = vec![NAK, 0, 0];
+    let e = Ymodem::new(Cursor::new(buffer.as_mut_slice()))
+        .send_file(&FileInfo::new("a", 1), &b"a"[..])
+        .expect_err("checksum mode");
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(&buffer[1..], &[CAN, CAN]);
+}
+
+#[test]
+fn test_ymodem_from_xmodem() {
+    let data = b"from a builder".to_vec();
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut sender = Ymodem::new(rx);
+        sender.send_file(&FileInfo::new("log.txt", data.len() as u64), &data[..])
+            .expect("send file");
+        sender.finish().expect("finish batch");
+    });
+
+    let xmodem = Xmodem::builder().max_retries(3).checksum(Checksum::Sum).build(tx).expect("build");
+    let mut receiver = Ymodem::from_xmodem(xmodem);
+    let info = receiver.next_file().expect("next file").expect("a file");
+    assert_eq!(info.name, "log.txt");
+
+    let mut received = Vec::new();
+    assert_eq!(receiver.receive_file(&mut received).expect("receive"), 14);
+    assert_eq!(&received[..], b"from a builder");
+    assert_eq!(receiver.next_file().expect("end of batch"), None);
+    tx_thread.join().expect("tx join okay");
+}
+
+#[test]
+fn test_ymodem_late_sender() {
+    use std::io::{Read, Write};
+
+    let (mut sender, receiver) = timeout_pipe();
+    let rx_thread = std::thread::spawn(move || {
+        let xmodem = Xmodem::builder()
+            .handshake_timeout(Duration::from_secs(5))
+            .clock(test_clock)
+            .build(receiver)
+            .expect("build");
+        let mut receiver = Ymodem::from_xmodem(xmodem);
+        receiver.next_file().expect("next file")
+    });
+
+    // The sender starts after more read timeouts than XMODEM waits for before
+    // falling back to checksum mode; YMODEM keeps asking for CRC mode.
+    let mut byte = [0u8; 1];
+    let mut requests = 0;
+    while requests < CRC_HANDSHAKE_ATTEMPTS + 3 {
+        if let Ok(1) = sender.read(&mut byte) {
+            assert_eq!(byte[0], CRC);
+            requests += 1;
+        }
+    }
+
+    let mut header = [0u8; 128];
+    header[..4].copy_from_slice(b"a\x0012");
+    let crc = crc::crc16(&header);
+    sender.write_all(&[SOH, 0, 255]).expect("header");
+    sender.write_all(&header).expect("packet");
+    sender.write_all(&[(crc >> 8) as u8, crc as u8]).expect("crc");
+
+    let info = rx_thread.join().expect("rx join okay");
+    assert_eq!(info, Some(FileInfo::new("a", 12)));
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc::crc32(b""), 0);
+    assert_eq!(crc::crc32(b"123456789"), 0xCBF43926);
+    assert_eq!(crc::crc32_update(crc::crc32_update(!0, b"1234"), b"56789"),
+               !0xCBF43926);
+}
+
+/// Sends `files` with a ZMODEM sender built by `sender` and receives them,
+/// resuming each from the offset returned by `offset`. Returns the amounts
+/// reported sent and the outputs received.
+fn zmodem_transfer<T, S, O>(files: Vec<(FileInfo, Vec<u8>)>, sender: S, offset: O)
+    -> (Vec<u64>, Vec<(String, Vec<u8>)>)
+    where T: io::Read + io::Write + Send + 'static,
+          S: FnOnce(Pipe) -> Zmodem<T> + Send + 'static,
+          O: Fn(&FileInfo) -> u64
+{
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut sender = sender(rx);
+        let sent: Vec<u64> = files.iter().map(|&(ref info, ref data)| {
+            sender.send_file(info, Cursor::new(data)).expect("send file")
+        }).collect();
+        sender.finish().expect("finish");
+        sent
+    });
+
+    let outputs: Outputs = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let received = {
+        let outputs = outputs.clone();
+        Zmodem::receive_batch(tx, move |info| {
+            outputs.lock().unwrap().push((info.name.clone(), Vec::new()));
+            Ok((SharedWriter(outputs.clone()), offset(info)))
+        }).expect("receive batch")
+    };
+
+    let sent = tx_thread.join().expect("tx join okay");
+    assert_eq!(received.len(), sent.len());
+    let outputs = outputs.lock().unwrap().clone();
+    (sent, outputs)
+}
+
+fn zmodem_pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+}
+
+#[test]
+fn test_zmodem_batch() {
+    let first = zmodem_pattern(5000);
+    let second = b"short file".to_vec();
+    let files = vec![
+        (FileInfo::new("first.bin", first.len() as u64), first.clone()),
+        (FileInfo::new("empty", 0), vec![]),
+        (FileInfo::new("second.txt", second.len() as u64), second.clone()),
+    ];
+
+    let (sent, outputs) = zmodem_transfer(files, Zmodem::new, |_| 0);
+    assert_eq!(sent, vec![5000, 0, 10]);
+    assert_eq!(outputs, vec![("first.bin".to_string(), first),
+                             ("empty".to_string(), vec![]),
+                             ("second.txt".to_string(), second)]);
+}
+
+#[test]
+fn test_zmodem_escaping() {
+    let data: Vec<u8> = (0..1024usize).map(|i| i as u8).collect();
+    let (tx, rx) = pipe();
+    let expected = data.clone();
+    let tx_thread = std::thread::spawn(move || {
+        let mut rx = rx;
+        {
+            let mut sender = Zmodem::new(&mut rx);
+            sender.send_file(&FileInfo::new("bytes", 1024), Cursor::new(&data))
+                .expect("send file");
+            sender.finish().expect("finish");
+        }
+        rx.2
+    });
+
+    let mut received = Vec::new();
+    let mut receiver = Zmodem::new(tx);
+    receiver.next_file().expect("next file").expect("file");
+    assert_eq!(receiver.receive_file(&mut received, 0).expect("receive"), 1024);
+    assert_eq!(receiver.next_file().expect("end"), None);
+    assert_eq!(received, expected);
+
+    // Flow control characters never appear unescaped in the data sent; only
+    // hexadecimal headers end with XON.
+    let written = tx_thread.join().expect("tx join okay");
+    for &byte in &[0x10, 0x13, 0x90, 0x91, 0x93] {
+        assert!(!written.contains(&byte), "{:#x} sent unescaped", byte);
+    }
+}
+
+#[test]
+fn test_zmodem_window() {
+    let data = zmodem_pattern(10000);
+    let files = vec![(FileInfo::new("windowed", 10000), data.clone())];
+    let (sent, outputs) = zmodem_transfer(files, |pipe| {
+        Zmodem::new(pipe).with_window(2048)
+    }, |_| 0);
+
+    assert_eq!(sent, vec![10000]);
+    assert_eq!(outputs, vec![("windowed".to_string(), data)]);
+}
+
+#[test]
+fn test_zmodem_resume() {
+    let data = zmodem_pattern(10000);
+    let files = vec![(FileInfo::new("partial", 10000), data.clone())];
+    let (sent, outputs) = zmodem_transfer(files, Zmodem::new, |_| 4000);
+
+    assert_eq!(sent, vec![6000]);
+    assert_eq!(outputs, vec![("partial".to_string(), data[4000..].to_vec())]);
+}
+
+/// A stream that flips a bit of the byte written at position `at`.
+struct Corrupting<T> {
+    pipe: T,
+    written: usize,
+    at: usize,
+}
+
+impl<T: io::Read> io::Read for Corrupting<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pipe.read(buf)
+    }
+}
+
+impl<T: io::Write> io::Write for Corrupting<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut buf = buf.to_vec();
+        if self.at >= self.written && self.at < self.written + buf.len() {
+            buf[self.at - self.written] ^= 0x04;
+        }
+
+        self.written += buf.len();
+        self.pipe.write_all(&buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_zmodem_corruption_recovery() {
+    for &window in &[0, 2048] {
+        let data = zmodem_pattern(8000);
+        let files = vec![(FileInfo::new("noisy", 8000), data.clone())];
+        let (sent, outputs) = zmodem_transfer(files, move |pipe| {
+            let corrupting = Corrupting { pipe, written: 0, at: 3000 };
+            Zmodem::new(corrupting).with_window(window)
+        }, |_| 0);
+
+        assert_eq!(sent, vec![8000]);
+        assert_eq!(outputs, vec![("noisy".to_string(), data)]);
+    }
+}
+
+/// A stream that drops the `drop`th write, counting from 0, of a data
+/// subpacket: of more than `MIN_SUBPACKET` bytes.
+struct Dropping<T> {
+    inner: T,
+    writes: usize,
+    drop: usize,
+}
+
+const MIN_SUBPACKET: usize = 64;
+
+impl<T: io::Read> io::Read for Dropping<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: io::Write> io::Write for Dropping<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > MIN_SUBPACKET {
+            self.writes += 1;
+            if self.writes - 1 == self.drop {
+                return Ok(buf.len());
+            }
+        }
+
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_zmodem_lost_last_subpacket() {
+    // 3000 bytes are sent in subpackets of 1024, 1024 and 952 bytes; the
+    // last is lost, so the receiver sees the end of the file early. The
+    // sender keeps repeating its `ZEOF` until the receiver asks for the rest.
+    let data = zmodem_pattern(3000);
+    let expected = data.clone();
+    let (tx, rx) = timeout_pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut sender = Zmodem::new(Dropping { inner: rx, writes: 0, drop: 2 });
+        let sent = sender.send_file(&FileInfo::new("truncated", 3000), Cursor::new(&data))
+            .expect("send file");
+        sender.finish().expect("finish");
+        sent
+    });
+
+    let events = Events::default();
+    let record = recorder(&events);
+    let mut received = Vec::new();
+    let mut receiver = Zmodem::new_with_progress(tx, record);
+    receiver.next_file().expect("next file").expect("file");
+    assert_eq!(receiver.receive_file(&mut received, 0).expect("receive"), 3000);
+    assert_eq!(receiver.next_file().expect("end"), None);
+    assert_eq!(received, expected);
+    assert_eq!(tx_thread.join().expect("tx join okay"), 3000);
+
+    // The early `ZEOF` is answered at once, not after a timeout.
+    let retries: Vec<RetryReason> = events.borrow().iter().filter_map(|event| match *event {
+        Progress::Retry { reason, .. } => Some(reason),
+        _ => None,
+    }).collect();
+    assert_eq!(retries, vec![RetryReason::Rejected]);
+}
+
+/// A stream that drops the `drop`th hex `ZRPOS` header written, counting
+/// from 0.
+struct DroppingZrpos<T> {
+    inner: T,
+    headers: usize,
+    drop: usize,
+}
+
+impl<T: io::Read> io::Read for DroppingZrpos<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: io::Write> io::Write for DroppingZrpos<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.starts_with(b"**\x18B09") {
+            self.headers += 1;
+            if self.headers - 1 == self.drop {
+                return Ok(buf.len());
+            }
+        }
+
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_zmodem_lost_zrpos() {
+    // The receiver's request to resend the corrupted data is lost, so the
+    // sender ends the file; its `ZEOF` is answered with the request again.
+    let data = zmodem_pattern(8000);
+    let expected = data.clone();
+    let (tx, rx) = timeout_pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut sender = Zmodem::new(Corrupting { pipe: rx, written: 0, at: 3000 });
+        let sent = sender.send_file(&FileInfo::new("noisy", 8000), Cursor::new(&data))
+            .expect("send file");
+        sender.finish().expect("finish");
+        sent
+    });
+
+    let events = Events::default();
+    let record = recorder(&events);
+    let mut received = Vec::new();
+    let stream = DroppingZrpos { inner: tx, headers: 0, drop: 1 };
+    let mut receiver = Zmodem::new_with_progress(stream, record);
+    receiver.next_file().expect("next file").expect("file");
+    assert_eq!(receiver.receive_file(&mut received, 0).expect("receive"), 8000);
+    assert_eq!(receiver.next_file().expect("end"), None);
+    assert_eq!(received, expected);
+    assert_eq!(tx_thread.join().expect("tx join okay"), 8000);
+
+    // The `ZEOF` is answered at once, not after a timeout.
+    let retries: Vec<RetryReason> = events.borrow().iter().filter_map(|event| match *event {
+        Progress::Retry { reason, .. } => Some(reason),
+        _ => None,
+    }).collect();
+    assert_eq!(retries, vec![RetryReason::Corrupted, RetryReason::Rejected]);
+}
+
+#[test]
+fn test_zmodem_streaming_resumes_early() {
+    // Without a window the sender still notices the receiver's `ZRPOS` well
+    // before the end of the file.
+    let data = zmodem_pattern(64 * 1024);
+    let expected = data.clone();
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let events = Events::default();
+        let record = recorder(&events);
+        let stream = Corrupting { pipe: rx, written: 0, at: 3000 };
+        let mut sender = Zmodem::new_with_progress(stream, record);
+        sender.send_file(&FileInfo::new("long", data.len() as u64), Cursor::new(&data))
+            .expect("send file");
+        sender.finish().expect("finish");
+        let events = events.borrow().clone();
+        events
+    });
+
+    let mut received = Vec::new();
+    let mut receiver = Zmodem::new(tx);
+    receiver.next_file().expect("next file").expect("file");
+    receiver.receive_file(&mut received, 0).expect("receive");
+    assert_eq!(receiver.next_file().expect("end"), None);
+    assert_eq!(received, expected);
+
+    let events = tx_thread.join().expect("tx join okay");
+    let retry = events.iter().position(|event| match *event {
+        Progress::Retry { .. } => true,
+        _ => false,
+    }).expect("a retry");
+    let streamed = events[..retry].iter().filter_map(|event| match *event {
+        Progress::Packet { bytes, .. } => Some(bytes),
+        _ => None,
+    }).max().expect("packets before the retry");
+    assert!(streamed <= 16 * 1024, "streamed {} bytes before resuming", streamed);
+}
+
+#[test]
+fn test_zmodem_skip() {
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut sender = Zmodem::new(rx);
+        let sent = sender.send_file(&FileInfo::new("unwanted", 3), Cursor::new(b"abc"))
+            .expect("send file");
+        sender.finish().expect("finish");
+        sent
+    });
+
+    let mut receiver = Zmodem::new(tx);
+    let info = receiver.next_file().expect("next file").expect("file");
+    assert_eq!(info, FileInfo::new("unwanted", 3));
+    receiver.skip_file().expect("skip");
+    assert_eq!(receiver.next_file().expect("end"), None);
+    assert_eq!(tx_thread.join().expect("tx join okay"), 0);
+}
+
+#[test]
+fn test_zmodem_cancel() {
+    let (mut tx, rx) = pipe();
+    io::Write::write_all(&mut tx, &[CAN; 8]).expect("write cancel");
+    let e = Zmodem::new(rx).next_file().expect_err("cancelled");
+    assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+}
+
+/// A clock for the tests' time limits.
+fn test_clock() -> Duration {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH).expect("clock")
+}
+
+/// Returns everything written to `pipe` by its peer, which must be dropped.
+fn drain(pipe: Pipe) -> Vec<u8> {
+    let Pipe(_, receiver, _) = pipe;
+    receiver.iter().collect()
+}
+
+#[test]
+fn test_nak_resends_packet() {
+    use std::io::Write;
+
+    let (tx, mut rx) = pipe();
+    let data = [7u8; 128];
+
+    // Start, reject the packet once, accept it, then the EOT sequence.
+    rx.write_all(&[NAK, NAK, ACK, NAK, ACK]).expect("responses");
+    assert_eq!(Xmodem::transmit(&data[..], tx).expect("transmit"), 128);
+
+    let sent = drain(rx);
+    let mut packet = vec![SOH, 1, 254];
+    packet.extend_from_slice(&data);
+    packet.push(sum(&data));
+    assert_eq!(&sent[..132], &packet[..]);
+    assert_eq!(&sent[132..264], &packet[..]);
+    assert_eq!(&sent[264..], &[EOT, EOT]);
+}
+
+#[test]
+fn test_max_retries() {
+    use std::io::Write;
+
+    for &abort_with_can in &[true, false] {
+        let (tx, mut rx) = pipe();
+        rx.write_all(&[NAK, NAK, NAK]).expect("responses");
+
+        let e = Xmodem::builder().max_retries(2).abort_with_can(abort_with_can)
+            .build(tx)
+            .expect("build")
+            .write_data(&[1u8; 128][..])
+            .expect_err("too many retries");
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+
+        let sent = drain(rx);
+        assert_eq!(sent.len(), if abort_with_can { 2 * 132 + 2 } else { 2 * 132 });
+        assert_eq!(sent.ends_with(&[CAN, CAN]), abort_with_can);
+    }
+}
+
+#[test]
+fn test_handshake_timeout() {
+    use std::io::Read;
+
+    let (mut sender, receiver) = timeout_pipe();
+    let e = Xmodem::builder()
+        .checksum(Checksum::Sum)
+        .handshake_timeout(Duration::from_millis(200))
+        .clock(test_clock)
+        .build(receiver)
+        .expect("build")
+        .read_packet(&mut [0u8; 128])
+        .expect_err("handshake timeout");
+    assert_eq!(e.kind(), io::ErrorKind::NotConnected);
+
+    let mut sent = Vec::new();
+    let mut byte = [0u8; 1];
+    while let Ok(1) = sender.read(&mut byte) {
+        sent.push(byte[0]);
+    }
+
+    // A NAK is sent for each read timeout until the handshake times out.
+    assert!(sent.len() >= 4 + 2, "sent {:?}", sent);
+    assert!(sent[..sent.len() - 2].iter().all(|&b| b == NAK));
+    assert!(sent.ends_with(&[CAN, CAN]));
+}
+
+#[test]
+fn test_handshake_without_timeout() {
+    let (_sender, receiver) = timeout_pipe();
+    let e = Xmodem::new(receiver).read_packet(&mut [0u8; 128])
+        .expect_err("no sender");
+    assert_eq!(e.kind(), io::ErrorKind::NotConnected);
+}
+
+#[test]
+fn test_deadline() {
+    use std::io::Write;
+
+    let (tx, mut rx) = pipe();
+    rx.write_all(&[NAK]).expect("start");
+
+    let e = Xmodem::builder()
+        .deadline(Duration::from_millis(0))
+        .clock(test_clock)
+        .build(tx)
+        .expect("build")
+        .write_packet(&[0u8; 128])
+        .expect_err("deadline");
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(drain(rx), vec![CAN, CAN]);
+}
+
+#[test]
+fn test_limits_require_clock() {
+    let (tx, _rx) = pipe();
+    let e = Xmodem::builder()
+        .deadline(Duration::from_secs(1))
+        .build(tx)
+        .err()
+        .expect("no clock");
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+
+    let (tx, _rx) = pipe();
+    let e = Xmodem::builder()
+        .handshake_timeout(Duration::from_secs(1))
+        .build(tx)
+        .err()
+        .expect("no clock");
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_cancel_token() {
+    use std::io::Write;
+
+    let (tx, mut rx) = pipe();
+    rx.write_all(&[NAK, ACK]).expect("responses");
+
+    let token = CancelToken::new();
+    let mut xmodem = Xmodem::builder().cancel_token(token.clone()).build(tx)
+        .expect("build");
+    xmodem.write_packet(&[0u8; 128]).expect("first packet");
+
+    token.cancel();
+    let e = xmodem.write_packet(&[0u8; 128]).expect_err("cancelled");
+    assert_eq!(e.kind(), io::ErrorKind::Other);
+    drop(xmodem);
+
+    let sent = drain(rx);
+    assert_eq!(sent.len(), 132 + 2);
+    assert!(sent.ends_with(&[CAN, CAN]));
+}
+
+#[test]
+fn test_timeout_requests_resend() {
+    use std::io::{Read, Write};
+
+    let (mut sender, receiver) = timeout_pipe();
+    let rx_thread = std::thread::spawn(move || {
+        let mut xmodem = Xmodem::new(receiver);
+        let mut packet = [0u8; 128];
+        let e = xmodem.read_packet(&mut packet).expect_err("truncated packet");
+        assert_eq!(e.kind(), io::ErrorKind::Interrupted);
+        xmodem.read_packet(&mut packet).expect("resent packet");
+        packet
+    });
+
+    let wait_for_nak = |sender: &mut TimeoutPipe| {
+        let mut byte = [0u8; 1];
+        loop {
+            match sender.read(&mut byte) {
+                Ok(1) if byte[0] == NAK => return,
+                _ => continue,
+            }
+        }
+    };
+
+    // The first attempt stops partway; the receiver times out and NAKs.
+    let packet = [9u8; 128];
+    wait_for_nak(&mut sender);
+    sender.write_all(&[SOH, 1, 254]).expect("header");
+    sender.write_all(&packet[..10]).expect("partial packet");
+
+    wait_for_nak(&mut sender);
+    sender.write_all(&[SOH, 1, 254]).expect("header");
+    sender.write_all(&packet).expect("packet");
+    sender.write_all(&[sum(&packet)]).expect("checksum");
+
+    let received = rx_thread.join().expect("rx join okay");
+    assert_eq!(&received[..], &packet[..]);
+}
+
+/// Progress events recorded by a callback.
+type Events = std::rc::Rc<std::cell::RefCell<Vec<Progress>>>;
+
+/// Returns a callback that records every event into `events`.
+fn recorder(events: &Events) -> impl Fn(Progress) {
+    let events = events.clone();
+    move |progress| events.borrow_mut().push(progress)
+}
+
+#[test]
+fn test_progress_bytes_and_retries() {
+    use std::io::Write;
+
+    let (tx, mut rx) = pipe();
+    // Start, accept the first packet, reject the second once, then the EOT
+    // sequence.
+    rx.write_all(&[NAK, ACK, NAK, ACK, NAK, ACK]).expect("responses");
+
+    let events = Events::default();
+    let record = recorder(&events);
+    let sent = Xmodem::builder().total(256).progress(record).build(tx)
+        .expect("build")
+        .write_data(&[5u8; 256][..])
+        .expect("transmit");
+    assert_eq!(sent, 256);
+
+    assert_eq!(*events.borrow(), vec![
+        Progress::Waiting,
+        Progress::Negotiated(Checksum::Sum),
+        Progress::Packet { number: 1, bytes: 128, total: Some(256) },
+        Progress::Retry { reason: RetryReason::Rejected, retries: 1, naks: 1 },
+        Progress::Packet { number: 2, bytes: 256, total: Some(256) },
+    ]);
+}
+
+#[test]
+fn test_progress_borrows_local_state() {
+    use std::io::Write;
+
+    let (tx, mut rx) = pipe();
+    rx.write_all(&[NAK, ACK, ACK, NAK, ACK]).expect("responses");
+
+    let mut packets = 0;
+    let sent = Xmodem::transmit_with_progress(&[5u8; 256][..], tx, |progress| {
+        if let Progress::Packet { .. } = progress {
+            packets += 1;
+        }
+    }).expect("transmit");
+    assert_eq!(sent, 256);
+    assert_eq!(packets, 2);
+}
+
+#[test]
+fn test_progress_reports_corruption() {
+    use std::io::Write;
+
+    let (mut tx, rx) = pipe();
+    let packet = [1u8; 128];
+    tx.write_all(&[SOH, 1, 254]).expect("header");
+    tx.write_all(&packet).expect("packet");
+    tx.write_all(&[sum(&packet).wrapping_add(1)]).expect("bad checksum");
+
+    let events = Events::default();
+    let record = recorder(&events);
+    let mut xmodem = Xmodem::new_with_progress(rx, record)
+        .with_checksum(Checksum::Sum);
+    let e = xmodem.read_packet(&mut [0u8; 128]).expect_err("bad checksum");
+    assert_eq!(e.kind(), io::ErrorKind::Interrupted);
+    assert_eq!(events.borrow().last(),
+               Some(&Progress::Retry { reason: RetryReason::Corrupted, retries: 1, naks: 1 }));
+}
+
+#[test]
+fn test_zmodem_progress() {
+    let data = zmodem_pattern(8000);
+    let expected = data.clone();
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let corrupting = Corrupting { pipe: rx, written: 0, at: 3000 };
+        let mut sender = Zmodem::new(corrupting);
+        sender.send_file(&FileInfo::new("noisy", 8000), Cursor::new(&data))
+            .expect("send file");
+        sender.finish().expect("finish");
+    });
+
+    let events = Events::default();
+    let record = recorder(&events);
+    let mut receiver = Zmodem::new_with_progress(tx, record);
+    let mut received = Vec::new();
+    receiver.next_file().expect("next file").expect("file");
+    receiver.receive_file(&mut received, 0).expect("receive");
+    assert_eq!(receiver.next_file().expect("end"), None);
+    tx_thread.join().expect("tx join okay");
+    assert_eq!(received, expected);
+
+    let events = events.borrow();
+    assert!(events.iter().any(|event| match *event {
+        Progress::Retry { naks, .. } => naks >= 1,
+        _ => false,
+    }));
+
+    match events.iter().rev().find(|event| match **event {
+        Progress::Packet { .. } => true,
+        _ => false,
+    }) {
+        Some(&Progress::Packet { bytes, total, .. }) => {
+            assert_eq!((bytes, total), (8000, Some(8000)));
+        }
+        other => panic!("unexpected last packet event {:?}", other),
+    }
+}
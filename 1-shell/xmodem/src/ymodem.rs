@@ -0,0 +1,256 @@
+use std::io;
+
+use progress::{self, Progress, ProgressFn};
+use {Xmodem, Checksum, BlockSize, CAN};
+
+/// The name and attributes of a file sent in a YMODEM batch.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FileInfo {
+    /// The file's name. It must not be empty or contain `NUL` bytes.
+    pub name: String,
+    /// The file's length in bytes, if known. A receiver truncates the padding
+    /// of the last packet to this length.
+    pub size: Option<u64>,
+    /// The file's modification time in seconds since the Unix epoch, if known.
+    pub modified: Option<u64>,
+    /// The file's Unix mode bits, if known.
+    pub mode: Option<u32>,
+}
+
+impl FileInfo {
+    /// Returns the information for a file named `name` of `size` bytes.
+    pub fn new<S: Into<String>>(name: S, size: u64) -> FileInfo {
+        FileInfo { name: name.into(), size: Some(size), ..FileInfo::default() }
+    }
+
+    /// Encodes this information as the data of a YMODEM header packet: the
+    /// name, a `NUL`, and the known attributes separated by spaces: the size
+    /// in decimal, the modification time and the mode in octal. An attribute
+    /// is only omitted if it and all following attributes are unknown.
+    pub(crate) fn encode(&self) -> io::Result<Vec<u8>> {
+        if self.name.is_empty() || self.name.contains('\0') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "Invalid YMODEM file name"));
+        }
+
+        let mut attributes = Vec::new();
+        if let Some(mode) = self.mode {
+            attributes.push(format!("{:o}", mode));
+        }
+        if self.modified.is_some() || !attributes.is_empty() {
+            attributes.push(format!("{:o}", self.modified.unwrap_or(0)));
+        }
+        if self.size.is_some() || !attributes.is_empty() {
+            attributes.push(format!("{}", self.size.unwrap_or(0)));
+        }
+        attributes.reverse();
+
+        let mut header = self.name.clone().into_bytes();
+        header.push(0);
+        header.extend_from_slice(attributes.join(" ").as_bytes());
+        if header.len() > BlockSize::OneK.bytes() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "YMODEM header too long"));
+        }
+
+        Ok(header)
+    }
+
+    /// Decodes the data of a YMODEM header packet. Returns `None` for the empty
+    /// header that ends a batch. Attributes that can't be parsed are treated
+    /// as unknown.
+    pub(crate) fn decode(packet: &[u8]) -> io::Result<Option<FileInfo>> {
+        let name_len = packet.iter().position(|&b| b == 0).unwrap_or(packet.len());
+        if name_len == 0 {
+            return Ok(None);
+        }
+
+        let name = String::from_utf8(packet[..name_len].to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData,
+                                        "YMODEM file name is not UTF-8"))?;
+
+        let rest = &packet[(name_len + 1).min(packet.len())..];
+        let rest = &rest[..rest.iter().position(|&b| b == 0).unwrap_or(rest.len())];
+        let rest = String::from_utf8_lossy(rest);
+        let mut fields = rest.split_whitespace();
+
+        let size = fields.next().and_then(|s| s.parse().ok());
+        let modified = fields.next().and_then(|s| u64::from_str_radix(s, 8).ok());
+        let mode = fields.next().and_then(|s| u32::from_str_radix(s, 8).ok());
+        Ok(Some(FileInfo { name, size, modified, mode }))
+    }
+}
+
+/// Implementation of the YMODEM batch protocol on top of `Xmodem` packets.
+///
+/// Each file is preceded by a header packet, numbered 0, carrying its
+/// `FileInfo`, and its data is sent as an XMODEM transfer in CRC mode. A batch
+/// ends with a header holding an empty name.
+pub struct Ymodem<T, F = ProgressFn> {
+    xmodem: Xmodem<T, F>,
+    current: Option<FileInfo>,
+}
+
+impl Ymodem<()> {
+    /// Receives every file of a YMODEM batch from `from`. For each file,
+    /// `open` is called with the file's information and returns the writer
+    /// that the file's data, truncated to its declared size, is written into.
+    ///
+    /// Returns the information of every file received, in order.
+    pub fn receive_batch<R, F, W>(from: R, mut open: F) -> io::Result<Vec<FileInfo>>
+        where R: io::Read + io::Write, F: FnMut(&FileInfo) -> io::Result<W>,
+              W: io::Write
+    {
+        let mut receiver = Ymodem::new(from);
+        let mut files = Vec::new();
+        while let Some(info) = receiver.next_file()? {
+            let into = open(&info)?;
+            receiver.receive_file(into)?;
+            files.push(info);
+        }
+
+        Ok(files)
+    }
+}
+
+impl<T: io::Read + io::Write> Ymodem<T> {
+    /// Returns a new `Ymodem` instance with the internal reader/writer set to
+    /// `inner`. The returned instance can be used for both receiving and
+    /// sending. Senders use 1K blocks.
+    pub fn new(inner: T) -> Self {
+        Ymodem::new_with_progress(inner, progress::noop as ProgressFn)
+    }
+}
+
+impl<T: io::Read + io::Write, F: FnMut(Progress)> Ymodem<T, F> {
+    /// Returns a new `Ymodem` instance like `new()`. The function `f` is used
+    /// as a callback to indicate progress throughout each file's transfer.
+    /// See the [`Progress`] enum for more information.
+    pub fn new_with_progress(inner: T, f: F) -> Self {
+        let xmodem = Xmodem::new_with_progress(inner, f)
+            .with_block_size(BlockSize::OneK);
+        Ymodem::from_xmodem(xmodem)
+    }
+
+    /// Returns a new `Ymodem` instance exchanging packets with `xmodem`, e.g.,
+    /// one built with [`Xmodem::builder()`] to set the transfer's limits and
+    /// progress callback. Receivers always ask for CRC-16, until the
+    /// handshake times out, and never fall back to checksum mode; senders use
+    /// `xmodem`'s block size.
+    pub fn from_xmodem(mut xmodem: Xmodem<T, F>) -> Self {
+        xmodem.checksum_fallback = false;
+        Ymodem { xmodem: xmodem.with_checksum(Checksum::Crc16), current: None }
+    }
+
+    /// Sets the largest packets a sender may use. Defaults to
+    /// `BlockSize::OneK`.
+    pub fn with_block_size(mut self, block_size: BlockSize) -> Self {
+        self.xmodem = self.xmodem.with_block_size(block_size);
+        self
+    }
+
+    /// Resets the packet layer so that the next packet exchanged is numbered
+    /// `packet`, preceded by a new handshake in CRC mode, and starts counting
+    /// progress towards `total` bytes.
+    fn restart(&mut self, packet: u8, total: Option<u64>) {
+        self.xmodem.started = false;
+        self.xmodem.checksum = Checksum::Crc16;
+        self.xmodem.packet = packet;
+        self.xmodem.bytes = 0;
+        self.xmodem.total = total;
+    }
+
+    /// Sends a header packet with data `header`, padded with zeroes.
+    fn write_header(&mut self, header: &[u8]) -> io::Result<()> {
+        self.restart(0, None);
+        self.xmodem.start_transmit()?;
+        if self.xmodem.checksum != Checksum::Crc16 {
+            self.xmodem.write_byte(CAN)?;
+            self.xmodem.write_byte(CAN)?;
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "YMODEM requires CRC mode"));
+        }
+
+        let mut packet = [0u8; 1024];
+        let len = if header.len() <= 128 { 128 } else { 1024 };
+        packet[..header.len()].copy_from_slice(header);
+        self.xmodem.write_packet_retrying(&packet[..len])?;
+        Ok(())
+    }
+
+    /// Sends the file described by `info` with contents `data`. Returns the
+    /// number of bytes read from `data`, which should equal `info.size`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `info` can't be encoded, of
+    /// `InvalidData` if the receiver doesn't ask for CRC mode, or any error
+    /// of `Xmodem::write_packet()`.
+    pub fn send_file<R: io::Read>(&mut self, info: &FileInfo, data: R)
+        -> io::Result<u64>
+    {
+        let header = info.encode()?;
+        self.write_header(&header)?;
+
+        self.restart(1, info.size);
+        Ok(self.xmodem.write_data(data)? as u64)
+    }
+
+    /// Ends the batch by sending an empty header.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.write_header(&[])
+    }
+
+    /// Waits for the next file of the batch and returns its information, or
+    /// `None` once the sender has ended the batch. The file's data must be
+    /// received with `receive_file()` before calling this method again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the header's file name is not
+    /// UTF-8, or any error of `Xmodem::read_packet()`.
+    pub fn next_file(&mut self) -> io::Result<Option<FileInfo>> {
+        self.restart(0, None);
+
+        let mut packet = [0u8; 1024];
+        let mut len = None;
+        for _ in 0..self.xmodem.config.max_retries {
+            match self.xmodem.read_packet(&mut packet) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => {
+                    len = Some(result?);
+                    break;
+                }
+            }
+        }
+
+        let len = match len {
+            Some(len) => len,
+            None => return Err(self.xmodem.abort(io::ErrorKind::BrokenPipe,
+                                                 "Too many retries")),
+        };
+        let info = FileInfo::decode(&packet[..len])?;
+        self.current = info.clone();
+        Ok(info)
+    }
+
+    /// Receives the data of the file last returned by `next_file()` into
+    /// `into`, truncated to the file's size if it was declared. Returns the
+    /// number of bytes written to `into`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if there is no current file, or any
+    /// error of `Xmodem::read_packet()` or from writing to `into`.
+    pub fn receive_file<W: io::Write>(&mut self, into: W) -> io::Result<u64> {
+        let info = self.current.take().ok_or(
+            io::Error::new(io::ErrorKind::InvalidInput, "No file to receive"))?;
+
+        self.restart(1, info.size);
+        let received = self.xmodem.read_data(into, info.size)? as u64;
+        Ok(match info.size {
+            Some(size) => ::std::cmp::min(size, received),
+            None => received,
+        })
+    }
+}
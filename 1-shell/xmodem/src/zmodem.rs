@@ -0,0 +1,1014 @@
+use std::io::{self, SeekFrom};
+
+use crc::{crc16, crc16_update, crc32, crc32_update};
+use progress::{self, Progress, ProgressFn, RetryReason, Retries};
+use read_ext::ReadExt;
+use ymodem::FileInfo;
+
+const ZPAD: u8 = b'*';
+const ZDLE: u8 = 0x18;
+const ZBIN: u8 = b'A';
+const ZHEX: u8 = b'B';
+const ZBIN32: u8 = b'C';
+
+// Frame types.
+const ZRQINIT: u8 = 0;
+const ZRINIT: u8 = 1;
+const ZSINIT: u8 = 2;
+const ZACK: u8 = 3;
+const ZFILE: u8 = 4;
+const ZSKIP: u8 = 5;
+const ZNAK: u8 = 6;
+const ZABORT: u8 = 7;
+const ZFIN: u8 = 8;
+const ZRPOS: u8 = 9;
+const ZDATA: u8 = 10;
+const ZEOF: u8 = 11;
+const ZFERR: u8 = 12;
+const ZCAN: u8 = 16;
+
+// Data subpacket terminators.
+const ZCRCE: u8 = b'h';
+const ZCRCG: u8 = b'i';
+const ZCRCQ: u8 = b'j';
+const ZCRCW: u8 = b'k';
+const ZRUB0: u8 = b'l';
+const ZRUB1: u8 = b'm';
+
+// Receiver capabilities, in ZF0 of ZRINIT.
+const CANFDX: u8 = 0x01;
+const CANOVIO: u8 = 0x02;
+const CANFC32: u8 = 0x20;
+const ESCCTL: u8 = 0x40;
+
+/// ZF0 of ZFILE: binary transfer, no conversion.
+const ZCBIN: u8 = 1;
+
+const DLE: u8 = 0x10;
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+/// The largest data subpacket sent.
+const MAX_SUBPACKET: usize = 1024;
+
+/// The largest data subpacket accepted. Some senders use 8KiB subpackets.
+const MAX_RECEIVE_SUBPACKET: usize = 8192;
+
+/// The number of bytes a sender streams without a window before asking the
+/// receiver to acknowledge them, so that its `ZRPOS` is noticed early.
+const SYNC_INTERVAL: u32 = 8 * MAX_SUBPACKET as u32;
+
+/// The size of a buffer large enough for any encoded header or subpacket
+/// sent: every byte of the data, terminator and CRC may need escaping.
+const FRAME_BUF_LEN: usize = 2 * (MAX_SUBPACKET + 1 + 4) + 8;
+
+/// The number of consecutive timeouts, corrupted frames or negative replies
+/// tolerated before a transfer is abandoned.
+const MAX_ERRORS: usize = 10;
+
+/// A ZMODEM frame header: a frame type and four bytes of data, which hold
+/// either a little-endian file position or the flags `ZF3` to `ZF0`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Header {
+    kind: u8,
+    data: [u8; 4],
+}
+
+impl Header {
+    fn with_position(kind: u8, position: u32) -> Header {
+        let data = [position as u8, (position >> 8) as u8,
+                    (position >> 16) as u8, (position >> 24) as u8];
+        Header { kind, data }
+    }
+
+    fn with_flags(kind: u8, zf0: u8) -> Header {
+        Header { kind, data: [0, 0, 0, zf0] }
+    }
+
+    fn position(&self) -> u32 {
+        (0..4).fold(0, |acc, i| acc | (self.data[i] as u32) << (8 * i))
+    }
+
+    fn zf0(&self) -> u8 {
+        self.data[3]
+    }
+
+    fn bytes(&self) -> [u8; 5] {
+        [self.kind, self.data[0], self.data[1], self.data[2], self.data[3]]
+    }
+}
+
+/// Collects an encoded frame so that it is written with a single call.
+struct FrameBuf {
+    buf: [u8; FRAME_BUF_LEN],
+    len: usize,
+    escape_ctl: bool,
+}
+
+impl FrameBuf {
+    fn new(escape_ctl: bool) -> FrameBuf {
+        FrameBuf { buf: [0; FRAME_BUF_LEN], len: 0, escape_ctl }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.buf[self.len] = byte;
+        self.len += 1;
+    }
+
+    fn push_hex(&mut self, byte: u8) {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        self.push(DIGITS[(byte >> 4) as usize]);
+        self.push(DIGITS[(byte & 0xF) as usize]);
+    }
+
+    /// Pushes `byte`, escaping it with `ZDLE` if it could be mistaken for, or
+    /// swallowed as, a control character.
+    fn push_escaped(&mut self, byte: u8) {
+        let escape = match byte {
+            ZDLE | DLE | XON | XOFF | 0x90 | 0x91 | 0x93 => true,
+            _ => self.escape_ctl && byte & 0x60 == 0,
+        };
+
+        if escape {
+            self.push(ZDLE);
+            self.push(byte ^ 0x40);
+        } else {
+            self.push(byte);
+        }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// A byte read from an escaped stream.
+enum Escaped {
+    /// A data byte.
+    Byte(u8),
+    /// The end of a data subpacket and its terminator.
+    End(u8),
+}
+
+/// A receiver's reply to data that asked for one.
+enum Reply {
+    /// The data was received.
+    Acknowledged,
+    /// The data should be sent again from this position.
+    Resume(u64),
+    /// The receiver skips the rest of the file.
+    Skip,
+}
+
+/// Returns `true` if `e` is an error that a transfer can recover from by
+/// asking for data again: a timeout or corrupted data.
+fn is_recoverable(e: &io::Error) -> bool {
+    let kind = e.kind();
+    kind == io::ErrorKind::Interrupted || kind == io::ErrorKind::InvalidData
+        || kind == io::ErrorKind::TimedOut || kind == io::ErrorKind::WouldBlock
+}
+
+/// Returns why a frame must be sent again after the recoverable error `e`.
+fn retry_reason(e: &io::Error) -> RetryReason {
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => RetryReason::Timeout,
+        _ => RetryReason::Corrupted,
+    }
+}
+
+fn aborted() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "ZMODEM transfer aborted")
+}
+
+fn too_many_errors() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "Too many ZMODEM errors")
+}
+
+/// Implementation of the ZMODEM protocol.
+///
+/// Data is streamed in subpackets protected by a CRC-32, or a CRC-16 if the
+/// receiver doesn't support CRC-32, without waiting for acknowledgements. A
+/// sender may be limited to a window of unacknowledged bytes. When data is
+/// corrupted, the receiver asks the sender to resume from the last good
+/// position; a receiver may also ask to resume a file it already partially
+/// holds.
+///
+/// No memory is allocated except for the names of files.
+pub struct Zmodem<T, F = ProgressFn> {
+    inner: T,
+    started: bool,
+    /// Whether subpackets sent are protected by a CRC-32.
+    crc32: bool,
+    /// Whether the subpackets following the last header received are
+    /// protected by a CRC-32.
+    rx_crc32: bool,
+    escape_ctl: bool,
+    window: u32,
+    cancels: usize,
+    packet: u8,
+    current: Option<FileInfo>,
+    progress: F,
+    /// The size of the file being transferred, if known.
+    total: Option<u64>,
+    retries: Retries,
+}
+
+impl Zmodem<()> {
+    /// Receives every file sent in a ZMODEM session from `from`. For each
+    /// file, `open` is called with the file's information and returns the
+    /// writer the file's data is written into and the number of bytes of the
+    /// file already held, from which the transfer resumes.
+    ///
+    /// Returns the information of every file received, in order.
+    pub fn receive_batch<R, F, W>(from: R, mut open: F) -> io::Result<Vec<FileInfo>>
+        where R: io::Read + io::Write, F: FnMut(&FileInfo) -> io::Result<(W, u64)>,
+              W: io::Write
+    {
+        let mut receiver = Zmodem::new(from);
+        let mut files = Vec::new();
+        while let Some(info) = receiver.next_file()? {
+            let (into, offset) = open(&info)?;
+            receiver.receive_file(into, offset)?;
+            files.push(info);
+        }
+
+        Ok(files)
+    }
+}
+
+impl<T: io::Read + io::Write> Zmodem<T> {
+    /// Returns a new `Zmodem` instance with the internal reader/writer set to
+    /// `inner`. The returned instance can be used for both receiving and
+    /// sending.
+    pub fn new(inner: T) -> Self {
+        Zmodem::new_with_progress(inner, progress::noop as ProgressFn)
+    }
+}
+
+impl<T: io::Read + io::Write, F: FnMut(Progress)> Zmodem<T, F> {
+    /// Returns a new `Zmodem` instance like `new()`. The function `f` is used
+    /// as a callback to indicate progress throughout the session. See the
+    /// [`Progress`] enum for more information.
+    pub fn new_with_progress(inner: T, f: F) -> Self {
+        Zmodem { inner, started: false, crc32: false, rx_crc32: false,
+                 escape_ctl: false, window: 0, cancels: 0, packet: 0,
+                 current: None, progress: f, total: None,
+                 retries: Retries::default() }
+    }
+
+    /// Limits a sender to `bytes` of data before it waits for the receiver to
+    /// acknowledge it. A window of 0, the default, streams each file without
+    /// waiting. A receiver with a limited buffer imposes a window regardless.
+    pub fn with_window(mut self, bytes: u32) -> Self {
+        self.window = bytes;
+        self
+    }
+
+    /// Reports a subpacket that leaves the transfer at `position` in the file
+    /// to the progress callback.
+    fn report_packet(&mut self, position: u64) {
+        self.packet = self.packet.wrapping_add(1);
+        let event = Progress::Packet { number: self.packet, bytes: position, total: self.total };
+        (self.progress)(event);
+    }
+
+    /// Reports a retry because of `reason` to the progress callback.
+    fn report_retry(&mut self, reason: RetryReason, nak: bool) {
+        let event = self.retries.record(reason, nak);
+        (self.progress)(event);
+    }
+
+    /// Reads a single byte from the inner stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `ConnectionAborted` once five consecutive `CAN`
+    /// bytes have been read.
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut buf = [0u8; 1];
+        self.inner.read_exact(&mut buf)?;
+        if buf[0] == ZDLE {
+            self.cancels += 1;
+            if self.cancels >= 5 {
+                return Err(aborted());
+            }
+        } else {
+            self.cancels = 0;
+        }
+
+        Ok(buf[0])
+    }
+
+    /// Reads a possibly escaped byte, ignoring flow control characters.
+    fn read_escaped(&mut self) -> io::Result<Escaped> {
+        loop {
+            match self.read_byte()? {
+                XON | XOFF | 0x91 | 0x93 => continue,
+                ZDLE => break,
+                byte => return Ok(Escaped::Byte(byte)),
+            }
+        }
+
+        loop {
+            return match self.read_byte()? {
+                XON | XOFF | 0x91 | 0x93 => continue,
+                end @ ZCRCE | end @ ZCRCG | end @ ZCRCQ | end @ ZCRCW => {
+                    Ok(Escaped::End(end))
+                }
+                ZRUB0 => Ok(Escaped::Byte(0x7F)),
+                ZRUB1 => Ok(Escaped::Byte(0xFF)),
+                byte if byte & 0x60 == 0x40 => Ok(Escaped::Byte(byte ^ 0x40)),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData,
+                                        "Invalid ZDLE escape")),
+            };
+        }
+    }
+
+    /// Reads an escaped byte that must not end a subpacket.
+    fn read_escaped_byte(&mut self) -> io::Result<u8> {
+        match self.read_escaped()? {
+            Escaped::Byte(byte) => Ok(byte),
+            Escaped::End(_) => Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                  "Unexpected subpacket end")),
+        }
+    }
+
+    /// Reads a byte encoded as two hexadecimal digits.
+    fn read_hex_byte(&mut self) -> io::Result<u8> {
+        let mut value = 0;
+        for _ in 0..2 {
+            let digit = (self.read_byte()? as char).to_digit(16).ok_or(
+                io::Error::new(io::ErrorKind::InvalidData, "Invalid hex header"))?;
+            value = value << 4 | digit as u8;
+        }
+
+        Ok(value)
+    }
+
+    /// Skips input up to the next frame header and reads it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Interrupted` if the header's CRC is wrong and of
+    /// `InvalidData` if it is malformed.
+    fn read_header(&mut self) -> io::Result<Header> {
+        loop {
+            if self.read_byte()? != ZPAD {
+                continue;
+            }
+
+            let mut byte = self.read_byte()?;
+            while byte == ZPAD {
+                byte = self.read_byte()?;
+            }
+
+            if byte != ZDLE {
+                continue;
+            }
+
+            match self.read_byte()? {
+                ZHEX => return self.read_hex_header(),
+                ZBIN => return self.read_binary_header(false),
+                ZBIN32 => return self.read_binary_header(true),
+                _ => continue,
+            }
+        }
+    }
+
+    fn read_hex_header(&mut self) -> io::Result<Header> {
+        let mut bytes = [0u8; 7];
+        for byte in bytes.iter_mut() {
+            *byte = self.read_hex_byte()?;
+        }
+
+        // The header ends with CR LF, possibly with their high bits set.
+        if self.read_byte()? & 0x7F == b'\r' {
+            self.read_byte()?;
+        }
+
+        let crc = (bytes[5] as u16) << 8 | bytes[6] as u16;
+        if crc != crc16(&bytes[..5]) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted,
+                                      "Header CRC mismatch"));
+        }
+
+        Ok(Header { kind: bytes[0], data: [bytes[1], bytes[2], bytes[3], bytes[4]] })
+    }
+
+    fn read_binary_header(&mut self, long_crc: bool) -> io::Result<Header> {
+        let mut bytes = [0u8; 5];
+        for byte in bytes.iter_mut() {
+            *byte = self.read_escaped_byte()?;
+        }
+
+        let valid = if long_crc {
+            let mut crc = 0u32;
+            for i in 0..4 {
+                crc |= (self.read_escaped_byte()? as u32) << (8 * i);
+            }
+            crc == crc32(&bytes)
+        } else {
+            let high = self.read_escaped_byte()? as u16;
+            let low = self.read_escaped_byte()? as u16;
+            (high << 8 | low) == crc16(&bytes)
+        };
+
+        if !valid {
+            return Err(io::Error::new(io::ErrorKind::Interrupted,
+                                      "Header CRC mismatch"));
+        }
+
+        self.rx_crc32 = long_crc;
+        Ok(Header { kind: bytes[0], data: [bytes[1], bytes[2], bytes[3], bytes[4]] })
+    }
+
+    /// Reads a data subpacket into `buf`. Returns the length of the data and
+    /// the subpacket's terminator.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Interrupted` if the subpacket's CRC is wrong and
+    /// of `InvalidData` if it is malformed or longer than `buf`.
+    fn read_subpacket(&mut self, buf: &mut [u8]) -> io::Result<(usize, u8)> {
+        let mut len = 0;
+        let end = loop {
+            match self.read_escaped()? {
+                Escaped::Byte(byte) => {
+                    if len == buf.len() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                  "Subpacket too long"));
+                    }
+
+                    buf[len] = byte;
+                    len += 1;
+                }
+                Escaped::End(end) => break end,
+            }
+        };
+
+        let valid = if self.rx_crc32 {
+            let mut crc = 0u32;
+            for i in 0..4 {
+                crc |= (self.read_escaped_byte()? as u32) << (8 * i);
+            }
+            crc == !crc32_update(crc32_update(!0, &buf[..len]), &[end])
+        } else {
+            let high = self.read_escaped_byte()? as u16;
+            let low = self.read_escaped_byte()? as u16;
+            (high << 8 | low) == crc16_update(crc16(&buf[..len]), &[end])
+        };
+
+        if !valid {
+            return Err(io::Error::new(io::ErrorKind::Interrupted,
+                                      "Subpacket CRC mismatch"));
+        }
+
+        Ok((len, end))
+    }
+
+    /// Writes `header` in hexadecimal, as used for headers sent by receivers
+    /// and for headers that are not followed by data.
+    fn write_hex_header(&mut self, header: Header) -> io::Result<()> {
+        let bytes = header.bytes();
+        let crc = crc16(&bytes);
+
+        let mut frame = FrameBuf::new(false);
+        for &byte in &[ZPAD, ZPAD, ZDLE, ZHEX] {
+            frame.push(byte);
+        }
+        for &byte in bytes.iter().chain(&[(crc >> 8) as u8, crc as u8]) {
+            frame.push_hex(byte);
+        }
+
+        frame.push(b'\r');
+        frame.push(b'\n' | 0x80);
+        if header.kind != ZACK && header.kind != ZFIN {
+            frame.push(XON);
+        }
+
+        self.inner.write_all(frame.as_slice())?;
+        self.inner.flush()
+    }
+
+    /// Writes `header` in binary with the negotiated CRC.
+    fn write_binary_header(&mut self, header: Header) -> io::Result<()> {
+        let bytes = header.bytes();
+
+        let mut frame = FrameBuf::new(self.escape_ctl);
+        frame.push(ZPAD);
+        frame.push(ZDLE);
+        frame.push(if self.crc32 { ZBIN32 } else { ZBIN });
+        for &byte in bytes.iter() {
+            frame.push_escaped(byte);
+        }
+        self.push_crc(&mut frame, &bytes, None);
+
+        self.inner.write_all(frame.as_slice())
+    }
+
+    /// Writes a data subpacket holding `data` and ending with `end`.
+    fn write_subpacket(&mut self, data: &[u8], end: u8) -> io::Result<()> {
+        let mut frame = FrameBuf::new(self.escape_ctl);
+        for &byte in data {
+            frame.push_escaped(byte);
+        }
+        frame.push(ZDLE);
+        frame.push(end);
+        self.push_crc(&mut frame, data, Some(end));
+        if end == ZCRCW {
+            frame.push(XON);
+        }
+
+        self.inner.write_all(frame.as_slice())?;
+        if end != ZCRCG {
+            self.inner.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Pushes the negotiated CRC of `data`, followed by `end` if it is set.
+    fn push_crc(&self, frame: &mut FrameBuf, data: &[u8], end: Option<u8>) {
+        let end_byte = [end.unwrap_or(0)];
+        let end = if end.is_some() { &end_byte[..] } else { &[] };
+        if self.crc32 {
+            let crc = !crc32_update(crc32_update(!0, data), end);
+            for i in 0..4 {
+                frame.push_escaped((crc >> (8 * i)) as u8);
+            }
+        } else {
+            let crc = crc16_update(crc16(data), end);
+            frame.push_escaped((crc >> 8) as u8);
+            frame.push_escaped(crc as u8);
+        }
+    }
+
+    /// Reads the next header, tolerating up to `MAX_ERRORS` consecutive
+    /// recoverable errors. `on_error` is called after each such error.
+    fn read_header_retrying<E>(&mut self, mut on_error: E) -> io::Result<Header>
+        where E: FnMut(&mut Self) -> io::Result<()>
+    {
+        for _ in 0..MAX_ERRORS {
+            match self.read_header() {
+                Err(ref e) if is_recoverable(e) => on_error(self)?,
+                result => return result,
+            }
+        }
+
+        Err(too_many_errors())
+    }
+
+    /// Starts a sending session, if it hasn't started yet, by asking the
+    /// receiver for its capabilities.
+    fn start_send(&mut self) -> io::Result<()> {
+        if self.started {
+            return Ok(());
+        }
+
+        (self.progress)(Progress::Waiting);
+        self.inner.write_all(b"rz\r")?;
+        self.write_hex_header(Header::with_position(ZRQINIT, 0))?;
+
+        for _ in 0..MAX_ERRORS {
+            let header = self.read_header_retrying(|zmodem| {
+                zmodem.write_hex_header(Header::with_position(ZRQINIT, 0))
+            })?;
+
+            match header.kind {
+                ZRINIT => {
+                    let flags = header.zf0();
+                    self.crc32 = flags & CANFC32 != 0;
+                    self.escape_ctl = flags & ESCCTL != 0;
+
+                    let buffer = header.data[0] as u32 | (header.data[1] as u32) << 8;
+                    if buffer != 0 && (self.window == 0 || buffer < self.window) {
+                        self.window = buffer;
+                    }
+
+                    self.started = true;
+                    (self.progress)(Progress::Started);
+                    return Ok(());
+                }
+                ZCAN | ZABORT => return Err(aborted()),
+                _ => self.write_hex_header(Header::with_position(ZRQINIT, 0))?,
+            }
+        }
+
+        Err(too_many_errors())
+    }
+
+    /// Sends the file described by `info` with contents `data`. If the
+    /// receiver already holds part of the file, only the remainder is sent.
+    /// Returns the number of bytes of `data` that were sent, or 0 if the
+    /// receiver skipped the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `info` can't be encoded, of
+    /// `ConnectionAborted` if the receiver cancels the transfer, of
+    /// `BrokenPipe` if too many errors occur in a row, or any error from
+    /// reading or seeking `data` or from the inner stream.
+    pub fn send_file<R>(&mut self, info: &FileInfo, mut data: R) -> io::Result<u64>
+        where R: io::Read + io::Seek
+    {
+        let mut header = [0u8; MAX_SUBPACKET];
+        let header_len = {
+            let encoded = info.encode()?;
+            if encoded.len() >= header.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "ZMODEM file header too long"));
+            }
+
+            header[..encoded.len()].copy_from_slice(&encoded);
+            encoded.len() + 1
+        };
+
+        self.start_send()?;
+        self.total = info.size;
+
+        let offset = match self.send_file_header(&header[..header_len])? {
+            Some(offset) => offset as u64,
+            None => return Ok(0),
+        };
+
+        let end = self.send_data(&mut data, offset)?;
+        Ok(end - offset)
+    }
+
+    /// Sends a ZFILE frame holding `header` until the receiver replies.
+    /// Returns the position the receiver asks to start from, or `None` if it
+    /// skips the file.
+    fn send_file_header(&mut self, header: &[u8]) -> io::Result<Option<u32>> {
+        for _ in 0..MAX_ERRORS {
+            self.write_binary_header(Header::with_flags(ZFILE, ZCBIN))?;
+            self.write_subpacket(header, ZCRCW)?;
+
+            loop {
+                let reply = match self.read_header() {
+                    Err(ref e) if is_recoverable(e) => break,
+                    reply => reply?,
+                };
+
+                match reply.kind {
+                    ZRPOS => return Ok(Some(reply.position())),
+                    ZSKIP => return Ok(None),
+                    // A late reply to our initial request: the receiver has
+                    // yet to see the file header.
+                    ZRINIT => continue,
+                    ZCAN | ZABORT | ZFIN | ZFERR => return Err(aborted()),
+                    _ => break,
+                }
+            }
+        }
+
+        Err(too_many_errors())
+    }
+
+    /// Waits for the receiver's reply to data ending at `position`. Data is
+    /// resumed from `acknowledged` if no reply arrives.
+    fn read_reply(&mut self, position: u64, acknowledged: u64) -> io::Result<Reply> {
+        loop {
+            let reply = match self.read_header() {
+                Err(ref e) if is_recoverable(e) => {
+                    self.report_retry(retry_reason(e), false);
+                    return Ok(Reply::Resume(acknowledged));
+                }
+                reply => reply?,
+            };
+
+            match reply.kind {
+                ZACK if reply.position() as u64 == position => return Ok(Reply::Acknowledged),
+                ZRPOS => {
+                    self.report_retry(RetryReason::Rejected, true);
+                    return Ok(Reply::Resume(reply.position() as u64));
+                }
+                ZSKIP => return Ok(Reply::Skip),
+                ZCAN | ZABORT | ZFERR => return Err(aborted()),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Sends `data` from `offset` until the receiver acknowledges its end.
+    /// Returns the final position.
+    fn send_data<R>(&mut self, data: &mut R, offset: u64) -> io::Result<u64>
+        where R: io::Read + io::Seek
+    {
+        let mut chunk = [0u8; MAX_SUBPACKET];
+        let mut position = offset;
+        let mut acknowledged = offset;
+        let mut errors = 0;
+
+        'frame: loop {
+            if errors >= MAX_ERRORS {
+                return Err(too_many_errors());
+            }
+
+            data.seek(SeekFrom::Start(position))?;
+            self.write_binary_header(Header::with_position(ZDATA, position as u32))?;
+
+            let mut unacknowledged = 0;
+            let mut unsynced = 0;
+            // The position of a `ZCRCQ` subpacket whose reply is yet to be read.
+            let mut pending = None;
+            loop {
+                let n = data.read_max(&mut chunk)?;
+                if n == 0 {
+                    break;
+                }
+
+                position += n as u64;
+                unacknowledged += n as u32;
+                unsynced += n as u32;
+
+                if self.window == 0 && unsynced >= SYNC_INTERVAL {
+                    // Ask for a reply, but only wait for the previous one so
+                    // that the stream doesn't stall.
+                    self.write_subpacket(&chunk[..n], ZCRCQ)?;
+                    self.report_packet(position);
+                    unsynced = 0;
+                    let previous = pending.take();
+                    pending = Some(position);
+                    if let Some(at) = previous {
+                        match self.read_reply(at, acknowledged)? {
+                            Reply::Acknowledged => {
+                                acknowledged = at;
+                                errors = 0;
+                            }
+                            Reply::Resume(at) => {
+                                errors += 1;
+                                position = at;
+                                continue 'frame;
+                            }
+                            Reply::Skip => return Ok(position),
+                        }
+                    }
+                    continue;
+                }
+
+                if self.window == 0 || unacknowledged < self.window {
+                    self.write_subpacket(&chunk[..n], ZCRCG)?;
+                    self.report_packet(position);
+                    continue;
+                }
+
+                // The window is full: end the frame and wait for the receiver.
+                self.write_subpacket(&chunk[..n], ZCRCW)?;
+                self.report_packet(position);
+                match self.read_reply(position, acknowledged)? {
+                    Reply::Acknowledged => {
+                        acknowledged = position;
+                        errors = 0;
+                        continue 'frame;
+                    }
+                    Reply::Resume(at) => {
+                        errors += 1;
+                        position = at;
+                        continue 'frame;
+                    }
+                    Reply::Skip => return Ok(position),
+                }
+            }
+
+            if let Some(at) = pending {
+                match self.read_reply(at, acknowledged)? {
+                    Reply::Acknowledged => acknowledged = at,
+                    Reply::Resume(at) => {
+                        errors += 1;
+                        position = at;
+                        continue 'frame;
+                    }
+                    Reply::Skip => return Ok(position),
+                }
+            }
+
+            self.write_subpacket(&[], ZCRCE)?;
+            self.write_binary_header(Header::with_position(ZEOF, position as u32))?;
+            self.inner.flush()?;
+            loop {
+                // Replies to earlier frames may still arrive; only a timeout
+                // or a corrupted reply calls for another `ZEOF`.
+                let reply = match self.read_header() {
+                    Err(ref e) if is_recoverable(e) => {
+                        self.report_retry(retry_reason(e), false);
+                        errors += 1;
+                        if errors >= MAX_ERRORS {
+                            return Err(too_many_errors());
+                        }
+                        self.write_binary_header(Header::with_position(ZEOF, position as u32))?;
+                        self.inner.flush()?;
+                        continue;
+                    }
+                    reply => reply?,
+                };
+
+                match reply.kind {
+                    ZRINIT | ZSKIP => return Ok(position),
+                    ZRPOS => {
+                        self.report_retry(RetryReason::Rejected, true);
+                        errors += 1;
+                        position = reply.position() as u64;
+                        continue 'frame;
+                    }
+                    ZCAN | ZABORT | ZFERR => return Err(aborted()),
+                    _ => continue,
+                }
+            }
+        }
+    }
+
+    /// Ends the session.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.start_send()?;
+
+        self.write_hex_header(Header::with_position(ZFIN, 0))?;
+        for _ in 0..MAX_ERRORS {
+            // Stale replies to the last file are skipped without sending
+            // another `ZFIN`, which the receiver would take for our "OO".
+            match self.read_header() {
+                Ok(ref header) if header.kind == ZFIN => {
+                    self.inner.write_all(b"OO")?;
+                    return self.inner.flush();
+                }
+                Ok(_) => continue,
+                Err(ref e) if is_recoverable(e) => {
+                    self.write_hex_header(Header::with_position(ZFIN, 0))?
+                }
+                Err(e) => return Err(io::Error::new(e.kind(), "ZFIN failed")),
+            }
+        }
+
+        Err(too_many_errors())
+    }
+
+    /// Tells the sender which capabilities this receiver has.
+    fn write_zrinit(&mut self) -> io::Result<()> {
+        self.write_hex_header(Header::with_flags(ZRINIT, CANFDX | CANOVIO | CANFC32))
+    }
+
+    /// Waits for the next file of the session and returns its information, or
+    /// `None` once the sender has ended the session. The file must be received
+    /// with `receive_file()` or skipped with `skip_file()` before calling this
+    /// method again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `ConnectionAborted` if the sender cancels the
+    /// session, of `BrokenPipe` if too many errors occur in a row, or any
+    /// error from the inner stream.
+    pub fn next_file(&mut self) -> io::Result<Option<FileInfo>> {
+        if !self.started {
+            self.started = true;
+            (self.progress)(Progress::Started);
+        }
+
+        let mut buf = [0u8; MAX_SUBPACKET];
+        self.write_zrinit()?;
+        for _ in 0..MAX_ERRORS {
+            let header = self.read_header_retrying(|zmodem| zmodem.write_zrinit())?;
+            match header.kind {
+                ZFILE => match self.read_subpacket(&mut buf) {
+                    Ok((len, _)) => {
+                        let info = FileInfo::decode(&buf[..len])?;
+                        self.current = info.clone();
+                        return Ok(info);
+                    }
+                    Err(ref e) if is_recoverable(e) => {
+                        self.write_hex_header(Header::with_position(ZNAK, 0))?
+                    }
+                    Err(e) => return Err(e),
+                },
+                ZSINIT => {
+                    // Options we don't use; acknowledge them.
+                    match self.read_subpacket(&mut buf) {
+                        Ok(_) => self.write_hex_header(Header::with_position(ZACK, 0))?,
+                        Err(ref e) if is_recoverable(e) => {
+                            self.write_hex_header(Header::with_position(ZNAK, 0))?
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                ZFIN => {
+                    self.write_hex_header(Header::with_position(ZFIN, 0))?;
+                    // The sender's closing "OO" is a courtesy; ignore errors.
+                    let mut over = [0u8; 2];
+                    let _ = self.inner.read_exact(&mut over);
+                    return Ok(None);
+                }
+                ZCAN | ZABORT => return Err(aborted()),
+                _ => self.write_zrinit()?,
+            }
+        }
+
+        Err(too_many_errors())
+    }
+
+    /// Declines the file last returned by `next_file()`.
+    pub fn skip_file(&mut self) -> io::Result<()> {
+        self.current = None;
+        self.write_hex_header(Header::with_position(ZSKIP, 0))
+    }
+
+    /// Receives the file last returned by `next_file()` into `into`. If
+    /// `offset` is not 0, the first `offset` bytes of the file are assumed
+    /// to be held already: the sender resumes from there and only the rest
+    /// of the file is written to `into`. Returns the number of bytes written
+    /// to `into`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if there is no current file or
+    /// `offset` does not fit in a ZMODEM position, of `ConnectionAborted` if
+    /// the sender cancels the session, of `BrokenPipe` if too many errors
+    /// occur in a row, or any error from the inner stream or from writing to
+    /// `into`.
+    pub fn receive_file<W: io::Write>(&mut self, mut into: W, offset: u64)
+        -> io::Result<u64>
+    {
+        let info = self.current.take().ok_or(
+            io::Error::new(io::ErrorKind::InvalidInput, "No file to receive"))?;
+        self.total = info.size;
+
+        if offset >> 32 != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Offset too large"));
+        }
+
+        let mut buf = [0u8; MAX_RECEIVE_SUBPACKET];
+        let mut position = offset as u32;
+        let mut errors = 0;
+        self.write_hex_header(Header::with_position(ZRPOS, position))?;
+
+        loop {
+            if errors >= MAX_ERRORS {
+                return Err(too_many_errors());
+            }
+
+            let header = match self.read_header() {
+                Err(ref e) if is_recoverable(e) => {
+                    self.report_retry(retry_reason(e), true);
+                    errors += 1;
+                    self.write_hex_header(Header::with_position(ZRPOS, position))?;
+                    continue;
+                }
+                header => header?,
+            };
+
+            match header.kind {
+                ZDATA if header.position() == position => {}
+                ZDATA => {
+                    // Data we can't use yet; ask again from where we are.
+                    self.report_retry(RetryReason::Rejected, true);
+                    errors += 1;
+                    self.write_hex_header(Header::with_position(ZRPOS, position))?;
+                    continue;
+                }
+                ZEOF if header.position() == position => {
+                    return Ok((position as u64) - offset);
+                }
+                ZEOF => {
+                    // Some of the data was lost, or our last `ZRPOS` was;
+                    // ask for the rest.
+                    self.report_retry(RetryReason::Rejected, true);
+                    errors += 1;
+                    self.write_hex_header(Header::with_position(ZRPOS, position))?;
+                    continue;
+                }
+                ZFILE => {
+                    // The sender missed our reply to its file header.
+                    let _ = self.read_subpacket(&mut buf);
+                    self.write_hex_header(Header::with_position(ZRPOS, position))?;
+                    continue;
+                }
+                ZCAN | ZABORT | ZFIN => return Err(aborted()),
+                _ => continue,
+            }
+
+            loop {
+                let (len, end) = match self.read_subpacket(&mut buf) {
+                    Err(ref e) if is_recoverable(e) => {
+                        self.report_retry(retry_reason(e), true);
+                        errors += 1;
+                            self.write_hex_header(Header::with_position(ZRPOS, position))?;
+                        break;
+                    }
+                    result => result?,
+                };
+
+                into.write_all(&buf[..len])?;
+                position = position.wrapping_add(len as u32);
+                errors = 0;
+                self.report_packet(position as u64);
+
+                match end {
+                    ZCRCW => {
+                        self.write_hex_header(Header::with_position(ZACK, position))?;
+                        break;
+                    }
+                    ZCRCQ => self.write_hex_header(Header::with_position(ZACK, position))?,
+                    ZCRCE => break,
+                    _ => continue,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,8 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate fat32;
+
+// Mounts an arbitrary disk image and exercises listing, opening and reading.
+fuzz_target!(|data: &[u8]| {
+    fat32::fuzz::exercise_image(data);
+});
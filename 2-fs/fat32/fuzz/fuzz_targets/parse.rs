@@ -0,0 +1,28 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate fat32;
+
+use std::io::Cursor;
+
+use fat32::MasterBootRecord;
+use fat32::vfat::BiosParameterBlock;
+
+// Parses the first sector as an MBR and the second as an EBPB. Neither may
+// panic, whatever the input.
+fuzz_target!(|data: &[u8]| {
+    let mut sectors = data.to_vec();
+    sectors.resize(1024, 0);
+
+    if let Ok(mbr) = MasterBootRecord::from(Cursor::new(sectors.clone())) {
+        for i in 0..4 {
+            let _ = mbr.partition_at(i).partition_type.is_fat();
+        }
+    }
+
+    if let Ok(ebpb) = BiosParameterBlock::from(Cursor::new(sectors), 1) {
+        if ebpb.validate().is_ok() {
+            let _ = ebpb.cluster_count();
+            let _ = ebpb.data_start_sector();
+        }
+    }
+});
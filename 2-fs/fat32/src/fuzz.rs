@@ -0,0 +1,48 @@
+//! Code shared by the fuzz targets and the tests that check the same
+//! properties.
+
+use std::io::{Cursor, Read};
+
+use traits::{Entry, FileSystem};
+use vfat::{self, VFat};
+
+/// The most entries and bytes per file read from a single image. Corrupted
+/// images can describe enormous files; reading them adds no coverage.
+const MAX_ENTRIES: usize = 256;
+const MAX_FILE_BYTES: usize = 64 * 1024;
+
+/// Mounts the disk image `data` and lists, opens and reads everything
+/// reachable, ignoring errors. Every failure must be reported as an error
+/// rather than a panic or a hang.
+pub fn exercise_image(data: &[u8]) {
+    let vfat = match VFat::from(Cursor::new(data.to_vec())) {
+        Ok(vfat) => vfat,
+        Err(_) => return,
+    };
+
+    let _ = vfat.open("/a/../b/./c");
+
+    let walk = match vfat::walk(&vfat, "/") {
+        Ok(walk) => walk.max_depth(8),
+        Err(_) => return,
+    };
+
+    for item in walk.take(MAX_ENTRIES) {
+        let item = match item {
+            Ok(item) => item,
+            Err(_) => break,
+        };
+
+        let _ = vfat.open(&item.path);
+        if let Some(mut file) = item.entry.into_file() {
+            let mut buf = [0u8; 4096];
+            let mut total = 0;
+            while total < MAX_FILE_BYTES {
+                match file.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => total += n,
+                }
+            }
+        }
+    }
+}
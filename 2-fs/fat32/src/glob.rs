@@ -0,0 +1,388 @@
+use std::fmt;
+use std::path::{Component, Path};
+
+/// A single-character matcher inside a path component pattern.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Matches exactly this character.
+    Literal(char),
+    /// `?`: matches any single character.
+    AnyChar,
+    /// `*`: matches any run of characters, including an empty one.
+    AnyRun,
+    /// `[...]`: matches one character in (or, if negated, not in) a set of
+    /// inclusive ranges.
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    /// `**`: matches zero or more whole path components.
+    AnyPath,
+    /// Matches exactly one path component.
+    Name(Vec<Token>),
+}
+
+/// A shell-style glob pattern over `/`-separated paths.
+///
+/// The following syntax is supported in each path component:
+///
+///   * `?` matches any single character.
+///   * `*` matches any sequence of characters, including the empty one.
+///   * `[abc]`, `[a-z]` match one character in the set; `[!abc]` or `[^abc]`
+///     match one character not in the set. A `]` immediately after the
+///     opening bracket is part of the set, so `[]]` matches `]`.
+///
+/// A component consisting only of `**` matches zero or more path components.
+/// Wildcards never match the `/` separator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    absolute: bool,
+    segments: Vec<Segment>,
+    case_sensitive: bool,
+}
+
+/// An error returned when a glob pattern is malformed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternError {
+    /// The byte offset in the pattern where the error was detected.
+    pub pos: usize,
+    /// A description of the error.
+    pub msg: &'static str,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid pattern at offset {}: {}", self.pos, self.msg)
+    }
+}
+
+impl Token {
+    fn matches(&self, c: char, case_sensitive: bool) -> bool {
+        let fold = |c: char| {
+            if case_sensitive { c } else { c.to_ascii_lowercase() }
+        };
+
+        match self {
+            &Token::Literal(l) => fold(l) == fold(c),
+            &Token::AnyChar => true,
+            &Token::AnyRun => true,
+            &Token::Class { negated, ref ranges } => {
+                let c = fold(c);
+                let found = ranges.iter().any(|&(lo, hi)| {
+                    let (lo, hi) = (fold(lo), fold(hi));
+                    lo <= c && c <= hi
+                });
+                found != negated
+            }
+        }
+    }
+}
+
+/// Parses a bracket expression starting at `chars[start]`, which must be `[`.
+/// Returns the token and the index of the first character after the closing
+/// `]`.
+fn parse_class(chars: &[(usize, char)], start: usize)
+    -> Result<(Token, usize), PatternError>
+{
+    let mut i = start + 1;
+    let negated = match chars.get(i) {
+        Some(&(_, '!')) | Some(&(_, '^')) => { i += 1; true },
+        _ => false,
+    };
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = match chars.get(i) {
+            Some(&(_, c)) => c,
+            None => return Err(PatternError { pos: chars[start].0,
+                                              msg: "unclosed character class" }),
+        };
+
+        if c == ']' && !first {
+            return Ok((Token::Class { negated, ranges }, i + 1));
+        }
+
+        first = false;
+        match (chars.get(i + 1), chars.get(i + 2)) {
+            (Some(&(_, '-')), Some(&(pos, hi))) if hi != ']' => {
+                if hi < c {
+                    return Err(PatternError { pos, msg: "invalid range" });
+                }
+
+                ranges.push((c, hi));
+                i += 3;
+            },
+            _ => {
+                ranges.push((c, c));
+                i += 1;
+            }
+        }
+    }
+}
+
+fn parse_segment(component: &str, offset: usize)
+    -> Result<Segment, PatternError>
+{
+    if component == "**" {
+        return Ok(Segment::AnyPath);
+    }
+
+    let chars: Vec<(usize, char)> = component.char_indices()
+        .map(|(i, c)| (offset + i, c))
+        .collect();
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i].1 {
+            '?' => { tokens.push(Token::AnyChar); i += 1; },
+            '*' => {
+                if chars.get(i + 1).map(|&(_, c)| c) == Some('*') {
+                    return Err(PatternError {
+                        pos: chars[i].0,
+                        msg: "`**` must be an entire path component" });
+                }
+
+                tokens.push(Token::AnyRun);
+                i += 1;
+            },
+            '[' => {
+                let (token, next) = parse_class(&chars, i)?;
+                tokens.push(token);
+                i = next;
+            },
+            c => { tokens.push(Token::Literal(c)); i += 1; }
+        }
+    }
+
+    Ok(Segment::Name(tokens))
+}
+
+/// Returns `true` if `tokens` match all of `chars`.
+///
+/// This is the usual star-backtracking matcher: on a mismatch, only the most
+/// recent `*` is retried, consuming one more character, since an earlier `*`
+/// can't do anything a later one can't. It runs in `O(tokens * chars)` time.
+fn match_tokens(tokens: &[Token], chars: &[char], case_sensitive: bool)
+    -> bool
+{
+    let (mut t, mut c) = (0, 0);
+    // The token after the last `*` seen, and the character it resumes from.
+    let mut star = None;
+    while c < chars.len() {
+        match tokens.get(t) {
+            Some(&Token::AnyRun) => {
+                star = Some((t + 1, c));
+                t += 1;
+            },
+            Some(token) if token.matches(chars[c], case_sensitive) => {
+                t += 1;
+                c += 1;
+            },
+            _ => match star {
+                Some((next, resume)) => {
+                    star = Some((next, resume + 1));
+                    t = next;
+                    c = resume + 1;
+                },
+                None => return false,
+            }
+        }
+    }
+
+    tokens[t..].iter().all(|token| *token == Token::AnyRun)
+}
+
+/// Returns `true` if `segments` match all of `components`, backtracking over
+/// `**` as `match_tokens` does over `*`.
+fn match_segments(segments: &[Segment], components: &[&str],
+                  case_sensitive: bool) -> bool {
+    let (mut s, mut c) = (0, 0);
+    let mut star = None;
+    while c < components.len() {
+        let matched = match segments.get(s) {
+            Some(&Segment::AnyPath) => {
+                star = Some((s + 1, c));
+                s += 1;
+                continue;
+            },
+            Some(&Segment::Name(ref tokens)) => {
+                let chars: Vec<char> = components[c].chars().collect();
+                match_tokens(tokens, &chars, case_sensitive)
+            },
+            None => false,
+        };
+
+        if matched {
+            s += 1;
+            c += 1;
+        } else if let Some((next, resume)) = star {
+            star = Some((next, resume + 1));
+            s = next;
+            c = resume + 1;
+        } else {
+            return false;
+        }
+    }
+
+    segments[s..].iter().all(|segment| *segment == Segment::AnyPath)
+}
+
+impl Pattern {
+    /// Compiles `pattern`. Matching is case-sensitive; see
+    /// `case_insensitive()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PatternError` if a character class is unterminated or
+    /// contains a reversed range, or if `**` appears as part of a component.
+    pub fn new(pattern: &str) -> Result<Pattern, PatternError> {
+        let mut segments = Vec::new();
+        let mut offset = 0;
+        for component in pattern.split('/') {
+            if !component.is_empty() {
+                segments.push(parse_segment(component, offset)?);
+            }
+
+            offset += component.len() + 1;
+        }
+
+        Ok(Pattern { absolute: pattern.starts_with('/'), segments,
+                     case_sensitive: true })
+    }
+
+    /// Makes ASCII letters match regardless of case, as FAT32 file names do.
+    pub fn case_insensitive(mut self) -> Pattern {
+        self.case_sensitive = false;
+        self
+    }
+
+    /// Returns `true` if the pattern starts with `/`.
+    pub fn is_absolute(&self) -> bool {
+        self.absolute
+    }
+
+    /// Returns the maximum number of path components this pattern can match,
+    /// or `None` if it contains `**` and is unbounded.
+    pub fn max_depth(&self) -> Option<usize> {
+        if self.segments.contains(&Segment::AnyPath) {
+            None
+        } else {
+            Some(self.segments.len())
+        }
+    }
+
+    /// Returns `true` if `path` matches this pattern. An absolute pattern only
+    /// matches absolute paths and a relative pattern only relative ones. `.`
+    /// components in `path` are ignored.
+    pub fn matches_path<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = path.as_ref();
+        if path.has_root() != self.absolute {
+            return false;
+        }
+
+        let mut components = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => match name.to_str() {
+                    Some(name) => components.push(name),
+                    None => return false,
+                },
+                Component::ParentDir => components.push(".."),
+                _ => (),
+            }
+        }
+
+        match_segments(&self.segments, &components, self.case_sensitive)
+    }
+
+    /// Returns `true` if `name`, a single path component, matches this
+    /// pattern.
+    pub fn matches(&self, name: &str) -> bool {
+        !self.absolute && !name.contains('/')
+            && match_segments(&self.segments, &[name], self.case_sensitive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(s: &str) -> Pattern {
+        Pattern::new(s).expect("valid pattern")
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert!(pattern("*.txt").matches("notes.txt"));
+        assert!(pattern("*.txt").matches(".txt"));
+        assert!(!pattern("*.txt").matches("notes.txt.bak"));
+        assert!(pattern("a?c").matches("abc"));
+        assert!(!pattern("a?c").matches("ac"));
+        assert!(pattern("*").matches(""));
+        assert!(pattern("a*b*c").matches("aXXbYYc"));
+        assert!(!pattern("a*b*c").matches("aXXbYY"));
+    }
+
+    #[test]
+    fn test_classes() {
+        assert!(pattern("[abc]").matches("b"));
+        assert!(!pattern("[abc]").matches("d"));
+        assert!(pattern("file[0-9]").matches("file7"));
+        assert!(!pattern("file[0-9]").matches("fileA"));
+        assert!(pattern("[!0-9]").matches("x"));
+        assert!(!pattern("[^0-9]").matches("5"));
+        assert!(pattern("[]]").matches("]"));
+        assert!(pattern("[a-]").matches("-"));
+        assert!(pattern("[*]").matches("*"));
+    }
+
+    #[test]
+    fn test_paths() {
+        assert!(pattern("/a/*/c").matches_path("/a/b/c"));
+        assert!(!pattern("/a/*/c").matches_path("/a/b/b/c"));
+        assert!(!pattern("/a/*/c").matches_path("a/b/c"));
+        assert!(!pattern("a/*").matches_path("/a/b"));
+        assert!(pattern("/**/c").matches_path("/c"));
+        assert!(pattern("/**/c").matches_path("/a/b/c"));
+        assert!(pattern("/a/**").matches_path("/a"));
+        assert!(pattern("/a/**").matches_path("/a/b/c"));
+        assert!(pattern("**/*.rs").matches_path("src/vfat/dir.rs"));
+        assert!(!pattern("*").matches("a/b"));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        assert!(!pattern("*.TXT").matches("a.txt"));
+        assert!(pattern("*.TXT").case_insensitive().matches("a.txt"));
+        assert!(pattern("[A-C]").case_insensitive().matches("b"));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        assert_eq!(pattern("/a/*/c").max_depth(), Some(3));
+        assert_eq!(pattern("*.txt").max_depth(), Some(1));
+        assert_eq!(pattern("/a/**/c").max_depth(), None);
+    }
+
+    #[test]
+    fn test_pathological_patterns() {
+        // Backtracking over every `*` would take exponential time on these.
+        let name: String = ::std::iter::repeat('a').take(200).collect();
+        assert!(!pattern("*a*a*a*a*a*a*a*a*a*a*b").matches(&name));
+        assert!(pattern("*a*a*a*a*a*a*a*a*a*a*").matches(&name));
+
+        let path: String = ::std::iter::repeat("/a").take(100).collect();
+        assert!(!pattern("/**/a/**/a/**/a/**/a/**/a/**/b").matches_path(&path));
+        assert!(pattern("/**/a/**/a/**/a/**/a/**/a/**").matches_path(&path));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Pattern::new("a/[bc").unwrap_err().pos, 2);
+        assert_eq!(Pattern::new("[z-a]").unwrap_err().msg, "invalid range");
+        assert_eq!(Pattern::new("a/b**").unwrap_err().pos, 3);
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::io::{self, Cursor};
+use std::sync::{Arc, Mutex};
+
+use traits::BlockDevice;
+use vfat::{Shared, VFat};
+
+/// Builds small FAT32 disk images in memory for tests.
+///
+/// The image uses 512-byte sectors, one sector per cluster and two copies of
+/// a single-sector FAT. A backup of the boot sector is written when the image
+/// is created. Directories occupy a single cluster.
+pub struct ImageBuilder {
+    data: Vec<u8>,
+    next_cluster: u32,
+    dir_slots: HashMap<u32, usize>,
+}
+
+pub const SECTOR_SIZE: usize = 512;
+pub const PARTITION_START: usize = 1;
+pub const RESERVED_SECTORS: usize = 8;
+pub const BACKUP_BOOT_SECTOR: usize = 6;
+pub const FSINFO_SECTOR: usize = 1;
+pub const FAT_COUNT: usize = 2;
+pub const SECTORS_PER_FAT: usize = 1;
+pub const DATA_CLUSTERS: u32 = 64;
+pub const ROOT_CLUSTER: u32 = 2;
+
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+
+pub const FAT_EOC: u32 = 0x0FFFFFFF;
+
+const FAT_ENTRIES: usize = SECTORS_PER_FAT * SECTOR_SIZE / 4;
+
+fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset] = value as u8;
+    buf[offset + 1] = (value >> 8) as u8;
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    for i in 0..4 {
+        buf[offset + i] = (value >> (8 * i)) as u8;
+    }
+}
+
+/// Splits `name` into a space-padded, upper-case 8.3 short name.
+fn short_name(name: &str) -> [u8; 11] {
+    let mut short = [b' '; 11];
+    let (base, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i + 1..]),
+        _ => (name, ""),
+    };
+
+    for (i, b) in base.bytes().take(8).enumerate() {
+        short[i] = b.to_ascii_uppercase();
+    }
+    for (i, b) in ext.bytes().take(3).enumerate() {
+        short[8 + i] = b.to_ascii_uppercase();
+    }
+
+    short
+}
+
+impl ImageBuilder {
+    /// Creates an image containing an MBR with a single FAT32 partition and
+    /// an empty root directory.
+    pub fn new() -> ImageBuilder {
+        let total_sectors = ImageBuilder::data_start_sector()
+            + DATA_CLUSTERS as usize;
+        let mut builder = ImageBuilder {
+            data: vec![0u8; total_sectors * SECTOR_SIZE],
+            next_cluster: ROOT_CLUSTER + 1,
+            dir_slots: HashMap::new(),
+        };
+
+        {
+            let mbr = &mut builder.data[..SECTOR_SIZE];
+            mbr[446 + 4] = 0x0C;
+            write_u32(mbr, 446 + 8, PARTITION_START as u32);
+            write_u32(mbr, 446 + 12, (total_sectors - PARTITION_START) as u32);
+            mbr[510] = 0x55;
+            mbr[511] = 0xAA;
+        }
+
+        {
+            let start = PARTITION_START * SECTOR_SIZE;
+            let ebpb = &mut builder.data[start..start + SECTOR_SIZE];
+            write_u16(ebpb, 11, SECTOR_SIZE as u16);
+            ebpb[13] = 1;
+            write_u16(ebpb, 14, RESERVED_SECTORS as u16);
+            ebpb[16] = FAT_COUNT as u8;
+            ebpb[21] = 0xF8;
+            write_u32(ebpb, 32, (total_sectors - PARTITION_START) as u32);
+            write_u32(ebpb, 36, SECTORS_PER_FAT as u32);
+            write_u32(ebpb, 44, ROOT_CLUSTER);
+            write_u16(ebpb, 48, FSINFO_SECTOR as u16);
+            write_u16(ebpb, 50, BACKUP_BOOT_SECTOR as u16);
+            ebpb[66] = 0x29;
+            ebpb[510] = 0x55;
+            ebpb[511] = 0xAA;
+        }
+
+        {
+            // An FSInfo sector claiming every cluster but the root's is free.
+            let start = (PARTITION_START + FSINFO_SECTOR) * SECTOR_SIZE;
+            let fsinfo = &mut builder.data[start..start + SECTOR_SIZE];
+            write_u32(fsinfo, 0, 0x41615252);
+            write_u32(fsinfo, 484, 0x61417272);
+            write_u32(fsinfo, 488, DATA_CLUSTERS - 1);
+            write_u32(fsinfo, 492, ROOT_CLUSTER + 1);
+            write_u32(fsinfo, 508, 0xAA550000);
+        }
+
+        {
+            let start = PARTITION_START * SECTOR_SIZE;
+            let backup = (PARTITION_START + BACKUP_BOOT_SECTOR) * SECTOR_SIZE;
+            let (head, tail) = builder.data.split_at_mut(backup);
+            tail[..SECTOR_SIZE].copy_from_slice(&head[start..start + SECTOR_SIZE]);
+        }
+
+        builder.set_fat(0, 0x0FFFFFF8);
+        builder.set_fat(1, FAT_EOC);
+        builder.set_fat(ROOT_CLUSTER, FAT_EOC);
+        builder
+    }
+
+    /// The absolute sector of the first FAT.
+    pub fn fat_start_sector() -> usize {
+        PARTITION_START + RESERVED_SECTORS
+    }
+
+    /// The absolute sector of the first data cluster.
+    pub fn data_start_sector() -> usize {
+        ImageBuilder::fat_start_sector() + FAT_COUNT * SECTORS_PER_FAT
+    }
+
+    /// The byte offset of `cluster` in the image.
+    pub fn cluster_offset(cluster: u32) -> usize {
+        (ImageBuilder::data_start_sector() + (cluster - 2) as usize)
+            * SECTOR_SIZE
+    }
+
+    /// Sets the FAT entry for `cluster` to `value` in every FAT copy.
+    pub fn set_fat(&mut self, cluster: u32, value: u32) {
+        for copy in 0..FAT_COUNT {
+            self.set_fat_copy(copy, cluster, value);
+        }
+    }
+
+    /// Sets the FAT entry for `cluster` to `value` in FAT copy `copy` only.
+    pub fn set_fat_copy(&mut self, copy: usize, cluster: u32, value: u32) {
+        assert!((cluster as usize) < FAT_ENTRIES);
+        let offset = (ImageBuilder::fat_start_sector()
+                      + copy * SECTORS_PER_FAT) * SECTOR_SIZE;
+        write_u32(&mut self.data, offset + cluster as usize * 4, value);
+    }
+
+    /// Allocates a chain of `count` consecutive clusters and returns the first.
+    pub fn alloc_chain(&mut self, count: usize) -> u32 {
+        assert!(count > 0);
+        let first = self.next_cluster;
+        assert!(first + count as u32 <= DATA_CLUSTERS + 2, "image is full");
+
+        for i in 0..count as u32 {
+            let next = if i + 1 == count as u32 { FAT_EOC } else { first + i + 1 };
+            self.set_fat(first + i, next);
+        }
+
+        self.next_cluster += count as u32;
+        first
+    }
+
+    /// Writes a raw 32-byte directory entry into directory `dir`.
+    pub fn add_entry(&mut self, dir: u32, name: &str, attributes: u8,
+                     cluster: u32, size: u32) {
+        let slot = {
+            let slot = self.dir_slots.entry(dir).or_insert(0);
+            *slot += 1;
+            *slot - 1
+        };
+        assert!(slot < SECTOR_SIZE / 32, "directory is full");
+
+        let offset = ImageBuilder::cluster_offset(dir) + slot * 32;
+        let entry = &mut self.data[offset..offset + 32];
+        let short = if name == "." || name == ".." {
+            let mut short = [b' '; 11];
+            short[..name.len()].copy_from_slice(name.as_bytes());
+            short
+        } else {
+            short_name(name)
+        };
+
+        entry[..11].copy_from_slice(&short);
+        entry[11] = attributes;
+        write_u16(entry, 20, (cluster >> 16) as u16);
+        write_u16(entry, 26, cluster as u16);
+        write_u32(entry, 28, size);
+    }
+
+    /// Creates a directory named `name` in directory `parent` and returns its
+    /// cluster.
+    pub fn add_dir(&mut self, parent: u32, name: &str) -> u32 {
+        let cluster = self.alloc_chain(1);
+        self.add_entry(parent, name, ATTR_DIRECTORY, cluster, 0);
+        self.add_entry(cluster, ".", ATTR_DIRECTORY, cluster, 0);
+
+        let parent_cluster = if parent == ROOT_CLUSTER { 0 } else { parent };
+        self.add_entry(cluster, "..", ATTR_DIRECTORY, parent_cluster, 0);
+        cluster
+    }
+
+    /// Creates a file named `name` with contents `data` in directory `parent`
+    /// and returns its first cluster, or 0 if the file is empty.
+    pub fn add_file(&mut self, parent: u32, name: &str, data: &[u8]) -> u32 {
+        let cluster = if data.is_empty() {
+            0
+        } else {
+            let count = (data.len() + SECTOR_SIZE - 1) / SECTOR_SIZE;
+            let first = self.alloc_chain(count);
+            let offset = ImageBuilder::cluster_offset(first);
+            self.data[offset..offset + data.len()].copy_from_slice(data);
+            first
+        };
+
+        self.add_entry(parent, name, ATTR_ARCHIVE, cluster, data.len() as u32);
+        cluster
+    }
+
+    /// Creates a file named `name` with contents `data` in directory `parent`
+    /// whose data is stored in `clusters`, in order, which must be free.
+    pub fn add_file_at(&mut self, parent: u32, name: &str, data: &[u8],
+                       clusters: &[u32]) {
+        assert_eq!(clusters.len(), (data.len() + SECTOR_SIZE - 1) / SECTOR_SIZE);
+        for (i, (&cluster, chunk)) in clusters.iter()
+                .zip(data.chunks(SECTOR_SIZE)).enumerate() {
+            assert!(cluster >= self.next_cluster && cluster < DATA_CLUSTERS + 2);
+            let next = match clusters.get(i + 1) {
+                Some(&next) => next,
+                None => FAT_EOC,
+            };
+            self.set_fat(cluster, next);
+
+            let offset = ImageBuilder::cluster_offset(cluster);
+            self.data[offset..offset + chunk.len()].copy_from_slice(chunk);
+        }
+
+        self.add_entry(parent, name, ATTR_ARCHIVE, clusters[0],
+                       data.len() as u32);
+    }
+
+    /// Mutable access to the partition's EBPB sector.
+    pub fn ebpb_mut(&mut self) -> &mut [u8] {
+        let start = PARTITION_START * SECTOR_SIZE;
+        &mut self.data[start..start + SECTOR_SIZE]
+    }
+
+    /// Returns the raw image bytes.
+    pub fn build(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Mounts the image.
+    pub fn mount(self) -> Shared<VFat> {
+        VFat::from(Cursor::new(self.build())).expect("mount test image")
+    }
+}
+
+/// An in-memory image that can be mounted any number of times. Writes made
+/// through one mount are visible to later mounts and to the test.
+#[derive(Clone)]
+pub struct SharedImage {
+    data: Arc<Mutex<Cursor<Vec<u8>>>>,
+}
+
+impl SharedImage {
+    pub fn new(data: Vec<u8>) -> SharedImage {
+        SharedImage { data: Arc::new(Mutex::new(Cursor::new(data))) }
+    }
+
+    /// Mounts the image.
+    pub fn mount(&self) -> Shared<VFat> {
+        VFat::from(self.clone()).expect("mount test image")
+    }
+
+    /// Returns the raw bytes of every FAT copy.
+    pub fn fats(&self) -> Vec<Vec<u8>> {
+        let data = self.data.lock().unwrap();
+        let fat_size = SECTORS_PER_FAT * SECTOR_SIZE;
+        (0..FAT_COUNT).map(|copy| {
+            let start = ImageBuilder::fat_start_sector() * SECTOR_SIZE
+                            + copy * fat_size;
+            data.get_ref()[start..start + fat_size].to_vec()
+        }).collect()
+    }
+
+    /// Returns the free cluster count and next free cluster hint of the
+    /// FSInfo sector.
+    pub fn fsinfo(&self) -> (u32, u32) {
+        let data = self.data.lock().unwrap();
+        let start = (PARTITION_START + FSINFO_SECTOR) * SECTOR_SIZE;
+        let read = |offset: usize| (0..4).fold(0u32, |acc, i| {
+            acc | (data.get_ref()[start + offset + i] as u32) << (8 * i)
+        });
+        (read(488), read(492))
+    }
+
+    /// Returns the entry for `cluster` in the first FAT.
+    pub fn fat_entry(&self, cluster: u32) -> u32 {
+        let fat = &self.fats()[0];
+        let offset = cluster as usize * 4;
+        (0..4).fold(0, |acc, i| acc | (fat[offset + i] as u32) << (8 * i))
+    }
+}
+
+impl BlockDevice for SharedImage {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.data.lock().unwrap().read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.data.lock().unwrap().write_sector(n, buf)
+    }
+}
+
+/// An in-memory image on which reads of some sectors fail.
+pub struct FaultyImage {
+    data: Cursor<Vec<u8>>,
+    bad_sectors: Vec<u64>,
+}
+
+impl FaultyImage {
+    pub fn new(data: Vec<u8>, bad_sectors: Vec<u64>) -> FaultyImage {
+        FaultyImage { data: Cursor::new(data), bad_sectors }
+    }
+}
+
+impl BlockDevice for FaultyImage {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if self.bad_sectors.contains(&n) {
+            Err(io::Error::new(io::ErrorKind::Other, "bad sector"))
+        } else {
+            self.data.read_sector(n, buf)
+        }
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.data.write_sector(n, buf)
+    }
+}
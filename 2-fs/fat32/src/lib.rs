@@ -6,10 +6,17 @@ compile_error!("only little endian platforms supported");
 
 #[cfg(test)]
 mod tests;
+#[cfg(test)]
+mod image;
 mod mbr;
 mod util;
 
 pub mod vfat;
 pub mod traits;
+pub mod path;
+pub mod glob;
+#[cfg(any(test, feature = "fuzz"))]
+#[doc(hidden)]
+pub mod fuzz;
 
 pub use mbr::*;
@@ -64,9 +64,15 @@ impl fmt::Debug for BootIndicator {
 pub struct PartitionType(u8);
 
 impl PartitionType {
+    const UNUSED: u8 = 0x00;
     const FAT32: u8 = 0x0B;
     const FAT32_ALT: u8 = 0x0C;
 
+    /// Returns `true` if the partition table entry is unused.
+    pub fn is_unused(self) -> bool {
+        self.0 == PartitionType::UNUSED
+    }
+
     pub fn is_fat(self) -> bool {
         self.0 == PartitionType::FAT32 || self.0 == PartitionType::FAT32_ALT
     }
@@ -83,6 +89,43 @@ pub struct PartitionEntry {
     pub total_sectors: u32,
 }
 
+impl PartitionEntry {
+    /// Checks that this entry, partition `index` of the table, is consistent
+    /// with its type and lies within `device`.
+    fn validate<T: BlockDevice>(&self, index: u8, device: &mut T) -> Result<(), Error> {
+        let invalid = |field: &'static str, value: u64| {
+            Err(Error::InvalidPartition { partition: index, field, value })
+        };
+
+        let (start, sectors) = (self.relative_sector as u64, self.total_sectors as u64);
+        if self.partition_type.is_unused() {
+            if self.boot_indicator.0 != BootIndicator::NO || sectors != 0 {
+                return invalid("partition_type", PartitionType::UNUSED as u64);
+            }
+            return Ok(());
+        }
+
+        if start == 0 {
+            return invalid("relative_sector", start);
+        }
+
+        if sectors == 0 {
+            return invalid("total_sectors", sectors);
+        }
+
+        // The partition's last sector must be readable.
+        let mut sector = [0u8; MBR_SIZE];
+        match device.read_sector(start + sectors - 1, &mut sector) {
+            Ok(bytes) if bytes == MBR_SIZE => Ok(()),
+            Ok(_) => invalid("total_sectors", sectors),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                invalid("total_sectors", sectors)
+            },
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+}
+
 /// The master boot record (MBR).
 #[repr(C, packed)]
 #[derive(Debug)]
@@ -104,6 +147,9 @@ pub enum Error {
     UnknownBootIndicator(u8),
     /// The MBR magic signature was invalid.
     BadSignature,
+    /// The field `field` of partition `partition` (0-indexed) holds `value`,
+    /// which is inconsistent with the rest of the entry or the device.
+    InvalidPartition { partition: u8, field: &'static str, value: u64 },
 }
 
 impl From<io::Error> for Error {
@@ -121,6 +167,11 @@ impl MasterBootRecord {
     /// Returns `UnknownBootIndicator(n)` if partition `n` contains an invalid
     /// boot indicator. Returns `Io(err)` if the I/O error `err` occurred while
     /// reading the MBR.
+    ///
+    /// Returns `InvalidPartition` naming the offending field if an unused
+    /// partition is active or has sectors, or if a used partition has no
+    /// sectors, starts in the MBR's own sector or ends past the end of
+    /// `device`.
     pub fn from<T: BlockDevice>(mut device: T) -> Result<MasterBootRecord, Error> {
         let mut sector = [0u8; MBR_SIZE];
         let bytes = device.read_sector(0, &mut sector)?;
@@ -139,6 +190,8 @@ impl MasterBootRecord {
             if !partition.boot_indicator.is_valid() {
                 return Err(Error::UnknownBootIndicator(i as u8));
             }
+
+            partition.validate(i as u8, &mut device)?;
         }
 
         Ok(mbr)
@@ -236,5 +289,51 @@ mod tests {
             _ => assert!(false, "Invalid error"),
         }
     }
+
+    fn expect_invalid(data: &mut [u8], partition: u8, field: &str) {
+        let result = MasterBootRecord::from(Cursor::new(data));
+        match result.expect_err("Invalid partition") {
+            Error::InvalidPartition { partition: p, field: f, .. } => {
+                assert_eq!((p, f), (partition, field));
+            },
+            _ => assert!(false, "Invalid error"),
+        }
+    }
+
+    #[test]
+    fn test_partition_bounds() {
+        // A device of 8 sectors with partition 1 covering sectors 2 to 7.
+        let mut data = [0u8; 4096];
+        data[462 + 4] = 0x0C;
+        data[462 + 8] = 2;
+        data[462 + 12] = 6;
+        data[510] = 0x55;
+        data[511] = 0xAA;
+        MasterBootRecord::from(Cursor::new(&mut data[..])).expect("Valid block");
+
+        data[462 + 12] = 7;
+        expect_invalid(&mut data, 1, "total_sectors");
+
+        data[462 + 12] = 0;
+        expect_invalid(&mut data, 1, "total_sectors");
+
+        data[462 + 8] = 0;
+        data[462 + 12] = 6;
+        expect_invalid(&mut data, 1, "relative_sector");
+    }
+
+    #[test]
+    fn test_unused_partition() {
+        let mut data = [0u8; 512];
+        data[510] = 0x55;
+        data[511] = 0xAA;
+
+        data[478] = 0x80;
+        expect_invalid(&mut data, 2, "partition_type");
+
+        data[478] = 0x00;
+        data[478 + 12] = 1;
+        expect_invalid(&mut data, 2, "partition_type");
+    }
 }
 
@@ -0,0 +1,75 @@
+use std::path::{Component, Path, PathBuf};
+
+/// Lexically normalizes `path`, without touching the file system.
+///
+/// `.` components are removed and each `..` removes the normal component
+/// before it. A `..` that would climb above the root of an absolute path is
+/// dropped, so `/..` normalizes to `/`. Leading `..` components of a relative
+/// path cannot be resolved lexically and are preserved.
+pub fn normalize<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut result = PathBuf::new();
+    let mut depth = 0usize;
+
+    for component in path.as_ref().components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => {
+                result.push(component.as_os_str());
+            },
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if depth > 0 {
+                    result.pop();
+                    depth -= 1;
+                } else if !result.has_root() {
+                    result.push("..");
+                }
+            },
+            Component::Normal(name) => {
+                result.push(name);
+                depth += 1;
+            }
+        }
+    }
+
+    result
+}
+
+/// Resolves `path` against the directory `base` and normalizes the result.
+///
+/// If `path` is absolute, `base` is ignored.
+pub fn resolve<P: AsRef<Path>, Q: AsRef<Path>>(base: P, path: Q) -> PathBuf {
+    normalize(base.as_ref().join(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_absolute() {
+        assert_eq!(normalize("/"), Path::new("/"));
+        assert_eq!(normalize("/a/./b/"), Path::new("/a/b"));
+        assert_eq!(normalize("/a/b/../c"), Path::new("/a/c"));
+        assert_eq!(normalize("/a/../../b"), Path::new("/b"));
+        assert_eq!(normalize("/.."), Path::new("/"));
+        assert_eq!(normalize("/../.."), Path::new("/"));
+    }
+
+    #[test]
+    fn test_normalize_relative() {
+        assert_eq!(normalize("a/b/.."), Path::new("a"));
+        assert_eq!(normalize("a/.."), Path::new(""));
+        assert_eq!(normalize("./a"), Path::new("a"));
+        assert_eq!(normalize("../a"), Path::new("../a"));
+        assert_eq!(normalize("a/../../b"), Path::new("../b"));
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve("/a/b", ".."), Path::new("/a"));
+        assert_eq!(resolve("/a/b", "../c"), Path::new("/a/c"));
+        assert_eq!(resolve("/a/b", "/c"), Path::new("/c"));
+        assert_eq!(resolve("/", "../.."), Path::new("/"));
+        assert_eq!(resolve("/a", "."), Path::new("/a"));
+    }
+}
@@ -349,3 +349,27 @@ fn shared_fs_is_sync_send_static() {
     fn f<T: Sync + Send + 'static>() {  }
     f::<Shared<VFat>>();
 }
+
+#[test]
+fn test_corrupted_images_do_not_panic() {
+    use self::rand::Rng;
+    use image::{ImageBuilder, ROOT_CLUSTER, SECTOR_SIZE};
+
+    let mut rng = rand::thread_rng();
+    for _ in 0..2000 {
+        let mut image = ImageBuilder::new();
+        let a = image.add_dir(ROOT_CLUSTER, "a");
+        image.add_file(a, "b.txt", &[0x42; 1500]);
+        image.add_file(ROOT_CLUSTER, "c.txt", b"c");
+
+        // Corrupt the metadata: the MBR, EBPB, FATs and first few clusters.
+        let mut data = image.build();
+        let metadata_len = (ImageBuilder::data_start_sector() + 4) * SECTOR_SIZE;
+        for _ in 0..rng.gen_range(1, 16) {
+            let i = rng.gen_range(0, metadata_len);
+            data[i] = rng.gen();
+        }
+
+        ::fuzz::exercise_image(&data);
+    }
+}
@@ -118,6 +118,38 @@ impl CachedDevice {
 
         Ok(entry.data.as_slice())
     }
+
+    /// Writes every dirty cached sector back to the underlying device and
+    /// marks it clean.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error writing a sector to the disk.
+    /// Sectors that were not yet written remain dirty.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut dirty: Vec<u64> = self.cache.iter()
+            .filter(|&(_, entry)| entry.dirty)
+            .map(|(&sector, _)| sector)
+            .collect();
+        dirty.sort();
+
+        for sector in dirty {
+            let (physical, count) = self.virtual_to_physical(sector);
+            let size = self.device.sector_size() as usize;
+            {
+                let entry = self.cache.get(&sector).unwrap();
+                for i in 0..count {
+                    let start = i as usize * size;
+                    self.device.write_sector(physical + i,
+                                             &entry.data[start..start + size])?;
+                }
+            }
+
+            self.cache.get_mut(&sector).unwrap().dirty = false;
+        }
+
+        Ok(())
+    }
 }
 
 impl BlockDevice for CachedDevice {
@@ -216,6 +248,25 @@ mod tests {
         assert_eq!(unsafe { TEST_DATA[0] }, 0);
     }
 
+    #[test]
+    fn test_cache_flush() {
+        static mut TEST_DATA: [u8; 8192] = [0u8; 8192];
+
+        let mut cache = unsafe { CachedDevice::new(
+            Cursor::new(&mut TEST_DATA[..]),
+            Partition { start: 2, sector_size: 1024 }) };
+
+        let mut sector = [0u8; 1024];
+        sector[0] = 7;
+        sector[1023] = 9;
+        cache.write_sector(3, &sector).expect("Valid write");
+        assert_eq!(unsafe { TEST_DATA[2048] }, 0);
+
+        cache.flush().expect("Valid flush");
+        assert_eq!(unsafe { TEST_DATA[2048] }, 7);
+        assert_eq!(unsafe { TEST_DATA[3071] }, 9);
+    }
+
     #[test]
     fn test_partition() {
         static mut TEST_DATA: [u8; 8192] = [0u8; 8192];
@@ -0,0 +1,258 @@
+use std::io;
+use std::path::PathBuf;
+
+use traits::Entry as EntryTrait;
+use vfat::{VFat, Shared, Cluster, AllocateError, walk};
+
+/// How a file's clusters are laid out on disk.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Fragmentation {
+    /// The number of clusters allocated to the file.
+    pub clusters: usize,
+    /// The number of runs of consecutive clusters the file is split into. An
+    /// empty file has no fragments.
+    pub fragments: usize,
+}
+
+impl Fragmentation {
+    /// Computes the fragmentation of the cluster chain `chain`.
+    pub(crate) fn of(chain: &[Cluster]) -> Fragmentation {
+        let mut fragments = 0;
+        let mut previous: Option<Cluster> = None;
+        for &cluster in chain {
+            match previous {
+                Some(p) if p.fat_index() + 1 == cluster.fat_index() => (),
+                _ => fragments += 1,
+            }
+
+            previous = Some(cluster);
+        }
+
+        Fragmentation { clusters: chain.len(), fragments }
+    }
+
+    /// Returns `true` if the file occupies at most one run of clusters.
+    pub fn is_contiguous(&self) -> bool {
+        self.fragments <= 1
+    }
+}
+
+/// The outcome of `defragment()`.
+#[derive(Debug, Default)]
+pub struct DefragReport {
+    /// The number of files examined.
+    pub files: usize,
+    /// The number of files that were fragmented.
+    pub fragmented: usize,
+    /// The number of files that were made contiguous.
+    pub relocated: usize,
+    /// Fragmented files that were left in place because no run of free
+    /// clusters was large enough to hold them.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Makes every file in the file system contiguous, as far as free space
+/// allows.
+///
+/// The file system must not be used by anything else while this runs: open
+/// `File`s and `Entry`s for relocated files are left pointing at freed
+/// clusters.
+///
+/// # Errors
+///
+/// Returns an error if walking the directory tree or relocating a file fails.
+/// Files relocated before the error remain relocated.
+pub fn defragment(vfat: &Shared<VFat>) -> io::Result<DefragReport> {
+    let mut files = Vec::new();
+    for item in walk(vfat, "/")? {
+        let item = item?;
+        let path = item.path;
+        if let Some(file) = item.entry.into_file() {
+            files.push((path, file));
+        }
+    }
+
+    let mut report = DefragReport::default();
+    for (path, mut file) in files {
+        report.files += 1;
+        if file.fragmentation()?.is_contiguous() {
+            continue;
+        }
+
+        report.fragmented += 1;
+        match file.allocate(0) {
+            Ok(()) => report.relocated += 1,
+            Err(AllocateError::NoSpace) => report.skipped.push(path),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use traits::FileSystem;
+    use image::{ImageBuilder, SharedImage, ROOT_CLUSTER, FAT_COUNT,
+                SECTOR_SIZE, DATA_CLUSTERS};
+
+    fn chain(vfat: &Shared<VFat>, path: &str) -> Vec<u32> {
+        let file = vfat.open_file(path).expect("file");
+        let start = file.cluster();
+        vfat.borrow_mut().chain(start).expect("chain")
+            .iter().map(|c| c.fat_index()).collect()
+    }
+
+    fn contents(vfat: &Shared<VFat>, path: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        vfat.open_file(path).expect("file").read_to_end(&mut data)
+            .expect("read");
+        data
+    }
+
+    fn pattern(len: usize, seed: u8) -> Vec<u8> {
+        (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+            .collect()
+    }
+
+    #[test]
+    fn test_fragmentation() {
+        let clusters = |v: &[u32]| -> Vec<Cluster> {
+            v.iter().map(|&c| Cluster::from(c)).collect()
+        };
+
+        assert_eq!(Fragmentation::of(&[]),
+                   Fragmentation { clusters: 0, fragments: 0 });
+        assert_eq!(Fragmentation::of(&clusters(&[5, 6, 7])),
+                   Fragmentation { clusters: 3, fragments: 1 });
+        assert_eq!(Fragmentation::of(&clusters(&[40, 30, 31, 50])),
+                   Fragmentation { clusters: 4, fragments: 3 });
+        assert!(!Fragmentation::of(&clusters(&[7, 6])).is_contiguous());
+    }
+
+    #[test]
+    fn test_defragment() {
+        let a = pattern(3 * SECTOR_SIZE - 100, 1);
+        let b = pattern(2 * SECTOR_SIZE, 2);
+
+        let mut image = ImageBuilder::new();
+        image.add_file_at(ROOT_CLUSTER, "a.bin", &a, &[40, 30, 50]);
+        image.add_file(ROOT_CLUSTER, "b.bin", &b);
+        let image = SharedImage::new(image.build());
+
+        {
+            let vfat = image.mount();
+            assert_eq!(vfat.open_file("/a.bin").expect("file")
+                           .fragmentation().expect("fragmentation"),
+                       Fragmentation { clusters: 3, fragments: 3 });
+
+            let report = defragment(&vfat).expect("defragment");
+            assert_eq!(report.files, 2);
+            assert_eq!(report.fragmented, 1);
+            assert_eq!(report.relocated, 1);
+            assert!(report.skipped.is_empty());
+        }
+
+        // Everything must have reached the device: remount from scratch.
+        let vfat = image.mount();
+        let new_chain = chain(&vfat, "/a.bin");
+        assert_eq!(new_chain.len(), 3);
+        assert!(Fragmentation::of(&new_chain.iter().map(|&c| Cluster::from(c))
+                                      .collect::<Vec<_>>()).is_contiguous());
+        assert_eq!(contents(&vfat, "/a.bin"), a);
+        assert_eq!(contents(&vfat, "/b.bin"), b);
+
+        let fats = image.fats();
+        assert_eq!(fats.len(), FAT_COUNT);
+        assert_eq!(fats[0], fats[1]);
+        for &old in &[30, 40, 50] {
+            if !new_chain.contains(&old) {
+                assert_eq!(image.fat_entry(old), 0, "cluster {} not freed", old);
+            }
+        }
+    }
+
+    #[test]
+    fn test_allocate() {
+        let data = pattern(SECTOR_SIZE + 10, 3);
+        let mut image = ImageBuilder::new();
+        let first = image.add_file(ROOT_CLUSTER, "grow.bin", &data);
+        // A neighbour immediately after the file prevents in-place growth.
+        image.add_file(ROOT_CLUSTER, "next.bin", b"next");
+        let image = SharedImage::new(image.build());
+
+        {
+            let vfat = image.mount();
+            let mut file = vfat.open_file("/grow.bin").expect("file");
+            file.allocate(5 * SECTOR_SIZE as u64).expect("allocate");
+            assert!(file.cluster().fat_index() != first);
+        }
+
+        let vfat = image.mount();
+        let chain = chain(&vfat, "/grow.bin");
+        assert_eq!(chain.len(), 5);
+        assert_eq!(chain[4], chain[0] + 4);
+        assert_eq!(contents(&vfat, "/grow.bin"), data);
+        assert_eq!(contents(&vfat, "/next.bin"), b"next");
+        assert_eq!(image.fat_entry(first), 0);
+    }
+
+    #[test]
+    fn test_allocate_in_place() {
+        let data = pattern(10, 4);
+        let mut image = ImageBuilder::new();
+        let first = image.add_file(ROOT_CLUSTER, "grow.bin", &data);
+        let image = SharedImage::new(image.build());
+
+        {
+            let vfat = image.mount();
+            let mut file = vfat.open_file("/grow.bin").expect("file");
+            file.allocate(3 * SECTOR_SIZE as u64).expect("allocate");
+            assert_eq!(file.cluster().fat_index(), first);
+        }
+
+        let vfat = image.mount();
+        assert_eq!(chain(&vfat, "/grow.bin"), vec![first, first + 1, first + 2]);
+        assert_eq!(contents(&vfat, "/grow.bin"), data);
+    }
+
+    #[test]
+    fn test_fsinfo_invalidated() {
+        let mut image = ImageBuilder::new();
+        image.add_file(ROOT_CLUSTER, "a.bin", b"a");
+        let image = SharedImage::new(image.build());
+        let stale = image.fsinfo();
+
+        {
+            let vfat = image.mount();
+            let mut file = vfat.open_file("/a.bin").expect("file");
+            // Already contiguous: nothing is allocated.
+            file.allocate(0).expect("allocate");
+        }
+        assert_eq!(image.fsinfo(), stale);
+
+        {
+            let vfat = image.mount();
+            let mut file = vfat.open_file("/a.bin").expect("file");
+            file.allocate(2 * SECTOR_SIZE as u64).expect("allocate");
+        }
+        assert_eq!(image.fsinfo(), (0xFFFFFFFF, 0xFFFFFFFF));
+    }
+
+    #[test]
+    fn test_allocate_no_space() {
+        let mut image = ImageBuilder::new();
+        image.add_file(ROOT_CLUSTER, "a.bin", b"a");
+        let vfat = image.mount();
+
+        let mut file = vfat.open_file("/a.bin").expect("file");
+        match file.allocate(DATA_CLUSTERS as u64 * SECTOR_SIZE as u64) {
+            Err(AllocateError::NoSpace) => (),
+            other => panic!("expected NoSpace, got {:?}", other),
+        }
+        assert_eq!(contents(&vfat, "/a.bin"), b"a");
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use vfat::{Cluster, Metadata, EntryLocation};
+
+/// The number of directory entries a `DentryCache` holds by default.
+pub const DEFAULT_DENTRY_CAPACITY: usize = 128;
+
+/// The on-disk information required to reconstruct an `Entry` without
+/// re-reading its parent directory.
+#[derive(Debug, Clone)]
+pub struct Dentry {
+    pub name: String,
+    pub metadata: Metadata,
+    pub cluster: Cluster,
+    pub size: u32,
+    pub is_dir: bool,
+    pub location: Option<EntryLocation>,
+}
+
+#[derive(Debug)]
+struct CacheSlot {
+    dentry: Dentry,
+    last_used: u64,
+}
+
+/// A bounded cache of directory entry lookups keyed by the cluster of the
+/// containing directory and the case-folded entry name.
+///
+/// When the cache is full, the least recently used entry is evicted.
+#[derive(Debug)]
+pub struct DentryCache {
+    entries: HashMap<(Cluster, String), CacheSlot>,
+    capacity: usize,
+    clock: u64,
+}
+
+impl DentryCache {
+    /// Creates an empty cache holding at most `capacity` entries. A capacity
+    /// of 0 disables caching.
+    pub fn new(capacity: usize) -> DentryCache {
+        DentryCache { entries: HashMap::new(), capacity, clock: 0 }
+    }
+
+    fn key(dir: Cluster, name: &str) -> (Cluster, String) {
+        (dir, name.to_ascii_lowercase())
+    }
+
+    /// Returns the cached entry named `name` in directory `dir`, if any.
+    /// Comparison is case-insensitive.
+    pub fn get(&mut self, dir: Cluster, name: &str) -> Option<Dentry> {
+        self.clock += 1;
+        let clock = self.clock;
+
+        match self.entries.get_mut(&DentryCache::key(dir, name)) {
+            Some(slot) => {
+                slot.last_used = clock;
+                Some(slot.dentry.clone())
+            },
+            None => None,
+        }
+    }
+
+    /// Caches `dentry` as the entry named `name` in directory `dir`, evicting
+    /// the least recently used entry if the cache is full.
+    pub fn insert(&mut self, dir: Cluster, name: &str, dentry: Dentry) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let key = DentryCache::key(dir, name);
+        if self.entries.len() >= self.capacity
+                && !self.entries.contains_key(&key) {
+            let oldest = self.entries.iter()
+                .min_by_key(|&(_, slot)| slot.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.clock += 1;
+        self.entries.insert(key, CacheSlot { dentry, last_used: self.clock });
+    }
+
+    /// Drops every cached entry that belongs to directory `dir`.
+    pub fn invalidate_dir(&mut self, dir: Cluster) {
+        self.entries.retain(|&(cluster, _), _| cluster != dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dentry(name: &str, cluster: u32) -> Dentry {
+        Dentry { name: name.to_string(), metadata: Metadata::default(),
+                 cluster: Cluster::from(cluster), size: 0, is_dir: true,
+                 location: None }
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let mut cache = DentryCache::new(4);
+        cache.insert(Cluster::from(2), "Notes", dentry("Notes", 3));
+
+        let found = cache.get(Cluster::from(2), "NOTES").expect("cached");
+        assert_eq!(found.cluster, Cluster::from(3));
+        assert!(cache.get(Cluster::from(3), "notes").is_none());
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = DentryCache::new(2);
+        cache.insert(Cluster::from(2), "a", dentry("a", 3));
+        cache.insert(Cluster::from(2), "b", dentry("b", 4));
+
+        // Touch "a" so that "b" is the eviction candidate.
+        cache.get(Cluster::from(2), "a").expect("cached");
+        cache.insert(Cluster::from(2), "c", dentry("c", 5));
+
+        assert_eq!(cache.entries.len(), 2);
+        assert!(cache.get(Cluster::from(2), "a").is_some());
+        assert!(cache.get(Cluster::from(2), "b").is_none());
+        assert!(cache.get(Cluster::from(2), "c").is_some());
+    }
+
+    #[test]
+    fn test_invalidate_dir() {
+        let mut cache = DentryCache::new(4);
+        cache.insert(Cluster::from(2), "a", dentry("a", 3));
+        cache.insert(Cluster::from(3), "b", dentry("b", 4));
+
+        cache.invalidate_dir(Cluster::from(2));
+        assert!(cache.get(Cluster::from(2), "a").is_none());
+        assert!(cache.get(Cluster::from(3), "b").is_some());
+    }
+
+    #[test]
+    fn test_zero_capacity() {
+        let mut cache = DentryCache::new(0);
+        cache.insert(Cluster::from(2), "a", dentry("a", 3));
+        assert!(cache.entries.is_empty());
+    }
+}
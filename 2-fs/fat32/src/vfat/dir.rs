@@ -1,8 +1,10 @@
 use std::ffi::OsStr;
 use std::borrow::Cow;
 use std::io;
+use std::path::{Component, Path};
 
 use traits;
+use path;
 use util::{VecExt, Unused};
 use vfat::{VFat, Shared, File, Cluster, Entry};
 use vfat::{Metadata, Attributes, Timestamp, Time, Date};
@@ -60,9 +62,19 @@ pub union VFatDirEntry {
 pub struct DirIterator {
     data: Vec<VFatDirEntry>,
     offset: usize,
+    dir: Cluster,
     vfat: Shared<VFat>,
 }
 
+/// The position of a regular directory entry on disk: the first cluster of
+/// the directory containing it and its index among the directory's 32-byte
+/// entries.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EntryLocation {
+    pub dir: Cluster,
+    pub index: usize,
+}
+
 impl VFatRegularDirEntry {
     pub fn filename(&self) -> String {
         let name = VFatRegularDirEntry::fat_string(&self.filename);
@@ -105,10 +117,10 @@ impl VFatRegularDirEntry {
 }
 
 impl VFatLfnDirEntry {
+    /// The position of this entry in its long file name, starting at 1. A
+    /// value of 0 indicates a corrupted entry.
     pub fn sequence_number(&self) -> usize {
-        let result = self.sequence_number & 0b11111;
-        assert!(result != 0);
-        result as usize
+        (self.sequence_number & 0b11111) as usize
     }
 
     pub fn last_entry(&self) -> bool {
@@ -155,6 +167,17 @@ impl Dir {
         Dir { start, vfat }
     }
 
+    /// Get the root directory of `vfat`.
+    pub fn root(vfat: Shared<VFat>) -> Dir {
+        let start = vfat.borrow().root_dir_cluster();
+        Dir::new(start, vfat)
+    }
+
+    /// The first cluster of the directory's entries.
+    pub(crate) fn cluster(&self) -> Cluster {
+        self.start
+    }
+
     /// Finds the entry named `name` in `self` and returns it. Comparison is
     /// case-insensitive.
     ///
@@ -175,6 +198,66 @@ impl Dir {
             item.name().eq_ignore_ascii_case(name_str)
         }).ok_or(io::Error::new(io::ErrorKind::NotFound, "Not found"))
     }
+
+    /// Opens the entry at `path` relative to `self`. If `path` is absolute,
+    /// it is opened relative to the root directory instead.
+    ///
+    /// `path` is normalized before it is resolved: `.` components are
+    /// ignored and `..` at the root directory refers to the root directory
+    /// itself. An empty path opens `self`.
+    ///
+    /// # Errors
+    ///
+    /// If any component but the last in `path` is not a directory, or there
+    /// is no entry at `path`, an error of `NotFound` is returned.
+    pub fn open_at<P: AsRef<Path>>(&self, path: P) -> io::Result<Entry> {
+        let path = path::normalize(path);
+        let start = if path.has_root() {
+            self.vfat.borrow().root_dir_cluster()
+        } else {
+            self.start
+        };
+
+        let mut entry = Entry::new_dir(String::new(), Metadata::default(),
+                                       Dir::new(start, self.vfat.clone()));
+
+        for component in path.components() {
+            let name = match component {
+                Component::ParentDir => OsStr::new(".."),
+                Component::Normal(name) => name,
+                _ => continue,
+            };
+
+            let dir = traits::Entry::into_dir(entry).ok_or(
+                io::Error::new(io::ErrorKind::NotFound, "Expected dir"))?;
+            entry = dir.lookup(name)?;
+        }
+
+        Ok(entry)
+    }
+
+    /// Finds the entry named `name` in `self`, consulting the directory entry
+    /// cache before reading the directory from disk.
+    fn lookup(&self, name: &OsStr) -> io::Result<Entry> {
+        let name_str = name.to_str().ok_or(
+            io::Error::new(io::ErrorKind::InvalidInput, "Invalid UTF-8"))?;
+
+        if name_str == ".." && self.start == self.vfat.borrow().root_dir_cluster() {
+            // The root directory has no `..` entry; it is its own parent.
+            return Ok(Entry::new_dir(String::new(), Metadata::default(),
+                                     Dir::new(self.start, self.vfat.clone())));
+        }
+
+        let cached = self.vfat.borrow_mut().cached_dentry(self.start, name_str);
+        if let Some(dentry) = cached {
+            return Ok(Entry::from_dentry(dentry, self.vfat.clone()));
+        }
+
+        let entry = self.find(name_str)?;
+        self.vfat.borrow_mut().cache_dentry(self.start, name_str,
+                                            entry.to_dentry());
+        Ok(entry)
+    }
 }
 
 impl DirIterator {
@@ -190,7 +273,7 @@ impl DirIterator {
     }
 
     pub fn create_entry(&self, lfn: &mut Vec<&VFatLfnDirEntry>,
-                        entry: VFatRegularDirEntry)
+                        entry: VFatRegularDirEntry, index: usize)
         -> Entry
     {
         let name = if lfn.is_empty() {
@@ -205,12 +288,20 @@ impl DirIterator {
             entry.modified);
 
         if entry.is_dir() {
-            Entry::new_dir(name, metadata, Dir::new(entry.cluster(),
-                                                    self.vfat.clone()))
+            // A `..` entry in a subdirectory of the root directory refers to
+            // the root directory as cluster 0.
+            let mut cluster = entry.cluster();
+            if cluster.fat_index() == 0 {
+                cluster = self.vfat.borrow().root_dir_cluster();
+            }
+
+            Entry::new_dir(name, metadata, Dir::new(cluster, self.vfat.clone()))
         } else {
             Entry::new_file(name, metadata,
                             File::new(entry.cluster(), self.vfat.clone(),
-                                      entry.file_size))
+                                      entry.file_size,
+                                      Some(EntryLocation { dir: self.dir,
+                                                           index })))
         }
     }
 }
@@ -234,11 +325,15 @@ impl Iterator for DirIterator {
             }
 
             if entry_unknown.is_lfn() {
-                lfn.push(unsafe { &entry.long_filename });
+                let long_filename = unsafe { &entry.long_filename };
+                if long_filename.sequence_number() != 0 {
+                    lfn.push(long_filename);
+                }
             } else {
                 self.offset = offset + 1;
                 return Some(self.create_entry(&mut lfn,
-                                              unsafe { entry.regular }));
+                                              unsafe { entry.regular },
+                                              offset));
             }
         }
 
@@ -257,7 +352,7 @@ impl traits::Dir for Dir {
         self.vfat.borrow_mut().read_chain(self.start, &mut data)?;
 
         Ok(DirIterator { data: unsafe { data.cast() }, offset: 0,
-                         vfat: self.vfat.clone() })
+                         dir: self.start, vfat: self.vfat.clone() })
     }
 }
 
@@ -43,6 +43,17 @@ pub struct BiosParameterBlock {
 const EBPB_SIZE: usize = 512;
 const VALID_BOOTABLE_SIGNATURE: u16 = 0xAA55;
 
+/// The sector, relative to the start of the partition, conventionally holding
+/// the backup boot sector.
+pub const DEFAULT_BACKUP_BOOT_SECTOR: u64 = 6;
+
+/// Set in the extended flags when only the active FAT is in use; clear when
+/// every FAT copy is a mirror of the first.
+const FLAG_NO_MIRRORING: u16 = 1 << 7;
+
+/// The bits of the extended flags holding the index of the active FAT.
+const FLAG_ACTIVE_FAT_MASK: u16 = 0x0F;
+
 impl BiosParameterBlock {
     /// Reads the FAT32 extended BIOS parameter block from sector `sector` of
     /// device `device`.
@@ -88,6 +99,38 @@ impl BiosParameterBlock {
         self.sectors_per_fat_32
     }
 
+    /// Number of copies of the FAT.
+    pub fn fat_count(&self) -> u8 {
+        self.fat_count
+    }
+
+    /// Returns `true` if changes to the FAT are mirrored to every FAT copy.
+    pub fn is_mirrored(&self) -> bool {
+        self.flags & FLAG_NO_MIRRORING == 0
+    }
+
+    /// The index of the FAT copy in use. Only meaningful when mirroring is
+    /// disabled; otherwise the first FAT is the primary copy.
+    pub fn active_fat(&self) -> u8 {
+        if self.is_mirrored() {
+            0
+        } else {
+            (self.flags & FLAG_ACTIVE_FAT_MASK) as u8
+        }
+    }
+
+    /// The sector, relative to the start of the partition, of the FSInfo
+    /// sector, or 0 or 0xFFFF if there is none.
+    pub fn fsinfo_sector(&self) -> u16 {
+        self.fsinfo_sector
+    }
+
+    /// The logical sector, relative to the start of the partition, of the
+    /// backup boot sector, or 0 if there is none.
+    pub fn backup_boot_sector(&self) -> u16 {
+        self.backup_boot_sector
+    }
+
     /// Sectors per cluster.
     pub fn sectors_per_cluster(&self) -> u8 {
         self.sectors_per_cluster
@@ -111,6 +154,79 @@ impl BiosParameterBlock {
         self.root_cluster
     }
 
+    /// The number of data clusters in the partition.
+    pub fn cluster_count(&self) -> u32 {
+        let data_sectors = (self.logical_sectors() as u64)
+            .saturating_sub(self.data_start_sector());
+        (data_sectors / self.sectors_per_cluster.max(1) as u64) as u32
+    }
+
+    /// Checks that every geometry field describes a usable FAT32 file system.
+    ///
+    /// `from()` only checks the signature; this must be called before any
+    /// value derived from the geometry is used to access the disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidGeometry` naming the first offending field if the
+    /// sector size is not a power of two between 512 and 4096, the cluster
+    /// size is not a nonzero power of two, there are no reserved sectors or
+    /// FATs, the active FAT does not exist, a FAT is empty, the FATs or
+    /// reserved sectors leave no room for a single data cluster, a FAT has
+    /// fewer entries than there are clusters, or the root cluster is outside
+    /// the data region.
+    pub fn validate(&self) -> Result<(), Error> {
+        fn invalid(field: &'static str, value: u64) -> Result<(), Error> {
+            Err(Error::InvalidGeometry { field, value })
+        }
+
+        let bytes_per_sector = self.bytes_per_sector;
+        if !bytes_per_sector.is_power_of_two()
+                || bytes_per_sector < 512 || bytes_per_sector > 4096 {
+            return invalid("bytes_per_sector", bytes_per_sector as u64);
+        }
+
+        if !self.sectors_per_cluster.is_power_of_two() {
+            return invalid("sectors_per_cluster",
+                           self.sectors_per_cluster as u64);
+        }
+
+        if self.reserved_sectors == 0 {
+            return invalid("reserved_sectors", 0);
+        }
+
+        if self.fat_count == 0 {
+            return invalid("fat_count", 0);
+        }
+
+        if self.active_fat() >= self.fat_count {
+            let flags = self.flags;
+            return invalid("flags", flags as u64);
+        }
+
+        let sectors_per_fat = self.sectors_per_fat_32;
+        if sectors_per_fat == 0 {
+            return invalid("sectors_per_fat", 0);
+        }
+
+        if self.cluster_count() == 0 {
+            return invalid("logical_sectors", self.logical_sectors() as u64);
+        }
+
+        // Every cluster, and the two reserved entries before them, must have
+        // an entry in the FAT, or looking up high clusters reads past it.
+        let fat_entries = sectors_per_fat as u64 * bytes_per_sector as u64 / 4;
+        if fat_entries < self.cluster_count() as u64 + 2 {
+            return invalid("sectors_per_fat", sectors_per_fat as u64);
+        }
+
+        let root_cluster = self.root_cluster;
+        if root_cluster < 2 || root_cluster - 2 >= self.cluster_count() {
+            return invalid("root_cluster", root_cluster as u64);
+        }
+
+        Ok(())
+    }
 }
 
 impl fmt::Debug for BiosParameterBlock {
@@ -163,6 +279,117 @@ mod tests {
             Cursor::new(&mut data[..]), 0).expect("Valid block");
     }
 
+    fn valid_block() -> [u8; 512] {
+        let mut data = [0u8; 512];
+        data[11..13].copy_from_slice(&[0x00, 0x02]); // 512 bytes per sector.
+        data[13] = 1; // Sectors per cluster.
+        data[14] = 1; // Reserved sectors.
+        data[16] = 2; // FAT count.
+        data[32..36].copy_from_slice(&[0x00, 0x01, 0x00, 0x00]); // 256 sectors.
+        data[36] = 2; // Sectors per FAT.
+        data[44] = 2; // Root cluster.
+        data[510] = 0x55;
+        data[511] = 0xAA;
+        data
+    }
+
+    fn expect_invalid(mut data: [u8; 512], field: &str) {
+        let ebpb = BiosParameterBlock::from(
+            Cursor::new(&mut data[..]), 0).expect("Valid signature");
+        match ebpb.validate().expect_err("Invalid geometry") {
+            Error::InvalidGeometry { field: f, .. } => assert_eq!(f, field),
+            _ => assert!(false, "Invalid error"),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut data = valid_block();
+        let ebpb = BiosParameterBlock::from(
+            Cursor::new(&mut data[..]), 0).expect("Valid block");
+        ebpb.validate().expect("Valid geometry");
+        assert_eq!(ebpb.cluster_count(), 251);
+
+        let mut data = valid_block();
+        data[11..13].copy_from_slice(&[0, 0]);
+        expect_invalid(data, "bytes_per_sector");
+
+        let mut data = valid_block();
+        data[11..13].copy_from_slice(&[0x01, 0x02]);
+        expect_invalid(data, "bytes_per_sector");
+
+        let mut data = valid_block();
+        data[13] = 0;
+        expect_invalid(data, "sectors_per_cluster");
+
+        let mut data = valid_block();
+        data[13] = 3;
+        expect_invalid(data, "sectors_per_cluster");
+
+        let mut data = valid_block();
+        data[14] = 0;
+        expect_invalid(data, "reserved_sectors");
+
+        let mut data = valid_block();
+        data[16] = 0;
+        expect_invalid(data, "fat_count");
+
+        let mut data = valid_block();
+        data[40] = 0x82; // No mirroring, active FAT 2.
+        expect_invalid(data, "flags");
+
+        let mut data = valid_block();
+        data[36] = 0;
+        expect_invalid(data, "sectors_per_fat");
+
+        let mut data = valid_block();
+        data[36] = 0xFF;
+        expect_invalid(data, "logical_sectors");
+
+        // A FAT of 1 sector has 128 entries, too few for 253 clusters.
+        let mut data = valid_block();
+        data[36] = 1;
+        expect_invalid(data, "sectors_per_fat");
+
+        // So is one of 2 sectors for a volume of 65536 sectors.
+        let mut data = valid_block();
+        data[32..36].copy_from_slice(&[0x00, 0x00, 0x01, 0x00]);
+        expect_invalid(data, "sectors_per_fat");
+
+        let mut data = valid_block();
+        data[44] = 1;
+        expect_invalid(data, "root_cluster");
+
+        let mut data = valid_block();
+        data[44] = 0xFF;
+        expect_invalid(data, "root_cluster");
+    }
+
+    #[test]
+    fn test_fat_flags() {
+        let mut data = valid_block();
+        let ebpb = BiosParameterBlock::from(
+            Cursor::new(&mut data[..]), 0).expect("Valid block");
+        assert!(ebpb.is_mirrored());
+        assert_eq!(ebpb.active_fat(), 0);
+
+        // The active FAT number is ignored while mirroring.
+        let mut data = valid_block();
+        data[40] = 0x01;
+        let ebpb = BiosParameterBlock::from(
+            Cursor::new(&mut data[..]), 0).expect("Valid block");
+        assert!(ebpb.is_mirrored());
+        assert_eq!(ebpb.active_fat(), 0);
+
+        let mut data = valid_block();
+        data[40] = 0x81;
+        let ebpb = BiosParameterBlock::from(
+            Cursor::new(&mut data[..]), 0).expect("Valid block");
+        assert!(!ebpb.is_mirrored());
+        assert_eq!(ebpb.active_fat(), 1);
+        ebpb.validate().expect("Valid geometry");
+    }
+
     #[test]
     fn test_invalid_signature() {
         let mut data = [0u8; 512];
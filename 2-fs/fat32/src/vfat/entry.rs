@@ -1,5 +1,6 @@
 use traits;
-use vfat::{File, Dir, Metadata};
+use traits::File as FileTrait;
+use vfat::{VFat, Shared, File, Dir, Metadata, Dentry};
 
 #[derive(Debug)]
 enum EntryData {
@@ -22,6 +23,31 @@ impl Entry {
     pub fn new_dir(name: String, metadata: Metadata, dir: Dir) -> Entry {
         Entry { item: EntryData::Dir(dir), name, metadata }
     }
+
+    /// Reconstructs an entry from a cached directory entry.
+    pub(crate) fn from_dentry(dentry: Dentry, vfat: Shared<VFat>) -> Entry {
+        if dentry.is_dir {
+            Entry::new_dir(dentry.name, dentry.metadata,
+                           Dir::new(dentry.cluster, vfat))
+        } else {
+            Entry::new_file(dentry.name, dentry.metadata,
+                            File::new(dentry.cluster, vfat, dentry.size,
+                                      dentry.location))
+        }
+    }
+
+    /// Returns the information required to reconstruct this entry later.
+    pub(crate) fn to_dentry(&self) -> Dentry {
+        let (cluster, size, is_dir, location) = match &self.item {
+            &EntryData::File(ref file) => {
+                (file.cluster(), file.size() as u32, false, file.location())
+            },
+            &EntryData::Dir(ref dir) => (dir.cluster(), 0, true, None),
+        };
+
+        Dentry { name: self.name.clone(), metadata: self.metadata.clone(),
+                 cluster, size, is_dir, location }
+    }
 }
 
 impl traits::Entry for Entry {
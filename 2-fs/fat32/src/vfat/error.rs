@@ -7,7 +7,10 @@ pub enum Error {
     Mbr(mbr::Error),
     Io(io::Error),
     BadSignature,
-    NotFound
+    NotFound,
+    /// The EBPB field `field` holds `value`, which describes an impossible
+    /// file system geometry.
+    InvalidGeometry { field: &'static str, value: u64 },
 }
 
 impl From<mbr::Error> for Error {
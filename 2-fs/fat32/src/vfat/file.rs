@@ -1,14 +1,64 @@
 use std::io::{self, SeekFrom};
 use std::cmp::min;
+use std::fmt;
+use std::error;
 
 use traits;
-use vfat::{VFat, Shared, Cluster};
+use vfat::{VFat, Shared, Cluster, EntryLocation, Fragmentation};
+
+/// Why `File::allocate()` failed.
+#[derive(Debug)]
+pub enum AllocateError {
+    /// There is no run of free clusters large enough to hold the file.
+    NoSpace,
+    /// The file has no directory entry to update.
+    NoEntry,
+    /// An I/O error occurred while reading or updating the file system.
+    Io(io::Error),
+}
+
+impl From<io::Error> for AllocateError {
+    fn from(error: io::Error) -> AllocateError {
+        AllocateError::Io(error)
+    }
+}
+
+impl fmt::Display for AllocateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AllocateError::NoSpace => write!(f, "No contiguous run of free clusters"),
+            AllocateError::NoEntry => write!(f, "File has no directory entry"),
+            AllocateError::Io(ref error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl error::Error for AllocateError {
+    fn description(&self) -> &str {
+        match *self {
+            AllocateError::NoSpace => "no contiguous run of free clusters",
+            AllocateError::NoEntry => "file has no directory entry",
+            AllocateError::Io(_) => "I/O error",
+        }
+    }
+}
+
+impl From<AllocateError> for io::Error {
+    fn from(error: AllocateError) -> io::Error {
+        match error {
+            AllocateError::NoSpace => io::Error::new(io::ErrorKind::Other, error),
+            AllocateError::NoEntry => io::Error::new(io::ErrorKind::InvalidInput, error),
+            AllocateError::Io(error) => error,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct File {
     start: Cluster,
     vfat: Shared<VFat>,
     size: u32,
+    location: Option<EntryLocation>,
 
     pointer: u64,
 
@@ -17,12 +67,64 @@ pub struct File {
 }
 
 impl File {
-    // Create a new file.
-    pub fn new(start: Cluster, vfat: Shared<VFat>, size: u32)
+    // Create a new file. `location` is the position of the file's directory
+    // entry, which is required to relocate the file's data.
+    pub fn new(start: Cluster, vfat: Shared<VFat>, size: u32,
+               location: Option<EntryLocation>)
         -> File
     {
-        File { start, vfat, size, pointer: 0, cluster_current: start,
-               cluster_current_start: 0 }
+        File { start, vfat, size, location, pointer: 0,
+               cluster_current: start, cluster_current_start: 0 }
+    }
+
+    /// The first cluster of the file's data.
+    pub(crate) fn cluster(&self) -> Cluster {
+        self.start
+    }
+
+    /// The position of the file's directory entry, if known.
+    pub(crate) fn location(&self) -> Option<EntryLocation> {
+        self.location
+    }
+
+    /// Reports how the file's clusters are laid out on disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file's cluster chain can't be read.
+    pub fn fragmentation(&self) -> io::Result<Fragmentation> {
+        let chain = self.vfat.borrow_mut().chain(self.start)?;
+        Ok(Fragmentation::of(&chain))
+    }
+
+    /// Ensures that the clusters allocated to the file can hold at least `len`
+    /// bytes and are contiguous on disk, relocating the file's data if needed.
+    /// Clusters already allocated to the file are never released, so
+    /// `allocate(0)` makes the file's existing data contiguous. The size of
+    /// the file is unchanged.
+    ///
+    /// The changes are written to the underlying device before this method
+    /// returns. Other `File` or `Entry` values for the same file are not
+    /// updated and must be reopened.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoSpace` if there is no contiguous run of free clusters large
+    /// enough, or `NoEntry` if the file has no directory entry to update.
+    pub fn allocate(&mut self, len: u64) -> Result<(), AllocateError> {
+        let location = self.location.ok_or(AllocateError::NoEntry)?;
+
+        let start = {
+            let mut vfat = self.vfat.borrow_mut();
+            let cluster_size = vfat.cluster_size() as u64;
+            let clusters = (len + cluster_size - 1) / cluster_size;
+            vfat.make_contiguous(location, self.start, clusters as u32)?
+        };
+
+        self.start = start;
+        let pointer = self.pointer;
+        self.set_pointer(pointer)?;
+        Ok(())
     }
 
     fn set_pointer(&mut self, pointer: u64) -> io::Result<u64> {
@@ -40,8 +142,7 @@ impl File {
 impl traits::File for File {
     /// Writes any buffered data to disk.
     fn sync(&mut self) -> io::Result<()> {
-        // No-ops, this is a write-only-to-memory filesystem.
-        Ok(())
+        self.vfat.borrow_mut().sync()
     }
 
     /// Returns the size of the file in bytes.
@@ -9,16 +9,23 @@ pub(crate) mod entry;
 pub(crate) mod metadata;
 pub(crate) mod cache;
 pub(crate) mod shared;
+pub(crate) mod dentry;
+pub(crate) mod walk;
+pub(crate) mod defrag;
 
 pub use self::ebpb::BiosParameterBlock;
-pub use self::file::File;
+pub use self::file::{File, AllocateError};
 pub use self::dir::Dir;
 pub use self::error::Error;
 pub use self::vfat::VFat;
 pub use self::entry::Entry;
 pub use self::metadata::{Metadata, Attributes, Date, Time, Timestamp};
 pub use self::shared::Shared;
+pub use self::walk::{walk, Walk, WalkEntry, Order, Glob};
+pub use self::defrag::{defragment, Fragmentation, DefragReport};
 
 pub(crate) use self::cache::{CachedDevice, Partition};
 pub(crate) use self::fat::{Status, FatEntry};
 pub(crate) use self::cluster::Cluster;
+pub(crate) use self::dir::EntryLocation;
+pub(crate) use self::dentry::{Dentry, DentryCache, DEFAULT_DENTRY_CAPACITY};
@@ -5,21 +5,90 @@ use std::path::Path;
 use util::SliceExt;
 use mbr::MasterBootRecord;
 use vfat::{Shared, Cluster, File, Dir, Entry, FatEntry, Error, Status};
+use vfat::{Fragmentation, AllocateError};
 use vfat::{BiosParameterBlock, CachedDevice, Partition};
+use vfat::ebpb::DEFAULT_BACKUP_BOOT_SECTOR;
+use vfat::{Dentry, DentryCache, DEFAULT_DENTRY_CAPACITY, EntryLocation};
 use traits::{FileSystem, BlockDevice};
 
+/// The value written to a FAT entry to mark the end of a cluster chain.
+const EOC: u32 = 0x0FFFFFFF;
+
+/// The size of an on-disk directory entry.
+const DIR_ENTRY_SIZE: usize = 32;
+
+/// The signatures identifying an FSInfo sector, at offsets 0 and 484.
+const FSINFO_LEAD_SIGNATURE: u32 = 0x41615252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x61417272;
+/// The offset of the free cluster count in an FSInfo sector. The next free
+/// cluster hint follows it.
+const FSINFO_FREE_COUNT: usize = 488;
+
 #[derive(Debug)]
 pub struct VFat {
     device: CachedDevice,
     bytes_per_sector: u16,
     sectors_per_cluster: u8,
     sectors_per_fat: u32,
+    fat_count: u8,
+    mirrored: bool,
+    active_fat: u8,
     fat_start_sector: u64,
     data_start_sector: u64,
     root_dir_cluster: Cluster,
+    cluster_count: u32,
+    /// The sector of the FSInfo structure, until its counts are invalidated.
+    fsinfo_sector: Option<u64>,
+    dentries: DentryCache,
+}
+
+/// Reads the EBPB at `sector` of `device` and checks its geometry.
+fn read_valid_ebpb<T: BlockDevice>(device: &mut T, sector: u64)
+    -> Result<BiosParameterBlock, Error>
+{
+    let ebpb = BiosParameterBlock::from(device, sector)?;
+    ebpb.validate()?;
+    Ok(ebpb)
+}
+
+/// Reads the backup EBPB at logical sector `sector` of the partition starting
+/// at physical sector `partition_start` of `device`.
+///
+/// The logical sector size, which places `sector` on the device, is unknown
+/// when the primary EBPB is damaged. Each possible size is tried in turn, and
+/// the first backup with a valid geometry and that size is returned.
+fn read_backup_ebpb<T: BlockDevice>(device: &mut T, partition_start: u64,
+                                    sector: u64)
+    -> Result<BiosParameterBlock, Error>
+{
+    let device_sector_size = device.sector_size();
+    let mut first_error = None;
+    for &size in &[512u64, 1024, 2048, 4096] {
+        if size % device_sector_size != 0 {
+            continue;
+        }
+
+        let physical = partition_start + sector * (size / device_sector_size);
+        match read_valid_ebpb(device, physical) {
+            Ok(ebpb) => if ebpb.bytes_per_sector() as u64 == size {
+                return Ok(ebpb);
+            },
+            Err(error) => if first_error.is_none() {
+                first_error = Some(error);
+            },
+        }
+    }
+
+    Err(first_error.unwrap_or(Error::BadSignature))
 }
 
 impl VFat {
+    /// Mounts the FAT32 file system in the first partition of `device`.
+    ///
+    /// If the partition's boot sector has an invalid signature, the backup
+    /// boot sector in its conventional place is used instead. If the boot
+    /// sector has a valid signature but invalid geometry, the backup boot
+    /// sector it names, if any, is used instead.
     pub fn from<T>(mut device: T) -> Result<Shared<VFat>, Error>
         where T: BlockDevice + 'static
     {
@@ -31,9 +100,27 @@ impl VFat {
         }
 
         let partition_start = partition.relative_sector as u64;
-        let ebpb = BiosParameterBlock::from(&mut device, partition_start)?;
+        let ebpb = match BiosParameterBlock::from(&mut device, partition_start) {
+            Ok(ebpb) => match ebpb.validate() {
+                Ok(()) => ebpb,
+                Err(error) => match ebpb.backup_boot_sector() {
+                    0 => return Err(error),
+                    sector => read_backup_ebpb(&mut device, partition_start,
+                                               sector as u64).map_err(|_| error)?,
+                }
+            },
+            Err(Error::BadSignature) => read_backup_ebpb(
+                &mut device, partition_start, DEFAULT_BACKUP_BOOT_SECTOR)?,
+            Err(error) => return Err(error),
+        };
 
         let bytes_per_sector = ebpb.bytes_per_sector();
+        if bytes_per_sector as u64 % device.sector_size() != 0 {
+            return Err(Error::InvalidGeometry {
+                field: "bytes_per_sector",
+                value: bytes_per_sector as u64 });
+        }
+
         let cache = CachedDevice::new(
             device,
             Partition { start: partition_start,
@@ -44,19 +131,70 @@ impl VFat {
             bytes_per_sector,
             sectors_per_cluster: ebpb.sectors_per_cluster(),
             sectors_per_fat: ebpb.sectors_per_fat(),
+            fat_count: ebpb.fat_count(),
+            mirrored: ebpb.is_mirrored(),
+            active_fat: ebpb.active_fat(),
             fat_start_sector: partition_start + ebpb.fat_start_sector(),
             data_start_sector: partition_start + ebpb.data_start_sector(),
             root_dir_cluster: Cluster::from(ebpb.root_cluster()),
+            cluster_count: ebpb.cluster_count(),
+            fsinfo_sector: match ebpb.fsinfo_sector() as u64 {
+                sector if sector > 0 && sector < ebpb.fat_start_sector() => {
+                    Some(partition_start + sector)
+                },
+                _ => None,
+            },
+            dentries: DentryCache::new(DEFAULT_DENTRY_CAPACITY),
         };
 
         Ok(Shared::new(vfat))
     }
 
+    /// The first cluster of the root directory.
+    pub fn root_dir_cluster(&self) -> Cluster {
+        self.root_dir_cluster
+    }
+
+    /// Returns the cached entry named `name` in the directory starting at
+    /// `dir`, if it has been looked up before.
+    pub(crate) fn cached_dentry(&mut self, dir: Cluster, name: &str)
+        -> Option<Dentry>
+    {
+        self.dentries.get(dir, name)
+    }
+
+    /// Records the entry named `name` in the directory starting at `dir` in
+    /// the directory entry cache.
+    pub(crate) fn cache_dentry(&mut self, dir: Cluster, name: &str,
+                               dentry: Dentry) {
+        self.dentries.insert(dir, name, dentry);
+    }
+
+    /// Drops all cached lookups in the directory starting at `dir`. This must
+    /// be called whenever the on-disk entries of `dir` are modified.
+    pub fn invalidate_dir(&mut self, dir: Cluster) {
+        self.dentries.invalidate_dir(dir);
+    }
+
+    /// Returns an error of `InvalidData` if `cluster` is not one of the data
+    /// clusters of this file system.
+    fn check_cluster(&self, cluster: Cluster) -> io::Result<()> {
+        let index = cluster.fat_index();
+        if index < 2 || index - 2 >= self.cluster_count {
+            Err(io::Error::new(io::ErrorKind::InvalidData,
+                               "Cluster out of range"))
+        } else {
+            Ok(())
+        }
+    }
+
     /// Read from an offset of a cluster into a buffer.
     pub fn read_cluster(&mut self, cluster: Cluster, offset: usize,
                         mut buf: &mut [u8])
         -> io::Result<usize>
     {
+        self.check_cluster(cluster)?;
+
         let cluster_start_sector = self.data_start_sector
                                         + cluster.data_index() as u64
                                             * self.sectors_per_cluster as u64;
@@ -151,8 +289,17 @@ impl VFat {
     {
         let mut cluster = start;
         let mut bytes_read = 0;
+        let mut clusters_read = 0;
 
         loop {
+            // A chain can't be longer than the number of clusters on the disk,
+            // if it is, the FAT contains a loop.
+            if clusters_read >= self.cluster_count {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "Cluster chain contains a loop"));
+            }
+
+            clusters_read += 1;
             let fat_entry = self.fat_entry(cluster)?.status();
 
             match fat_entry {
@@ -172,10 +319,9 @@ impl VFat {
         Ok(bytes_read)
     }
 
-    /// Return a reference to a `FatEntry` for a cluster where the reference
-    /// points directly into a cached sector.
-    fn fat_entry(&mut self, cluster: Cluster) -> io::Result<&FatEntry> {
-        // Map the cluster index to the FAT sector offset.
+    /// Maps `cluster` to the sector, relative to the start of a FAT, and the
+    /// byte offset within that sector of its FAT entry.
+    fn fat_position(&self, cluster: Cluster) -> io::Result<(u64, usize)> {
         let fat_sector = cluster.fat_index() * 4 / self.bytes_per_sector as u32;
         let fat_index: usize = (cluster.fat_index() * 4
                                 - fat_sector * self.bytes_per_sector as u32)
@@ -185,9 +331,324 @@ impl VFat {
                                       "Invalid cluster index"));
         }
 
-        let data = self.device.get(self.fat_start_sector + fat_sector as u64)?;
+        Ok((fat_sector as u64, fat_index))
+    }
+
+    /// The absolute sector holding sector `fat_sector` of FAT copy `copy`.
+    fn fat_copy_sector(&self, copy: u8, fat_sector: u64) -> u64 {
+        self.fat_start_sector + copy as u64 * self.sectors_per_fat as u64
+            + fat_sector
+    }
+
+    /// Returns the absolute sector of the first readable copy of sector
+    /// `fat_sector` of the FAT, trying the active FAT first and then each
+    /// following copy in turn.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the last copy tried if no copy can be read.
+    fn readable_fat_sector(&mut self, fat_sector: u64) -> io::Result<u64> {
+        let mut error = None;
+        for i in 0..self.fat_count {
+            let copy = (self.active_fat + i) % self.fat_count;
+            let sector = self.fat_copy_sector(copy, fat_sector);
+            match self.device.get(sector) {
+                Ok(_) => return Ok(sector),
+                Err(e) => error = Some(e),
+            }
+        }
+
+        Err(error.expect("at least one FAT"))
+    }
+
+    /// Return a reference to a `FatEntry` for a cluster where the reference
+    /// points directly into a cached sector.
+    fn fat_entry(&mut self, cluster: Cluster) -> io::Result<&FatEntry> {
+        let (fat_sector, fat_index) = self.fat_position(cluster)?;
+        let sector = self.readable_fat_sector(fat_sector)?;
+        let data = self.device.get(sector)?;
         Ok(unsafe { &data[fat_index..fat_index+4].cast()[0] })
     }
+
+    /// Sets the FAT entry for `cluster` to `value`. The entry is updated in
+    /// every FAT copy if the FAT is mirrored and only in the active FAT
+    /// otherwise. The reserved upper four bits of each entry are preserved.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, leaving every copy unmodified, if the sector holding
+    /// the entry can't be read from any copy being updated.
+    fn set_fat_entry(&mut self, cluster: Cluster, value: u32)
+        -> io::Result<()>
+    {
+        let (fat_sector, fat_index) = self.fat_position(cluster)?;
+        let copies = if self.mirrored {
+            (0..self.fat_count).collect::<Vec<_>>()
+        } else {
+            vec![self.active_fat]
+        };
+
+        // Load every copy before modifying any so the copies stay in sync.
+        for &copy in &copies {
+            let sector = self.fat_copy_sector(copy, fat_sector);
+            self.device.get(sector)?;
+        }
+
+        for &copy in &copies {
+            let sector = self.fat_copy_sector(copy, fat_sector);
+            let data = self.device.get_mut(sector)?;
+            let entry = &mut data[fat_index..fat_index + 4];
+
+            let old = (0..4).fold(0u32, |acc, i| acc | (entry[i] as u32) << (8 * i));
+            let new = (old & 0xF0000000) | (value & 0x0FFFFFFF);
+            for i in 0..4 {
+                entry[i] = (new >> (8 * i)) as u8;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The size of a cluster in bytes.
+    pub fn cluster_size(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+
+    /// Writes all modified sectors to the underlying device.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.device.flush()
+    }
+
+    /// Returns every cluster in the chain beginning at `start`, in order. A
+    /// `start` of cluster 0, which denotes an empty file, has no clusters.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the chain contains a loop, an
+    /// out-of-range cluster or a free, reserved or bad cluster.
+    pub(crate) fn chain(&mut self, start: Cluster) -> io::Result<Vec<Cluster>> {
+        let mut chain = Vec::new();
+        if start.fat_index() == 0 {
+            return Ok(chain);
+        }
+
+        let mut cluster = start;
+        loop {
+            self.check_cluster(cluster)?;
+            if chain.len() >= self.cluster_count as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "Cluster chain contains a loop"));
+            }
+
+            chain.push(cluster);
+            match self.fat_entry(cluster)?.status() {
+                Status::Data(next) => cluster = next,
+                Status::Eoc(_) => return Ok(chain),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                               "Invalid cluster entry")),
+            }
+        }
+    }
+
+    /// Overwrites the contents of `cluster` with `buf`, which must be exactly
+    /// one cluster long.
+    fn write_cluster(&mut self, cluster: Cluster, buf: &[u8])
+        -> io::Result<()>
+    {
+        self.check_cluster(cluster)?;
+        assert_eq!(buf.len(), self.cluster_size());
+
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let cluster_start_sector = self.data_start_sector
+                                        + cluster.data_index() as u64
+                                            * self.sectors_per_cluster as u64;
+        for (i, chunk) in buf.chunks(bytes_per_sector).enumerate() {
+            let data = self.device.get_mut(cluster_start_sector + i as u64)?;
+            data.copy_from_slice(chunk);
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if the `count` clusters beginning at `first` exist and
+    /// are all free.
+    fn is_free_run(&mut self, first: u32, count: u32) -> io::Result<bool> {
+        if first < 2 || first - 2 + count > self.cluster_count {
+            return Ok(false);
+        }
+
+        for index in first..first + count {
+            if self.fat_entry(Cluster::from(index))?.status() != Status::Free {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Finds the first run of `count` contiguous free clusters.
+    fn find_free_run(&mut self, count: u32) -> io::Result<Option<Cluster>> {
+        let mut run_start = 2;
+        let mut run_len = 0;
+        for index in 2..self.cluster_count + 2 {
+            if self.fat_entry(Cluster::from(index))?.status() == Status::Free {
+                if run_len == 0 {
+                    run_start = index;
+                }
+
+                run_len += 1;
+                if run_len == count {
+                    return Ok(Some(Cluster::from(run_start)));
+                }
+            } else {
+                run_len = 0;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Links `count` clusters beginning at `first` into a chain ending with an
+    /// end-of-chain marker.
+    fn link_run(&mut self, first: u32, count: u32) -> io::Result<()> {
+        for index in first..first + count {
+            let next = if index + 1 == first + count { EOC } else { index + 1 };
+            self.set_fat_entry(Cluster::from(index), next)?;
+        }
+
+        Ok(())
+    }
+
+    /// Marks the free cluster count and next free cluster hint in the FSInfo
+    /// sector, if there is one, as unknown. Other implementations then count
+    /// free clusters themselves instead of trusting values made stale by
+    /// clusters this file system allocated or freed. This is done once per
+    /// mount.
+    fn invalidate_fsinfo(&mut self) -> io::Result<()> {
+        let sector = match self.fsinfo_sector {
+            Some(sector) => sector,
+            None => return Ok(()),
+        };
+
+        let read_u32 = |data: &[u8], offset: usize| {
+            (0..4).fold(0u32, |acc, i| acc | (data[offset + i] as u32) << (8 * i))
+        };
+        let valid = {
+            let data = self.device.get(sector)?;
+            read_u32(data, 0) == FSINFO_LEAD_SIGNATURE
+                && read_u32(data, 484) == FSINFO_STRUCT_SIGNATURE
+        };
+
+        if valid {
+            let data = self.device.get_mut(sector)?;
+            for byte in data[FSINFO_FREE_COUNT..FSINFO_FREE_COUNT + 8].iter_mut() {
+                *byte = 0xFF;
+            }
+        }
+
+        self.fsinfo_sector = None;
+        Ok(())
+    }
+
+    /// Points the directory entry at `location` to the first cluster
+    /// `cluster`.
+    fn set_entry_cluster(&mut self, location: EntryLocation, cluster: Cluster)
+        -> io::Result<()>
+    {
+        let offset = location.index * DIR_ENTRY_SIZE;
+        let (dir_cluster, dir_cluster_start) =
+            self.find_sector(location.dir, offset)?;
+        self.check_cluster(dir_cluster)?;
+
+        let offset = offset - dir_cluster_start;
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let sector = self.data_start_sector
+                        + dir_cluster.data_index() as u64
+                            * self.sectors_per_cluster as u64
+                        + (offset / bytes_per_sector) as u64;
+        let offset = offset % bytes_per_sector;
+
+        let value = cluster.fat_index();
+        let data = self.device.get_mut(sector)?;
+        data[offset + 20] = (value >> 16) as u8;
+        data[offset + 21] = (value >> 24) as u8;
+        data[offset + 26] = value as u8;
+        data[offset + 27] = (value >> 8) as u8;
+
+        self.invalidate_dir(location.dir);
+        Ok(())
+    }
+
+    /// Makes the chain beginning at `start`, belonging to the file whose
+    /// directory entry is at `location`, contiguous and at least `clusters`
+    /// clusters long. Returns the new first cluster of the file.
+    ///
+    /// The chain is extended in place when the clusters following it are
+    /// free. Otherwise the data is copied to the first sufficiently large
+    /// free run, which is linked and written to disk before the directory
+    /// entry is updated and, finally, the old chain is freed. An interruption
+    /// at any point therefore leaves the file intact, at worst leaking the
+    /// clusters of the new or old chain. The FSInfo free cluster count is
+    /// invalidated before any cluster is allocated.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoSpace` if there is no run of free clusters large enough.
+    pub(crate) fn make_contiguous(&mut self, location: EntryLocation,
+                                  start: Cluster, clusters: u32)
+        -> Result<Cluster, AllocateError>
+    {
+        let old_chain = self.chain(start)?;
+        let len = old_chain.len() as u32;
+        let target = if clusters > len { clusters } else { len };
+        let contiguous = Fragmentation::of(&old_chain).is_contiguous();
+
+        if contiguous && len == target {
+            return Ok(start);
+        }
+
+        self.invalidate_fsinfo()?;
+
+        if contiguous && len > 0 {
+            let last = old_chain[old_chain.len() - 1].fat_index();
+            if self.is_free_run(last + 1, target - len)? {
+                // Link the new tail before attaching it to the chain.
+                self.link_run(last + 1, target - len)?;
+                self.sync()?;
+                self.set_fat_entry(Cluster::from(last), last + 1)?;
+                self.sync()?;
+                return Ok(start);
+            }
+        }
+
+        let new_start = self.find_free_run(target)?.ok_or(AllocateError::NoSpace)?;
+
+        let mut buf = vec![0u8; self.cluster_size()];
+        for i in 0..target {
+            if (i as usize) < old_chain.len() {
+                self.read_cluster(old_chain[i as usize], 0, &mut buf)?;
+            } else {
+                for byte in buf.iter_mut() {
+                    *byte = 0;
+                }
+            }
+
+            self.write_cluster(Cluster::from(new_start.fat_index() + i), &buf)?;
+        }
+
+        self.link_run(new_start.fat_index(), target)?;
+        self.sync()?;
+
+        self.set_entry_cluster(location, new_start)?;
+        self.sync()?;
+
+        for cluster in old_chain {
+            self.set_fat_entry(cluster, 0)?;
+        }
+        self.sync()?;
+
+        Ok(new_start)
+    }
 }
 
 impl<'a> FileSystem for &'a Shared<VFat> {
@@ -196,32 +657,8 @@ impl<'a> FileSystem for &'a Shared<VFat> {
     type Entry = Entry;
 
     fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
-        use vfat::{Entry, Metadata};
-        use std::path::Component;
-
-        let root_cluster = self.borrow().root_dir_cluster;
-        let mut dir = Entry::new_dir("".to_string(), Metadata::default(),
-                                     Dir::new(root_cluster, self.clone()));
-
-        for segment in path.as_ref().components() {
-            match segment {
-                Component::ParentDir => {
-                    use traits::Entry;
-                    dir = dir.into_dir().ok_or(
-                        io::Error::new(io::ErrorKind::NotFound,
-                                       "Expected dir"))?.find("..")?;
-                },
-                Component::Normal(name) => {
-                    use traits::Entry;
-                    dir = dir.into_dir().ok_or(
-                        io::Error::new(io::ErrorKind::NotFound,
-                                       "Expected dir"))?.find(name)?;
-                }
-                _ => (),
-            }
-        }
-
-        Ok(dir)
+        // Relative paths are resolved from the root directory too.
+        Dir::root(self.clone()).open_at(path)
     }
 
     fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
@@ -268,6 +705,8 @@ mod tests {
             mbr[p1_offset + 4] = 0xB; // Fat32.
              // Relative sector, 4-byte little endian so write to lowest byte.
             mbr[p1_offset + 8] = 2;
+            // Total sectors: the rest of the image.
+            mbr[p1_offset + 12] = 14;
             // Signature.
             mbr[510] = 0x55;
             mbr[511] = 0xAA;
@@ -282,10 +721,12 @@ mod tests {
             let fat_count = 1u8;
             let sectors_per_fat: [u8; 4] = mem::transmute(2u32);
             let root_cluster: [u8; 4] = mem::transmute(2u32);
+            let logical_sectors: [u8; 4] = mem::transmute(7u32);
             ebpb[11..13].copy_from_slice(&bytes_per_sector);
             ebpb[13] = sectors_per_cluster;
             ebpb[14..16].copy_from_slice(&reserved_sectors);
             ebpb[16] = fat_count;
+            ebpb[32..36].copy_from_slice(&logical_sectors);
             ebpb[36..40].copy_from_slice(&sectors_per_fat);
             ebpb[44..48].copy_from_slice(&root_cluster);
 
@@ -304,5 +745,291 @@ mod tests {
         assert_eq!(vfat.sectors_per_fat, 2);
         assert_eq!(vfat.fat_start_sector, 3); // 2 physical + 1 logical.
         assert_eq!(vfat.data_start_sector, 5); // 2 physical + 3 logical.
+        assert_eq!(vfat.cluster_count, 2);
+    }
+
+    #[test]
+    fn test_invalid_geometry() {
+        use image::ImageBuilder;
+
+        // Without a backup boot sector to fall back to.
+        let mut image = ImageBuilder::new();
+        image.ebpb_mut()[50..52].copy_from_slice(&[0, 0]);
+        image.ebpb_mut()[13] = 0;
+        match VFat::from(Cursor::new(image.build())) {
+            Err(Error::InvalidGeometry { field: "sectors_per_cluster", .. }) => (),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        let mut image = ImageBuilder::new();
+        image.ebpb_mut()[50..52].copy_from_slice(&[0, 0]);
+        image.ebpb_mut()[11] = 0;
+        image.ebpb_mut()[12] = 0;
+        match VFat::from(Cursor::new(image.build())) {
+            Err(Error::InvalidGeometry { field: "bytes_per_sector", .. }) => (),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_chain_loop() {
+        use traits::{Dir, Entry};
+        use image::{ImageBuilder, ROOT_CLUSTER};
+
+        let mut image = ImageBuilder::new();
+        let a = image.add_dir(ROOT_CLUSTER, "a");
+        image.set_fat(a, a);
+        let vfat = image.mount();
+
+        let dir = vfat.open("/a").expect("open /a").into_dir().expect("dir");
+        let error = dir.entries().err().expect("loop detected");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_cluster_out_of_range() {
+        use std::io::Read;
+        use traits::Entry;
+        use image::{ImageBuilder, ROOT_CLUSTER, ATTR_ARCHIVE};
+
+        let mut image = ImageBuilder::new();
+        image.add_entry(ROOT_CLUSTER, "zero.bin", ATTR_ARCHIVE, 0, 100);
+        image.add_entry(ROOT_CLUSTER, "far.bin", ATTR_ARCHIVE, 0x0FFFFFF, 100);
+        let vfat = image.mount();
+
+        let mut buf = [0u8; 100];
+        for path in ["/zero.bin", "/far.bin"].iter() {
+            let mut file = vfat.open(path).expect("open").into_file()
+                .expect("file");
+            let error = file.read(&mut buf).expect_err("invalid cluster");
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    fn nested_image() -> Shared<VFat> {
+        use image::{ImageBuilder, ROOT_CLUSTER};
+
+        let mut image = ImageBuilder::new();
+        let a = image.add_dir(ROOT_CLUSTER, "a");
+        let b = image.add_dir(a, "b");
+        image.add_file(b, "c.txt", b"hello");
+        image.add_file(ROOT_CLUSTER, "d.txt", b"world");
+        image.mount()
+    }
+
+    fn cluster_of(entry: &Entry) -> Cluster {
+        entry.to_dentry().cluster
+    }
+
+    #[test]
+    fn test_open_normalizes_path() {
+        use traits::Entry;
+
+        let vfat = nested_image();
+        let root = vfat.borrow().root_dir_cluster();
+
+        let entry = vfat.open("/a/./b/../b/c.txt").expect("open c.txt");
+        assert_eq!(entry.name(), "C.TXT");
+
+        let entry = vfat.open("/a/..").expect("open /a/..");
+        assert_eq!(cluster_of(&entry), root);
+
+        let entry = vfat.open("/../../d.txt").expect("open above root");
+        assert_eq!(entry.name(), "D.TXT");
+
+        let entry = vfat.open("a/b/c.txt").expect("relative path");
+        assert_eq!(entry.name(), "C.TXT");
+
+        let error = vfat.open("/d.txt/b").expect_err("file as dir");
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_open_at() {
+        use traits::Entry;
+
+        let vfat = nested_image();
+        let root = vfat.borrow().root_dir_cluster();
+        let a = vfat.open_dir("/a").expect("open /a");
+
+        let entry = a.open_at("b/c.txt").expect("relative open");
+        assert_eq!(entry.name(), "C.TXT");
+
+        // `..` from a first-level directory resolves through the on-disk `..`
+        // entry, which records the root directory as cluster 0.
+        let entry = a.open_at("..").expect("parent of /a");
+        assert_eq!(cluster_of(&entry), root);
+
+        let entry = a.open_at("../../d.txt").expect("above root");
+        assert_eq!(entry.name(), "D.TXT");
+
+        let entry = a.open_at("/d.txt").expect("absolute open");
+        assert_eq!(entry.name(), "D.TXT");
+
+        let entry = a.open_at("").expect("empty path");
+        assert_eq!(cluster_of(&entry), a.cluster());
+    }
+
+    #[test]
+    fn test_dentry_cache() {
+        use traits::Entry;
+
+        let vfat = nested_image();
+        let root = vfat.borrow().root_dir_cluster();
+        vfat.open("/a/b/c.txt").expect("open c.txt");
+
+        let a = vfat.borrow_mut().cached_dentry(root, "A").expect("cached a");
+        assert!(vfat.borrow_mut().cached_dentry(a.cluster, "b").is_some());
+
+        // Cached lookups reconstruct the same entry.
+        let entry = vfat.open("/A/B/C.TXT").expect("cached open");
+        assert_eq!(entry.name(), "C.TXT");
+
+        vfat.borrow_mut().invalidate_dir(a.cluster);
+        assert!(vfat.borrow_mut().cached_dentry(a.cluster, "b").is_none());
+        assert!(vfat.borrow_mut().cached_dentry(root, "a").is_some());
+    }
+
+    fn read_all(vfat: &Shared<VFat>, path: &str) -> io::Result<Vec<u8>> {
+        use std::io::Read;
+
+        let mut data = Vec::new();
+        vfat.open_file(path)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn test_fat_read_fallback() {
+        use image::{ImageBuilder, FaultyImage, ROOT_CLUSTER, SECTORS_PER_FAT};
+
+        let data = vec![7u8; 1500];
+        let mut image = ImageBuilder::new();
+        image.add_file(ROOT_CLUSTER, "a.bin", &data);
+        let image = image.build();
+
+        // The first FAT is unreadable; the second is used.
+        let first_fat = ImageBuilder::fat_start_sector() as u64;
+        let device = FaultyImage::new(image.clone(), vec![first_fat]);
+        let vfat = VFat::from(device).expect("mount");
+        assert_eq!(read_all(&vfat, "/a.bin").expect("read"), data);
+
+        let second_fat = first_fat + SECTORS_PER_FAT as u64;
+        let device = FaultyImage::new(image, vec![first_fat, second_fat]);
+        let vfat = VFat::from(device).expect("mount");
+        assert!(read_all(&vfat, "/a.bin").is_err());
+    }
+
+    #[test]
+    fn test_unmirrored_fat() {
+        use image::{ImageBuilder, SharedImage, ROOT_CLUSTER, FAT_EOC};
+
+        let data = vec![3u8; 1500];
+        let mut image = ImageBuilder::new();
+        let first = image.add_file(ROOT_CLUSTER, "a.bin", &data);
+        // No mirroring, FAT 1 is active; FAT 0 holds a stale, broken chain.
+        image.ebpb_mut()[40] = 0x81;
+        image.set_fat_copy(0, first, 0);
+        let image = SharedImage::new(image.build());
+
+        {
+            let vfat = image.mount();
+            assert_eq!(read_all(&vfat, "/a.bin").expect("read"), data);
+
+            let mut file = vfat.open_file("/a.bin").expect("file");
+            file.allocate(4 * 512).expect("allocate");
+        }
+
+        // Only the active FAT was written.
+        let fats = image.fats();
+        let entry = |fat: &[u8], cluster: u32| {
+            let offset = cluster as usize * 4;
+            (0..4).fold(0u32, |acc, i| acc | (fat[offset + i] as u32) << (8 * i))
+        };
+        assert_eq!(entry(&fats[0], first), 0);
+        assert_eq!(entry(&fats[0], first + 3), 0);
+        assert_eq!(entry(&fats[1], first + 2), first + 3);
+        assert_eq!(entry(&fats[1], first + 3), FAT_EOC);
+    }
+
+    /// Returns an image of 512-byte device sectors holding a partition at
+    /// device sector 2 with 1024-byte logical sectors, and a backup boot
+    /// sector at logical sector 6.
+    fn large_sector_image() -> Vec<u8> {
+        let write_u32 = |buf: &mut [u8], offset: usize, value: u32| {
+            for i in 0..4 {
+                buf[offset + i] = (value >> (8 * i)) as u8;
+            }
+        };
+
+        let mut data = vec![0u8; (2 + 40 * 2) * 512];
+        data[446 + 4] = 0x0C;
+        write_u32(&mut data, 446 + 8, 2);
+        write_u32(&mut data, 446 + 12, 80);
+        data[510] = 0x55;
+        data[511] = 0xAA;
+
+        {
+            let ebpb = &mut data[1024..1536];
+            ebpb[11..13].copy_from_slice(&[0x00, 0x04]);
+            ebpb[13] = 1;
+            ebpb[14] = 8;
+            ebpb[16] = 1;
+            write_u32(ebpb, 32, 40);
+            write_u32(ebpb, 36, 1);
+            write_u32(ebpb, 44, 2);
+            ebpb[50] = DEFAULT_BACKUP_BOOT_SECTOR as u8;
+            ebpb[510] = 0x55;
+            ebpb[511] = 0xAA;
+        }
+
+        // Logical sector 6 is device sector 2 + 12.
+        let backup = (2 + 12) * 512;
+        let (head, tail) = data.split_at_mut(backup);
+        tail[..512].copy_from_slice(&head[1024..1536]);
+        data
+    }
+
+    #[test]
+    fn test_backup_boot_sector_large_sectors() {
+        let mut data = large_sector_image();
+        data[1024 + 13] = 0;
+        let vfat = VFat::from(Cursor::new(data)).expect("mount");
+        assert_eq!(vfat.borrow().bytes_per_sector, 1024);
+        assert_eq!(vfat.borrow().fat_start_sector, 2 + 8);
+
+        let mut data = large_sector_image();
+        data[1024 + 510] = 0;
+        let vfat = VFat::from(Cursor::new(data)).expect("mount");
+        assert_eq!(vfat.borrow().sectors_per_cluster, 1);
+    }
+
+    #[test]
+    fn test_backup_boot_sector() {
+        use image::{ImageBuilder, ROOT_CLUSTER, PARTITION_START,
+                    BACKUP_BOOT_SECTOR, SECTOR_SIZE};
+
+        let mut image = ImageBuilder::new();
+        image.add_file(ROOT_CLUSTER, "a.txt", b"backup");
+        image.ebpb_mut()[510] = 0;
+        let vfat = VFat::from(Cursor::new(image.build())).expect("mount");
+        assert_eq!(read_all(&vfat, "/a.txt").expect("read"), b"backup");
+
+        // The primary has a valid signature but no sectors; the backup it
+        // names is used.
+        let mut image = ImageBuilder::new();
+        image.add_file(ROOT_CLUSTER, "a.txt", b"backup");
+        image.ebpb_mut()[11..13].copy_from_slice(&[0, 0]);
+        let vfat = VFat::from(Cursor::new(image.build())).expect("mount");
+        assert_eq!(read_all(&vfat, "/a.txt").expect("read"), b"backup");
+
+        let mut image = ImageBuilder::new();
+        image.ebpb_mut()[510] = 0;
+        let mut data = image.build();
+        let backup = (PARTITION_START + BACKUP_BOOT_SECTOR) * SECTOR_SIZE;
+        data[backup + 510] = 0;
+        match VFat::from(Cursor::new(data)) {
+            Err(Error::BadSignature) => (),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
     }
 }
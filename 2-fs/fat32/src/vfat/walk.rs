@@ -0,0 +1,362 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use glob::Pattern;
+use path;
+use traits::{self, FileSystem};
+use vfat::{VFat, Shared, Cluster, Dir, Entry};
+use vfat::dir::DirIterator;
+
+/// The order in which a `Walk` yields directories relative to their contents.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Order {
+    /// A directory is yielded before its contents.
+    PreOrder,
+    /// A directory is yielded after its contents.
+    PostOrder,
+}
+
+/// An entry found while walking a directory tree.
+#[derive(Debug)]
+pub struct WalkEntry {
+    /// The path of the entry: the path of the walk's root joined with the
+    /// names of every directory leading to the entry.
+    pub path: PathBuf,
+    /// The entry itself.
+    pub entry: Entry,
+    /// The number of directories between the walk's root and the entry. The
+    /// root's own entries have depth 1.
+    pub depth: usize,
+}
+
+struct Frame {
+    path: PathBuf,
+    depth: usize,
+    entries: DirIterator,
+    /// For post-order walks, the directory to yield once `entries` is
+    /// exhausted.
+    dir: Option<WalkEntry>,
+}
+
+/// A recursive, depth-first iterator over the entries below a directory.
+///
+/// The root directory itself is not yielded, nor are `.` and `..` entries.
+/// Each directory is entered at most once, as identified by its first
+/// cluster, so a corrupted file system whose directories form a cycle cannot
+/// cause the walk to loop forever; a directory seen again is yielded but not
+/// descended into.
+///
+/// A directory whose entries can't be read is still yielded, along with an
+/// `Err` item for the error: after the directory in a pre-order walk, before
+/// it in a post-order one.
+pub struct Walk {
+    root: Option<(Dir, PathBuf)>,
+    stack: Vec<Frame>,
+    /// An item to yield before resuming the walk.
+    pending: Option<io::Result<WalkEntry>>,
+    visited: HashSet<Cluster>,
+    order: Order,
+    max_depth: Option<usize>,
+}
+
+/// Returns a pre-order `Walk` over the directory at absolute path `root`.
+///
+/// # Errors
+///
+/// Returns the errors of `FileSystem::open_dir()` for `root`.
+pub fn walk<P: AsRef<Path>>(vfat: &Shared<VFat>, root: P) -> io::Result<Walk> {
+    let dir = vfat.open_dir(root.as_ref())?;
+    Ok(Walk::new(dir, root.as_ref()))
+}
+
+impl Walk {
+    /// Returns a pre-order walk over the entries below `dir`, whose path is
+    /// `path`. Entry paths are joined to `path` once it is normalized.
+    pub fn new<P: AsRef<Path>>(dir: Dir, path: P) -> Walk {
+        Walk {
+            root: Some((dir, path::normalize(path))),
+            stack: Vec::new(),
+            pending: None,
+            visited: HashSet::new(),
+            order: Order::PreOrder,
+            max_depth: None,
+        }
+    }
+
+    /// Sets the order in which directories are yielded.
+    pub fn order(mut self, order: Order) -> Walk {
+        self.order = order;
+        self
+    }
+
+    /// Limits the walk to entries at most `depth` directories below the root.
+    /// A `depth` of 1 yields only the root's entries.
+    pub fn max_depth(mut self, depth: usize) -> Walk {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    fn next_entry(&mut self) -> Option<io::Result<WalkEntry>> {
+        if let Some(item) = self.pending.take() {
+            return Some(item);
+        }
+
+        if let Some((dir, path)) = self.root.take() {
+            self.visited.insert(dir.cluster());
+            match traits::Dir::entries(&dir) {
+                Ok(entries) => {
+                    self.stack.push(Frame { path, depth: 1, entries, dir: None });
+                },
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        loop {
+            let next = match self.stack.last_mut() {
+                Some(frame) => match frame.entries.next() {
+                    Some(entry) => {
+                        let path = frame.path.join(traits::Entry::name(&entry));
+                        Some((entry, path, frame.depth))
+                    },
+                    None => None,
+                },
+                None => return None,
+            };
+
+            let (entry, path, depth) = match next {
+                Some(next) => next,
+                None => {
+                    let frame = self.stack.pop().expect("non-empty stack");
+                    match frame.dir {
+                        Some(dir) => return Some(Ok(dir)),
+                        None => continue,
+                    }
+                }
+            };
+
+            let is_dot = {
+                let name = traits::Entry::name(&entry);
+                name == "." || name == ".."
+            };
+            if is_dot {
+                continue;
+            }
+
+            let descend = match self.max_depth {
+                Some(max_depth) => depth < max_depth,
+                None => true,
+            };
+
+            let cluster = traits::Entry::as_dir(&entry).map(|dir| dir.cluster());
+            match cluster {
+                Some(cluster) if descend && self.visited.insert(cluster) => (),
+                _ => return Some(Ok(WalkEntry { path, entry, depth })),
+            }
+
+            let entries = {
+                let dir = traits::Entry::as_dir(&entry).expect("directory");
+                traits::Dir::entries(dir)
+            };
+            let item = WalkEntry { path: path.clone(), entry, depth };
+            let entries = match entries {
+                Ok(entries) => entries,
+                Err(e) => {
+                    let (first, second) = match self.order {
+                        Order::PreOrder => (Ok(item), Err(e)),
+                        Order::PostOrder => (Err(e), Ok(item)),
+                    };
+                    self.pending = Some(second);
+                    return Some(first);
+                }
+            };
+
+            match self.order {
+                Order::PreOrder => {
+                    self.stack.push(Frame { path, depth: depth + 1, entries,
+                                            dir: None });
+                    return Some(Ok(item));
+                },
+                Order::PostOrder => {
+                    self.stack.push(Frame { path, depth: depth + 1, entries,
+                                            dir: Some(item) });
+                }
+            }
+        }
+    }
+
+    /// Returns an iterator over the entries of this walk whose paths match
+    /// `pattern`.
+    ///
+    /// If `pattern` is relative, it is matched against each entry's path
+    /// relative to the walk's root. If `pattern` cannot match paths deeper
+    /// than some depth, the walk does not descend further than that depth.
+    pub fn glob(mut self, pattern: Pattern) -> Glob {
+        if let Some(depth) = pattern.max_depth() {
+            let root_depth = match (pattern.is_absolute(), &self.root) {
+                (true, &Some((_, ref path))) => {
+                    path.components().count().saturating_sub(1)
+                },
+                _ => 0,
+            };
+
+            let depth = depth.saturating_sub(root_depth);
+            self.max_depth = Some(match self.max_depth {
+                Some(max_depth) if max_depth < depth => max_depth,
+                _ => depth,
+            });
+        }
+
+        let base = match self.root {
+            Some((_, ref path)) => path.clone(),
+            None => PathBuf::new(),
+        };
+
+        Glob { walk: self, pattern, base }
+    }
+}
+
+impl Iterator for Walk {
+    type Item = io::Result<WalkEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry()
+    }
+}
+
+/// An iterator over the entries of a `Walk` that match a glob `Pattern`.
+pub struct Glob {
+    walk: Walk,
+    pattern: Pattern,
+    base: PathBuf,
+}
+
+impl Glob {
+    fn is_match(&self, path: &Path) -> bool {
+        if self.pattern.is_absolute() {
+            self.pattern.matches_path(path)
+        } else {
+            match path.strip_prefix(&self.base) {
+                Ok(relative) => self.pattern.matches_path(relative),
+                Err(_) => false,
+            }
+        }
+    }
+}
+
+impl Iterator for Glob {
+    type Item = io::Result<WalkEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.walk.next() {
+                Some(Ok(item)) => {
+                    if self.is_match(&item.path) {
+                        return Some(Ok(item));
+                    }
+                },
+                other => return other,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuilder, ROOT_CLUSTER, ATTR_DIRECTORY};
+
+    fn tree() -> Shared<VFat> {
+        let mut image = ImageBuilder::new();
+        let a = image.add_dir(ROOT_CLUSTER, "a");
+        let b = image.add_dir(a, "b");
+        image.add_file(b, "c.txt", b"c");
+        image.add_file(a, "d.rs", b"d");
+        image.add_file(ROOT_CLUSTER, "e.txt", b"e");
+        image.mount()
+    }
+
+    fn paths<I: Iterator<Item = io::Result<WalkEntry>>>(iter: I) -> Vec<String> {
+        iter.map(|item| {
+            let item = item.expect("walk entry");
+            format!("{}:{}", item.path.display(), item.depth)
+        }).collect()
+    }
+
+    #[test]
+    fn test_pre_order() {
+        let vfat = tree();
+        assert_eq!(paths(walk(&vfat, "/").expect("walk")),
+                   vec!["/A:1", "/A/B:2", "/A/B/C.TXT:3", "/A/D.RS:2",
+                        "/E.TXT:1"]);
+    }
+
+    #[test]
+    fn test_post_order() {
+        let vfat = tree();
+        let walk = walk(&vfat, "/a").expect("walk").order(Order::PostOrder);
+        assert_eq!(paths(walk), vec!["/a/B/C.TXT:2", "/a/B:1", "/a/D.RS:1"]);
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let vfat = tree();
+        let walk = walk(&vfat, "/").expect("walk").max_depth(2);
+        assert_eq!(paths(walk), vec!["/A:1", "/A/B:2", "/A/D.RS:2", "/E.TXT:1"]);
+    }
+
+    #[test]
+    fn test_cycle() {
+        let mut image = ImageBuilder::new();
+        let a = image.add_dir(ROOT_CLUSTER, "a");
+        let b = image.add_dir(a, "b");
+        // A corrupted entry pointing back at an ancestor directory.
+        image.add_entry(b, "loop", ATTR_DIRECTORY, a, 0);
+        let vfat = image.mount();
+
+        assert_eq!(paths(walk(&vfat, "/").expect("walk")),
+                   vec!["/A:1", "/A/B:2", "/A/B/LOOP:3"]);
+    }
+
+    #[test]
+    fn test_normalized_root() {
+        let vfat = tree();
+        assert_eq!(paths(walk(&vfat, "/a/../a/./").expect("walk")),
+                   vec!["/a/B:1", "/a/B/C.TXT:2", "/a/D.RS:1"]);
+    }
+
+    #[test]
+    fn test_unreadable_dir() {
+        let mut image = ImageBuilder::new();
+        // A directory whose first cluster is past the end of the FAT.
+        image.add_entry(ROOT_CLUSTER, "bad", ATTR_DIRECTORY, 500, 0);
+        image.add_file(ROOT_CLUSTER, "e.txt", b"e");
+        let vfat = image.mount();
+
+        let items: Vec<_> = walk(&vfat, "/").expect("walk").collect();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].as_ref().expect("dir").path, Path::new("/BAD"));
+        assert!(items[1].is_err());
+        assert_eq!(items[2].as_ref().expect("file").path, Path::new("/E.TXT"));
+
+        let walk = walk(&vfat, "/").expect("walk").order(Order::PostOrder);
+        let items: Vec<_> = walk.collect();
+        assert!(items[0].is_err());
+        assert_eq!(items[1].as_ref().expect("dir").path, Path::new("/BAD"));
+    }
+
+    #[test]
+    fn test_glob() {
+        let vfat = tree();
+        let pattern = |s: &str| Pattern::new(s).expect("pattern").case_insensitive();
+
+        let glob = walk(&vfat, "/").expect("walk").glob(pattern("**/*.txt"));
+        assert_eq!(paths(glob), vec!["/A/B/C.TXT:3", "/E.TXT:1"]);
+
+        let glob = walk(&vfat, "/a").expect("walk").glob(pattern("*"));
+        assert_eq!(paths(glob), vec!["/a/B:1", "/a/D.RS:1"]);
+
+        let glob = walk(&vfat, "/a").expect("walk").glob(pattern("/a/*/c.*"));
+        assert_eq!(paths(glob), vec!["/a/B/C.TXT:2"]);
+    }
+}
@@ -0,0 +1,251 @@
+//! Boot images: a payload, compressed or not, behind a header saying where
+//! to load it, where to start it and how to check it. `ttywrite pack` makes
+//! them and the bootloader loads them.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(all(test, not(feature = "std")))]
+#[macro_use]
+extern crate std;
+
+extern crate lz4;
+
+#[cfg(test)]
+mod tests;
+#[cfg(any(test, feature = "std"))]
+mod pack;
+
+#[cfg(any(test, feature = "std"))]
+pub use pack::pack;
+
+use core::fmt;
+
+/// The first bytes of a boot image.
+pub const MAGIC: [u8; 4] = *b"PIMG";
+
+/// The size of the header of each version, in bytes. Uncompressed payloads
+/// get a version 1 header, compressed ones a version 2 header.
+pub const V1_HEADER_SIZE: usize = 32;
+pub const V2_HEADER_SIZE: usize = 40;
+
+/// The values of the version 2 header's compression.
+const COMPRESSION_NONE: u32 = 0;
+const COMPRESSION_LZ4: u32 = 1;
+
+/// How the payload of a boot image is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    /// An LZ4 block.
+    Lz4,
+}
+
+/// The header at the start of a boot image, followed by the stored payload.
+/// All fields are little-endian:
+///
+/// | offset | size | field                                      |
+/// |--------|------|--------------------------------------------|
+/// | 0      | 4    | magic, `PIMG`                              |
+/// | 4      | 4    | version, 1 or 2                            |
+/// | 8      | 8    | address the payload is loaded to           |
+/// | 16     | 8    | entry point                                |
+/// | 24     | 4    | payload length, in bytes                   |
+/// | 28     | 4    | CRC-32 (IEEE 802.3) of the payload         |
+/// | 32     | 4    | version 2: compression, 0 (none) or 1 (LZ4)|
+/// | 36     | 4    | version 2: stored payload length, in bytes |
+///
+/// The payload of version 1 images is stored uncompressed. The length and the
+/// CRC-32 are those of the payload once decompressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    pub load_addr: u64,
+    pub entry: u64,
+    pub length: u32,
+    pub crc: u32,
+    pub compression: Compression,
+    pub stored: u32,
+}
+
+/// Why a boot image was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Fewer bytes than a header were received.
+    Truncated(usize),
+    /// The image doesn't start with `MAGIC`: it may be a raw binary.
+    BadMagic,
+    /// The header has an unknown version.
+    BadVersion(u32),
+    /// The header has an unknown compression.
+    BadCompression(u32),
+    /// The stored payload is longer than what was received.
+    Incomplete { stored: u32, received: usize },
+    /// The payload doesn't fit in the memory it may be loaded to.
+    BadLoadAddress { load_addr: u64, length: u32 },
+    /// The entry point is outside of the payload.
+    BadEntry(u64),
+    /// The payload couldn't be decompressed.
+    Decompress(lz4::Error),
+    /// The payload's length doesn't match the header's once decompressed.
+    BadLength { expected: u32, actual: usize },
+    /// The payload's CRC-32 doesn't match the header's.
+    BadCrc { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Truncated(received) => {
+                write!(f, "received {} bytes, less than a header", received)
+            }
+            Error::BadMagic => write!(f, "bad magic: not a boot image"),
+            Error::BadVersion(version) => write!(f, "unsupported header version {}", version),
+            Error::BadCompression(compression) => {
+                write!(f, "unsupported compression {}", compression)
+            }
+            Error::Incomplete { stored, received } => {
+                write!(f, "payload of {} bytes but only {} received", stored, received)
+            }
+            Error::BadLoadAddress { load_addr, length } => {
+                write!(f, "payload of {} bytes can't be loaded at {:#x}", length, load_addr)
+            }
+            Error::BadEntry(entry) => write!(f, "entry point {:#x} outside of the payload", entry),
+            Error::Decompress(ref e) => write!(f, "{}", e),
+            Error::BadLength { expected, actual } => {
+                write!(f, "payload of {} bytes once decompressed, expected {}", actual, expected)
+            }
+            Error::BadCrc { expected, actual } => {
+                write!(f, "CRC-32 mismatch: expected {:#010x}, got {:#010x}", expected, actual)
+            }
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    (0..4).fold(0, |value, i| value | (bytes[offset + i] as u32) << (8 * i))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    read_u32(bytes, offset) as u64 | (read_u32(bytes, offset + 4) as u64) << 32
+}
+
+impl Header {
+    /// Parses the header at the start of `image`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Truncated` if `image` is shorter than its header, `BadMagic`
+    /// if it doesn't start with `MAGIC`, and `BadVersion` or `BadCompression`
+    /// if the header isn't supported.
+    pub fn parse(image: &[u8]) -> Result<Header, Error> {
+        if image.len() < 8 {
+            return Err(Error::Truncated(image.len()));
+        }
+
+        if image[..4] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        let version = read_u32(image, 4);
+        let size = match version {
+            1 => V1_HEADER_SIZE,
+            2 => V2_HEADER_SIZE,
+            _ => return Err(Error::BadVersion(version)),
+        };
+
+        if image.len() < size {
+            return Err(Error::Truncated(image.len()));
+        }
+
+        let length = read_u32(image, 24);
+        let (compression, stored) = match version {
+            1 => (Compression::None, length),
+            _ => match read_u32(image, 32) {
+                COMPRESSION_NONE => (Compression::None, read_u32(image, 36)),
+                COMPRESSION_LZ4 => (Compression::Lz4, read_u32(image, 36)),
+                other => return Err(Error::BadCompression(other)),
+            },
+        };
+
+        Ok(Header {
+            version,
+            load_addr: read_u64(image, 8),
+            entry: read_u64(image, 16),
+            length,
+            crc: read_u32(image, 28),
+            compression,
+            stored,
+        })
+    }
+
+    /// Returns the size of the header, in bytes.
+    pub fn size(&self) -> usize {
+        match self.version {
+            1 => V1_HEADER_SIZE,
+            _ => V2_HEADER_SIZE,
+        }
+    }
+}
+
+/// Loads the payload of the boot image `image`, everything received including
+/// any padding, into `memory`, which starts at the address `base`: the
+/// payload is decompressed if needed, then its length and CRC-32 are checked.
+/// Returns the header.
+///
+/// Nothing is written unless the payload fits in `memory` with the entry
+/// point inside of it. `memory` must not overlap `image`.
+pub fn load(image: &[u8], memory: &mut [u8], base: usize) -> Result<Header, Error> {
+    let header = Header::parse(image)?;
+    let received = image.len() - header.size();
+    if header.stored as usize > received {
+        return Err(Error::Incomplete { stored: header.stored, received });
+    }
+
+    let (load_addr, entry, length) = (header.load_addr, header.entry, header.length);
+    let limit = (base + memory.len()) as u64;
+    let end = match load_addr.checked_add(length as u64) {
+        Some(end) if load_addr >= base as u64 && end <= limit => end,
+        _ => return Err(Error::BadLoadAddress { load_addr, length }),
+    };
+
+    if entry < load_addr || entry >= end {
+        return Err(Error::BadEntry(entry));
+    }
+
+    let stored = &image[header.size()..header.size() + header.stored as usize];
+    let start = load_addr as usize - base;
+    let payload = &mut memory[start..start + length as usize];
+    let actual = match header.compression {
+        Compression::None if stored.len() == payload.len() => {
+            payload.copy_from_slice(stored);
+            stored.len()
+        }
+        Compression::None => stored.len(),
+        Compression::Lz4 => lz4::decompress(stored, payload).map_err(Error::Decompress)?,
+    };
+
+    if actual != length as usize {
+        return Err(Error::BadLength { expected: length, actual });
+    }
+
+    let crc = crc32(payload);
+    if crc != header.crc {
+        return Err(Error::BadCrc { expected: header.crc, actual: crc });
+    }
+
+    Ok(header)
+}
+
+/// Computes the CRC-32 (IEEE 802.3) of `data`, as stored in headers.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        let mut crc = crc ^ byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+
+        crc
+    })
+}
@@ -0,0 +1,61 @@
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+use lz4;
+use {crc32, COMPRESSION_LZ4, MAGIC, V1_HEADER_SIZE, V2_HEADER_SIZE};
+
+/// The first bytes of ELF files, which the bootloader loads as they are.
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+
+/// Returns the boot image of `payload`, loaded at `load_addr` and started at
+/// `entry`: a header followed by the payload, compressed into an LZ4 block if
+/// `compress` is set.
+///
+/// # Errors
+///
+/// Returns an error if the payload is an ELF file, if it doesn't fit in a
+/// header's 32-bit length, or if `entry` is outside of it.
+pub fn pack(payload: &[u8], load_addr: u64, entry: u64, compress: bool)
+    -> Result<Vec<u8>, String>
+{
+    if payload.starts_with(&ELF_MAGIC) {
+        return Err("send ELF files as they are: the bootloader loads them itself".to_string());
+    }
+
+    if payload.len() as u64 >> 32 != 0 {
+        return Err(format!("payload of {} bytes is too large", payload.len()));
+    }
+
+    if entry < load_addr || entry - load_addr >= payload.len() as u64 {
+        return Err(format!("entry point {:#x} is outside of the payload at {:#x}",
+                           entry, load_addr));
+    }
+
+    let compressed;
+    let (version, header_size, stored) = if compress {
+        compressed = lz4::compress(payload);
+        (2, V2_HEADER_SIZE, &compressed[..])
+    } else {
+        (1, V1_HEADER_SIZE, payload)
+    };
+
+    let mut image = Vec::with_capacity(header_size + stored.len());
+    image.extend_from_slice(&MAGIC);
+    image.extend_from_slice(&le_bytes(version, 4));
+    image.extend_from_slice(&le_bytes(load_addr, 8));
+    image.extend_from_slice(&le_bytes(entry, 8));
+    image.extend_from_slice(&le_bytes(payload.len() as u64, 4));
+    image.extend_from_slice(&le_bytes(crc32(payload) as u64, 4));
+    if compress {
+        image.extend_from_slice(&le_bytes(COMPRESSION_LZ4 as u64, 4));
+        image.extend_from_slice(&le_bytes(stored.len() as u64, 4));
+    }
+
+    image.extend_from_slice(stored);
+    Ok(image)
+}
+
+/// Returns the `len` low bytes of `value`, least significant first.
+pub(crate) fn le_bytes(value: u64, len: usize) -> Vec<u8> {
+    (0..len).map(|i| (value >> (8 * i)) as u8).collect()
+}
@@ -0,0 +1,205 @@
+use std::vec::Vec;
+
+use lz4;
+use pack::le_bytes;
+use {crc32, load, pack, Compression, Error, Header, V1_HEADER_SIZE, V2_HEADER_SIZE};
+
+/// The address the tests' memory starts at, and its size.
+const BASE: usize = 0x80000;
+const MEMORY: usize = 8192;
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 64) as u8).collect()
+}
+
+/// Pads `image` with zeroes to a whole number of 128-byte XMODEM packets.
+fn padded(mut image: Vec<u8>) -> Vec<u8> {
+    let padding = (128 - image.len() % 128) % 128;
+    let len = image.len() + padding;
+    image.resize(len, 0);
+    image
+}
+
+/// Sets the header field at `offset` of `image` to `value`.
+fn patch(image: &mut [u8], offset: usize, value: u64, len: usize) {
+    image[offset..offset + len].copy_from_slice(&le_bytes(value, len));
+}
+
+#[test]
+fn header() {
+    let image = pack(b"kernel", 0x80000, 0x80004, false).expect("valid image");
+    assert_eq!(image.len(), V1_HEADER_SIZE + 6);
+    assert_eq!(&image[..4], b"PIMG");
+    assert_eq!(&image[4..8], &[1, 0, 0, 0]);
+    assert_eq!(&image[8..16], &[0x00, 0x00, 0x08, 0, 0, 0, 0, 0]);
+    assert_eq!(&image[16..24], &[0x04, 0x00, 0x08, 0, 0, 0, 0, 0]);
+    assert_eq!(&image[24..28], &[6, 0, 0, 0]);
+    assert_eq!(&image[28..32], &le_bytes(crc32(b"kernel") as u64, 4)[..]);
+    assert_eq!(&image[32..], b"kernel");
+
+    assert_eq!(Header::parse(&image), Ok(Header {
+        version: 1,
+        load_addr: 0x80000,
+        entry: 0x80004,
+        length: 6,
+        crc: crc32(b"kernel"),
+        compression: Compression::None,
+        stored: 6,
+    }));
+}
+
+#[test]
+fn compressed() {
+    let payload = pattern(4096);
+    let image = pack(&payload, 0x80000, 0x80000, true).expect("valid image");
+    assert!(image.len() < V2_HEADER_SIZE + 256);
+    assert_eq!(&image[4..8], &[2, 0, 0, 0]);
+    assert_eq!(&image[24..28], &[0, 0x10, 0, 0]);
+    assert_eq!(&image[28..32], &le_bytes(crc32(&payload) as u64, 4)[..]);
+    assert_eq!(&image[32..36], &[1, 0, 0, 0]);
+    assert_eq!(&image[36..40], &le_bytes((image.len() - V2_HEADER_SIZE) as u64, 4)[..]);
+
+    let mut decompressed = vec![0; payload.len()];
+    assert_eq!(lz4::decompress(&image[V2_HEADER_SIZE..], &mut decompressed), Ok(4096));
+    assert_eq!(decompressed, payload);
+}
+
+#[test]
+fn crc() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+}
+
+#[test]
+fn pack_errors() {
+    assert!(pack(b"kernel", 0x80000, 0x7fffc, false).is_err());
+    assert!(pack(b"kernel", 0x80000, 0x80006, true).is_err());
+    assert!(pack(b"", 0x80000, 0x80000, false).is_err());
+
+    let elf = b"\x7fELF\x02\x01\x01";
+    assert!(pack(elf, 0x80000, 0x80000, false).unwrap_err().contains("ELF"));
+}
+
+#[test]
+fn parse_errors() {
+    let image = pack(b"kernel", 0x80000, 0x80000, false).expect("valid image");
+    assert_eq!(Header::parse(&image[..7]), Err(Error::Truncated(7)));
+    assert_eq!(Header::parse(&image[..31]), Err(Error::Truncated(31)));
+    assert_eq!(Header::parse(b"\x7fELF\x02\x01\x01\x00"), Err(Error::BadMagic));
+
+    let mut bad = image.clone();
+    patch(&mut bad, 4, 3, 4);
+    assert_eq!(Header::parse(&bad), Err(Error::BadVersion(3)));
+
+    let image = pack(&pattern(256), 0x80000, 0x80000, true).expect("valid image");
+    assert_eq!(Header::parse(&image[..39]), Err(Error::Truncated(39)));
+
+    let mut bad = image.clone();
+    patch(&mut bad, 32, 2, 4);
+    assert_eq!(Header::parse(&bad), Err(Error::BadCompression(2)));
+}
+
+#[test]
+fn loads_padded_images() {
+    for &compress in &[false, true] {
+        let payload = pattern(3000);
+        let image = padded(pack(&payload, 0x80100, 0x80104, compress).expect("valid image"));
+
+        let mut memory = vec![0xAA; MEMORY];
+        let header = load(&image, &mut memory, BASE).expect("valid image");
+        assert_eq!((header.entry, header.length), (0x80104, 3000));
+        assert_eq!(&memory[0x100..0x100 + 3000], &payload[..]);
+
+        // Neither the padding nor anything past the payload is written.
+        assert!(memory[..0x100].iter().all(|&byte| byte == 0xAA));
+        assert!(memory[0x100 + 3000..].iter().all(|&byte| byte == 0xAA));
+    }
+}
+
+#[test]
+fn incomplete() {
+    let image = pack(b"kernel", 0x80000, 0x80000, false).expect("valid image");
+    let mut memory = vec![0; MEMORY];
+    assert_eq!(load(&image[..V1_HEADER_SIZE + 4], &mut memory, BASE),
+               Err(Error::Incomplete { stored: 6, received: 4 }));
+    assert_eq!(load(&image[..V1_HEADER_SIZE + 3], &mut memory, BASE),
+               Err(Error::Incomplete { stored: 6, received: 3 }));
+}
+
+#[test]
+fn bad_load_address() {
+    let mut memory = vec![0xAA; MEMORY];
+    let mut check = |load_addr: u64, length: usize| {
+        let image = pack(&pattern(length), load_addr, load_addr, false).expect("valid image");
+        let error = Error::BadLoadAddress { load_addr, length: length as u32 };
+        assert_eq!(load(&image, &mut memory, BASE), Err(error));
+    };
+
+    // Before the memory, past its end, and wrapping around.
+    check(BASE as u64 - 4, 16);
+    check((BASE + MEMORY) as u64 - 8, 16);
+    check(BASE as u64, MEMORY + 1);
+    check(!0 - 4, 16);
+
+    assert!(memory.iter().all(|&byte| byte == 0xAA));
+}
+
+#[test]
+fn bad_entry() {
+    let mut memory = vec![0xAA; MEMORY];
+    for &entry in &[0x7fffc, 0x80006, 0] {
+        let mut image = pack(b"kernel", 0x80000, 0x80000, false).expect("valid image");
+        patch(&mut image, 16, entry, 8);
+        assert_eq!(load(&image, &mut memory, BASE), Err(Error::BadEntry(entry)));
+    }
+
+    assert!(memory.iter().all(|&byte| byte == 0xAA));
+}
+
+#[test]
+fn bad_length() {
+    let mut memory = vec![0; MEMORY];
+
+    // A compressed payload shorter than the header says.
+    let payload = pattern(4096);
+    let mut image = pack(&payload, 0x80000, 0x80000, true).expect("valid image");
+    patch(&mut image, 24, 4097, 4);
+    assert_eq!(load(&image, &mut memory, BASE),
+               Err(Error::BadLength { expected: 4097, actual: 4096 }));
+
+    // An uncompressed version 2 payload whose stored length isn't its length.
+    let mut image = pack(&payload, 0x80000, 0x80000, true).expect("valid image");
+    let stored = (image.len() - V2_HEADER_SIZE) as u64;
+    patch(&mut image, 32, 0, 4);
+    assert_eq!(load(&image, &mut memory, BASE),
+               Err(Error::BadLength { expected: 4096, actual: stored as usize }));
+}
+
+#[test]
+fn bad_crc() {
+    let mut memory = vec![0; MEMORY];
+    for &compress in &[false, true] {
+        let mut image = padded(pack(b"kernel", 0x80000, 0x80000, compress).expect("valid image"));
+        let expected = crc32(b"kernel");
+        patch(&mut image, 28, expected as u64 ^ 1, 4);
+        assert_eq!(load(&image, &mut memory, BASE),
+                   Err(Error::BadCrc { expected: expected ^ 1, actual: expected }));
+    }
+
+    let mut image = pack(b"kernel", 0x80000, 0x80000, false).expect("valid image");
+    image[V1_HEADER_SIZE] ^= 0x20;
+    assert_eq!(load(&image, &mut memory, BASE),
+               Err(Error::BadCrc { expected: crc32(b"kernel"), actual: crc32(b"Kernel") }));
+}
+
+#[test]
+fn decompress_error() {
+    let mut memory = vec![0; MEMORY];
+    let mut image = pack(&pattern(4096), 0x80000, 0x80000, true).expect("valid image");
+    let stored = image.len() - V2_HEADER_SIZE;
+    patch(&mut image, 36, stored as u64 - 1, 4);
+    match load(&image, &mut memory, BASE) {
+        Err(Error::Decompress(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
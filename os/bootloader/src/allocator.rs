@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::heap::{Alloc, AllocErr, Layout};
+
+/// A "bump" allocator: allocates memory by bumping a pointer; never frees.
+///
+/// The bootloader only allocates while reading the file system and receiving
+/// uploads, then jumps away, so nothing is worth freeing.
+#[derive(Debug)]
+pub struct Allocator {
+    current: AtomicUsize,
+    end: usize,
+}
+
+impl Allocator {
+    /// Returns an allocator that allocates memory from the region starting at
+    /// address `start` and ending at address `end`.
+    pub const fn new(start: usize, end: usize) -> Allocator {
+        Allocator { current: AtomicUsize::new(start), end }
+    }
+}
+
+/// Align `addr` upwards to the nearest multiple of `align`, a power of 2.
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+unsafe impl<'a> Alloc for &'a Allocator {
+    /// Allocates memory. Returns a pointer meeting the size and alignment
+    /// properties of `layout.size()` and `layout.align()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AllocErr::Exhausted` if the region has no room left.
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        // The bootloader runs on a single core with interrupts disabled.
+        let start = align_up(self.current.load(Ordering::Relaxed), layout.align());
+
+        if self.end.saturating_sub(layout.size()) < start {
+            Err(AllocErr::Exhausted { request: layout })
+        } else {
+            self.current.store(start + layout.size(), Ordering::Relaxed);
+            Ok(start as *mut u8)
+        }
+    }
+
+    /// Does nothing: memory is never freed.
+    unsafe fn dealloc(&mut self, _ptr: *mut u8, _layout: Layout) {
+    }
+}
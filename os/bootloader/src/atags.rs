@@ -0,0 +1,69 @@
+use std::ptr;
+
+/// The address at which the firmware writes the ATAG list.
+const ATAG_BASE: usize = 0x100;
+
+/// Tags of the ATAGs the bootloader cares about.
+const NONE: u32 = 0x00000000;
+const CORE: u32 = 0x54410001;
+const CMDLINE: u32 = 0x54410009;
+
+/// The size of an ATAG's header in words: its size in words, then its tag.
+const HEADER_WORDS: usize = 2;
+
+/// Returns `true` if the firmware wrote an ATAG list, which starts with a
+/// `CORE` ATAG. It doesn't when it passes a device tree instead.
+fn has_list() -> bool {
+    unsafe { *(ATAG_BASE as *const u32).add(1) == CORE }
+}
+
+/// Returns the address of the ATAG list, to pass to the loaded binary in
+/// `x0`, or 0 if there is none.
+pub fn address() -> usize {
+    if has_list() { ATAG_BASE } else { 0 }
+}
+
+/// Replaces the `CMDLINE` ATAG of the firmware's list with one holding
+/// `cmdline`, or adds one before the list's end if there was none. Every
+/// other ATAG is preserved. If the firmware wrote no list, a new one is
+/// written with only a `CORE` ATAG and the `CMDLINE` one.
+///
+/// # Safety
+///
+/// The firmware's ATAG list, if any, must be intact, and the memory after it
+/// free.
+pub unsafe fn set_cmdline(cmdline: &str) {
+    let base = ATAG_BASE as *mut u32;
+    if !has_list() {
+        // An empty `CORE` ATAG, then the end of the list.
+        *base = HEADER_WORDS as u32;
+        *base.add(1) = CORE;
+        *base.add(2) = 0;
+        *base.add(3) = NONE;
+    }
+
+    // Move every other ATAG down over any `CMDLINE` one.
+    let (mut read, mut write) = (0, 0);
+    while *base.add(read + 1) != NONE {
+        let size = *base.add(read) as usize;
+        if *base.add(read + 1) != CMDLINE {
+            ptr::copy(base.add(read), base.add(write), size);
+            write += size;
+        }
+
+        read += size;
+    }
+
+    // The string is NUL-terminated and padded to a whole number of words.
+    let words = HEADER_WORDS + (cmdline.len() + 1 + 3) / 4;
+    *base.add(write) = words as u32;
+    *base.add(write + 1) = CMDLINE;
+
+    let string = base.add(write + HEADER_WORDS) as *mut u8;
+    ptr::write_bytes(string, 0, (words - HEADER_WORDS) * 4);
+    ptr::copy_nonoverlapping(cmdline.as_ptr(), string, cmdline.len());
+
+    write += words;
+    *base.add(write) = 0;
+    *base.add(write + 1) = NONE;
+}
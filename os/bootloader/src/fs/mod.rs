@@ -0,0 +1,91 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+
+use fat32::traits::{File, FileSystem};
+use fat32::vfat::{self, Shared, VFat};
+
+use pi::sd::{self, Sd};
+
+/// The file loaded from the SD card unless `config.txt` names another one.
+pub const DEFAULT_KERNEL: &str = "kernel8.img";
+
+/// The firmware's configuration file, which may name the kernel to load with
+/// a `bootloader_kernel=<name>` line. The firmware ignores the setting.
+const CONFIG_FILE: &str = "/config.txt";
+const KERNEL_KEY: &str = "bootloader_kernel";
+
+/// Why the kernel couldn't be read from the SD card.
+#[derive(Debug)]
+pub enum Error {
+    Sd(sd::Error),
+    Mount(vfat::Error),
+    Open(String, io::Error),
+    Read(String, io::Error),
+    /// The kernel file is larger than the memory it may be read into.
+    TooLarge(String, u64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Sd(ref e) => write!(f, "SD card initialization failed: {:?}", e),
+            Error::Mount(ref e) => write!(f, "no FAT32 partition: {:?}", e),
+            Error::Open(ref name, ref e) => write!(f, "can't open {}: {}", name, e),
+            Error::Read(ref name, ref e) => write!(f, "can't read {}: {}", name, e),
+            Error::TooLarge(ref name, size) => {
+                write!(f, "{} is too large: {} bytes", name, size)
+            }
+        }
+    }
+}
+
+/// Returns the value of the setting `key` in the firmware configuration
+/// `config`, made of `key=value` lines and `#` comments. The last setting
+/// wins, like for the firmware.
+fn setting<'a>(config: &'a str, key: &str) -> Option<&'a str> {
+    config.lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) if name.trim() == key => Some(value.trim()),
+                _ => None,
+            }
+        })
+        .last()
+}
+
+/// Returns the name of the kernel file to load: the `bootloader_kernel`
+/// setting of `config.txt` if any, `DEFAULT_KERNEL` otherwise.
+fn kernel_name(vfat: &Shared<VFat>) -> String {
+    let mut config = String::new();
+    let read = vfat.open_file(CONFIG_FILE)
+        .and_then(|mut file| file.read_to_string(&mut config));
+
+    match read.ok().and_then(|_| setting(&config, KERNEL_KEY)) {
+        Some(name) if !name.is_empty() => name.trim_left_matches('/').to_string(),
+        _ => DEFAULT_KERNEL.to_string(),
+    }
+}
+
+/// Reads the kernel file from the FAT32 partition of the SD card into the
+/// start of `into`. Returns the file's name and size.
+pub fn read_kernel(into: &mut [u8]) -> Result<(String, usize), Error> {
+    let sd = Sd::new().map_err(Error::Sd)?;
+    let vfat = VFat::from(sd).map_err(Error::Mount)?;
+
+    let name = kernel_name(&vfat);
+    let mut file = vfat.open_file(Path::new("/").join(&name))
+        .map_err(|e| Error::Open(name.clone(), e))?;
+
+    let size = file.size();
+    if size > into.len() as u64 {
+        return Err(Error::TooLarge(name, size));
+    }
+
+    match file.read_exact(&mut into[..size as usize]) {
+        Ok(()) => Ok((name, size as usize)),
+        Err(e) => Err(Error::Read(name, e)),
+    }
+}
@@ -1,13 +1,34 @@
 #![feature(asm, lang_items)]
+#![feature(const_fn)]
+#![feature(pointer_methods)]
+#![feature(alloc, allocator_api, global_allocator)]
 
+extern crate alloc;
+extern crate bootimage;
+extern crate core;
+extern crate elf;
+extern crate fat32;
+extern crate stack_vec;
 extern crate xmodem;
 extern crate pi;
 
 pub mod lang_items;
+pub mod allocator;
+pub mod atags;
+pub mod fs;
+pub mod monitor;
 
-use xmodem::Xmodem;
+use std::fmt::Write;
+use std::io;
+
+use elf::Elf;
+use xmodem::{Xmodem, Checksum};
 use pi::uart::MiniUart;
-use pi::gpio::Gpio;
+use pi::gpio::{Gpio, Output};
+use pi::timer::{current_time, spin_sleep_ms};
+use pi::power;
+
+use allocator::Allocator;
 
 /// Start address of the binary to load and of the bootloader.
 const BINARY_START_ADDR: usize = 0x80000;
@@ -19,49 +40,273 @@ const BINARY_START: *mut u8 = BINARY_START_ADDR as *mut u8;
 /// Free space between the bootloader and the loaded binary's start address.
 const MAX_BINARY_SIZE: usize = BOOTLOADER_START_ADDR - BINARY_START_ADDR;
 
-/// Branches to the address `addr` unconditionally.
+/// Where uploads are received, well above the bootloader, so that loading
+/// them into the free space never overwrites what is still to be read.
+const STAGING_START_ADDR: usize = 0x8000000;
+const STAGING_START: *mut u8 = STAGING_START_ADDR as *mut u8;
+
+/// Where a new bootloader is loaded before it relocates itself over this one,
+/// between the bootloader and the staging area.
+const UPDATE_START_ADDR: usize = 0x6000000;
+const UPDATE_START: *mut u8 = UPDATE_START_ADDR as *mut u8;
+const MAX_UPDATE_SIZE: usize = STAGING_START_ADDR - UPDATE_START_ADDR;
+
+/// Follows the first instruction of bootloaders that relocate themselves to
+/// their link address when started elsewhere: see `ext/init.S`.
+const RELOCATE_MARKER: &[u8] = b"RELOCATE";
+
+/// How long a new bootloader has to signal that it booted by stopping the
+/// watchdog, in milliseconds. Otherwise, the Pi is reset and the firmware
+/// boots the bootloader on the SD card again.
+const UPDATE_TIMEOUT_MS: u32 = 15_000;
+
+/// The heap, above the staging area.
+const HEAP_START_ADDR: usize = 0xC000000;
+const HEAP_END_ADDR: usize = 0x10000000;
+
+#[global_allocator]
+pub static ALLOCATOR: Allocator = Allocator::new(HEAP_START_ADDR, HEAP_END_ADDR);
+
+/// How long to wait for an upload before booting from the SD card, in
+/// microseconds. An attempt started before then runs to its end.
+const UPLOAD_WAIT_US: u64 = 5_000_000;
+
+/// The number of quick blinks of the ready LED showing which path was taken:
+/// booting an upload, booting from the SD card, or failing to and waiting for
+/// uploads only. While waiting, the LED toggles on every attempt as long as
+/// the SD card may be booted from, and stays lit in serial mode.
+const BLINKS_UPLOAD: usize = 1;
+const BLINKS_SD: usize = 2;
+const BLINKS_SD_FAILED: usize = 3;
+
+/// Branches to the address `addr` unconditionally, passing the address of
+/// the ATAG list in `x0` and zeroes in `x1` to `x3`, like the Linux arm64
+/// boot protocol does with a device tree.
 fn jump_to(addr: *mut u8) -> ! {
     unsafe {
-        asm!("br $0" : : "r"(addr as usize));
+        asm!("mov x1, xzr
+              mov x2, xzr
+              mov x3, xzr
+              br $0"
+             :: "r"(addr as usize), "{x0}"(atags::address())
+             : "x1", "x2", "x3"
+             : "volatile");
         loop { asm!("nop" :::: "volatile")  }
     }
 }
 
+/// Blinks `led` quickly `times` times, leaving it off.
+fn blink(led: &mut Gpio<Output>, times: usize) {
+    led.clear();
+    for _ in 0..times {
+        spin_sleep_ms(150);
+        led.set();
+        spin_sleep_ms(150);
+        led.clear();
+    }
+
+    spin_sleep_ms(300);
+}
+
+/// A loaded binary, ready to boot.
+#[derive(Debug, Clone, Copy)]
+enum Boot {
+    /// A binary in the free space, with its entry point.
+    Binary(*mut u8),
+    /// A new bootloader at `UPDATE_START`.
+    Update,
+}
+
+/// Boots `boot`. A new bootloader is chainloaded with the watchdog started:
+/// it must stop it within `UPDATE_TIMEOUT_MS`, or the Pi is reset into the
+/// bootloader on the SD card.
+fn boot(boot: Boot) -> ! {
+    match boot {
+        Boot::Binary(entry) => jump_to(entry),
+        Boot::Update => {
+            power::start_watchdog(UPDATE_TIMEOUT_MS);
+            jump_to(UPDATE_START)
+        }
+    }
+}
+
+/// Why an upload was refused.
+enum Error {
+    Image(bootimage::Error),
+    Elf(elf::Error),
+    /// A boot image loaded at the bootloader's address isn't a bootloader
+    /// that relocates itself.
+    NotRelocatable,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Error::Image(ref e) => write!(f, "bad boot image: {}", e),
+            Error::Elf(ref e) => write!(f, "bad ELF file: {}", e),
+            Error::NotRelocatable => {
+                write!(f, "bootloader update that doesn't relocate itself")
+            }
+        }
+    }
+}
+
+/// Loads the upload of `received` bytes at `STAGING_START` into the free
+/// space, ready to boot. The upload is either an ELF file, whose segments
+/// are loaded, or a boot image, whose payload is decompressed if needed to
+/// its load address, then verified.
+///
+/// A boot image loaded at `BOOTLOADER_START_ADDR` is a bootloader update: it
+/// is loaded at `UPDATE_START` instead, and must relocate itself.
+///
+/// If `raw` is `true`, anything else is taken as a raw binary loaded at
+/// `BINARY_START`, padding included. Uploads are only taken as raw binaries
+/// with the `raw-images` feature, files from the SD card always are.
+fn load(received: usize, raw: bool) -> Result<Boot, Error> {
+    let upload = unsafe { std::slice::from_raw_parts(STAGING_START, received) };
+    let memory = unsafe { std::slice::from_raw_parts_mut(BINARY_START, MAX_BINARY_SIZE) };
+    if upload.starts_with(&elf::MAGIC) {
+        let elf = Elf::parse(upload).map_err(Error::Elf)?;
+        let entry = elf.load(memory, BINARY_START_ADDR as u64).map_err(Error::Elf)?;
+        return Ok(Boot::Binary(entry as usize as *mut u8));
+    }
+
+    if let Ok(header) = bootimage::Header::parse(upload) {
+        if header.load_addr == BOOTLOADER_START_ADDR as u64 {
+            return load_update(upload);
+        }
+    }
+
+    match bootimage::load(upload, memory, BINARY_START_ADDR) {
+        Ok(header) => Ok(Boot::Binary(header.entry as usize as *mut u8)),
+        Err(bootimage::Error::BadMagic) if raw => {
+            memory[..upload.len()].copy_from_slice(upload);
+            Ok(Boot::Binary(BINARY_START))
+        }
+        Err(e) => Err(Error::Image(e)),
+    }
+}
+
+/// Loads the bootloader update `upload`, a boot image, at `UPDATE_START`.
+/// It must start at its first byte with `RELOCATE_MARKER` after the first
+/// instruction, like this bootloader.
+fn load_update(upload: &[u8]) -> Result<Boot, Error> {
+    let memory = unsafe { std::slice::from_raw_parts_mut(UPDATE_START, MAX_UPDATE_SIZE) };
+    let header = bootimage::load(upload, memory, BOOTLOADER_START_ADDR).map_err(Error::Image)?;
+
+    let marker = 4..4 + RELOCATE_MARKER.len();
+    let length = header.length as usize;
+    if header.entry != BOOTLOADER_START_ADDR as u64 || length < marker.end
+        || &memory[marker] != RELOCATE_MARKER
+    {
+        return Err(Error::NotRelocatable);
+    }
+
+    Ok(Boot::Update)
+}
+
+/// Reads the kernel file from the SD card into the staging area, then loads
+/// it.
+fn load_from_sd(uart: &mut MiniUart) -> Option<Boot> {
+    let storage = unsafe { std::slice::from_raw_parts_mut(STAGING_START, MAX_BINARY_SIZE) };
+    match fs::read_kernel(storage) {
+        Ok((name, size)) => match load(size, true) {
+            Ok(boot) => Some(boot),
+            Err(e) => {
+                let _ = writeln!(uart, "\nbootloader: {} refused: {}", name, e);
+                None
+            }
+        },
+        Err(e) => {
+            let _ = writeln!(uart, "\nbootloader: SD boot failed: {}", e);
+            None
+        }
+    }
+}
+
+/// Waits for an upload, then boots it. If none arrives within
+/// `UPLOAD_WAIT_US`, the kernel file is booted from the SD card instead; if
+/// that fails, uploads are waited for indefinitely.
+///
+/// Any byte received while waiting that doesn't start an upload, such as a
+/// keypress in a terminal, forces serial mode: the SD card is left alone.
+/// `monitor::BREAK` enters the monitor instead.
 #[no_mangle]
 pub extern "C" fn kmain() {
-    {
+    let loaded = {
         let mut ready_led = Gpio::new(16).into_output();
         let mut on = false;
 
         let mut uart = MiniUart::new();
         uart.set_read_timeout(750);
 
-        let mut storage: &mut [u8];
-        unsafe {
-            storage = std::slice::from_raw_parts_mut(BINARY_START, MAX_BINARY_SIZE);
-        }
+        // Signal a successful boot to the bootloader that chainloaded this
+        // one, if any: its watchdog resets the Pi otherwise.
+        power::stop_watchdog();
+
+        let deadline = current_time() + UPLOAD_WAIT_US;
+        let mut sd_boot = true;
 
         loop {
-            // Toggle the ready led.
-            if on {
+            if sd_boot && current_time() >= deadline {
+                sd_boot = false;
+                if let Some(loaded) = load_from_sd(&mut uart) {
+                    blink(&mut ready_led, BLINKS_SD);
+                    break loaded;
+                }
+
+                blink(&mut ready_led, BLINKS_SD_FAILED);
+            }
+
+            // Toggle the ready led until the SD card is given up on.
+            if on && sd_boot {
                 ready_led.clear();
             } else {
                 ready_led.set();
             }
             on = !on;
 
-            match Xmodem::receive(&mut uart, &mut storage) {
-                // Receive failed, retry.
-                Err(_) => continue,
-                // Break out of the retry loop and load the binary.
-                Ok(_) => {
-                    ready_led.clear();
-                    break;
+            // Every attempt writes from the start of the staging area again.
+            let mut storage: &mut [u8] = unsafe {
+                std::slice::from_raw_parts_mut(STAGING_START, MAX_BINARY_SIZE)
+            };
+
+            // Ask for CRC-16, which also lets the sender use 1K blocks; senders
+            // that only support checksums are handled by the fallback to `NAK`.
+            let (result, broken) = {
+                let mut watch = monitor::Watch::new(&mut uart);
+                let result = Xmodem::receive_with_checksum(&mut watch, &mut storage,
+                                                           Checksum::Crc16, |_| ());
+                (result, watch.broken())
+            };
+
+            match result {
+                // The break key was pressed.
+                Err(_) if broken => monitor::run(&mut uart),
+                // Nobody answered the handshake: retry.
+                Err(ref e) if e.kind() == io::ErrorKind::NotConnected => continue,
+                // Someone is there, but the transfer failed: retry, in serial
+                // mode from now on.
+                Err(_) => {
+                    sd_boot = false;
+                    continue;
+                }
+                // Load the upload, then break out of the retry loop to jump
+                // to it. A bad upload is reported and another one awaited.
+                Ok(received) => match load(received, cfg!(feature = "raw-images")) {
+                    Ok(loaded) => {
+                        blink(&mut ready_led, BLINKS_UPLOAD);
+                        break loaded;
+                    }
+                    Err(e) => {
+                        sd_boot = false;
+                        let _ = writeln!(uart, "\nbootloader: upload refused: {}", e);
+                    }
                 },
             }
         }
-    }
+    };
 
     // Bootloader is loaded, jump to the start.
-    jump_to(BINARY_START);
+    boot(loaded);
 }
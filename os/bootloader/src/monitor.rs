@@ -0,0 +1,349 @@
+use std::cmp::min;
+use std::fmt::Write;
+use std::io;
+use std::ptr;
+use std::slice;
+use std::str;
+
+use core::time::Duration;
+
+use pi::timer::current_time;
+use pi::uart::MiniUart;
+use stack_vec::StackVec;
+use xmodem::{crc32, Checksum, Xmodem};
+
+use atags;
+use {boot, load, load_from_sd, Boot};
+use {BINARY_START_ADDR, BOOTLOADER_START_ADDR, STAGING_START_ADDR, HEAP_START_ADDR};
+
+/// The key that enters the monitor instead of waiting for an upload: Ctrl-C.
+pub const BREAK: u8 = 0x03;
+
+const BELL: u8 = 7;
+const BACKSPACE: u8 = 8;
+const DELETE: u8 = 127;
+
+/// How long `load` waits for the sender, which must be started once the
+/// command was typed.
+const LOAD_HANDSHAKE_TIMEOUT_SECS: u64 = 60;
+
+/// The number of bytes `md` displays unless told otherwise.
+const DEFAULT_DUMP_LEN: usize = 64;
+
+const HELP: &str = "\
+load [addr]        receive an upload; at `addr`, as it is
+md <addr> [len]    display memory
+mw <addr> <word>.. write 32-bit words to memory
+crc <addr> <len>   compute the CRC-32 of memory
+args [string]      set the kernel command line, or show it
+go <addr>          jump to `addr`
+boot               boot the upload, or the SD card if none
+                   (a bootloader update is chainloaded)";
+
+/// A `MiniUart` that notices when a transfer starts with `BREAK`.
+pub struct Watch<'a> {
+    uart: &'a mut MiniUart,
+    read: usize,
+    broken: bool,
+}
+
+impl<'a> Watch<'a> {
+    pub fn new(uart: &'a mut MiniUart) -> Watch<'a> {
+        Watch { uart, read: 0, broken: false }
+    }
+
+    /// Returns `true` if the first byte read was `BREAK`.
+    pub fn broken(&self) -> bool {
+        self.broken
+    }
+}
+
+impl<'a> io::Read for Watch<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = io::Read::read(self.uart, buf)?;
+        if self.read == 0 && read > 0 && buf[0] == BREAK {
+            self.broken = true;
+        }
+
+        self.read += read;
+        Ok(read)
+    }
+}
+
+impl<'a> io::Write for Watch<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::Write::write(self.uart, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::Write::flush(self.uart)
+    }
+}
+
+/// Returns the time since the timer started, for XMODEM's timeouts.
+fn clock() -> Duration {
+    let us = current_time();
+    Duration::new(us / 1_000_000, (us % 1_000_000) as u32 * 1000)
+}
+
+/// Parses `s` as a number, in hexadecimal if it starts with `0x`.
+fn parse_number(s: &str) -> Result<usize, String> {
+    let result = if s.starts_with("0x") {
+        usize::from_str_radix(&s[2..], 16)
+    } else {
+        s.parse()
+    };
+
+    result.map_err(|_| format!("invalid number: {}", s))
+}
+
+/// Returns the memory `load` may write to from `addr`: the rest of the free
+/// space or of the staging area.
+fn loadable(addr: usize) -> Result<&'static mut [u8], String> {
+    let end = if addr >= BINARY_START_ADDR && addr < BOOTLOADER_START_ADDR {
+        BOOTLOADER_START_ADDR
+    } else if addr >= STAGING_START_ADDR && addr < HEAP_START_ADDR {
+        HEAP_START_ADDR
+    } else {
+        return Err(format!("{:#x} is outside of the free space and the staging area", addr));
+    };
+
+    Ok(unsafe { slice::from_raw_parts_mut(addr as *mut u8, end - addr) })
+}
+
+/// Reads a line from `uart` into `buf`, echoing it, with backspace and
+/// delete erasing a character. Returns the line.
+fn read_line<'a>(uart: &mut MiniUart, buf: &'a mut [u8]) -> &'a str {
+    let mut line = StackVec::new(buf);
+    loop {
+        let byte = uart.read_byte();
+        if byte == b'\r' || byte == b'\n' {
+            let _ = uart.write_str("\n");
+            break;
+        } else if byte == BACKSPACE || byte == DELETE {
+            if line.pop().is_none() {
+                uart.write_byte(BELL);
+            } else {
+                let _ = uart.write_str("\x08 \x08");
+            }
+        } else if byte < 32 || byte >= 127 || line.push(byte).is_err() {
+            uart.write_byte(BELL);
+        } else {
+            uart.write_byte(byte);
+        }
+    }
+
+    // Only printable ASCII was kept.
+    str::from_utf8(line.into_slice()).unwrap_or("")
+}
+
+/// The monitor's state.
+struct Monitor<'a> {
+    uart: &'a mut MiniUart,
+    /// The last upload loaded with `load`.
+    loaded: Option<Boot>,
+    /// The kernel command line set with `args`.
+    cmdline: Option<String>,
+}
+
+impl<'a> Monitor<'a> {
+    /// Runs the command `args`. Returns what to boot, if anything.
+    fn execute(&mut self, args: &[&str]) -> Result<Option<Boot>, String> {
+        match args[0] {
+            "help" => {
+                let _ = writeln!(self.uart, "{}", HELP);
+            }
+            "load" => self.load(&args[1..])?,
+            "md" => self.md(&args[1..])?,
+            "mw" => self.mw(&args[1..])?,
+            "crc" => self.crc(&args[1..])?,
+            "args" => self.args(&args[1..]),
+            "go" if args.len() == 2 => return parse_number(args[1]).map(|addr| {
+                Some(Boot::Binary(addr as *mut u8))
+            }),
+            "go" => return Err("usage: go <addr>".to_string()),
+            "boot" => return self.boot().map(Some),
+            command => return Err(format!("unknown command: {}; try `help`", command)),
+        }
+
+        Ok(None)
+    }
+
+    /// `load [addr]`: receives an upload into the staging area and loads it
+    /// like the automatic path, or receives it at `addr` as it is.
+    fn load(&mut self, args: &[&str]) -> Result<(), String> {
+        let addr = match args.len() {
+            0 => None,
+            1 => Some(parse_number(args[0])?),
+            _ => return Err("usage: load [addr]".to_string()),
+        };
+
+        let mut memory = loadable(addr.unwrap_or(STAGING_START_ADDR))?;
+        let _ = writeln!(self.uart, "waiting for an XMODEM upload...");
+        let received = Xmodem::builder()
+            .checksum(Checksum::Crc16)
+            .handshake_timeout(Duration::from_secs(LOAD_HANDSHAKE_TIMEOUT_SECS))
+            .clock(clock)
+            .build(&mut *self.uart)
+            .and_then(|mut xmodem| xmodem.read_data(&mut memory, None))
+            .map_err(|e| format!("upload failed: {}", e))?;
+
+        match addr {
+            Some(addr) => {
+                let _ = writeln!(self.uart, "received {} bytes at {:#x}", received, addr);
+            }
+            None => {
+                let loaded = load(received, cfg!(feature = "raw-images"))
+                    .map_err(|e| format!("upload refused: {}", e))?;
+                self.loaded = Some(loaded);
+                match loaded {
+                    Boot::Binary(entry) => {
+                        let _ = writeln!(self.uart, "loaded {} bytes, entry point {:#x}",
+                                         received, entry as usize);
+                    }
+                    Boot::Update => {
+                        let _ = writeln!(self.uart, "loaded a bootloader update");
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `md <addr> [len]`: displays memory, 16 bytes per line.
+    fn md(&mut self, args: &[&str]) -> Result<(), String> {
+        let (addr, len) = match args.len() {
+            1 => (parse_number(args[0])?, DEFAULT_DUMP_LEN),
+            2 => (parse_number(args[0])?, parse_number(args[1])?),
+            _ => return Err("usage: md <addr> [len]".to_string()),
+        };
+
+        let end = addr.saturating_add(len);
+        let mut line = addr;
+        while line < end {
+            let bytes: Vec<u8> = (line..min(line + 16, end))
+                .map(|a| unsafe { ptr::read_volatile(a as *const u8) })
+                .collect();
+
+            let _ = write!(self.uart, "{:08x}:", line);
+            for byte in &bytes {
+                let _ = write!(self.uart, " {:02x}", byte);
+            }
+
+            let _ = write!(self.uart, "{:1$} ", "", (16 - bytes.len()) * 3);
+            for &byte in &bytes {
+                let c = if byte >= 32 && byte < 127 { byte as char } else { '.' };
+                let _ = write!(self.uart, "{}", c);
+            }
+
+            let _ = writeln!(self.uart, "");
+            line += 16;
+        }
+
+        Ok(())
+    }
+
+    /// `mw <addr> <word>..`: writes 32-bit words from `addr` on.
+    fn mw(&mut self, args: &[&str]) -> Result<(), String> {
+        if args.len() < 2 {
+            return Err("usage: mw <addr> <word>..".to_string());
+        }
+
+        let addr = parse_number(args[0])?;
+        if addr % 4 != 0 {
+            return Err(format!("{:#x} isn't aligned to 4 bytes", addr));
+        }
+
+        // Parse every word before writing any.
+        let mut words = Vec::new();
+        for arg in &args[1..] {
+            let word = parse_number(arg)?;
+            if word >> 32 != 0 {
+                return Err(format!("{} doesn't fit in 32 bits", arg));
+            }
+
+            words.push(word as u32);
+        }
+
+        for (i, &word) in words.iter().enumerate() {
+            unsafe { ptr::write_volatile((addr + 4 * i) as *mut u32, word) };
+        }
+
+        Ok(())
+    }
+
+    /// `crc <addr> <len>`: displays the CRC-32 of memory, as in boot images.
+    fn crc(&mut self, args: &[&str]) -> Result<(), String> {
+        if args.len() != 2 {
+            return Err("usage: crc <addr> <len>".to_string());
+        }
+
+        let addr = parse_number(args[0])?;
+        let len = parse_number(args[1])?;
+        let memory = unsafe { slice::from_raw_parts(addr as *const u8, len) };
+        let _ = writeln!(self.uart, "{:#010x}", crc32(memory));
+        Ok(())
+    }
+
+    /// `args [string]`: sets the kernel command line, passed as a `CMDLINE`
+    /// ATAG, or shows it.
+    fn args(&mut self, args: &[&str]) {
+        if args.is_empty() {
+            let cmdline = self.cmdline.as_ref().map(|s| s.as_str()).unwrap_or("");
+            let _ = writeln!(self.uart, "{}", cmdline);
+        } else {
+            self.cmdline = Some(args.join(" "));
+        }
+    }
+
+    /// `boot`: returns the upload loaded with `load`, or
+    /// boots from the SD card if there is none.
+    fn boot(&mut self) -> Result<Boot, String> {
+        match self.loaded {
+            Some(loaded) => Ok(loaded),
+            None => load_from_sd(self.uart).ok_or_else(|| "nothing to boot".to_string()),
+        }
+    }
+}
+
+/// Runs the monitor on `uart` until a command boots something, then boots
+/// it.
+pub fn run(uart: &mut MiniUart) -> ! {
+    let _ = writeln!(uart, "\nbootloader monitor; type `help` for the commands");
+    let mut monitor = Monitor { uart, loaded: None, cmdline: None };
+
+    loop {
+        let _ = write!(monitor.uart, "boot> ");
+        let mut line_storage = [0u8; 512];
+        let line = read_line(monitor.uart, &mut line_storage);
+
+        let mut args_storage: [&str; 64] = [""; 64];
+        let mut args = StackVec::new(&mut args_storage);
+        let mut too_many = false;
+        for arg in line.split(' ').filter(|a| !a.is_empty()) {
+            too_many |= args.push(arg).is_err();
+        }
+
+        if args.is_empty() {
+            continue;
+        } else if too_many {
+            let _ = writeln!(monitor.uart, "error: too many arguments");
+            continue;
+        }
+
+        match monitor.execute(args.as_slice()) {
+            Ok(Some(loaded)) => {
+                if let Some(ref cmdline) = monitor.cmdline {
+                    unsafe { atags::set_cmdline(cmdline) };
+                }
+
+                boot(loaded);
+            }
+            Ok(None) => (),
+            Err(e) => {
+                let _ = writeln!(monitor.uart, "error: {}", e);
+            }
+        }
+    }
+}
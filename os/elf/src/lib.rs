@@ -0,0 +1,278 @@
+#![no_std]
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+#[cfg(test)]
+mod tests;
+
+use core::fmt;
+
+/// The first bytes of every ELF file.
+pub const MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const VERSION_CURRENT: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_AARCH64: u16 = 183;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// The type of program headers describing segments to load.
+const PT_LOAD: u32 = 1;
+
+/// Why an ELF file can't be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The file is shorter than an ELF header.
+    Truncated,
+    /// The file doesn't start with `MAGIC`.
+    BadMagic,
+    /// The file isn't a little-endian ELF64 file of the current version.
+    UnsupportedFormat,
+    /// The file isn't an executable.
+    NotExecutable,
+    /// The file isn't for AArch64.
+    WrongMachine(u16),
+    /// The program headers aren't within the file.
+    BadProgramHeaders,
+    /// The file has no segment to load.
+    NoSegments,
+    /// The file contents of the segment at `paddr` aren't within the file,
+    /// or are larger than the segment.
+    BadSegment { paddr: u64 },
+    /// The segment at `paddr` of `memsz` bytes isn't within the memory it may
+    /// be loaded to.
+    SegmentOutOfRange { paddr: u64, memsz: u64 },
+    /// The segments at `a` and `b` overlap.
+    SegmentsOverlap { a: u64, b: u64 },
+    /// The entry point isn't within a loaded segment.
+    BadEntry(u64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Truncated => write!(f, "file shorter than an ELF header"),
+            Error::BadMagic => write!(f, "bad magic: not an ELF file"),
+            Error::UnsupportedFormat => write!(f, "not a little-endian ELF64 file"),
+            Error::NotExecutable => write!(f, "not an executable"),
+            Error::WrongMachine(machine) => write!(f, "machine {} isn't AArch64", machine),
+            Error::BadProgramHeaders => write!(f, "program headers outside of the file"),
+            Error::NoSegments => write!(f, "no segment to load"),
+            Error::BadSegment { paddr } => {
+                write!(f, "contents of segment at {:#x} outside of the file", paddr)
+            }
+            Error::SegmentOutOfRange { paddr, memsz } => {
+                write!(f, "segment of {} bytes can't be loaded at {:#x}", memsz, paddr)
+            }
+            Error::SegmentsOverlap { a, b } => {
+                write!(f, "segments at {:#x} and {:#x} overlap", a, b)
+            }
+            Error::BadEntry(entry) => write!(f, "entry point {:#x} outside of the segments", entry),
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    read_u16(bytes, offset) as u32 | (read_u16(bytes, offset + 2) as u32) << 16
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    read_u32(bytes, offset) as u64 | (read_u32(bytes, offset + 4) as u64) << 32
+}
+
+/// A loadable segment: `filesz` bytes of the file at `offset`, followed by
+/// zeroes up to `memsz` bytes, loaded at the physical address `paddr` and
+/// mapped at the virtual address `vaddr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+impl Segment {
+    /// Returns the physical address just past the end of the segment, or
+    /// `None` if it overflows.
+    fn end(&self) -> Option<u64> {
+        self.paddr.checked_add(self.memsz)
+    }
+
+    /// Returns the physical address of the virtual address `vaddr` if the
+    /// segment maps it.
+    fn physical(&self, vaddr: u64) -> Option<u64> {
+        match vaddr.checked_sub(self.vaddr) {
+            Some(offset) if offset < self.memsz => Some(self.paddr + offset),
+            _ => None,
+        }
+    }
+}
+
+/// An AArch64 ELF64 executable.
+#[derive(Debug)]
+pub struct Elf<'a> {
+    data: &'a [u8],
+    entry: u64,
+    phoff: usize,
+    phnum: usize,
+}
+
+/// An iterator over the loadable segments of an ELF file.
+pub struct Segments<'a> {
+    elf: &'a Elf<'a>,
+    index: usize,
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = Segment;
+
+    fn next(&mut self) -> Option<Segment> {
+        while self.index < self.elf.phnum {
+            let header = &self.elf.data[self.elf.phoff + self.index * PROGRAM_HEADER_SIZE..];
+            self.index += 1;
+            if read_u32(header, 0) == PT_LOAD {
+                return Some(Segment {
+                    offset: read_u64(header, 8),
+                    vaddr: read_u64(header, 16),
+                    paddr: read_u64(header, 24),
+                    filesz: read_u64(header, 32),
+                    memsz: read_u64(header, 40),
+                });
+            }
+        }
+
+        None
+    }
+}
+
+impl<'a> Elf<'a> {
+    /// Parses the headers of the ELF file `data`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `data` isn't an AArch64 ELF64 executable or if its
+    /// program headers aren't within `data`. Segments are only checked by
+    /// [`Elf::load()`].
+    pub fn parse(data: &'a [u8]) -> Result<Elf<'a>, Error> {
+        if data.len() < HEADER_SIZE {
+            return Err(Error::Truncated);
+        }
+
+        if data[..4] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        if data[4] != CLASS_64 || data[5] != DATA_LITTLE_ENDIAN || data[6] != VERSION_CURRENT {
+            return Err(Error::UnsupportedFormat);
+        }
+
+        if read_u16(data, 16) != TYPE_EXECUTABLE {
+            return Err(Error::NotExecutable);
+        }
+
+        let machine = read_u16(data, 18);
+        if machine != MACHINE_AARCH64 {
+            return Err(Error::WrongMachine(machine));
+        }
+
+        let phoff = read_u64(data, 32);
+        let phentsize = read_u16(data, 54) as usize;
+        let phnum = read_u16(data, 56) as usize;
+        if phnum > 0 && phentsize != PROGRAM_HEADER_SIZE {
+            return Err(Error::BadProgramHeaders);
+        }
+
+        let table_size = (phnum * PROGRAM_HEADER_SIZE) as u64;
+        match phoff.checked_add(table_size) {
+            Some(end) if end <= data.len() as u64 => (),
+            _ => return Err(Error::BadProgramHeaders),
+        }
+
+        Ok(Elf { data, entry: read_u64(data, 24), phoff: phoff as usize, phnum })
+    }
+
+    /// Returns the virtual address of the entry point.
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// Returns an iterator over the loadable segments.
+    pub fn segments<'b>(&'b self) -> Segments<'b> {
+        Segments { elf: self, index: 0 }
+    }
+
+    /// Checks that every segment can be loaded into `memory`, which starts at
+    /// the physical address `base`, and returns the physical address of the
+    /// entry point.
+    fn validate(&self, memory: &[u8], base: u64) -> Result<u64, Error> {
+        let limit = base + memory.len() as u64;
+        let mut entry = None;
+        for (i, segment) in self.segments().enumerate() {
+            let in_file = match segment.offset.checked_add(segment.filesz) {
+                Some(end) => end <= self.data.len() as u64,
+                None => false,
+            };
+            if !in_file || segment.filesz > segment.memsz {
+                return Err(Error::BadSegment { paddr: segment.paddr });
+            }
+
+            match segment.end() {
+                Some(end) if segment.paddr >= base && end <= limit => (),
+                _ => return Err(Error::SegmentOutOfRange {
+                    paddr: segment.paddr,
+                    memsz: segment.memsz,
+                }),
+            }
+
+            // Both ends are known not to overflow by now.
+            for other in self.segments().take(i) {
+                let (start, end) = (segment.paddr, segment.paddr + segment.memsz);
+                if start < other.paddr + other.memsz && other.paddr < end {
+                    return Err(Error::SegmentsOverlap { a: other.paddr, b: segment.paddr });
+                }
+            }
+
+            entry = entry.or_else(|| segment.physical(self.entry));
+        }
+
+        if self.segments().next().is_none() {
+            return Err(Error::NoSegments);
+        }
+
+        entry.ok_or(Error::BadEntry(self.entry))
+    }
+
+    /// Copies every segment into `memory`, which starts at the physical
+    /// address `base`, zeroing what isn't in the file, like `.bss`. Returns the
+    /// physical address of the entry point.
+    ///
+    /// Nothing is written unless every segment fits in `memory`, none overlap
+    /// another, and the entry point is within one of them. `memory` must not
+    /// overlap the file.
+    pub fn load(&self, memory: &mut [u8], base: u64) -> Result<u64, Error> {
+        let entry = self.validate(memory, base)?;
+        for segment in self.segments() {
+            let start = (segment.paddr - base) as usize;
+            let (filesz, memsz) = (segment.filesz as usize, segment.memsz as usize);
+            let offset = segment.offset as usize;
+
+            let destination = &mut memory[start..start + memsz];
+            destination[..filesz].copy_from_slice(&self.data[offset..offset + filesz]);
+            for byte in destination[filesz..].iter_mut() {
+                *byte = 0;
+            }
+        }
+
+        Ok(entry)
+    }
+}
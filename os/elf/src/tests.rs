@@ -0,0 +1,174 @@
+use std::vec::Vec;
+
+use {Elf, Error, Segment, MAGIC};
+
+const BASE: u64 = 0x80000;
+
+/// A program header of type `kind` for the segment `segment`.
+struct Program {
+    kind: u32,
+    segment: Segment,
+}
+
+fn load(offset: u64, paddr: u64, filesz: u64, memsz: u64) -> Program {
+    Program { kind: 1, segment: Segment { offset, vaddr: paddr, paddr, filesz, memsz } }
+}
+
+fn put(file: &mut Vec<u8>, offset: usize, value: u64, len: usize) {
+    if file.len() < offset + len {
+        file.resize(offset + len, 0);
+    }
+
+    for i in 0..len {
+        file[offset + i] = (value >> (8 * i)) as u8;
+    }
+}
+
+/// Returns an AArch64 executable starting at `entry` with the program headers
+/// `programs` right after its header, then `contents` at offset 0x100.
+fn elf(entry: u64, programs: &[Program], contents: &[u8]) -> Vec<u8> {
+    let mut file = vec![0; 0x100];
+    file[..4].copy_from_slice(&MAGIC);
+    file[4] = 2;
+    file[5] = 1;
+    file[6] = 1;
+    put(&mut file, 16, 2, 2);
+    put(&mut file, 18, 183, 2);
+    put(&mut file, 20, 1, 4);
+    put(&mut file, 24, entry, 8);
+    put(&mut file, 32, 64, 8);
+    put(&mut file, 52, 64, 2);
+    put(&mut file, 54, 56, 2);
+    put(&mut file, 56, programs.len() as u64, 2);
+
+    for (i, program) in programs.iter().enumerate() {
+        let header = 64 + i * 56;
+        let segment = &program.segment;
+        put(&mut file, header, program.kind as u64, 4);
+        put(&mut file, header + 8, segment.offset, 8);
+        put(&mut file, header + 16, segment.vaddr, 8);
+        put(&mut file, header + 24, segment.paddr, 8);
+        put(&mut file, header + 32, segment.filesz, 8);
+        put(&mut file, header + 40, segment.memsz, 8);
+    }
+
+    file.truncate(0x100);
+    file.extend_from_slice(contents);
+    file
+}
+
+fn contents() -> Vec<u8> {
+    (0..64).map(|i| i as u8 + 1).collect()
+}
+
+fn load_error(file: &[u8]) -> Error {
+    let elf = match Elf::parse(file) {
+        Ok(elf) => elf,
+        Err(e) => return e,
+    };
+
+    let mut memory = vec![0xaa; 0x1000];
+    let error = elf.load(&mut memory, BASE).expect_err("invalid ELF loaded");
+    assert!(memory.iter().all(|&byte| byte == 0xaa), "memory written for {:?}", error);
+    error
+}
+
+#[test]
+fn loads_segments() {
+    let file = elf(BASE + 8, &[
+        load(0x100, BASE, 32, 32),
+        load(0x120, BASE + 0x800, 16, 0x40),
+    ], &contents());
+
+    let elf = Elf::parse(&file).expect("valid ELF");
+    assert_eq!(elf.segments().count(), 2);
+
+    let mut memory = vec![0xaa; 0x1000];
+    assert_eq!(elf.load(&mut memory, BASE), Ok(BASE + 8));
+    assert_eq!(&memory[..32], &contents()[..32]);
+    assert!(memory[32..0x800].iter().all(|&byte| byte == 0xaa));
+    assert_eq!(&memory[0x800..0x810], &contents()[32..48]);
+    assert!(memory[0x810..0x840].iter().all(|&byte| byte == 0), "bss not zeroed");
+    assert!(memory[0x840..].iter().all(|&byte| byte == 0xaa));
+}
+
+#[test]
+fn skips_other_program_headers() {
+    let mut note = load(0, 0, 0x1000, 0x1000);
+    note.kind = 4;
+    let file = elf(BASE, &[note, load(0x100, BASE, 64, 64)], &contents());
+
+    let elf = Elf::parse(&file).expect("valid ELF");
+    assert_eq!(elf.segments().count(), 1);
+    let mut memory = vec![0; 0x100];
+    assert_eq!(elf.load(&mut memory, BASE), Ok(BASE));
+    assert_eq!(&memory[..64], &contents()[..]);
+}
+
+#[test]
+fn translates_virtual_entry() {
+    let mut kernel = load(0x100, BASE, 64, 64);
+    kernel.segment.vaddr = 0xffff_0000_0008_0000;
+    let file = elf(0xffff_0000_0008_0010, &[kernel], &contents());
+
+    let mut memory = vec![0; 0x100];
+    assert_eq!(Elf::parse(&file).unwrap().load(&mut memory, BASE), Ok(BASE + 0x10));
+}
+
+#[test]
+fn rejects_bad_headers() {
+    let valid = elf(BASE, &[load(0x100, BASE, 64, 64)], &contents());
+    assert_eq!(load_error(&valid[..63]), Error::Truncated);
+
+    let corrupt = |offset: usize, value: u64, len: usize| {
+        let mut file = valid.clone();
+        put(&mut file, offset, value, len);
+        load_error(&file)
+    };
+
+    assert_eq!(corrupt(0, 0x7e, 1), Error::BadMagic);
+    assert_eq!(corrupt(1, b'e' as u64, 1), Error::BadMagic);
+    assert_eq!(corrupt(4, 1, 1), Error::UnsupportedFormat);
+    assert_eq!(corrupt(5, 2, 1), Error::UnsupportedFormat);
+    assert_eq!(corrupt(6, 0, 1), Error::UnsupportedFormat);
+    assert_eq!(corrupt(16, 3, 2), Error::NotExecutable);
+    assert_eq!(corrupt(18, 62, 2), Error::WrongMachine(62));
+    assert_eq!(corrupt(32, 0x1000, 8), Error::BadProgramHeaders);
+    assert_eq!(corrupt(32, !0, 8), Error::BadProgramHeaders);
+    assert_eq!(corrupt(54, 64, 2), Error::BadProgramHeaders);
+    assert_eq!(corrupt(56, 0, 2), Error::NoSegments);
+}
+
+#[test]
+fn rejects_bad_segments() {
+    let data = contents();
+    let check = |programs: &[Program], entry: u64| load_error(&elf(entry, programs, &data));
+
+    assert_eq!(check(&[load(0x120, BASE, 64, 64)], BASE), Error::BadSegment { paddr: BASE });
+    assert_eq!(check(&[load(!0, BASE, 64, 64)], BASE), Error::BadSegment { paddr: BASE });
+    assert_eq!(check(&[load(0x100, BASE, 64, 32)], BASE), Error::BadSegment { paddr: BASE });
+
+    assert_eq!(check(&[load(0x100, BASE - 8, 64, 64)], BASE),
+               Error::SegmentOutOfRange { paddr: BASE - 8, memsz: 64 });
+    assert_eq!(check(&[load(0x100, BASE + 0xfc0, 64, 0x48)], BASE + 0xfc0),
+               Error::SegmentOutOfRange { paddr: BASE + 0xfc0, memsz: 0x48 });
+    assert_eq!(check(&[load(0x100, !0 - 8, 64, 64)], BASE),
+               Error::SegmentOutOfRange { paddr: !0 - 8, memsz: 64 });
+
+    assert_eq!(check(&[load(0x100, BASE, 32, 32), load(0x120, BASE + 16, 32, 32)], BASE),
+               Error::SegmentsOverlap { a: BASE, b: BASE + 16 });
+    assert_eq!(check(&[load(0x100, BASE + 0x100, 32, 32), load(0x120, BASE, 16, 0x101)], BASE),
+               Error::SegmentsOverlap { a: BASE + 0x100, b: BASE });
+
+    assert_eq!(check(&[load(0x100, BASE, 64, 64)], BASE + 64), Error::BadEntry(BASE + 64));
+    assert_eq!(check(&[load(0x100, BASE, 64, 64)], BASE - 1), Error::BadEntry(BASE - 1));
+}
+
+#[test]
+fn adjacent_segments() {
+    let file = elf(BASE, &[load(0x100, BASE, 32, 32), load(0x120, BASE + 32, 32, 32)],
+                   &contents());
+    let mut memory = vec![0; 64];
+    assert_eq!(Elf::parse(&file).unwrap().load(&mut memory, BASE), Ok(BASE));
+    assert_eq!(memory, contents());
+}
@@ -10,6 +10,7 @@ mod tests;
 use mutex::Mutex;
 use alloc::heap::{Alloc, AllocErr, Layout};
 use pi::atags::Atags;
+use pi::mailbox::{self, ArmMemory};
 
 /// Used to print allocator stats to the console.
 pub trait AllocStats {
@@ -98,7 +99,9 @@ extern "C" {
 /// Returns the (start address, end address) of the available memory on this
 /// system if it can be determined. If it cannot, `None` is returned.
 ///
-/// This function is expected to return `Some` under all normal cirumstances.
+/// The memory is given by the `Mem` ATAG or, if the firmware passed none, by
+/// asking it through the mailbox. This function is expected to return `Some`
+/// under all normal cirumstances.
 fn memory_map() -> Option<(usize, usize)> {
     let binary_end = unsafe { (&_end as *const u8) as u32 };
 
@@ -109,5 +112,6 @@ fn memory_map() -> Option<(usize, usize)> {
         }
     }
 
-    None
+    mailbox::property(&ArmMemory).ok()
+        .map(|mem| (binary_end as usize, (mem.start + mem.size) as usize))
 }
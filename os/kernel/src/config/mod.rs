@@ -0,0 +1,194 @@
+#[cfg(test)]
+mod tests;
+
+use std::fmt;
+
+use mutex::Mutex;
+use pi::atags::{Atag, Atags};
+
+/// The scheduler's tick rate unless `tick_hz` is given, in Hz.
+pub const DEFAULT_TICK_HZ: u32 = 100;
+
+/// The slowest and fastest tick rates `tick_hz` accepts.
+const MIN_TICK_HZ: u32 = 1;
+const MAX_TICK_HZ: u32 = 10_000;
+
+/// How much the kernel reports at boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+/// The program run by the first process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Init {
+    Shell,
+    Blinky,
+}
+
+/// The UART the console uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Uart {
+    Mini,
+    Pl011,
+}
+
+/// The kernel's options, parsed from the `key=value` words of the kernel
+/// command line:
+///
+///   * `log=error|warn|info|debug`: the log level, `warn` by default.
+///   * `init=shell|blinky`: the first process's program, `shell` by default,
+///     which has the blinky run alongside it.
+///   * `tick_hz=<n>`: the scheduler's tick rate, `DEFAULT_TICK_HZ` by
+///     default.
+///   * `uart=mini|pl011`: the console's UART, on GPIO pins 14 and 15; `mini`
+///     by default.
+///
+/// Other words, such as the firmware's own options, are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootConfig {
+    pub log_level: LogLevel,
+    pub init: Init,
+    pub tick_hz: u32,
+    pub uart: Uart,
+}
+
+/// An option of the command line with an invalid value.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Invalid<'a> {
+    pub key: &'a str,
+    pub value: &'a str,
+}
+
+impl<'a> fmt::Display for Invalid<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid value for `{}`: {}", self.key, self.value)
+    }
+}
+
+fn parse_log_level(value: &str) -> Option<LogLevel> {
+    match value {
+        "error" => Some(LogLevel::Error),
+        "warn" => Some(LogLevel::Warn),
+        "info" => Some(LogLevel::Info),
+        "debug" => Some(LogLevel::Debug),
+        _ => None,
+    }
+}
+
+fn parse_uart(value: &str) -> Option<Uart> {
+    match value {
+        "mini" => Some(Uart::Mini),
+        "pl011" => Some(Uart::Pl011),
+        _ => None,
+    }
+}
+
+fn parse_init(value: &str) -> Option<Init> {
+    match value {
+        "shell" => Some(Init::Shell),
+        "blinky" => Some(Init::Blinky),
+        _ => None,
+    }
+}
+
+impl Default for BootConfig {
+    fn default() -> BootConfig {
+        BootConfig {
+            log_level: LogLevel::Warn,
+            init: Init::Shell,
+            tick_hz: DEFAULT_TICK_HZ,
+            uart: Uart::Mini,
+        }
+    }
+}
+
+impl BootConfig {
+    /// Parses the kernel command line `cmdline`. Later options override
+    /// earlier ones; options with an invalid value are ignored and returned.
+    pub fn parse<'a>(cmdline: &'a str) -> (BootConfig, Vec<Invalid<'a>>) {
+        let mut config = BootConfig::default();
+        let mut invalid = Vec::new();
+
+        for word in cmdline.split_whitespace() {
+            let mut parts = word.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => continue,
+            };
+
+            match key {
+                "log" => match parse_log_level(value) {
+                    Some(level) => config.log_level = level,
+                    None => invalid.push(Invalid { key, value }),
+                },
+                "init" => match parse_init(value) {
+                    Some(init) => config.init = init,
+                    None => invalid.push(Invalid { key, value }),
+                },
+                "tick_hz" => match value.parse() {
+                    Ok(hz) if hz >= MIN_TICK_HZ && hz <= MAX_TICK_HZ => config.tick_hz = hz,
+                    _ => invalid.push(Invalid { key, value }),
+                },
+                "uart" => match parse_uart(value) {
+                    Some(uart) => config.uart = uart,
+                    None => invalid.push(Invalid { key, value }),
+                },
+                _ => (),
+            }
+        }
+
+        (config, invalid)
+    }
+
+    /// Returns the time between two ticks of the scheduler, in microseconds.
+    pub fn tick_us(&self) -> u32 {
+        1_000_000 / self.tick_hz
+    }
+}
+
+/// Returns the ATAG list at `address` if it starts with a `CORE` ATAG, like
+/// every list does.
+unsafe fn atags_at(address: usize) -> Option<Atags> {
+    if address == 0 {
+        return None;
+    }
+
+    match Atags::at(address).next() {
+        Some(Atag::Core(_)) => Some(Atags::at(address)),
+        _ => None,
+    }
+}
+
+/// The boot configuration of the running kernel.
+#[derive(Debug)]
+pub struct GlobalBootConfig(Mutex<Option<BootConfig>>);
+
+impl GlobalBootConfig {
+    /// Returns an uninitialized `GlobalBootConfig`, which holds the default
+    /// configuration until `initialize()` is called.
+    pub const fn uninitialized() -> GlobalBootConfig {
+        GlobalBootConfig(Mutex::new(None))
+    }
+
+    /// Parses the command line of the ATAG list at `atags`, as passed by the
+    /// bootloader, or of the firmware's list if `atags` isn't one. Returns
+    /// the configuration and the invalid options.
+    pub fn initialize(&self, atags: usize) -> (BootConfig, Vec<Invalid<'static>>) {
+        let list = unsafe { atags_at(atags) }.unwrap_or_else(Atags::get);
+        let cmdline = list.filter_map(|atag| atag.cmd()).next().unwrap_or("");
+
+        let (config, invalid) = BootConfig::parse(cmdline);
+        *self.0.lock() = Some(config);
+        (config, invalid)
+    }
+
+    /// Returns the boot configuration.
+    pub fn get(&self) -> BootConfig {
+        let config = *self.0.lock();
+        config.unwrap_or_default()
+    }
+}
@@ -0,0 +1,57 @@
+use config::{BootConfig, Init, Invalid, LogLevel, Uart, DEFAULT_TICK_HZ};
+
+#[test]
+fn defaults() {
+    let (config, invalid) = BootConfig::parse("");
+    assert_eq!(config, BootConfig::default());
+    assert_eq!(config.log_level, LogLevel::Warn);
+    assert_eq!(config.init, Init::Shell);
+    assert_eq!(config.tick_hz, DEFAULT_TICK_HZ);
+    assert_eq!(config.tick_us(), 10_000);
+    assert_eq!(config.uart, Uart::Mini);
+    assert!(invalid.is_empty());
+}
+
+#[test]
+fn options() {
+    let (config, invalid) = BootConfig::parse("log=debug  init=blinky tick_hz=1000 uart=pl011");
+    assert_eq!(config, BootConfig {
+        log_level: LogLevel::Debug,
+        init: Init::Blinky,
+        tick_hz: 1000,
+        uart: Uart::Pl011,
+    });
+    assert_eq!(config.tick_us(), 1000);
+    assert!(invalid.is_empty());
+
+    let (config, _) = BootConfig::parse("log=error log=info");
+    assert_eq!(config.log_level, LogLevel::Info);
+}
+
+#[test]
+fn ignores_other_words() {
+    // The firmware adds options of its own to the command line.
+    let cmdline = "bcm2708_fb.fbwidth=656 dma.dmachans=0x7f35 console=ttyS0,115200 quiet \
+                   init=blinky =x log=";
+    let (config, invalid) = BootConfig::parse(cmdline);
+    assert_eq!(config.init, Init::Blinky);
+    assert_eq!(invalid, vec![Invalid { key: "log", value: "" }]);
+}
+
+#[test]
+fn invalid_values() {
+    let (config, invalid) = BootConfig::parse("log=loud init=blinky tick_hz=0 tick_hz=ten init=sh \
+                                               uart=uart0");
+    assert_eq!(config, BootConfig { init: Init::Blinky, ..BootConfig::default() });
+    assert_eq!(invalid, vec![
+        Invalid { key: "log", value: "loud" },
+        Invalid { key: "tick_hz", value: "0" },
+        Invalid { key: "tick_hz", value: "ten" },
+        Invalid { key: "init", value: "sh" },
+        Invalid { key: "uart", value: "uart0" },
+    ]);
+
+    assert_eq!(invalid[0].to_string(), "invalid value for `log`: loud");
+    assert!(BootConfig::parse("tick_hz=10001").1.len() == 1);
+    assert!(BootConfig::parse("tick_hz=10000").1.is_empty());
+}
@@ -0,0 +1,398 @@
+use std::cmp::{max, min};
+use std::fmt;
+use std::ptr;
+
+use pi::framebuffer::{Error, Framebuffer, PixelOrder};
+
+use console::font::{glyph, GLYPH_WIDTH, GLYPH_HEIGHT};
+
+/// The 16 ANSI colors, as `0xRRGGBB`: the 8 normal ones, then the 8 bright
+/// ones.
+const PALETTE: [u32; 16] = [
+    0x000000, 0xAA0000, 0x00AA00, 0xAA5500, 0x0000AA, 0xAA00AA, 0x00AAAA, 0xAAAAAA,
+    0x555555, 0xFF5555, 0x55FF55, 0xFFFF55, 0x5555FF, 0xFF55FF, 0x55FFFF, 0xFFFFFF,
+];
+
+const DEFAULT_FOREGROUND: usize = 7;
+const DEFAULT_BACKGROUND: usize = 0;
+/// Added to a normal color's index to get the bright one.
+const BRIGHT: usize = 8;
+
+/// The number of columns between tab stops.
+const TAB_WIDTH: usize = 8;
+
+/// The most parameters an escape sequence may have; later ones are ignored.
+const MAX_PARAMETERS: usize = 8;
+
+const BELL: u8 = 7;
+const BACKSPACE: u8 = 8;
+const TAB: u8 = b'\t';
+const ESCAPE: u8 = 0x1b;
+const DELETE: u8 = 127;
+
+/// Where the console is in an escape sequence.
+#[derive(Debug, Clone, Copy)]
+enum Escape {
+    None,
+    /// After `ESC`.
+    Start,
+    /// In a control sequence, `ESC [`, with its parameters so far. `private`
+    /// is set by a `?` before them.
+    Control {
+        parameters: [u32; MAX_PARAMETERS],
+        count: usize,
+        private: bool,
+    },
+}
+
+/// A text console drawn on a framebuffer with a built-in 8x8 font.
+///
+/// It understands the control characters a terminal does, and these ANSI
+/// escape sequences, `n` and `m` standing for numbers:
+///
+///   * `ESC [ n ; .. m`: select colors and attributes; normal (30-37, 40-47)
+///     and bright (90-97, 100-107) colors, bold (1, 22) and reverse (7, 27).
+///   * `ESC [ n A`, `B`, `C`, `D`: move the cursor up, down, right or left.
+///   * `ESC [ n ; m H`: move the cursor to row `n` and column `m`, from 1.
+///   * `ESC [ n J`, `K`: erase the screen or the line after (0), before (1)
+///     or around (2) the cursor.
+///   * `ESC [ ? 25 h`, `l`: show or hide the cursor.
+///
+/// Other sequences are ignored. The cursor is shown by inverting its cell.
+pub struct FramebufferConsole<'a> {
+    pixels: &'a mut [u32],
+    /// The number of pixels between the starts of two rows of pixels.
+    stride: usize,
+    order: PixelOrder,
+    columns: usize,
+    rows: usize,
+    /// The cursor's position. `column` may be `columns`, after the last
+    /// column was written: the next character wraps.
+    column: usize,
+    row: usize,
+    foreground: usize,
+    background: usize,
+    bold: bool,
+    reverse: bool,
+    cursor_enabled: bool,
+    /// Whether the cursor's cell is inverted.
+    cursor_drawn: bool,
+    escape: Escape,
+}
+
+impl<'a> FramebufferConsole<'a> {
+    /// Returns a console drawn on `pixels`, `width` by `height` pixels in
+    /// rows `stride` pixels apart, in the pixel format `order`. The screen is
+    /// cleared.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pixels` is too small, or if it can't fit a single character.
+    pub fn new(pixels: &'a mut [u32], width: usize, height: usize, stride: usize,
+               order: PixelOrder) -> FramebufferConsole<'a> {
+        assert!(stride >= width && pixels.len() >= stride * height);
+        assert!(width >= GLYPH_WIDTH && height >= GLYPH_HEIGHT);
+
+        let mut console = FramebufferConsole {
+            pixels,
+            stride,
+            order,
+            columns: width / GLYPH_WIDTH,
+            rows: height / GLYPH_HEIGHT,
+            column: 0,
+            row: 0,
+            foreground: DEFAULT_FOREGROUND,
+            background: DEFAULT_BACKGROUND,
+            bold: false,
+            reverse: false,
+            cursor_enabled: true,
+            cursor_drawn: false,
+            escape: Escape::None,
+        };
+
+        let cells = console.columns * console.rows;
+        console.erase(0, cells);
+        console.show_cursor();
+        console
+    }
+
+    /// Returns a console drawn on the whole of `framebuffer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::NoBuffer` if `framebuffer` can't fit a single character.
+    pub fn on(framebuffer: Framebuffer) -> Result<FramebufferConsole<'static>, Error> {
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        if width < GLYPH_WIDTH || height < GLYPH_HEIGHT {
+            return Err(Error::NoBuffer);
+        }
+
+        let (stride, order) = (framebuffer.stride, framebuffer.order);
+        Ok(FramebufferConsole::new(framebuffer.into_pixels(), width, height, stride, order))
+    }
+
+    /// Returns the size of the console in characters: (columns, rows).
+    pub fn size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    /// Returns the cursor's position: (column, row), from 0.
+    pub fn cursor(&self) -> (usize, usize) {
+        (min(self.column, self.columns - 1), self.row)
+    }
+
+    /// Writes the byte `byte`: a character, part of an escape sequence or a
+    /// control character.
+    pub fn write_byte(&mut self, byte: u8) {
+        self.hide_cursor();
+        self.process(byte);
+        self.show_cursor();
+    }
+
+    /// Writes the bytes `bytes`, as `write_byte` does.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.hide_cursor();
+        for &byte in bytes {
+            self.process(byte);
+        }
+
+        self.show_cursor();
+    }
+
+    /// Returns the pixel of the palette color `index`.
+    fn pixel(&self, index: usize) -> u32 {
+        self.order.pixel(PALETTE[index])
+    }
+
+    /// Returns the pixels of the current foreground and background colors.
+    fn colors(&self) -> (u32, u32) {
+        let mut foreground = self.foreground;
+        if self.bold && foreground < BRIGHT {
+            foreground += BRIGHT;
+        }
+
+        let (foreground, background) = (self.pixel(foreground), self.pixel(self.background));
+        if self.reverse { (background, foreground) } else { (foreground, background) }
+    }
+
+    /// Inverts the colors of the cursor's cell if the cursor is enabled.
+    fn show_cursor(&mut self) {
+        if self.cursor_enabled && !self.cursor_drawn {
+            self.invert_cursor();
+        }
+    }
+
+    /// Restores the cursor's cell if the cursor is drawn.
+    fn hide_cursor(&mut self) {
+        if self.cursor_drawn {
+            self.invert_cursor();
+        }
+    }
+
+    fn invert_cursor(&mut self) {
+        let (column, row) = self.cursor();
+        let (x, y) = (column * GLYPH_WIDTH, row * GLYPH_HEIGHT);
+        for line in y..y + GLYPH_HEIGHT {
+            let start = line * self.stride + x;
+            for pixel in &mut self.pixels[start..start + GLYPH_WIDTH] {
+                *pixel ^= 0xFFFFFF;
+            }
+        }
+
+        self.cursor_drawn = !self.cursor_drawn;
+    }
+
+    /// Handles `byte` with the cursor hidden.
+    fn process(&mut self, byte: u8) {
+        let escape = self.escape;
+        match escape {
+            Escape::None => self.character(byte),
+            Escape::Start => {
+                self.escape = if byte == b'[' {
+                    Escape::Control { parameters: [0; MAX_PARAMETERS], count: 0, private: false }
+                } else {
+                    // Other escape sequences are two bytes long; ignore them.
+                    Escape::None
+                };
+            }
+            Escape::Control { mut parameters, mut count, mut private } => {
+                match byte {
+                    b'0'...b'9' => {
+                        count = count.max(1);
+                        if let Some(parameter) = parameters.get_mut(count - 1) {
+                            let digit = (byte - b'0') as u32;
+                            *parameter = parameter.saturating_mul(10).saturating_add(digit);
+                        }
+                    }
+                    b';' => count = count.max(1) + 1,
+                    b'?' => private = true,
+                    // Another parameter byte or an intermediate one.
+                    0x20...0x3f => (),
+                    _ => {
+                        self.escape = Escape::None;
+                        let count = min(count, MAX_PARAMETERS);
+                        self.control(byte, &parameters[..count], private);
+                        return;
+                    }
+                }
+
+                self.escape = Escape::Control { parameters, count, private };
+            }
+        }
+    }
+
+    /// Handles `byte` outside of an escape sequence.
+    fn character(&mut self, byte: u8) {
+        match byte {
+            b'\n' => {
+                self.column = 0;
+                self.line_feed();
+            }
+            b'\r' => self.column = 0,
+            BACKSPACE => self.column = min(self.column, self.columns - 1).saturating_sub(1),
+            TAB => self.column = min((self.column / TAB_WIDTH + 1) * TAB_WIDTH, self.columns),
+            ESCAPE => self.escape = Escape::Start,
+            BELL | DELETE => (),
+            // UTF-8 continuation bytes: the lead byte drew the character.
+            0x80...0xbf => (),
+            byte if byte < 0x20 => (),
+            byte => {
+                if self.column == self.columns {
+                    self.column = 0;
+                    self.line_feed();
+                }
+
+                let (column, row) = (self.column, self.row);
+                self.draw(column, row, byte);
+                self.column += 1;
+            }
+        }
+    }
+
+    /// Moves the cursor down a row, scrolling the screen up at its bottom.
+    fn line_feed(&mut self) {
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.scroll();
+        }
+    }
+
+    /// Moves every row of characters up one, and erases the last one.
+    fn scroll(&mut self) {
+        let row_pixels = GLYPH_HEIGHT * self.stride;
+        let kept = (self.rows - 1) * row_pixels;
+        assert!(row_pixels + kept <= self.pixels.len());
+        unsafe {
+            let pixels = self.pixels.as_mut_ptr();
+            ptr::copy(pixels.add(row_pixels), pixels, kept);
+        }
+
+        let cells = self.columns * self.rows;
+        let last = cells - self.columns;
+        self.erase(last, cells);
+    }
+
+    /// Draws the glyph of `byte` at `column` and `row` in the current colors.
+    fn draw(&mut self, column: usize, row: usize, byte: u8) {
+        let (foreground, background) = self.colors();
+        let (x, y) = (column * GLYPH_WIDTH, row * GLYPH_HEIGHT);
+        for (line, &bits) in glyph(byte).iter().enumerate() {
+            let start = (y + line) * self.stride + x;
+            let pixels = &mut self.pixels[start..start + GLYPH_WIDTH];
+            for (i, pixel) in pixels.iter_mut().enumerate() {
+                *pixel = if bits & (1 << i) != 0 { foreground } else { background };
+            }
+        }
+    }
+
+    /// Erases the cells from `start` until `end` in the background color,
+    /// counting cells row after row from the top left one.
+    fn erase(&mut self, start: usize, end: usize) {
+        let background = self.pixel(self.background);
+        let columns = self.columns;
+        for row in start / columns..(end + columns - 1) / columns {
+            let from = (max(start, row * columns) - row * columns) * GLYPH_WIDTH;
+            let to = (min(end, (row + 1) * columns) - row * columns) * GLYPH_WIDTH;
+            for line in row * GLYPH_HEIGHT..(row + 1) * GLYPH_HEIGHT {
+                let start = line * self.stride;
+                for pixel in &mut self.pixels[start + from..start + to] {
+                    *pixel = background;
+                }
+            }
+        }
+    }
+
+    /// Runs the control sequence ending with `byte`.
+    fn control(&mut self, byte: u8, parameters: &[u32], private: bool) {
+        // Most sequences take a count, which is 1 when omitted or 0.
+        let first = parameters.get(0).cloned().unwrap_or(0) as usize;
+        let count = first.max(1);
+        let (column, row) = self.cursor();
+        let (columns, rows) = (self.columns, self.rows);
+        let (cursor, line) = (row * columns + column, row * columns);
+
+        match byte {
+            b'h' | b'l' if private && first == 25 => self.cursor_enabled = byte == b'h',
+            _ if private => (),
+            b'A' => self.row = row.saturating_sub(count),
+            b'B' => self.row = min(row + count, rows - 1),
+            b'C' => self.column = min(column + count, columns - 1),
+            b'D' => self.column = column.saturating_sub(count),
+            b'H' | b'f' => {
+                let column = parameters.get(1).cloned().unwrap_or(0).max(1) as usize;
+                self.row = min(count, rows) - 1;
+                self.column = min(column, columns) - 1;
+            }
+            b'J' => match first {
+                0 => self.erase(cursor, rows * columns),
+                1 => self.erase(0, cursor + 1),
+                _ => self.erase(0, rows * columns),
+            },
+            b'K' => match first {
+                0 => self.erase(cursor, line + columns),
+                1 => self.erase(line, cursor + 1),
+                _ => self.erase(line, line + columns),
+            },
+            b'm' => self.select_graphics(parameters),
+            _ => (),
+        }
+    }
+
+    /// Runs `ESC [ .. m`: selects the colors and attributes of the
+    /// characters written next.
+    fn select_graphics(&mut self, parameters: &[u32]) {
+        if parameters.is_empty() {
+            return self.select_graphics(&[0]);
+        }
+
+        for &parameter in parameters {
+            match parameter {
+                0 => {
+                    self.foreground = DEFAULT_FOREGROUND;
+                    self.background = DEFAULT_BACKGROUND;
+                    self.bold = false;
+                    self.reverse = false;
+                }
+                1 => self.bold = true,
+                22 => self.bold = false,
+                7 => self.reverse = true,
+                27 => self.reverse = false,
+                30...37 => self.foreground = (parameter - 30) as usize,
+                39 => self.foreground = DEFAULT_FOREGROUND,
+                40...47 => self.background = (parameter - 40) as usize,
+                49 => self.background = DEFAULT_BACKGROUND,
+                90...97 => self.foreground = (parameter - 90) as usize + BRIGHT,
+                100...107 => self.background = (parameter - 100) as usize + BRIGHT,
+                _ => (),
+            }
+        }
+    }
+}
+
+impl<'a> fmt::Write for FramebufferConsole<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
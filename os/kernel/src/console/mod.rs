@@ -0,0 +1,185 @@
+mod font;
+mod framebuffer;
+
+#[cfg(test)]
+mod tests;
+
+use std::io;
+use std::fmt;
+
+use pi::uart::{MiniUart, Pl011};
+
+use mutex::Mutex;
+
+pub use self::framebuffer::FramebufferConsole;
+
+/// The UART the console reads from and writes to.
+enum Uart {
+    Mini(MiniUart),
+    Pl011(Pl011),
+}
+
+impl Uart {
+    fn read_byte(&mut self) -> u8 {
+        match *self {
+            Uart::Mini(ref mut uart) => uart.read_byte(),
+            Uart::Pl011(ref mut uart) => uart.read_byte(),
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        match *self {
+            Uart::Mini(ref mut uart) => uart.write_byte(byte),
+            Uart::Pl011(ref mut uart) => uart.write_byte(byte),
+        }
+    }
+}
+
+impl io::Read for Uart {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Uart::Mini(ref mut uart) => io::Read::read(uart, buf),
+            Uart::Pl011(ref mut uart) => io::Read::read(uart, buf),
+        }
+    }
+}
+
+impl io::Write for Uart {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Uart::Mini(ref mut uart) => io::Write::write(uart, buf),
+            Uart::Pl011(ref mut uart) => io::Write::write(uart, buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Uart::Mini(ref mut uart) => io::Write::flush(uart),
+            Uart::Pl011(ref mut uart) => io::Write::flush(uart),
+        }
+    }
+}
+
+impl fmt::Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match *self {
+            Uart::Mini(ref mut uart) => fmt::Write::write_str(uart, s),
+            Uart::Pl011(ref mut uart) => fmt::Write::write_str(uart, s),
+        }
+    }
+}
+
+/// A global singleton allowing read/write access to the console.
+pub struct Console {
+    inner: Option<Uart>,
+    /// Mirrors everything written to the UART, if set.
+    mirror: Option<FramebufferConsole<'static>>,
+}
+
+impl Console {
+    /// Creates a new instance of `Console`.
+    const fn new() -> Console {
+        Console { inner: None, mirror: None }
+    }
+
+    /// Mirrors everything written to the console from now on to `mirror`.
+    pub fn mirror_to(&mut self, mirror: FramebufferConsole<'static>) {
+        self.mirror = Some(mirror);
+    }
+
+    /// Switches the console to the PL011 UART `uart`, from the mini UART it
+    /// initializes itself with.
+    pub fn use_pl011(&mut self, uart: Pl011) {
+        self.inner = Some(Uart::Pl011(uart));
+    }
+
+    /// Initializes the console if it's not already initialized.
+    #[inline]
+    fn initialize(&mut self) {
+        self.inner = Some(Uart::Mini(MiniUart::new()))
+    }
+
+    /// Returns a mutable borrow to the inner UART, initializing the mini UART
+    /// as needed.
+    fn inner(&mut self) -> &mut Uart {
+        if self.inner.is_none() {
+            self.initialize();
+        }
+
+        self.inner.as_mut().unwrap()
+    }
+
+    /// Reads a byte from the UART device, blocking until a byte is available.
+    pub fn read_byte(&mut self) -> u8 {
+        self.inner().read_byte()
+    }
+
+    /// Writes the byte `byte` to the UART device.
+    pub fn write_byte(&mut self, byte: u8) {
+        self.inner().write_byte(byte);
+        if let Some(ref mut mirror) = self.mirror {
+            mirror.write_byte(byte);
+        }
+    }
+}
+
+impl io::Read for Console {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner().read(buf)
+    }
+}
+
+impl io::Write for Console {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner().write(buf)?;
+        if let Some(ref mut mirror) = self.mirror {
+            mirror.write_bytes(&buf[..written]);
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner().flush()
+    }
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner().write_str(s)?;
+        if let Some(ref mut mirror) = self.mirror {
+            mirror.write_bytes(s.as_bytes());
+        }
+
+        Ok(())
+    }
+}
+
+/// Global `Console` singleton.
+pub static CONSOLE: Mutex<Console> = Mutex::new(Console::new());
+
+/// Internal function called by the `kprint[ln]!` macros.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    #[cfg(not(test))]
+    {
+        use std::fmt::Write;
+        let mut console = CONSOLE.lock();
+        console.write_fmt(args).unwrap();
+    }
+
+    #[cfg(test)]
+    { print!("{}", args); }
+}
+
+/// Like `println!`, but for kernel-space.
+pub macro kprintln {
+    () => (kprint!("\n")),
+    ($fmt:expr) => (kprint!(concat!($fmt, "\n"))),
+    ($fmt:expr, $($arg:tt)*) => (kprint!(concat!($fmt, "\n"), $($arg)*))
+}
+
+/// Like `print!`, but for kernel-space.
+pub macro kprint($($arg:tt)*) {
+    _print(format_args!($($arg)*))
+}
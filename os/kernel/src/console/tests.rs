@@ -0,0 +1,204 @@
+use std::fmt::Write;
+
+use pi::framebuffer::PixelOrder;
+
+use super::FramebufferConsole;
+use super::font::{glyph, GLYPH_WIDTH, GLYPH_HEIGHT};
+
+/// The screen: 10 columns and 3 rows, with rows of pixels padded to `STRIDE`.
+const WIDTH: usize = 10 * GLYPH_WIDTH;
+const HEIGHT: usize = 3 * GLYPH_HEIGHT;
+const STRIDE: usize = WIDTH + 3;
+
+/// The pixels past the end of each row, which the console mustn't touch.
+const PADDING: u32 = 0x123456;
+
+const BLACK: u32 = 0x000000;
+const GRAY: u32 = 0xAAAAAA;
+const WHITE: u32 = 0xFFFFFF;
+
+fn screen() -> Vec<u32> {
+    vec![PADDING; STRIDE * HEIGHT]
+}
+
+fn write(pixels: &mut [u32], order: PixelOrder, text: &str) {
+    let mut console = FramebufferConsole::new(pixels, WIDTH, HEIGHT, STRIDE, order);
+    console.write_str(text).unwrap();
+}
+
+/// Returns the character drawn at `column` and `row`, and its foreground and
+/// background colors; a space's foreground is its background.
+fn cell(pixels: &[u32], column: usize, row: usize) -> (char, u32, u32) {
+    let pixel = |x: usize, y: usize| {
+        pixels[(row * GLYPH_HEIGHT + y) * STRIDE + column * GLYPH_WIDTH + x]
+    };
+
+    'glyphs: for byte in b' '..b'~' + 1 {
+        let (mut foreground, mut background) = (None, None);
+        for (y, &bits) in glyph(byte).iter().enumerate() {
+            for x in 0..GLYPH_WIDTH {
+                let color = if bits & (1 << x) != 0 { &mut foreground } else { &mut background };
+                if *color.get_or_insert(pixel(x, y)) != pixel(x, y) {
+                    continue 'glyphs;
+                }
+            }
+        }
+
+        let background = background.unwrap();
+        return (byte as char, foreground.unwrap_or(background), background);
+    }
+
+    panic!("no glyph at ({}, {})", column, row);
+}
+
+/// Returns the characters of `row`, with the cursor's cell read as `_`.
+fn text(pixels: &[u32], row: usize) -> String {
+    (0..WIDTH / GLYPH_WIDTH).map(|column| match cell(pixels, column, row) {
+        (' ', WHITE, WHITE) => '_',
+        (c, _, _) => c,
+    }).collect()
+}
+
+fn check_padding(pixels: &[u32]) {
+    for line in 0..HEIGHT {
+        assert!(pixels[line * STRIDE + WIDTH..(line + 1) * STRIDE].iter().all(|&p| p == PADDING));
+    }
+}
+
+#[test]
+fn draws_characters() {
+    let mut pixels = screen();
+    write(&mut pixels, PixelOrder::Bgr, "Hi!");
+
+    assert_eq!(cell(&pixels, 0, 0), ('H', GRAY, BLACK));
+    assert_eq!(cell(&pixels, 2, 0), ('!', GRAY, BLACK));
+    // The cursor inverts its cell.
+    assert_eq!(cell(&pixels, 3, 0), (' ', WHITE, WHITE));
+    assert_eq!(cell(&pixels, 4, 0), (' ', BLACK, BLACK));
+    assert_eq!(text(&pixels, 1), "          ");
+    check_padding(&pixels);
+}
+
+#[test]
+fn wraps_and_scrolls() {
+    let mut pixels = screen();
+    {
+        let mut console = FramebufferConsole::new(&mut pixels, WIDTH, HEIGHT, STRIDE,
+                                                  PixelOrder::Bgr);
+        assert_eq!(console.size(), (10, 3));
+
+        console.write_str("0123456789").unwrap();
+        // The cursor stays on the last column until the next character.
+        assert_eq!(console.cursor(), (9, 0));
+        console.write_str("abc\nde").unwrap();
+        assert_eq!(console.cursor(), (2, 2));
+        console.write_str("\nfg").unwrap();
+    }
+
+    assert_eq!(text(&pixels, 0), "abc       ");
+    assert_eq!(text(&pixels, 1), "de        ");
+    assert_eq!(text(&pixels, 2), "fg_       ");
+    check_padding(&pixels);
+}
+
+#[test]
+fn control_characters() {
+    let mut pixels = screen();
+    write(&mut pixels, PixelOrder::Bgr, "ab\rc\nx\ty\n12\x08\x08 \x07\x7f");
+
+    assert_eq!(text(&pixels, 0), "cb        ");
+    assert_eq!(text(&pixels, 1), "x       y ");
+    assert_eq!(text(&pixels, 2), " 2        ");
+}
+
+#[test]
+fn colors() {
+    let mut pixels = screen();
+    write(&mut pixels, PixelOrder::Bgr,
+          "\x1b[31mR\x1b[1;44mB\x1b[22mN\x1b[0mD\x1b[7mI\x1b[27;92;103mX\x1b[39;49m ");
+
+    assert_eq!(cell(&pixels, 0, 0), ('R', 0xAA0000, BLACK));
+    assert_eq!(cell(&pixels, 1, 0), ('B', 0xFF5555, 0x0000AA));
+    assert_eq!(cell(&pixels, 2, 0), ('N', 0xAA0000, 0x0000AA));
+    assert_eq!(cell(&pixels, 3, 0), ('D', GRAY, BLACK));
+    assert_eq!(cell(&pixels, 4, 0), ('I', BLACK, GRAY));
+    assert_eq!(cell(&pixels, 5, 0), ('X', 0x55FF55, 0xFFFF55));
+    assert_eq!(cell(&pixels, 6, 0), (' ', BLACK, BLACK));
+}
+
+#[test]
+fn pixel_order() {
+    let mut pixels = screen();
+    write(&mut pixels, PixelOrder::Rgb, "\x1b[31;43mR");
+
+    assert_eq!(cell(&pixels, 0, 0), ('R', 0x0000AA, 0x0055AA));
+}
+
+#[test]
+fn moves_the_cursor() {
+    let mut pixels = screen();
+    write(&mut pixels, PixelOrder::Bgr,
+          "\x1b[2;3Ha\x1b[Ab\x1b[99Bc\x1b[4Dd\x1b[Ce\x1b[99;99Hf\x1b[Hg");
+
+    assert_eq!(text(&pixels, 0), "g_ b      ");
+    assert_eq!(text(&pixels, 1), "  a       ");
+    assert_eq!(text(&pixels, 2), " d ec    f");
+}
+
+#[test]
+fn erases() {
+    let text_rows = "0123456789abcdefghijABCDEFGHIJ";
+
+    let mut pixels = screen();
+    write(&mut pixels, PixelOrder::Bgr, &format!("{}\x1b[2;5H\x1b[K", text_rows));
+    assert_eq!(text(&pixels, 0), "0123456789");
+    assert_eq!(text(&pixels, 1), "abcd_     ");
+    assert_eq!(text(&pixels, 2), "ABCDEFGHIJ");
+
+    let mut pixels = screen();
+    write(&mut pixels, PixelOrder::Bgr, &format!("{}\x1b[2;5H\x1b[1K", text_rows));
+    assert_eq!(text(&pixels, 1), "    _fghij");
+
+    let mut pixels = screen();
+    write(&mut pixels, PixelOrder::Bgr, &format!("{}\x1b[2;5H\x1b[2K", text_rows));
+    assert_eq!(text(&pixels, 1), "    _     ");
+
+    let mut pixels = screen();
+    write(&mut pixels, PixelOrder::Bgr, &format!("{}\x1b[2;5H\x1b[J", text_rows));
+    assert_eq!(text(&pixels, 0), "0123456789");
+    assert_eq!(text(&pixels, 1), "abcd_     ");
+    assert_eq!(text(&pixels, 2), "          ");
+
+    let mut pixels = screen();
+    write(&mut pixels, PixelOrder::Bgr, &format!("{}\x1b[2;5H\x1b[1J", text_rows));
+    assert_eq!(text(&pixels, 0), "          ");
+    assert_eq!(text(&pixels, 1), "    _fghij");
+    assert_eq!(text(&pixels, 2), "ABCDEFGHIJ");
+
+    // Erasing uses the background color.
+    let mut pixels = screen();
+    write(&mut pixels, PixelOrder::Bgr, &format!("{}\x1b[44m\x1b[2J", text_rows));
+    assert_eq!(cell(&pixels, 0, 0), (' ', 0x0000AA, 0x0000AA));
+    assert_eq!(text(&pixels, 2), "          ");
+    check_padding(&pixels);
+}
+
+#[test]
+fn hides_the_cursor() {
+    let mut pixels = screen();
+    write(&mut pixels, PixelOrder::Bgr, "a\x1b[?25l");
+    assert_eq!(text(&pixels, 0), "a         ");
+
+    let mut pixels = screen();
+    write(&mut pixels, PixelOrder::Bgr, "a\x1b[?25lb\x1b[?25h");
+    assert_eq!(text(&pixels, 0), "ab_       ");
+}
+
+#[test]
+fn ignores_unknown_sequences() {
+    let mut pixels = screen();
+    write(&mut pixels, PixelOrder::Bgr, "a\x1b[5zb\x1bMc\x1b[1;2;3;4;5;6;7;8;9;10md\u{e9}!");
+
+    // Characters outside of ASCII are drawn as `?`.
+    assert_eq!(text(&pixels, 0), "abcd?!_   ");
+}
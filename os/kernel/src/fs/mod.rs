@@ -1,5 +1,3 @@
-pub mod sd;
-
 use std::io;
 use std::path::Path;
 
@@ -7,7 +5,7 @@ use fat32::vfat::{self, Shared, VFat};
 pub use fat32::traits;
 
 use mutex::Mutex;
-use self::sd::Sd;
+use pi::sd::Sd;
 
 pub struct FileSystem(Mutex<Option<Shared<VFat>>>);
 
@@ -31,6 +29,11 @@ impl FileSystem {
         *self.0.lock() = Some(vfat);
     }
 
+    /// Returns a pre-order walk over the directory at absolute path `root`.
+    pub fn walk<P: AsRef<Path>>(&self, root: P) -> io::Result<vfat::Walk> {
+        vfat::walk(&self.get_vfat()?, root)
+    }
+
     fn get_vfat(&self) -> io::Result<Shared<VFat>> {
         match *self.0.lock() {
             Some(ref vfat) => Ok(vfat.clone()),
@@ -23,6 +23,7 @@ extern crate stack_vec;
 extern crate fat32;
 
 pub mod allocator;
+pub mod config;
 pub mod lang_items;
 pub mod mutex;
 pub mod console;
@@ -33,11 +34,20 @@ pub mod aarch64;
 pub mod process;
 pub mod vm;
 
+#[cfg(not(test))]
+use pi::framebuffer::Framebuffer;
 use pi::gpio::Gpio;
+#[cfg(not(test))]
+use pi::uart::{self, Pl011};
 use pi::timer::spin_sleep_ms;
 
 #[cfg(not(test))]
 use allocator::Allocator;
+use config::GlobalBootConfig;
+#[cfg(not(test))]
+use config::{LogLevel, Uart};
+#[cfg(not(test))]
+use console::{kprintln, FramebufferConsole, CONSOLE};
 use fs::FileSystem;
 use process::GlobalScheduler;
 use process::sys_sleep;
@@ -50,9 +60,13 @@ pub static FILE_SYSTEM: FileSystem = FileSystem::uninitialized();
 
 pub static SCHEDULER: GlobalScheduler = GlobalScheduler::uninitialized();
 
+pub static BOOT_CONFIG: GlobalBootConfig = GlobalBootConfig::uninitialized();
+
+/// The kernel's entry point. `atags` is the address of the ATAG list the
+/// bootloader passes in `x0`; see `GlobalBootConfig::initialize()`.
 #[no_mangle]
 #[cfg(not(test))]
-pub extern "C" fn kmain() {
+pub extern "C" fn kmain(atags: usize) {
     let mut loading_leds = [
         Gpio::new(5).into_output(),
         Gpio::new(6).into_output(),
@@ -73,6 +87,39 @@ pub extern "C" fn kmain() {
     }
 
     ALLOCATOR.initialize();
+
+    // Parsing the command line allocates.
+    let (config, invalid) = BOOT_CONFIG.initialize(atags);
+
+    if config.uart == Uart::Pl011 {
+        match Pl011::new(uart::Config::default()) {
+            Ok(pl011) => CONSOLE.lock().use_pl011(pl011),
+            Err(e) => kprintln!("warning: can't use the PL011 UART: {:?}", e),
+        }
+    }
+
+    // Mirror the console on HDMI if the firmware gives us a framebuffer big
+    // enough for it.
+    let console = Framebuffer::display_size()
+        .and_then(|(width, height)| Framebuffer::new(width, height))
+        .and_then(FramebufferConsole::on);
+    match console {
+        Ok(console) => CONSOLE.lock().mirror_to(console),
+        Err(e) => if config.log_level >= LogLevel::Info {
+            kprintln!("no framebuffer console: {:?}", e);
+        }
+    }
+
+    if config.log_level >= LogLevel::Warn {
+        for option in invalid {
+            kprintln!("warning: {}", option);
+        }
+    }
+
+    if config.log_level >= LogLevel::Debug {
+        kprintln!("boot configuration: {:?}", config);
+    }
+
     FILE_SYSTEM.initialize();
     SCHEDULER.start();
 }
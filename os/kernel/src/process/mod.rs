@@ -5,7 +5,7 @@ mod stack;
 
 pub use self::process::{Process, Id};
 pub use self::state::State;
-pub use self::scheduler::{GlobalScheduler, TICK};
+pub use self::scheduler::GlobalScheduler;
 pub use self::stack::Stack;
 
 pub fn sys_sleep(ms: u32) -> u32 {
@@ -6,11 +6,10 @@ use traps::TrapFrame;
 use pi::interrupt::{Interrupt, Controller};
 use pi::timer::tick_in;
 use aarch64;
+use config::Init;
 use run_blinky;
 use run_shell;
-
-/// The `tick` time.
-pub const TICK: u32 = 10 * 1000;
+use BOOT_CONFIG;
 
 /// Process scheduler for the entire machine.
 #[derive(Debug)]
@@ -37,14 +36,32 @@ impl GlobalScheduler {
         self.0.lock().as_mut().expect("scheduler uninitialized").switch(new_state, tf)
     }
 
+    /// Handles a timer interrupt: rearms the timer for the next time slice and
+    /// switches `tf` to the next ready process.
+    pub fn tick(&self, tf: &mut TrapFrame) {
+        let mut guard = self.0.lock();
+        let scheduler = guard.as_mut().expect("scheduler uninitialized");
+        tick_in(scheduler.tick_us);
+        scheduler.switch(State::Ready, tf).expect("IRQ switch process");
+    }
+
     /// Initializes the scheduler and starts executing processes in user space
     /// using timer interrupt based preemptive scheduling. This method should
     /// not return under normal conditions.
     pub fn start(&self) {
-        *self.0.lock() = Some(Scheduler::new());
-
+        // The boot configuration is read once here; the timer interrupt
+        // handler only uses the time slice saved in the scheduler.
+        let config = BOOT_CONFIG.get();
+        let (init, tick_us) = (config.init, config.tick_us());
+        *self.0.lock() = Some(Scheduler::new(tick_us));
+
+        // The first process runs the program chosen on the command line. The
+        // shell has the blinky alongside it, showing that the Pi is alive.
         let mut process = Process::new().expect("First process failed");
-        process.trap_frame.elr = run_shell as u64;
+        process.trap_frame.elr = match init {
+            Init::Shell => run_shell as u64,
+            Init::Blinky => run_blinky as u64,
+        };
         process.trap_frame.sp = process.stack.top().as_u64();
         // Don't mask DAIF, set execution level to 0.
         process.trap_frame.spsr = 0x0;
@@ -52,13 +69,15 @@ impl GlobalScheduler {
 
         self.add(process);
 
-        let mut process_1 = Process::new().unwrap();
-        process_1.trap_frame.elr = run_blinky as u64;
-        process_1.trap_frame.sp = process_1.stack.top().as_u64();
-        self.add(process_1);
+        if init == Init::Shell {
+            let mut process_1 = Process::new().unwrap();
+            process_1.trap_frame.elr = run_blinky as u64;
+            process_1.trap_frame.sp = process_1.stack.top().as_u64();
+            self.add(process_1);
+        }
 
         Controller::new().enable(Interrupt::Timer1);
-        tick_in(TICK);
+        tick_in(tick_us);
 
         // Switch to process.
         unsafe {
@@ -88,15 +107,18 @@ struct Scheduler {
     processes: VecDeque<Process>,
     current: Option<Id>,
     last_id: Option<Id>,
+    tick_us: u32,
 }
 
 impl Scheduler {
-    /// Returns a new `Scheduler` with an empty queue.
-    fn new() -> Scheduler {
+    /// Returns a new `Scheduler` with an empty queue that preempts processes
+    /// every `tick_us` microseconds.
+    fn new(tick_us: u32) -> Scheduler {
         Scheduler {
             processes: VecDeque::new(),
             current: None,
-            last_id: None
+            last_id: None,
+            tick_us
         }
     }
 
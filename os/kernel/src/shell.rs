@@ -1,10 +1,15 @@
 use stack_vec::StackVec;
 use console::{kprint, kprintln, CONSOLE};
 use std::str;
-use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use fat32::path;
+use fat32::glob::Pattern;
+use fat32::vfat::WalkEntry;
 use fat32::traits::{Dir, Entry, FileSystem, Timestamp, Metadata};
 
+use pi::power;
+
 use FILE_SYSTEM;
 use process::sys_sleep;
 
@@ -60,6 +65,7 @@ impl<'a> Command<'a> {
             "cd" => handle_cd(&self.args[1..], working_dir),
             "ls" => handle_ls(&self.args[1..], working_dir),
             "cat" => handle_cat(&self.args[1..], working_dir),
+            "find" => handle_find(&self.args[1..], working_dir),
             "sleep" => handle_sleep(&self.args[1..]),
             path => kprintln!("Unknown command: {}", path)
         }
@@ -116,27 +122,18 @@ fn handle_cd(args: &[&str], working_dir: &mut PathBuf) {
         return;
     }
 
-    if args[0] == "." {
-        // No-op.
-    } else if args[0] == ".." {
-        working_dir.pop();
-    } else {
-        let path = Path::new(args[0]);
-
-        let mut new_dir = working_dir.clone();
-        new_dir.push(path);
+    let new_dir = path::resolve(working_dir.as_path(), args[0]);
 
-        let entry = FILE_SYSTEM.open(new_dir.as_path());
-        if entry.is_err() {
-            kprintln!("Path not found.");
-            return;
-        }
+    let entry = FILE_SYSTEM.open(new_dir.as_path());
+    if entry.is_err() {
+        kprintln!("Path not found.");
+        return;
+    }
 
-        if entry.unwrap().as_dir().is_some() {
-            working_dir.push(path);
-        } else {
-            kprintln!("Not a directory.");
-        }
+    if entry.unwrap().as_dir().is_some() {
+        *working_dir = new_dir;
+    } else {
+        kprintln!("Not a directory.");
     }
 }
 
@@ -177,16 +174,11 @@ fn handle_ls(mut args: &[&str], working_dir: &PathBuf) {
         return;
     }
 
-    let mut dir = working_dir.clone();
-    if !args.is_empty() {
-        if args[0] == "." {
-            // No-op.
-        } else if args[0] == ".." {
-            dir.pop();
-        } else {
-            dir.push(args[0]);
-        }
-    }
+    let dir = if args.is_empty() {
+        working_dir.clone()
+    } else {
+        path::resolve(working_dir.as_path(), args[0])
+    };
 
     let entry_result = FILE_SYSTEM.open(dir.as_path());
     if entry_result.is_err() {
@@ -216,8 +208,7 @@ fn handle_cat(args: &[&str], working_dir: &PathBuf) {
         return;
     }
 
-    let mut dir = working_dir.clone();
-    dir.push(args[0]);
+    let dir = path::resolve(working_dir.as_path(), args[0]);
 
     let entry_result = FILE_SYSTEM.open(dir.as_path());
     if entry_result.is_err() {
@@ -244,6 +235,50 @@ fn handle_cat(args: &[&str], working_dir: &PathBuf) {
     }
 }
 
+fn print_walk<I: Iterator<Item = io::Result<WalkEntry>>>(entries: I) {
+    for item in entries {
+        match item {
+            Ok(item) => kprintln!("{}", item.path.display()),
+            Err(e) => {
+                kprintln!("Failed to read directory: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+fn handle_find(args: &[&str], working_dir: &PathBuf) {
+    if args.len() > 2 {
+        kprintln!("Usage:");
+        kprintln!("find [directory] [pattern]");
+        kprintln!();
+        return;
+    }
+
+    let dir = if args.is_empty() {
+        working_dir.clone()
+    } else {
+        path::resolve(working_dir.as_path(), args[0])
+    };
+
+    let walk = match FILE_SYSTEM.walk(dir.as_path()) {
+        Ok(walk) => walk,
+        Err(_) => {
+            kprintln!("Path not found.");
+            return;
+        }
+    };
+
+    if args.len() == 2 {
+        match Pattern::new(args[1]) {
+            Ok(pattern) => print_walk(walk.glob(pattern.case_insensitive())),
+            Err(e) => kprintln!("{}", e),
+        }
+    } else {
+        print_walk(walk);
+    }
+}
+
 fn handle_sleep(args: &[&str]) {
     if args.len() != 1 {
         kprintln!("Usage:");
@@ -270,10 +305,45 @@ const BELL: u8 = 7;
 const BACKSPACE: u8 = 8;
 const DELETE: u8 = 127;
 
+/// The bytes that make the shell reset the Pi into the bootloader, sent by
+/// `ttywrite` before it uploads a new kernel.
+const RESET_MAGIC: &[u8] = b"\x1b!reset-to-bootloader!\x1b";
+
+/// Recognizes `RESET_MAGIC` in a stream of bytes.
+struct ResetMatcher {
+    matched: usize
+}
+
+impl ResetMatcher {
+    fn new() -> ResetMatcher {
+        ResetMatcher { matched: 0 }
+    }
+
+    /// Feeds `byte` to the matcher. Returns `true` if it completes the
+    /// sequence.
+    fn feed(&mut self, byte: u8) -> bool {
+        if byte == RESET_MAGIC[self.matched] {
+            self.matched += 1;
+        } else {
+            // `ESC` only appears at the start and the end of the sequence, so
+            // a mismatch can only be the start of a new match.
+            self.matched = if byte == RESET_MAGIC[0] { 1 } else { 0 };
+        }
+
+        if self.matched == RESET_MAGIC.len() {
+            self.matched = 0;
+            return true;
+        }
+
+        false
+    }
+}
+
 /// Starts a shell using `prefix` as the prefix for each line. This function
 /// returns if the `exit` command is called.
 pub fn shell(prefix: &str) {
     let mut working_dir = PathBuf::from("/");
+    let mut reset = ResetMatcher::new();
 
     loop {
         let mut buf_storage = [0u8; 512];
@@ -283,6 +353,10 @@ pub fn shell(prefix: &str) {
 
         loop {
             let byte = CONSOLE.lock().read_byte();
+            if reset.feed(byte) {
+                kprintln!("\nResetting to the bootloader...");
+                power::reset();
+            }
 
             if byte == b'\r' || byte == b'\n' {
                 let mut command_storage: [&str; 64] = [""; 64];
@@ -0,0 +1,15 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate lz4;
+
+// Decompresses arbitrary blocks, as the bootloader does with uploads. Every
+// corrupt block must be reported as an error rather than a panic, and nothing
+// may be written past the output.
+fuzz_target!(|data: &[u8]| {
+    let mut output = vec![0u8; 4096];
+    if let Ok(written) = lz4::decompress(data, &mut output[..4000]) {
+        assert!(written <= 4000);
+    }
+
+    assert!(output[4000..].iter().all(|&byte| byte == 0));
+});
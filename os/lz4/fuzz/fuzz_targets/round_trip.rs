@@ -0,0 +1,12 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate lz4;
+
+// Compresses arbitrary data and checks that it decompresses back exactly,
+// into an output of exactly its size.
+fuzz_target!(|data: &[u8]| {
+    let block = lz4::compress(data);
+    let mut output = vec![0u8; data.len()];
+    assert_eq!(lz4::decompress(&block, &mut output), Ok(data.len()));
+    assert_eq!(&output[..], data);
+});
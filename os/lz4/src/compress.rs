@@ -0,0 +1,93 @@
+use std::cmp::min;
+use std::vec::Vec;
+
+use {MIN_MATCH, MORE_LENGTH};
+
+/// The number of bits of the hashes of 4-byte sequences.
+const HASH_BITS: u32 = 16;
+
+/// The farthest a match may be.
+const MAX_OFFSET: usize = 65535;
+
+/// The last match must start at least this many bytes before the end of the
+/// input, and the last bytes must be literals, so that decompressors can copy
+/// matches in fast, wide chunks.
+const MATCH_LIMIT: usize = 12;
+const LAST_LITERALS: usize = 5;
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = bytes[0] as u32 | (bytes[1] as u32) << 8
+        | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24;
+    (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Writes the rest of a length that didn't fit in its nibble.
+fn write_length(output: &mut Vec<u8>, mut length: usize) {
+    while length >= 255 {
+        output.push(255);
+        length -= 255;
+    }
+
+    output.push(length as u8);
+}
+
+/// Writes a sequence of `literals` followed by the match at `offset` of
+/// `length` bytes, if any.
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], found: Option<(usize, usize)>) {
+    let literal_nibble = min(literals.len(), MORE_LENGTH);
+    let match_nibble = match found {
+        Some((_, length)) => min(length - MIN_MATCH, MORE_LENGTH),
+        None => 0,
+    };
+
+    output.push((literal_nibble << 4 | match_nibble) as u8);
+    if literal_nibble == MORE_LENGTH {
+        write_length(output, literals.len() - MORE_LENGTH);
+    }
+
+    output.extend_from_slice(literals);
+    if let Some((offset, length)) = found {
+        output.push(offset as u8);
+        output.push((offset >> 8) as u8);
+        if match_nibble == MORE_LENGTH {
+            write_length(output, length - MIN_MATCH - MORE_LENGTH);
+        }
+    }
+}
+
+/// Compresses `input` into an LZ4 block, greedily taking the most recent
+/// match of every 4-byte sequence.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() + input.len() / 255 + 16);
+    // The position after the last sequence with each hash, 0 if none.
+    let mut recent = vec![0usize; 1 << HASH_BITS];
+
+    let (mut anchor, mut i) = (0, 0);
+    let match_end = input.len().saturating_sub(LAST_LITERALS);
+    while i + MATCH_LIMIT < input.len() {
+        let slot = &mut recent[hash(&input[i..])];
+        let candidate = *slot;
+        *slot = i + 1;
+
+        if candidate > 0 {
+            let start = candidate - 1;
+            let close = i - start <= MAX_OFFSET;
+            if close && input[start..start + MIN_MATCH] == input[i..i + MIN_MATCH] {
+                let mut length = MIN_MATCH;
+                while i + length < match_end && input[start + length] == input[i + length] {
+                    length += 1;
+                }
+
+                write_sequence(&mut output, &input[anchor..i], Some((i - start, length)));
+                i += length;
+                anchor = i;
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    write_sequence(&mut output, &input[anchor..], None);
+    output
+}
@@ -0,0 +1,124 @@
+//! The LZ4 block format: a sequence of literals copied as they are, each
+//! followed by a match copying bytes already decompressed.
+//!
+//! Every sequence starts with a token whose high nibble is the number of
+//! literals and whose low nibble is the length of the match minus 4. A nibble
+//! of 15 is followed by bytes added to it, up to and including the first byte
+//! that isn't 255. The literals come next, then the match's offset back into
+//! the output as two little-endian bytes. The last sequence has no match.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(all(test, not(feature = "std")))]
+#[macro_use]
+extern crate std;
+
+#[cfg(test)]
+mod tests;
+#[cfg(any(test, feature = "std"))]
+mod compress;
+
+#[cfg(any(test, feature = "std"))]
+pub use compress::compress;
+
+use core::fmt;
+
+/// The shortest match.
+const MIN_MATCH: usize = 4;
+
+/// A nibble of this value is followed by more length bytes.
+const MORE_LENGTH: usize = 15;
+
+/// Why a block can't be decompressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The block ends in the middle of a sequence.
+    Truncated,
+    /// A match refers to bytes before the start of the output.
+    BadOffset,
+    /// The output is too small for the decompressed block.
+    OutputTooSmall,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Truncated => write!(f, "truncated LZ4 block"),
+            Error::BadOffset => write!(f, "LZ4 match before the start of the output"),
+            Error::OutputTooSmall => write!(f, "LZ4 block larger than its output"),
+        }
+    }
+}
+
+/// Reads the rest of the length whose nibble is `nibble` from `input` at
+/// `position`, which is advanced past it.
+fn read_length(input: &[u8], position: &mut usize, nibble: usize) -> Result<usize, Error> {
+    let mut length = nibble;
+    if nibble == MORE_LENGTH {
+        loop {
+            let byte = *input.get(*position).ok_or(Error::Truncated)?;
+            *position += 1;
+            length = length.checked_add(byte as usize).ok_or(Error::Truncated)?;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+
+    Ok(length)
+}
+
+/// Decompresses the block `input` into `output`. Returns the number of bytes
+/// written to `output`.
+///
+/// # Errors
+///
+/// Returns an error if `input` isn't a valid block or if `output` is too
+/// small. Some of `output` may have been written by then.
+pub fn decompress(input: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+    let (mut i, mut o) = (0, 0);
+    loop {
+        let token = *input.get(i).ok_or(Error::Truncated)? as usize;
+        i += 1;
+
+        let literals = read_length(input, &mut i, token >> 4)?;
+        if literals > input.len() - i {
+            return Err(Error::Truncated);
+        } else if literals > output.len() - o {
+            return Err(Error::OutputTooSmall);
+        }
+
+        output[o..o + literals].copy_from_slice(&input[i..i + literals]);
+        i += literals;
+        o += literals;
+
+        // Only the last sequence has no match.
+        if i == input.len() {
+            return Ok(o);
+        } else if input.len() - i < 2 {
+            return Err(Error::Truncated);
+        }
+
+        let offset = input[i] as usize | (input[i + 1] as usize) << 8;
+        i += 2;
+        if offset == 0 || offset > o {
+            return Err(Error::BadOffset);
+        }
+
+        let length = read_length(input, &mut i, token & 0xf)?
+            .checked_add(MIN_MATCH)
+            .ok_or(Error::Truncated)?;
+        if length > output.len() - o {
+            return Err(Error::OutputTooSmall);
+        }
+
+        // The match may overlap the bytes it writes, repeating them.
+        for k in o..o + length {
+            output[k] = output[k - offset];
+        }
+
+        o += length;
+    }
+}
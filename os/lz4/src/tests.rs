@@ -0,0 +1,133 @@
+use std::vec::Vec;
+
+use {compress, decompress, Error};
+
+fn round_trip(input: &[u8]) -> Vec<u8> {
+    let block = compress(input);
+    let mut output = vec![0; input.len()];
+    assert_eq!(decompress(&block, &mut output), Ok(input.len()));
+    assert_eq!(&output[..], input);
+    block
+}
+
+fn repeat(bytes: &[u8], times: usize) -> Vec<u8> {
+    let mut repeated = Vec::new();
+    for _ in 0..times {
+        repeated.extend_from_slice(bytes);
+    }
+
+    repeated
+}
+
+/// Bytes that don't compress, from a linear congruential generator.
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491u32;
+    (0..len).map(|_| {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        (state >> 16) as u8
+    }).collect()
+}
+
+#[test]
+fn decompresses_sequences() {
+    // "abc" as literals, then a match 3 back of 9 bytes, then the literals
+    // "defgh" with a match 1 back of 4 bytes, then the literal "!".
+    let block = [0x35, b'a', b'b', b'c', 3, 0, 0x50, b'd', b'e', b'f', b'g', b'h', 1, 0, 0x10, b'!'];
+    let mut output = [0; 32];
+    assert_eq!(decompress(&block, &mut output), Ok(22));
+    assert_eq!(&output[..22], b"abcabcabcabcdefghhhhh!");
+}
+
+#[test]
+fn decompresses_long_lengths() {
+    // 15 + 255 + 2 literals, then a match 1 back of 4 + 15 + 1 bytes.
+    let mut block = vec![0xff, 255, 2];
+    block.extend((0..272).map(|i| i as u8));
+    block.extend_from_slice(&[1, 0, 1, 0x00]);
+    let mut output = vec![0; 512];
+    assert_eq!(decompress(&block, &mut output), Ok(292));
+    assert_eq!(output[271], 271u16 as u8);
+    assert!(output[272..292].iter().all(|&byte| byte == 271u16 as u8));
+}
+
+#[test]
+fn decompresses_reference_blocks() {
+    // Produced by the reference implementation from 64 times "hello, lz4! ".
+    let block = [
+        0xcf, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x6c, 0x7a, 0x34, 0x21, 0x20, 0x0c, 0x00,
+        0xff, 0xff, 0xde, 0x50, 0x6c, 0x7a, 0x34, 0x21, 0x20,
+    ];
+    let mut output = vec![0; 768];
+    assert_eq!(decompress(&block, &mut output), Ok(768));
+    assert_eq!(output, repeat(b"hello, lz4! ", 64));
+}
+
+#[test]
+fn round_trips() {
+    round_trip(b"");
+    round_trip(b"a");
+    round_trip(b"abcdefghijkl");
+    round_trip(b"abcdabcdabcdabcd");
+    round_trip(&noise(5000));
+
+    let text = repeat(b"the quick brown fox jumps over the lazy dog. ", 200);
+    assert!(round_trip(&text).len() < text.len() / 10);
+
+    let zeroes = vec![0; 100_000];
+    assert!(round_trip(&zeroes).len() < 500);
+}
+
+#[test]
+fn round_trips_far_matches() {
+    // The repeat is too far back to be found, then close enough.
+    let far = noise(70_000);
+    let close = noise(40_000);
+    round_trip(&[&far[..], &far[..1000]].concat());
+    assert!(round_trip(&[&close[..], &close[..1000]].concat()).len() < 40_500);
+}
+
+#[test]
+fn ends_with_literals() {
+    // Decompressors may rely on the last 5 bytes being literals and the last
+    // match starting 12 bytes before the end.
+    let input = vec![7; 64];
+    let block = round_trip(&input);
+    let last = block.len() - 6;
+    assert_eq!(block[last], 0x50);
+    assert!(block[last + 1..].iter().all(|&byte| byte == 7));
+}
+
+#[test]
+fn rejects_corrupt_blocks() {
+    let mut output = [0; 64];
+    assert_eq!(decompress(&[], &mut output), Err(Error::Truncated));
+    assert_eq!(decompress(&[0x30, b'a'], &mut output), Err(Error::Truncated));
+    assert_eq!(decompress(&[0xf0], &mut output), Err(Error::Truncated));
+    assert_eq!(decompress(&[0xf0, 255], &mut output), Err(Error::Truncated));
+    assert_eq!(decompress(&[0x10, b'a', 1], &mut output), Err(Error::Truncated));
+    assert_eq!(decompress(&[0x1f, b'a', 1, 0], &mut output), Err(Error::Truncated));
+    assert_eq!(decompress(&[0x10, b'a', 0, 0, 0x00], &mut output), Err(Error::BadOffset));
+    assert_eq!(decompress(&[0x10, b'a', 2, 0, 0x00], &mut output), Err(Error::BadOffset));
+    assert_eq!(decompress(&[0x00, 1, 0, 0x00], &mut output), Err(Error::BadOffset));
+}
+
+#[test]
+fn rejects_small_outputs() {
+    let block = compress(&[1; 100]);
+    let mut output = [0; 99];
+    assert_eq!(decompress(&block, &mut output), Err(Error::OutputTooSmall));
+    assert_eq!(decompress(&[0x20, b'a', b'b'], &mut output[..1]), Err(Error::OutputTooSmall));
+}
+
+#[test]
+fn survives_every_truncation() {
+    let input = repeat(b"the quick brown fox jumps over the lazy dog. ", 20);
+    let block = compress(&input);
+    let mut output = vec![0; input.len()];
+    for len in 0..block.len() {
+        // Cutting at a sequence boundary leaves a valid, shorter block.
+        if let Ok(written) = decompress(&block[..len], &mut output) {
+            assert_eq!(&output[..written], &input[..written]);
+        }
+    }
+}
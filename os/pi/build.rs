@@ -0,0 +1,11 @@
+pub fn main() {
+    // The SD card driver links against the controller driver in the
+    // kernel's `ext/libsd.a`.
+    let sd = ::std::env::var("CARGO_FEATURE_SD").is_ok();
+    if sd && ::std::env::var("TARGET").unwrap() == "aarch64-none-elf" {
+        let dir = ::std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        println!("cargo:rustc-link-search=native={}/../kernel/ext", dir);
+        println!("cargo:rustc-link-lib=static=sd");
+        println!("cargo:rerun-if-changed=../kernel/ext/libsd.a");
+    }
+}
@@ -18,6 +18,18 @@ impl Atags {
             ptr: unsafe { &*(ATAG_BASE as *const raw::Atag) }
         }
     }
+
+    /// Returns an iterator over the ATAGS at `address`, such as a list passed
+    /// on by a bootloader.
+    ///
+    /// # Safety
+    ///
+    /// `address` must point to a valid ATAG list that is never overwritten.
+    pub unsafe fn at(address: usize) -> Atags {
+        Atags {
+            ptr: &*(address as *const raw::Atag)
+        }
+    }
 }
 
 impl Iterator for Atags {
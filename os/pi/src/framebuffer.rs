@@ -0,0 +1,104 @@
+use core::slice;
+
+use mailbox::{self, Buffer, Mailbox, Message};
+use mailbox::{AllocateBuffer, DisplaySize, Pitch, SetDepth, SetPhysicalSize, SetPixelOrder};
+use mailbox::SetVirtualSize;
+
+pub use mailbox::PixelOrder;
+
+/// The only depth supported: a 32-bit word per pixel.
+const DEPTH: u32 = 32;
+
+/// The alignment asked for the framebuffer, in bytes.
+const ALIGNMENT: u32 = 16;
+
+/// The VideoCore returns bus addresses; these bits select the bus alias.
+const BUS_ALIAS_MASK: u32 = 0xC0000000;
+
+/// Why a framebuffer couldn't be allocated.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    Mailbox(mailbox::Error),
+    /// The firmware settled on a depth other than 32 bits per pixel.
+    Depth(u32),
+    /// The firmware returned no buffer, or one too small.
+    NoBuffer,
+}
+
+impl From<mailbox::Error> for Error {
+    fn from(error: mailbox::Error) -> Error {
+        Error::Mailbox(error)
+    }
+}
+
+/// A framebuffer allocated by the firmware and displayed on HDMI, with 32-bit
+/// pixels.
+#[derive(Debug)]
+pub struct Framebuffer {
+    /// The size of the framebuffer in pixels.
+    pub width: usize,
+    pub height: usize,
+    /// The number of pixels between the starts of two rows, at least `width`.
+    pub stride: usize,
+    pub order: PixelOrder,
+    pixels: &'static mut [u32],
+}
+
+impl Framebuffer {
+    /// Returns the size in pixels of the attached display.
+    pub fn display_size() -> Result<(usize, usize), Error> {
+        let (width, height) = mailbox::property(&DisplaySize)?;
+        Ok((width as usize, height as usize))
+    }
+
+    /// Allocates a framebuffer of `width` by `height` pixels.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the firmware refused the request or settled on a
+    /// format other than 32 bits per pixel.
+    pub fn new(width: usize, height: usize) -> Result<Framebuffer, Error> {
+        let (width, height) = (width as u32, height as u32);
+        let mut buffer = Buffer::new();
+        let mut message = Message::new(&mut buffer);
+        message.add(&SetPhysicalSize { width, height })?;
+        message.add(&SetVirtualSize { width, height })?;
+        let depth = message.add(&SetDepth { depth: DEPTH })?;
+        let order = message.add(&SetPixelOrder(PixelOrder::Bgr))?;
+        let allocated = message.add(&AllocateBuffer { alignment: ALIGNMENT })?;
+        let pitch = message.add(&Pitch)?;
+        message.send(&mut Mailbox::new())?;
+
+        let depth = message.response(&depth)?.depth;
+        if depth != DEPTH {
+            return Err(Error::Depth(depth));
+        }
+
+        let allocated = message.response(&allocated)?;
+        let stride = message.response(&pitch)? as usize / 4;
+        let (width, height) = (width as usize, height as usize);
+        if allocated.start == 0 || stride < width
+            || (allocated.size as usize) < stride * height * 4 {
+            return Err(Error::NoBuffer);
+        }
+
+        let start = (allocated.start & !BUS_ALIAS_MASK) as usize;
+        Ok(Framebuffer {
+            width,
+            height,
+            stride,
+            order: message.response(&order)?,
+            pixels: unsafe { slice::from_raw_parts_mut(start as *mut u32, stride * height) },
+        })
+    }
+
+    /// Returns the framebuffer's pixels, row after row, `stride` pixels apart.
+    pub fn pixels(&mut self) -> &mut [u32] {
+        &mut *self.pixels
+    }
+
+    /// Returns the framebuffer's pixels, which it owns until the end.
+    pub fn into_pixels(self) -> &'static mut [u32] {
+        self.pixels
+    }
+}
@@ -12,6 +12,12 @@
 #[cfg(feature = "std")]
 extern crate core;
 extern crate volatile;
+#[cfg(feature = "sd")]
+extern crate fat32;
+
+#[cfg(all(test, not(feature = "std")))]
+#[macro_use]
+extern crate std;
 
 pub mod timer;
 pub mod uart;
@@ -19,3 +25,8 @@ pub mod gpio;
 pub mod common;
 pub mod atags;
 pub mod interrupt;
+pub mod power;
+pub mod mailbox;
+pub mod framebuffer;
+#[cfg(feature = "sd")]
+pub mod sd;
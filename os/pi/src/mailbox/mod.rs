@@ -0,0 +1,252 @@
+mod property;
+
+#[cfg(test)]
+mod tests;
+
+use core::marker::PhantomData;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use common::IO_BASE;
+use volatile::prelude::*;
+use volatile::{Volatile, ReadVolatile, Reserved};
+
+pub use self::property::*;
+
+/// The base address of the VideoCore mailbox registers.
+const MAILBOX_REG_BASE: usize = IO_BASE + 0xB880;
+
+/// `STATUS` bits: the mailbox can't take another message, or has none.
+const STATUS_FULL: u32 = 1 << 31;
+const STATUS_EMPTY: u32 = 1 << 30;
+
+/// The low 4 bits of a message are its channel, so buffers are aligned to 16.
+const CHANNEL_MASK: u32 = 0xF;
+
+/// The VideoCore sees RAM at this bus address, uncached.
+const BUS_ADDRESS_OFFSET: u32 = 0xC0000000;
+
+/// The size of a `Buffer` in words.
+const BUFFER_WORDS: usize = 256;
+
+/// The buffer's header: its size in bytes, then its request or response code.
+const HEADER_WORDS: usize = 2;
+/// A tag's header: its identifier, the size of its value buffer in bytes, then
+/// its request or response code.
+const TAG_HEADER_WORDS: usize = 3;
+
+const REQUEST: u32 = 0;
+const RESPONSE_SUCCESS: u32 = 0x80000000;
+/// Set in a tag's code by the firmware, whose other bits are then the length
+/// of the response in bytes.
+const TAG_RESPONSE: u32 = 1 << 31;
+const END_TAG: u32 = 0;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    DATA: Volatile<u32>,
+    __r0: [Reserved<u32>; 3],
+    PEEK: ReadVolatile<u32>,
+    SENDER: ReadVolatile<u32>,
+    STATUS: ReadVolatile<u32>,
+    CONFIG: Volatile<u32>,
+}
+
+/// A mailbox channel.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Channel {
+    Power = 0,
+    Framebuffer = 1,
+    Vuart = 2,
+    Vchiq = 3,
+    Leds = 4,
+    Buttons = 5,
+    Touchscreen = 6,
+    /// The property interface: ARM to VideoCore.
+    Property = 8,
+}
+
+/// The mailboxes between the ARM cores and the VideoCore: the ARM reads the
+/// first and writes the second.
+pub struct Mailbox {
+    registers: &'static mut [Registers; 2]
+}
+
+impl Mailbox {
+    /// Returns a new instance of `Mailbox`.
+    pub fn new() -> Mailbox {
+        Mailbox {
+            registers: unsafe { &mut *(MAILBOX_REG_BASE as *mut [Registers; 2]) },
+        }
+    }
+
+    /// Sends `data`, whose low 4 bits must be clear, to the VideoCore on
+    /// `channel`, then blocks until it replies on that channel. Returns the
+    /// reply without its channel.
+    pub fn call(&mut self, channel: Channel, data: u32) -> u32 {
+        let channel = channel as u32;
+        while self.registers[1].STATUS.has_mask(STATUS_FULL) {  }
+        self.registers[1].DATA.write((data & !CHANNEL_MASK) | channel);
+
+        loop {
+            while self.registers[0].STATUS.has_mask(STATUS_EMPTY) {  }
+            let reply = self.registers[0].DATA.read();
+            if reply & CHANNEL_MASK == channel {
+                return reply & !CHANNEL_MASK;
+            }
+        }
+    }
+}
+
+/// Why a property request failed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The buffer has no room for another tag.
+    BufferFull,
+    /// The firmware couldn't parse the buffer; the code it replied with.
+    Failed(u32),
+    /// The firmware didn't answer the tag, which it likely doesn't know.
+    NoResponse(u32),
+    /// The response, of `length` bytes, is larger than the tag's value buffer.
+    Truncated { tag: u32, length: u32 },
+    /// The response, of `length` bytes, is too short to decode.
+    ShortResponse { tag: u32, length: u32 },
+}
+
+/// The memory of a property message, which the VideoCore reads and writes.
+#[repr(C, align(16))]
+pub struct Buffer([u32; BUFFER_WORDS]);
+
+impl Buffer {
+    /// Returns a new, zeroed buffer.
+    pub fn new() -> Buffer {
+        Buffer([0; BUFFER_WORDS])
+    }
+}
+
+/// Where a property of type `P` is in a `Message`, to read its response.
+#[derive(Debug)]
+pub struct Slot<P> {
+    offset: usize,
+    property: PhantomData<P>,
+}
+
+/// A property message: any number of property tags sent to the firmware at
+/// once, each answered in place.
+///
+/// ```rust,ignore
+/// let mut buffer = Buffer::new();
+/// let mut message = Message::new(&mut buffer);
+/// let revision = message.add(&BoardRevision)?;
+/// let memory = message.add(&ArmMemory)?;
+/// message.send(&mut Mailbox::new())?;
+/// let (revision, memory) = (message.response(&revision)?, message.response(&memory)?);
+/// ```
+pub struct Message<'a> {
+    buffer: &'a mut Buffer,
+    /// The offset of the next tag in words.
+    end: usize,
+}
+
+impl<'a> Message<'a> {
+    /// Returns an empty message written into `buffer`.
+    pub fn new(buffer: &'a mut Buffer) -> Message<'a> {
+        Message { buffer, end: HEADER_WORDS }
+    }
+
+    /// Adds the request `property` to the message. Returns its slot.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::BufferFull` if the buffer has no room for it.
+    pub fn add<P: Property>(&mut self, property: &P) -> Result<Slot<P>, Error> {
+        let start = self.end;
+        let values = start + TAG_HEADER_WORDS;
+        // The end tag must fit after it.
+        if values + P::WORDS + 1 > BUFFER_WORDS {
+            return Err(Error::BufferFull);
+        }
+
+        let words = &mut self.buffer.0;
+        words[start] = P::TAG;
+        words[start + 1] = (P::WORDS * 4) as u32;
+        words[start + 2] = REQUEST;
+        for word in &mut words[values..values + P::WORDS] {
+            *word = 0;
+        }
+
+        property.encode(&mut words[values..values + P::WORDS]);
+        self.end = values + P::WORDS;
+        Ok(Slot { offset: start, property: PhantomData })
+    }
+
+    /// Writes the header and the end tag. Returns the message's size in words.
+    fn finish(&mut self) -> usize {
+        let words = self.end + 1;
+        self.buffer.0[0] = (words * 4) as u32;
+        self.buffer.0[1] = REQUEST;
+        self.buffer.0[self.end] = END_TAG;
+        words
+    }
+
+    /// Checks the firmware's response code.
+    fn check(&self) -> Result<(), Error> {
+        match self.buffer.0[1] {
+            RESPONSE_SUCCESS => Ok(()),
+            code => Err(Error::Failed(code)),
+        }
+    }
+
+    /// Sends the message on the property channel of `mailbox` and waits for
+    /// the firmware's response.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Failed` if the firmware couldn't parse the message.
+    pub fn send(&mut self, mailbox: &mut Mailbox) -> Result<(), Error> {
+        self.finish();
+
+        // The firmware reads and writes the buffer behind the compiler's back.
+        let address = self.buffer as *mut Buffer as usize;
+        compiler_fence(Ordering::SeqCst);
+        mailbox.call(Channel::Property, address as u32 | BUS_ADDRESS_OFFSET);
+        compiler_fence(Ordering::SeqCst);
+
+        self.check()
+    }
+
+    /// Returns the decoded response to the property in `slot`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::NoResponse` if the firmware didn't answer the property,
+    /// `Error::Truncated` if its response didn't fit, and
+    /// `Error::ShortResponse` if the response is too short to decode.
+    pub fn response<P: Property>(&self, slot: &Slot<P>) -> Result<P::Response, Error> {
+        let code = self.buffer.0[slot.offset + 2];
+        if code & TAG_RESPONSE == 0 {
+            return Err(Error::NoResponse(P::TAG));
+        }
+
+        let length = code & !TAG_RESPONSE;
+        if length as usize > P::WORDS * 4 {
+            return Err(Error::Truncated { tag: P::TAG, length });
+        }
+
+        let values = slot.offset + TAG_HEADER_WORDS;
+        let words = (length as usize + 3) / 4;
+        P::decode(&self.buffer.0[values..values + words])
+            .ok_or(Error::ShortResponse { tag: P::TAG, length })
+    }
+}
+
+/// Sends a message with only `property` to the firmware. Returns the decoded
+/// response.
+pub fn property<P: Property>(property: &P) -> Result<P::Response, Error> {
+    let mut buffer = Buffer::new();
+    let mut message = Message::new(&mut buffer);
+    let slot = message.add(property)?;
+    message.send(&mut Mailbox::new())?;
+    message.response(&slot)
+}
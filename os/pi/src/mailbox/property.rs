@@ -0,0 +1,365 @@
+/// A property tag: a request to the firmware, and the response it writes in
+/// place of the request.
+pub trait Property {
+    /// The tag's identifier.
+    const TAG: u32;
+
+    /// The size of the tag's value buffer in words: the larger of the request
+    /// and of the response.
+    const WORDS: usize;
+
+    /// The decoded response.
+    type Response;
+
+    /// Writes the request into `values`, the tag's `WORDS` zeroed words.
+    fn encode(&self, _values: &mut [u32]) {  }
+
+    /// Decodes the response from `values`, the words of the value buffer the
+    /// response's length covers. Returns `None` if they are too few.
+    fn decode(values: &[u32]) -> Option<Self::Response>;
+}
+
+/// A clock of the SoC.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Clock {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+    V3d = 5,
+    H264 = 6,
+    Isp = 7,
+    Sdram = 8,
+    Pixel = 9,
+    Pwm = 10,
+}
+
+/// A device whose power the firmware controls.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Device {
+    SdCard = 0,
+    Uart0 = 1,
+    Uart1 = 2,
+    UsbHcd = 3,
+    I2c0 = 4,
+    I2c1 = 5,
+    I2c2 = 6,
+    Spi = 7,
+    Ccp2tx = 8,
+}
+
+/// A region of memory: its start address and size in bytes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub start: u32,
+    pub size: u32,
+}
+
+/// The power state of a device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PowerState {
+    pub on: bool,
+    /// `false` if the board has no such device.
+    pub exists: bool,
+}
+
+const POWER_ON: u32 = 1 << 0;
+const POWER_MISSING: u32 = 1 << 1;
+const POWER_WAIT: u32 = 1 << 1;
+
+impl PowerState {
+    fn decode(state: u32) -> PowerState {
+        PowerState {
+            on: state & POWER_ON != 0,
+            exists: state & POWER_MISSING == 0,
+        }
+    }
+}
+
+/// Defines a property without a request, whose response is decoded by
+/// `$decode` from its `$words` words of values.
+macro simple_property($(#[$attr:meta])* $name:ident($tag:expr, $words:expr) -> $response:ty,
+                      |$values:ident| $decode:expr) {
+    $(#[$attr])*
+    #[derive(Debug, Copy, Clone)]
+    pub struct $name;
+
+    impl Property for $name {
+        const TAG: u32 = $tag;
+        const WORDS: usize = $words;
+        type Response = $response;
+
+        fn decode($values: &[u32]) -> Option<$response> {
+            if $values.len() < $words { None } else { Some($decode) }
+        }
+    }
+}
+
+/// Defines a property whose request is an identifier, of a clock or of a
+/// sensor, and whose response is the identifier and a value.
+macro identified_property($(#[$attr:meta])* $name:ident($tag:expr, $id:ty)) {
+    $(#[$attr])*
+    #[derive(Debug, Copy, Clone)]
+    pub struct $name(pub $id);
+
+    impl Property for $name {
+        const TAG: u32 = $tag;
+        const WORDS: usize = 2;
+        type Response = u32;
+
+        fn encode(&self, values: &mut [u32]) {
+            values[0] = self.0 as u32;
+        }
+
+        fn decode(values: &[u32]) -> Option<u32> {
+            values.get(1).cloned()
+        }
+    }
+}
+
+simple_property! {
+    /// The firmware's revision.
+    FirmwareRevision(0x00000001, 1) -> u32, |values| values[0]
+}
+
+simple_property! {
+    /// The board's model.
+    BoardModel(0x00010001, 1) -> u32, |values| values[0]
+}
+
+simple_property! {
+    /// The board's revision code, which identifies the model, its memory size
+    /// and its manufacturer.
+    BoardRevision(0x00010002, 1) -> u32, |values| values[0]
+}
+
+simple_property! {
+    /// The MAC address of the board's Ethernet interface, in network order.
+    MacAddress(0x00010003, 2) -> [u8; 6], |values| {
+        let mut address = [0; 6];
+        for (i, byte) in address.iter_mut().enumerate() {
+            *byte = (values[i / 4] >> (8 * (i % 4))) as u8;
+        }
+
+        address
+    }
+}
+
+simple_property! {
+    /// The board's serial number.
+    BoardSerial(0x00010004, 2) -> u64, |values| {
+        (values[1] as u64) << 32 | values[0] as u64
+    }
+}
+
+simple_property! {
+    /// The memory the ARM cores own: everything below the VideoCore's.
+    ArmMemory(0x00010005, 2) -> MemoryRegion, |values| {
+        MemoryRegion { start: values[0], size: values[1] }
+    }
+}
+
+simple_property! {
+    /// The memory the VideoCore owns, at the top of RAM.
+    VcMemory(0x00010006, 2) -> MemoryRegion, |values| {
+        MemoryRegion { start: values[0], size: values[1] }
+    }
+}
+
+identified_property! {
+    /// The rate of a clock in Hz, 0 if it doesn't exist.
+    ClockRate(0x00030002, Clock)
+}
+
+identified_property! {
+    /// The highest rate a clock may be set to, in Hz.
+    MaxClockRate(0x00030004, Clock)
+}
+
+identified_property! {
+    /// The lowest rate a clock may be set to, in Hz.
+    MinClockRate(0x00030007, Clock)
+}
+
+/// The sensor whose temperature is reported: the SoC's, the only one.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Sensor {
+    Soc = 0,
+}
+
+identified_property! {
+    /// The temperature of the SoC in thousandths of a degree Celsius.
+    Temperature(0x00030006, Sensor)
+}
+
+identified_property! {
+    /// The temperature in thousandths of a degree Celsius above which the
+    /// firmware throttles the clocks.
+    MaxTemperature(0x0003000a, Sensor)
+}
+
+/// The power state of a device.
+#[derive(Debug, Copy, Clone)]
+pub struct GetPowerState(pub Device);
+
+impl Property for GetPowerState {
+    const TAG: u32 = 0x00020001;
+    const WORDS: usize = 2;
+    type Response = PowerState;
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.0 as u32;
+    }
+
+    fn decode(values: &[u32]) -> Option<PowerState> {
+        values.get(1).map(|&state| PowerState::decode(state))
+    }
+}
+
+/// Powers a device on or off, waiting until it's stable if `wait`. The
+/// response is the device's new state.
+#[derive(Debug, Copy, Clone)]
+pub struct SetPowerState {
+    pub device: Device,
+    pub on: bool,
+    pub wait: bool,
+}
+
+impl Property for SetPowerState {
+    const TAG: u32 = 0x00028001;
+    const WORDS: usize = 2;
+    type Response = PowerState;
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.device as u32;
+        values[1] = if self.on { POWER_ON } else { 0 }
+            | if self.wait { POWER_WAIT } else { 0 };
+    }
+
+    fn decode(values: &[u32]) -> Option<PowerState> {
+        values.get(1).map(|&state| PowerState::decode(state))
+    }
+}
+
+/// The order of a framebuffer pixel's color components in memory.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PixelOrder {
+    Bgr = 0,
+    Rgb = 1,
+}
+
+impl PixelOrder {
+    /// Returns the 32-bit pixel of the color `rgb`, written `0xRRGGBB`.
+    pub fn pixel(self, rgb: u32) -> u32 {
+        match self {
+            PixelOrder::Bgr => rgb & 0xFFFFFF,
+            PixelOrder::Rgb => {
+                (rgb & 0xFF) << 16 | (rgb & 0xFF00) | (rgb >> 16) & 0xFF
+            }
+        }
+    }
+}
+
+/// Defines a framebuffer property setting one or two values, whose response
+/// is the values the firmware settled on.
+macro setting_property($(#[$attr:meta])* $name:ident($tag:expr) { $($field:ident),* }) {
+    $(#[$attr])*
+    #[derive(Debug, Copy, Clone)]
+    pub struct $name { $(pub $field: u32),* }
+
+    impl Property for $name {
+        const TAG: u32 = $tag;
+        const WORDS: usize = 2;
+        type Response = $name;
+
+        fn encode(&self, values: &mut [u32]) {
+            let mut words = values.iter_mut();
+            $(*words.next().unwrap() = self.$field;)*
+        }
+
+        fn decode(values: &[u32]) -> Option<$name> {
+            let mut words = values.iter();
+            Some($name { $($field: *words.next()?),* })
+        }
+    }
+}
+
+simple_property! {
+    /// The size in pixels of the display attached to the board, or the
+    /// firmware's default one.
+    DisplaySize(0x00040003, 2) -> (u32, u32), |values| (values[0], values[1])
+}
+
+setting_property! {
+    /// Sets the size of the framebuffer's display in pixels.
+    SetPhysicalSize(0x00048003) { width, height }
+}
+
+setting_property! {
+    /// Sets the size of the framebuffer in pixels, of which the display shows
+    /// a part.
+    SetVirtualSize(0x00048004) { width, height }
+}
+
+setting_property! {
+    /// Sets the number of bits per pixel.
+    SetDepth(0x00048005) { depth }
+}
+
+/// Sets the order of a pixel's color components. The response is the order
+/// the firmware settled on.
+#[derive(Debug, Copy, Clone)]
+pub struct SetPixelOrder(pub PixelOrder);
+
+impl Property for SetPixelOrder {
+    const TAG: u32 = 0x00048006;
+    const WORDS: usize = 1;
+    type Response = PixelOrder;
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.0 as u32;
+    }
+
+    fn decode(values: &[u32]) -> Option<PixelOrder> {
+        match values.get(0) {
+            Some(&0) => Some(PixelOrder::Bgr),
+            Some(&1) => Some(PixelOrder::Rgb),
+            _ => None,
+        }
+    }
+}
+
+/// Allocates the framebuffer, aligned to `alignment` bytes. The response is
+/// its bus address and size in bytes; the address is 0 if the allocation
+/// failed.
+#[derive(Debug, Copy, Clone)]
+pub struct AllocateBuffer {
+    pub alignment: u32,
+}
+
+impl Property for AllocateBuffer {
+    const TAG: u32 = 0x00040001;
+    const WORDS: usize = 2;
+    type Response = MemoryRegion;
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.alignment;
+    }
+
+    fn decode(values: &[u32]) -> Option<MemoryRegion> {
+        if values.len() < 2 {
+            None
+        } else {
+            Some(MemoryRegion { start: values[0], size: values[1] })
+        }
+    }
+}
+
+simple_property! {
+    /// The number of bytes between the starts of two rows of the framebuffer.
+    Pitch(0x00040008, 1) -> u32, |values| values[0]
+}
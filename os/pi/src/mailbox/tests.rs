@@ -0,0 +1,123 @@
+use std::mem;
+
+use super::*;
+
+/// Answers the tag at `offset` of `message` like the firmware: `values`,
+/// with a response code claiming `length` bytes.
+fn respond(message: &mut Message, offset: usize, length: u32, values: &[u32]) {
+    message.buffer.0[1] = RESPONSE_SUCCESS;
+    message.buffer.0[offset + 2] = TAG_RESPONSE | length;
+    let start = offset + TAG_HEADER_WORDS;
+    message.buffer.0[start..start + values.len()].copy_from_slice(values);
+}
+
+#[test]
+fn buffer_is_aligned() {
+    assert_eq!(mem::align_of::<Buffer>(), 16);
+    assert_eq!(mem::size_of::<Buffer>(), BUFFER_WORDS * 4);
+}
+
+#[test]
+fn encodes_requests() {
+    let mut buffer = Buffer::new();
+    buffer.0 = [0xdeadbeef; BUFFER_WORDS];
+
+    let mut message = Message::new(&mut buffer);
+    let revision = message.add(&BoardRevision).unwrap();
+    let clock = message.add(&ClockRate(Clock::Arm)).unwrap();
+    let power = message.add(&SetPowerState { device: Device::UsbHcd, on: true, wait: true })
+        .unwrap();
+    assert_eq!(message.finish(), 17);
+
+    assert_eq!((revision.offset, clock.offset, power.offset), (2, 6, 11));
+    assert_eq!(&message.buffer.0[..17], &[
+        68, REQUEST,
+        0x00010002, 4, REQUEST, 0,
+        0x00030002, 8, REQUEST, 3, 0,
+        0x00028001, 8, REQUEST, 3, 0b11,
+        END_TAG,
+    ]);
+}
+
+#[test]
+fn decodes_responses() {
+    let mut buffer = Buffer::new();
+    let mut message = Message::new(&mut buffer);
+    let revision = message.add(&BoardRevision).unwrap();
+    let memory = message.add(&ArmMemory).unwrap();
+    let clock = message.add(&ClockRate(Clock::Uart)).unwrap();
+    let temperature = message.add(&Temperature(Sensor::Soc)).unwrap();
+    message.finish();
+
+    respond(&mut message, revision.offset, 4, &[0xa02082]);
+    respond(&mut message, memory.offset, 8, &[0, 0x3b400000]);
+    respond(&mut message, clock.offset, 8, &[2, 48_000_000]);
+    respond(&mut message, temperature.offset, 8, &[0, 47_236]);
+
+    assert_eq!(message.check(), Ok(()));
+    assert_eq!(message.response(&revision), Ok(0xa02082));
+    assert_eq!(message.response(&memory), Ok(MemoryRegion { start: 0, size: 0x3b400000 }));
+    assert_eq!(message.response(&clock), Ok(48_000_000));
+    assert_eq!(message.response(&temperature), Ok(47_236));
+}
+
+#[test]
+fn decodes_byte_responses() {
+    let mut buffer = Buffer::new();
+    let mut message = Message::new(&mut buffer);
+    let mac = message.add(&MacAddress).unwrap();
+    let serial = message.add(&BoardSerial).unwrap();
+    let power = message.add(&GetPowerState(Device::I2c2)).unwrap();
+    message.finish();
+
+    // The MAC address is 6 bytes, not a whole number of words.
+    respond(&mut message, mac.offset, 6, &[0x12eb27b8, 0x5634]);
+    respond(&mut message, serial.offset, 8, &[0x89abcdef, 0x01234567]);
+    respond(&mut message, power.offset, 8, &[6, 0b10]);
+
+    assert_eq!(message.response(&mac), Ok([0xb8, 0x27, 0xeb, 0x12, 0x34, 0x56]));
+    assert_eq!(message.response(&serial), Ok(0x0123456789abcdef));
+    assert_eq!(message.response(&power), Ok(PowerState { on: false, exists: false }));
+}
+
+#[test]
+fn reports_failures() {
+    let mut buffer = Buffer::new();
+    let mut message = Message::new(&mut buffer);
+    let revision = message.add(&BoardRevision).unwrap();
+    let clock = message.add(&MaxClockRate(Clock::Core)).unwrap();
+    let memory = message.add(&VcMemory).unwrap();
+    message.finish();
+
+    // The firmware didn't parse the message.
+    assert_eq!(message.check(), Err(Error::Failed(REQUEST)));
+    message.buffer.0[1] = 0x80000001;
+    assert_eq!(message.check(), Err(Error::Failed(0x80000001)));
+
+    // It parsed the message but ignored the first tag, and answered the
+    // others with too much and too little.
+    respond(&mut message, clock.offset, 12, &[]);
+    respond(&mut message, memory.offset, 4, &[0x3b400000]);
+    assert_eq!(message.check(), Ok(()));
+    assert_eq!(message.response(&revision), Err(Error::NoResponse(0x00010002)));
+    assert_eq!(message.response(&clock),
+               Err(Error::Truncated { tag: 0x00030004, length: 12 }));
+    assert_eq!(message.response(&memory),
+               Err(Error::ShortResponse { tag: 0x00010006, length: 4 }));
+}
+
+#[test]
+fn fills_the_buffer() {
+    let mut buffer = Buffer::new();
+    let mut message = Message::new(&mut buffer);
+
+    // The header, then tags of 5 words, and the end tag.
+    let mut added = 0;
+    while message.add(&ArmMemory).is_ok() {
+        added += 1;
+    }
+
+    assert_eq!(added, (BUFFER_WORDS - HEADER_WORDS - 1) / 5);
+    assert_eq!(message.add(&ArmMemory).unwrap_err(), Error::BufferFull);
+    assert_eq!(message.finish(), HEADER_WORDS + added * 5 + 1);
+}
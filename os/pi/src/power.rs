@@ -0,0 +1,68 @@
+use common::IO_BASE;
+use volatile::prelude::*;
+use volatile::Volatile;
+
+/// The base address of the power management watchdog registers.
+const PM_REG_BASE: usize = IO_BASE + 0x10001c;
+
+/// Every write to a power management register must include this password.
+const PM_PASSWORD: u32 = 0x5a000000;
+
+/// The bits of `RSTC` that configure what the watchdog does when it expires.
+const PM_RSTC_WRCFG_MASK: u32 = 0x30;
+/// Configures the watchdog to fully reset the chip when it expires.
+const PM_RSTC_WRCFG_FULL_RESET: u32 = 0x20;
+
+/// Written to `RSTC` to stop the watchdog.
+const PM_RSTC_RESET: u32 = 0x102;
+
+/// The number of watchdog ticks, of about 16µs each, before the reset.
+const RESET_TICKS: u32 = 10;
+
+/// The watchdog ticks this many times per second, and counts down from at
+/// most `MAX_TICKS`, about 16 seconds.
+const TICKS_PER_SECOND: u64 = 65536;
+const MAX_TICKS: u32 = 0xfffff;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    RSTC: Volatile<u32>,
+    RSTS: Volatile<u32>,
+    WDOG: Volatile<u32>,
+}
+
+fn registers() -> &'static mut Registers {
+    unsafe { &mut *(PM_REG_BASE as *mut Registers) }
+}
+
+/// Starts the watchdog to fully reset the chip in `ticks` ticks.
+fn start(ticks: u32) {
+    let registers = registers();
+    registers.WDOG.write(PM_PASSWORD | ticks);
+    let config = registers.RSTC.read() & !PM_RSTC_WRCFG_MASK;
+    registers.RSTC.write(PM_PASSWORD | config | PM_RSTC_WRCFG_FULL_RESET);
+}
+
+/// Starts the watchdog timer: the Raspberry Pi is reset, like by `reset()`,
+/// once `ms` milliseconds have passed, unless `stop_watchdog()` is called
+/// before. Timeouts longer than about 16 seconds are shortened to that.
+pub fn start_watchdog(ms: u32) {
+    let ticks = ms as u64 * TICKS_PER_SECOND / 1000;
+    start(if ticks > MAX_TICKS as u64 { MAX_TICKS } else { ticks as u32 });
+}
+
+/// Stops the watchdog timer started by `start_watchdog()`, if any.
+pub fn stop_watchdog() {
+    registers().RSTC.write(PM_PASSWORD | PM_RSTC_RESET);
+}
+
+/// Resets the Raspberry Pi with the watchdog timer. The firmware then boots
+/// `kernel8.img` from the SD card again, exactly like after a power cycle.
+pub fn reset() -> ! {
+    start(RESET_TICKS);
+
+    loop {
+        unsafe { asm!("wfe" :::: "volatile"); }
+    }
+}
@@ -1,6 +1,6 @@
 use std::{i32, io};
 use fat32::traits::BlockDevice;
-use pi::timer::spin_sleep_us;
+use timer::spin_sleep_us;
 
 extern "C" {
     /// A global representing the last SD controller error that occurred.
@@ -60,10 +60,8 @@ impl Sd {
             Error::Timeout
         } else if code == -2 {
             Error::CommandError
-        } else if code <= -3 {
-            Error::Unknown
         } else {
-            panic!("Unexpected SD error");
+            Error::Unknown
         }
     }
 }
@@ -103,7 +101,10 @@ impl BlockDevice for Sd {
         }
     }
 
+    /// The SD card is read only: an error of kind `PermissionDenied` is
+    /// always returned.
     fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+        Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                           "SD card is read only"))
     }
 }
@@ -1,3 +1,8 @@
+mod pl011;
+
+#[cfg(test)]
+mod tests;
+
 use core::fmt;
 
 use volatile::prelude::*;
@@ -7,6 +12,8 @@ use timer;
 use common::IO_BASE;
 use gpio::{Gpio, Function};
 
+pub use self::pl011::{Pl011, Config, Parity, StopBits, FifoLevel, Error};
+
 /// The base address for the `MU` registers.
 const MU_REG_BASE: usize = IO_BASE + 0x215040;
 
@@ -0,0 +1,401 @@
+use core::fmt;
+
+use volatile::prelude::*;
+use volatile::{Volatile, ReadVolatile, WriteVolatile, Reserved};
+
+use timer;
+use common::IO_BASE;
+use gpio::{Gpio, Function};
+use mailbox::{self, Clock, ClockRate};
+
+/// The base address for the PL011 `UART0` registers.
+const UART0_REG_BASE: usize = IO_BASE + 0x201000;
+
+/// The UART's reference clock unless the firmware reports another: the
+/// default of its `init_uart_clock` setting.
+const DEFAULT_CLOCK_HZ: u32 = 48_000_000;
+
+/// `FR` bits.
+const FR_BUSY: u32 = 1 << 3;
+const FR_RXFE: u32 = 1 << 4;
+const FR_TXFF: u32 = 1 << 5;
+
+/// `DR` bits set when a byte was received with a framing, parity or break
+/// error.
+const DR_ERRORS: u32 = 0b111 << 8;
+
+/// `LCRH` bits.
+const LCRH_PEN: u32 = 1 << 1;
+const LCRH_EPS: u32 = 1 << 2;
+const LCRH_STP2: u32 = 1 << 3;
+const LCRH_FEN: u32 = 1 << 4;
+const LCRH_WLEN_8: u32 = 0b11 << 5;
+
+/// `CR` bits.
+const CR_UARTEN: u32 = 1 << 0;
+const CR_TXE: u32 = 1 << 8;
+const CR_RXE: u32 = 1 << 9;
+const CR_RTSEN: u32 = 1 << 14;
+const CR_CTSEN: u32 = 1 << 15;
+
+/// Interrupt bits of `IMSC`, `MIS` and `ICR`: receive, transmit and receive
+/// timeout, then every interrupt.
+const INT_RX: u32 = 1 << 4;
+const INT_TX: u32 = 1 << 5;
+const INT_RT: u32 = 1 << 6;
+const INT_ALL: u32 = 0x7FF;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    DR: Volatile<u32>, // Data.
+    RSRECR: Volatile<u32>, // Receive status, error clear.
+    __r0: [Reserved<u32>; 4],
+    FR: ReadVolatile<u32>, // Flags.
+    __r1: Reserved<u32>,
+    ILPR: Reserved<u32>, // IrDA, not used.
+    IBRD: Volatile<u32>, // Integer baud rate divisor.
+    FBRD: Volatile<u32>, // Fractional baud rate divisor.
+    LCRH: Volatile<u32>, // Line control.
+    CR: Volatile<u32>, // Control.
+    IFLS: Volatile<u32>, // Interrupt FIFO level select.
+    IMSC: Volatile<u32>, // Interrupt mask set/clear.
+    RIS: ReadVolatile<u32>, // Raw interrupt status.
+    MIS: ReadVolatile<u32>, // Masked interrupt status.
+    ICR: WriteVolatile<u32>, // Interrupt clear.
+}
+
+/// The parity bit sent after each byte.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// The number of stop bits sent after each byte.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// How full a FIFO, of 16 bytes, is when its interrupt is raised: the receive
+/// FIFO as full or fuller, the transmit FIFO as empty or emptier.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FifoLevel {
+    Eighth = 0,
+    Quarter = 1,
+    Half = 2,
+    ThreeQuarters = 3,
+    SevenEighths = 4,
+}
+
+/// The line settings and FIFO thresholds of a `Pl011`. The data is always 8
+/// bits per byte.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub baud_rate: u32,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    /// Use the CTS and RTS lines on GPIO pins 16 and 17.
+    pub flow_control: bool,
+    pub rx_threshold: FifoLevel,
+    pub tx_threshold: FifoLevel,
+}
+
+impl Default for Config {
+    /// 115200 baud, 8N1, no flow control and FIFO interrupts at half.
+    fn default() -> Config {
+        Config {
+            baud_rate: 115200,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: false,
+            rx_threshold: FifoLevel::Half,
+            tx_threshold: FifoLevel::Half,
+        }
+    }
+}
+
+impl Config {
+    /// Returns the `LCRH` value of the settings, with the FIFOs enabled.
+    pub(super) fn line_control(&self) -> u32 {
+        let parity = match self.parity {
+            Parity::None => 0,
+            Parity::Even => LCRH_PEN | LCRH_EPS,
+            Parity::Odd => LCRH_PEN,
+        };
+
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => LCRH_STP2,
+        };
+
+        LCRH_WLEN_8 | LCRH_FEN | parity | stop_bits
+    }
+
+    /// Returns the `CR` value of the settings, with the UART enabled.
+    pub(super) fn control(&self) -> u32 {
+        let flow_control = if self.flow_control { CR_RTSEN | CR_CTSEN } else { 0 };
+        CR_UARTEN | CR_TXE | CR_RXE | flow_control
+    }
+
+    /// Returns the `IFLS` value of the FIFO thresholds.
+    pub(super) fn fifo_levels(&self) -> u32 {
+        (self.rx_threshold as u32) << 3 | self.tx_threshold as u32
+    }
+}
+
+/// Returns the integer and fractional baud rate divisors of `baud_rate` for
+/// the reference clock `clock_hz`: `clock_hz / (16 * baud_rate)`, the
+/// fraction in 64ths, rounded. Returns `None` if the rate is out of range.
+pub(super) fn divisors(clock_hz: u32, baud_rate: u32) -> Option<(u32, u32)> {
+    if baud_rate == 0 {
+        return None;
+    }
+
+    let (clock_hz, baud_rate) = (clock_hz as u64, baud_rate as u64);
+    let divisor = (4 * clock_hz + baud_rate / 2) / baud_rate;
+    let (integer, fraction) = (divisor >> 6, divisor & 0x3F);
+    if integer == 0 || integer > 0xFFFF {
+        None
+    } else {
+        Some((integer as u32, fraction as u32))
+    }
+}
+
+/// Why a `Pl011` couldn't be set up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The baud rate can't be derived from the UART's reference clock.
+    BaudRate(u32),
+}
+
+/// The Raspberry Pi's full PL011 UART, `UART0`.
+pub struct Pl011 {
+    registers: &'static mut Registers,
+    timeout: Option<u32>,
+}
+
+impl Pl011 {
+    /// Initializes the PL011 UART with `config`, taking over GPIO pins 14 and
+    /// 15 (TXD0/RXD0), and 16 and 17 (CTS0/RTS0) for flow control. The baud
+    /// rate divisors are computed from the UART's clock rate, as reported by
+    /// the firmware. Interrupts start disabled.
+    ///
+    /// By default, reads will never time out. To set a read timeout, use
+    /// `set_read_timeout()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::BaudRate` if the baud rate is out of range.
+    pub fn new(config: Config) -> Result<Pl011, Error> {
+        let clock_hz = match mailbox::property(&ClockRate(Clock::Uart)) {
+            Ok(rate) if rate != 0 => rate,
+            _ => DEFAULT_CLOCK_HZ,
+        };
+
+        let (integer, fraction) = divisors(clock_hz, config.baud_rate)
+            .ok_or(Error::BaudRate(config.baud_rate))?;
+
+        let registers = unsafe { &mut *(UART0_REG_BASE as *mut Registers) };
+
+        // Disable the UART, let it finish the byte it's sending, then flush
+        // the transmit FIFO by disabling the FIFOs.
+        registers.CR.write(0);
+        while registers.FR.has_mask(FR_BUSY) {  }
+        registers.LCRH.write(0);
+
+        Gpio::new(14).into_alt(Function::Alt0);
+        Gpio::new(15).into_alt(Function::Alt0);
+        if config.flow_control {
+            Gpio::new(16).into_alt(Function::Alt3);
+            Gpio::new(17).into_alt(Function::Alt3);
+        }
+
+        registers.IMSC.write(0);
+        registers.ICR.write(INT_ALL);
+        registers.RSRECR.write(0);
+
+        // The divisors are latched by the write to `LCRH`, which must follow.
+        registers.IBRD.write(integer);
+        registers.FBRD.write(fraction);
+        registers.LCRH.write(config.line_control());
+        registers.IFLS.write(config.fifo_levels());
+        registers.CR.write(config.control());
+
+        Ok(Pl011 { registers, timeout: None })
+    }
+
+    /// Set the read timeout to `milliseconds` milliseconds.
+    pub fn set_read_timeout(&mut self, milliseconds: u32) {
+        self.timeout = Some(milliseconds);
+    }
+
+    /// Enables or disables the receive interrupts: raised when the receive
+    /// FIFO reaches its threshold, or holds bytes and the line went idle.
+    pub fn set_rx_interrupt(&mut self, enabled: bool) {
+        self.set_interrupts(INT_RX | INT_RT, enabled);
+    }
+
+    /// Enables or disables the transmit interrupt: raised when the transmit
+    /// FIFO drains to its threshold.
+    pub fn set_tx_interrupt(&mut self, enabled: bool) {
+        self.set_interrupts(INT_TX, enabled);
+    }
+
+    fn set_interrupts(&mut self, mask: u32, enabled: bool) {
+        if enabled {
+            self.registers.IMSC.or_mask(mask);
+        } else {
+            self.registers.IMSC.and_mask(!mask);
+        }
+    }
+
+    /// Returns `true` if an enabled receive interrupt is pending. It is
+    /// cleared by reading the receive FIFO below its threshold.
+    pub fn rx_interrupt_pending(&self) -> bool {
+        self.registers.MIS.read() & (INT_RX | INT_RT) != 0
+    }
+
+    /// Returns `true` if an enabled transmit interrupt is pending. It is
+    /// cleared by filling the transmit FIFO above its threshold, or by
+    /// `clear_tx_interrupt()`.
+    pub fn tx_interrupt_pending(&self) -> bool {
+        self.registers.MIS.has_mask(INT_TX)
+    }
+
+    /// Clears the transmit interrupt, when there's nothing left to send.
+    pub fn clear_tx_interrupt(&mut self) {
+        self.registers.ICR.write(INT_TX);
+    }
+
+    /// Write the byte `byte`. This method blocks until there is space available
+    /// in the output FIFO.
+    pub fn write_byte(&mut self, byte: u8) {
+        while self.registers.FR.has_mask(FR_TXFF) {
+            continue
+        }
+
+        self.registers.DR.write(byte as u32);
+    }
+
+    /// Returns `true` if there is at least one byte ready to be read. If this
+    /// method returns `true`, a subsequent call to `read_byte` is guaranteed to
+    /// return immediately. This method does not block.
+    pub fn has_byte(&self) -> bool {
+        !self.registers.FR.has_mask(FR_RXFE)
+    }
+
+    /// Blocks until there is a byte ready to read. If a read timeout is set,
+    /// this method blocks for at most that amount of time. Otherwise, this
+    /// method blocks indefinitely until there is a byte to read.
+    ///
+    /// Returns `Ok(())` if a byte is ready to read. Returns `Err(())` if the
+    /// timeout expired while waiting for a byte to be ready.
+    pub fn wait_for_byte(&self) -> Result<(), ()> {
+        let start_time: u64 = timer::current_time();
+
+        while !self.has_byte() {
+            if let Some(duration) = self.timeout {
+                if timer::current_time() > start_time + (duration as u64) * 1000 {
+                    return Err(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a byte and whether it was received with a framing, parity or
+    /// break error. Blocks indefinitely until a byte is ready to be read.
+    fn receive(&mut self) -> (u8, bool) {
+        while !self.has_byte() {
+            continue
+        }
+
+        let data = self.registers.DR.read();
+        ((data & 0xFF) as u8, data & DR_ERRORS != 0)
+    }
+
+    /// Reads a byte, even one received with an error. Blocks indefinitely
+    /// until a byte is ready to be read.
+    pub fn read_byte(&mut self) -> u8 {
+        self.receive().0
+    }
+
+    /// Blocks until every byte written was sent.
+    pub fn flush(&mut self) {
+        while self.registers.FR.has_mask(FR_BUSY) {
+            continue
+        }
+    }
+}
+
+impl fmt::Write for Pl011 {
+    /// Writes a string to the UART. For any \n character, a \r is
+    /// automatically written preceding it.
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+
+            self.write_byte(byte);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+mod uart_io {
+    use std::io;
+    use super::Pl011;
+
+    impl io::Read for Pl011 {
+        /// Waits until the timeout duration for data to arrive, and then reads
+        /// any available data, up to buf.len() bytes. A byte received with an
+        /// error is dropped, and fails the read if it's the first one.
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.wait_for_byte().is_err() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut,
+                                          "Timeout waiting for data"));
+            }
+
+            let mut bytes_read: usize = 0;
+            while self.has_byte() && bytes_read < buf.len() {
+                match self.receive() {
+                    (byte, false) => buf[bytes_read] = byte,
+                    (_, true) if bytes_read == 0 => {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                  "Framing, parity or break error"));
+                    }
+                    (_, true) => break,
+                }
+
+                bytes_read += 1;
+            }
+
+            Ok(bytes_read)
+        }
+    }
+
+    impl io::Write for Pl011 {
+        /// Write the requested buffer to the UART.
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for &byte in buf {
+                self.write_byte(byte);
+            }
+
+            Ok(buf.len())
+        }
+
+        /// Waits until every byte written was sent.
+        fn flush(&mut self) -> io::Result<()> {
+            Pl011::flush(self);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use super::pl011::divisors;
+use super::{Config, Parity, StopBits, FifoLevel};
+
+#[test]
+fn baud_rate_divisors() {
+    // The firmware's default clock, and the older one of 3 MHz.
+    assert_eq!(divisors(48_000_000, 115200), Some((26, 3)));
+    assert_eq!(divisors(48_000_000, 9600), Some((312, 32)));
+    assert_eq!(divisors(48_000_000, 921600), Some((3, 16)));
+    assert_eq!(divisors(3_000_000, 115200), Some((1, 40)));
+
+    // The fastest rate is a 16th of the clock.
+    assert_eq!(divisors(48_000_000, 3_000_000), Some((1, 0)));
+    assert_eq!(divisors(48_000_000, 3_000_001), Some((1, 0)));
+    assert_eq!(divisors(48_000_000, 4_000_000), None);
+
+    // The slowest rate is the one the 16-bit integer divisor allows.
+    assert_eq!(divisors(48_000_000, 46), Some((65217, 25)));
+    assert_eq!(divisors(48_000_000, 45), None);
+    assert_eq!(divisors(48_000_000, 0), None);
+}
+
+#[test]
+fn line_control() {
+    let config = Config::default();
+    assert_eq!(config.line_control(), 0b0111_0000);
+
+    let config = Config { parity: Parity::Even, stop_bits: StopBits::Two, ..config };
+    assert_eq!(config.line_control(), 0b0111_1110);
+
+    let config = Config { parity: Parity::Odd, ..Config::default() };
+    assert_eq!(config.line_control(), 0b0111_0010);
+}
+
+#[test]
+fn control() {
+    let config = Config::default();
+    assert_eq!(config.control(), 0x301);
+
+    let config = Config { flow_control: true, ..config };
+    assert_eq!(config.control(), 0xC301);
+}
+
+#[test]
+fn fifo_levels() {
+    assert_eq!(Config::default().fifo_levels(), 0b010_010);
+
+    let config = Config {
+        rx_threshold: FifoLevel::SevenEighths,
+        tx_threshold: FifoLevel::Eighth,
+        ..Config::default()
+    };
+    assert_eq!(config.fifo_levels(), 0b100_000);
+}